@@ -13,12 +13,10 @@ use crate::{
     timestamp::Timestamp,
 };
 
-/// Reads a UTF-16-LE encoded file to a string.
-fn read_utf16le_file(path: impl AsRef<Path>) -> io::Result<String> {
-    let path = path.as_ref();
-
+/// Reads UTF-16-LE encoded data to a string.
+fn read_utf16le(mut reader: impl io::Read) -> io::Result<String> {
     let mut data = Vec::new();
-    io::Read::read_to_end(&mut File::open(path)?, &mut data)?;
+    reader.read_to_end(&mut data)?;
 
     char::decode_utf16(
         data.chunks_exact(2)
@@ -188,7 +186,19 @@ impl Autoruns {
     pub(crate) fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
         let path = path.as_ref();
 
-        let content = read_utf16le_file(path)?;
+        let metadata = path.metadata()?;
+
+        Self::from_reader(File::open(path)?, metadata.modified()?.into())
+    }
+
+    /// Reads the autoruns information from the given reader.
+    ///
+    /// The `recording_time` should be the last modification time of the autoruns file.
+    pub(crate) fn from_reader(
+        reader: impl io::Read,
+        recording_time: Timestamp,
+    ) -> io::Result<Self> {
+        let content = read_utf16le(reader)?;
 
         let mut entries: Vec<AutorunsEntry> = Vec::new();
 
@@ -199,11 +209,9 @@ impl Autoruns {
             }
         }
 
-        let metadata = path.metadata()?;
-
         Ok(Self {
             entries,
-            recording_time: metadata.modified()?.into(),
+            recording_time,
         })
     }
 }
@@ -4,6 +4,7 @@ use anyhow::Context as _;
 use owo_colors::OwoColorize as _;
 use rusqlite as sql;
 use std::{
+    cell::OnceCell,
     collections::BTreeMap,
     ffi::OsString,
     fmt,
@@ -23,6 +24,8 @@ struct SnapshotInsertionStatements<'a> {
     insert_file: sql::Statement<'a>,
     /// The statement to get the `id` of an entry in the file table.
     get_file_id: sql::Statement<'a>,
+    /// The statement to add the digests to a file recorded without them.
+    set_file_digests: sql::Statement<'a>,
     /// The statement to insert an entry into the path table.
     insert_path: sql::Statement<'a>,
     /// The statement to get the `id` of an entry in the path table.
@@ -37,6 +40,8 @@ struct SnapshotInsertionStatements<'a> {
     insert_snapshot: sql::Statement<'a>,
     /// The statement to get the `id` of an entry in the snapshot table.
     get_snapshot_id: sql::Statement<'a>,
+    /// The statement to insert a tag of a snapshot into the snapshot tags table.
+    insert_tag: sql::Statement<'a>,
     /// The statement to insert an autorun into the autoruns table.
     insert_autorun: sql::Statement<'a>,
 }
@@ -44,6 +49,12 @@ struct SnapshotInsertionStatements<'a> {
 /// The type of an `id` in SQL statements.
 type SqlId = i64;
 
+/// The maximum TLSH distance at which files are still considered similar.
+const MAX_SIMILARITY_DISTANCE: u32 = 100;
+
+/// The maximum number of similar files that are looked up for a file.
+const MAX_SIMILAR_FILES: usize = 5;
+
 /// Access to a database containing information about multiple snapshots.
 #[derive(Debug)]
 pub(crate) struct Database {
@@ -53,6 +64,8 @@ pub(crate) struct Database {
     main_snapshot_id: Option<SqlId>,
     /// The id of the snapshot to compare to.
     comparison_snapshot_id: Option<SqlId>,
+    /// The TLSH digests of all files in other snapshots, loaded on the first similarity lookup.
+    tlsh_digests: OnceCell<Vec<(SqlId, fs::file::tlsh::Tlsh)>>,
 }
 
 /// Returns the prepared statements necessary for snapshot insertion.
@@ -73,7 +86,10 @@ fn snapshot_insertion_stmts(
                     valid_utf16be,
                     valid_utf16le,
                     valid_utf32be,
-                    valid_utf32le
+                    valid_utf32le,
+                    tlsh,
+                    imphash,
+                    rich_hash
                 ) VALUES (
                     :sha256,
                     :md5,
@@ -85,7 +101,10 @@ fn snapshot_insertion_stmts(
                     :valid_utf16be,
                     :valid_utf16le,
                     :valid_utf32be,
-                    :valid_utf32le
+                    :valid_utf32le,
+                    :tlsh,
+                    :imphash,
+                    :rich_hash
                 )",
             )
             .context("Failed to prepare file insertion statement")?,
@@ -108,11 +127,26 @@ fn snapshot_insertion_stmts(
                             valid_utf16be = :valid_utf16be AND
                             valid_utf16le = :valid_utf16le AND
                             valid_utf32be = :valid_utf32be AND
-                            valid_utf32le = :valid_utf32le
+                            valid_utf32le = :valid_utf32le AND
+                            tlsh IS :tlsh AND
+                            imphash IS :imphash AND
+                            rich_hash IS :rich_hash
                         )
                     )",
             )
             .context("Failed to prepare file id statement")?,
+        set_file_digests: connection
+            .prepare(
+                "UPDATE
+                    Files
+                SET
+                    tlsh = coalesce(tlsh, :tlsh),
+                    imphash = coalesce(imphash, :imphash),
+                    rich_hash = coalesce(rich_hash, :rich_hash)
+                WHERE
+                    id = :id",
+            )
+            .context("Failed to prepare file digests statement")?,
         insert_path: connection
             .prepare(
                 "INSERT INTO Paths (
@@ -171,11 +205,13 @@ fn snapshot_insertion_stmts(
                 "INSERT INTO Snapshots (
                     date,
                     version,
-                    comment
+                    comment,
+                    source_sha256
                 ) VALUES (
                     :date,
                     :version,
-                    :comment
+                    :comment,
+                    :source_sha256
                 )",
             )
             .context("Failed to prepare snapshot insertion statement")?,
@@ -190,11 +226,25 @@ fn snapshot_insertion_stmts(
                     version IS :version AND
                     (1 OR -- this just exists to use the parameters
                         (
-                            comment = :comment
+                            comment = :comment AND
+                            source_sha256 IS :source_sha256
                         )
                     )",
             )
             .context("Failed to prepare snapshot id statement")?,
+        insert_tag: connection
+            .prepare(
+                "INSERT INTO SnapshotTags (
+                    snapshot_id,
+                    key,
+                    value
+                ) VALUES (
+                    :snapshot_id,
+                    :key,
+                    :value
+                )",
+            )
+            .context("Failed to prepare tag insertion statement")?,
         insert_autorun: connection
             .prepare(
                 "INSERT INTO Autoruns (
@@ -244,7 +294,10 @@ fn insert_file(
     size: u64,
 ) -> anyhow::Result<SqlId> {
     use fs::file::FileFlags;
-    insert_and_get_id(
+    let tlsh = file.tlsh.map(|tlsh| tlsh.to_string());
+    let imphash = file.imphash.as_ref().map(|hash| hash.bytes);
+    let rich_hash = file.rich_hash.as_ref().map(|hash| hash.bytes);
+    let id = insert_and_get_id(
         connection,
         &mut stmts.insert_file,
         &mut stmts.get_file_id,
@@ -260,8 +313,26 @@ fn insert_file(
             ":valid_utf16le": file.flags.contains(FileFlags::UTF16LE),
             ":valid_utf32be": file.flags.contains(FileFlags::UTF32BE),
             ":valid_utf32le": file.flags.contains(FileFlags::UTF32LE),
+            ":tlsh": tlsh,
+            ":imphash": imphash,
+            ":rich_hash": rich_hash,
         },
-    )
+    )?;
+
+    // files first recorded by older versions do not have the digests yet
+    if tlsh.is_some() || imphash.is_some() || rich_hash.is_some() {
+        stmts
+            .set_file_digests
+            .execute(sql::named_params! {
+                ":id": id,
+                ":tlsh": tlsh,
+                ":imphash": imphash,
+                ":rich_hash": rich_hash,
+            })
+            .context("Failed to add the digests")?;
+    }
+
+    Ok(id)
 }
 
 /// Inserts the given path into the database, returning the `id` of the path entries.
@@ -320,26 +391,69 @@ fn insert_entry(
     path: impl AsRef<Path>,
     entry: &fs::MetaDEntry,
 ) -> anyhow::Result<()> {
+    let path = path.as_ref();
+
     if let fs::DirEntry::File(file) = &entry.entry {
-        let file_id = insert_file(connection, stmts, file, entry.metadata.size)
-            .context("Failed to insert the file into the files table")?;
-        let (path_id, normalized_path_id) = insert_path(connection, stmts, path)
-            .context("Failed to insert the path into the paths table")?;
+        insert_record(
+            connection,
+            stmts,
+            snapshot_id,
+            path,
+            file,
+            entry.metadata.size,
+        )?;
+    }
 
-        stmts
-            .insert_entry
-            .execute(sql::named_params! {
-                ":snapshot_id": snapshot_id,
-                ":path_id": path_id,
-                ":normalized_path_id": normalized_path_id,
-                ":file_id": file_id
-            })
-            .context("Failed to insert the entry into the records table")?;
+    // Alternate data streams are recorded like files at `path:name`, as Windows refers to them
+    for (name, stream) in entry.metadata.streams.iter().flat_map(|s| &s.streams) {
+        if let Some(stream) = stream {
+            let name = name.to_string_lossy();
+            let mut stream_path = path.as_os_str().to_owned();
+            stream_path.push(":");
+            stream_path.push(name.strip_prefix("user.").unwrap_or(&name));
+
+            insert_record(
+                connection,
+                stmts,
+                snapshot_id,
+                stream_path,
+                &stream.file,
+                stream.size,
+            )
+            .context("Failed to insert an alternate data stream")?;
+        }
     }
 
     Ok(())
 }
 
+/// Inserts a record of the given file at the given path into the database.
+fn insert_record(
+    connection: &sql::Connection,
+    stmts: &mut SnapshotInsertionStatements,
+    snapshot_id: SqlId,
+    path: impl AsRef<Path>,
+    file: &fs::File,
+    size: u64,
+) -> anyhow::Result<()> {
+    let file_id = insert_file(connection, stmts, file, size)
+        .context("Failed to insert the file into the files table")?;
+    let (path_id, normalized_path_id) = insert_path(connection, stmts, path)
+        .context("Failed to insert the path into the paths table")?;
+
+    stmts
+        .insert_entry
+        .execute(sql::named_params! {
+            ":snapshot_id": snapshot_id,
+            ":path_id": path_id,
+            ":normalized_path_id": normalized_path_id,
+            ":file_id": file_id
+        })
+        .context("Failed to insert the entry into the records table")?;
+
+    Ok(())
+}
+
 /// Returns the ID of the given file, if it exists in the database.
 fn get_file_id(connection: &sql::Connection, file: &fs::File) -> anyhow::Result<Option<SqlId>> {
     let mut stmt = connection
@@ -401,6 +515,78 @@ pub(crate) struct FileOccurrences {
     pub(crate) paths: BTreeMap<SnapshotRef, Vec<PathBuf>>,
 }
 
+/// A file in another snapshot that is similar to a given file.
+#[derive(Debug)]
+pub(crate) struct SimilarFile {
+    /// The latest snapshot in which the similar file was seen.
+    pub(crate) snapshot: SnapshotRef,
+    /// The path of the similar file in that snapshot.
+    pub(crate) path: PathBuf,
+    /// The TLSH distance between the files, lower scores mean more similar files.
+    pub(crate) score: u32,
+}
+
+/// A hash of PE files that is used to group similar files.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub(crate) enum ClusterHash {
+    /// The import hash of the file.
+    Imphash,
+    /// The MD5 hash of the decoded Rich header of the file.
+    RichHash,
+}
+
+impl ClusterHash {
+    /// Returns the column of the files table that contains the hash.
+    fn column(self) -> &'static str {
+        match self {
+            ClusterHash::Imphash => "imphash",
+            ClusterHash::RichHash => "rich_hash",
+        }
+    }
+}
+
+impl fmt::Display for ClusterHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClusterHash::Imphash => write!(f, "import hash"),
+            ClusterHash::RichHash => write!(f, "Rich header hash"),
+        }
+    }
+}
+
+/// A group of unknown files in a snapshot that share a hash with known files.
+#[derive(Debug)]
+pub(crate) struct FileCluster {
+    /// The kind of hash that the files share.
+    pub(crate) kind: ClusterHash,
+    /// The hash that the files share.
+    pub(crate) hash: fs::file::Md5Hash,
+    /// The paths of the unknown files in the snapshot.
+    pub(crate) unknown: Vec<PathBuf>,
+    /// The known files with the same hash in the other snapshots.
+    pub(crate) known: FileOccurrences,
+}
+
+impl fmt::Display for FileCluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}:", self.kind, self.hash)?;
+
+        for path in &self.unknown {
+            writeln!(f, "    {:?}", path.yellow())?;
+        }
+
+        for (snapshot, paths) in &self.known.paths {
+            writeln!(f, "    known in {snapshot}:")?;
+
+            for path in paths {
+                writeln!(f, "        {path:?}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl Database {
     /// Opens the database at the specified `path`.
     pub(crate) fn open(path: impl AsRef<Path>) -> sql::Result<Self> {
@@ -408,6 +594,7 @@ impl Database {
             connection: sql::Connection::open(path)?,
             main_snapshot_id: None,
             comparison_snapshot_id: None,
+            tlsh_digests: OnceCell::new(),
         };
 
         this.setup_database()?;
@@ -432,9 +619,19 @@ impl Database {
                 date TEXT NOT NULL,
                 version TEXT,
                 comment TEXT NOT NULL,
+                source_sha256 BLOB,
                 UNIQUE (date, version) ON CONFLICT IGNORE
             ) STRICT;
 
+            CREATE TABLE IF NOT EXISTS SnapshotTags (
+                id INTEGER PRIMARY KEY,
+                snapshot_id INTEGER NOT NULL REFERENCES Snapshots(id),
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                UNIQUE (snapshot_id, key) ON CONFLICT REPLACE
+            ) STRICT;
+            CREATE INDEX IF NOT EXISTS SnapshotTagsIdx on SnapshotTags (key, value);
+
             CREATE TABLE IF NOT EXISTS Paths (
                 id INTEGER PRIMARY KEY,
                 path BLOB NOT NULL,
@@ -460,6 +657,9 @@ impl Database {
                 valid_utf16le INT NOT NULL,
                 valid_utf32be INT NOT NULL,
                 valid_utf32le INT NOT NULL,
+                tlsh TEXT,
+                imphash BLOB,
+                rich_hash BLOB,
                 UNIQUE (sha256, md5, size, first_bytes) ON CONFLICT IGNORE
             ) STRICT;
             CREATE INDEX IF NOT EXISTS Sha256Idx on Files (sha256);
@@ -488,6 +688,35 @@ impl Database {
             CREATE INDEX IF NOT EXISTS AutorunsPathIdIdx on Autoruns (normalized_path_id);",
         )?;
 
+        // databases created by older versions need the additional columns
+        for (table, column, ty) in [
+            ("Snapshots", "source_sha256", "BLOB"),
+            ("Files", "tlsh", "TEXT"),
+            ("Files", "imphash", "BLOB"),
+            ("Files", "rich_hash", "BLOB"),
+        ] {
+            let has_column: bool = self.connection.query_row(
+                "SELECT
+                    COUNT(1) > 0
+                FROM
+                    pragma_table_info(:table)
+                WHERE
+                    name = :column",
+                sql::named_params! { ":table": table, ":column": column },
+                |row| row.get(0),
+            )?;
+            if !has_column {
+                self.connection
+                    .execute(&format!("ALTER TABLE {table} ADD COLUMN {column} {ty}"), [])?;
+            }
+        }
+
+        // the indices can only be created once the columns exist
+        self.connection.execute_batch(
+            "CREATE INDEX IF NOT EXISTS ImphashIdx on Files (imphash);
+            CREATE INDEX IF NOT EXISTS RichHashIdx on Files (rich_hash);",
+        )?;
+
         Ok(())
     }
 
@@ -582,11 +811,23 @@ impl Database {
             sql::named_params! {
                 ":date": date,
                 ":version": snapshot.version,
-                ":comment": comment
+                ":comment": comment,
+                ":source_sha256": snapshot.source_hash.as_ref().map(|hash| hash.bytes)
             },
         )
         .context("Failed to insert the snapshot into the snapshots table")?;
 
+        for (key, value) in &snapshot.tags {
+            stmts
+                .insert_tag
+                .execute(sql::named_params! {
+                    ":snapshot_id": snapshot_id,
+                    ":key": key,
+                    ":value": value
+                })
+                .with_context(|| format!("Failed to insert the tag {key}"))?;
+        }
+
         for entry in snapshot.root.walk() {
             insert_entry(
                 &transaction,
@@ -699,9 +940,43 @@ impl Database {
                 return Ok(FileOccurrences { paths: Default::default() });
             };
 
+        self.file_id_occurrences(file_id)
+    }
+
+    /// Returns the occurrences of the file with the given ID in the other snapshots.
+    fn file_id_occurrences(&self, file_id: SqlId) -> anyhow::Result<FileOccurrences> {
+        self.occurrences(
+            "file_id = :file_id",
+            sql::named_params! { ":file_id": file_id },
+        )
+    }
+
+    /// Returns the occurrences of all files with the given hash in the other snapshots.
+    fn hash_occurrences(
+        &self,
+        kind: ClusterHash,
+        hash: &fs::file::Md5Hash,
+    ) -> anyhow::Result<FileOccurrences> {
+        self.occurrences(
+            &format!(
+                "file_id IN (SELECT id FROM Files WHERE {} = :hash)",
+                kind.column()
+            ),
+            sql::named_params! { ":hash": hash.bytes },
+        )
+    }
+
+    /// Returns the occurrences of the files selected by `condition` in the other snapshots.
+    ///
+    /// `condition` is an SQL expression on the records table, which may use the given `params`.
+    fn occurrences(
+        &self,
+        condition: &str,
+        params: &[(&str, &dyn sql::ToSql)],
+    ) -> anyhow::Result<FileOccurrences> {
         let mut stmt = self
             .connection
-            .prepare_cached(
+            .prepare_cached(&format!(
                 "SELECT
                     date,
                     version,
@@ -712,40 +987,39 @@ impl Database {
                     Snapshots,
                     Paths
                 WHERE
-                    file_id = :file_id AND
+                    {condition} AND
                     snapshot_id = Snapshots.id AND
                     path_id = Paths.id AND
                     snapshot_id IS NOT :main_id AND
-                    snapshot_id IS NOT :comparison_id",
-            )
+                    snapshot_id IS NOT :comparison_id"
+            ))
             .context("Failed to prepare statement for file existence checking")?;
 
         let mut paths: BTreeMap<SnapshotRef, Vec<PathBuf>> = BTreeMap::new();
 
+        let mut all_params = params.to_vec();
+        all_params.extend_from_slice(sql::named_params! {
+            ":main_id": self.main_snapshot_id,
+            ":comparison_id": self.comparison_snapshot_id,
+        });
+
         for row in stmt
-            .query_map(
-                sql::named_params! {
-                    ":file_id": file_id,
-                    ":main_id": self.main_snapshot_id,
-                    ":comparison_id": self.comparison_snapshot_id,
-                },
-                |row| {
-                    let date: time::OffsetDateTime = row.get("date")?;
-                    let timestamp = Timestamp::from(date);
-                    let version = row.get("version")?;
-                    let comment = row.get("comment")?;
-                    let path = row.get("path")?;
-
-                    Ok((
-                        SnapshotRef {
-                            timestamp,
-                            version,
-                            comment,
-                        },
-                        path,
-                    ))
-                },
-            )
+            .query_map(&all_params[..], |row| {
+                let date: time::OffsetDateTime = row.get("date")?;
+                let timestamp = Timestamp::from(date);
+                let version = row.get("version")?;
+                let comment = row.get("comment")?;
+                let path = row.get("path")?;
+
+                Ok((
+                    SnapshotRef {
+                        timestamp,
+                        version,
+                        comment,
+                    },
+                    path,
+                ))
+            })
             .context("Failed to query for occurrence of file")?
         {
             let (snapshot, path) = row.context("Failed to get row in database")?;
@@ -759,6 +1033,141 @@ impl Database {
         Ok(FileOccurrences { paths })
     }
 
+    /// Groups the unknown files in the snapshot with the known files that share their import hash
+    /// or their Rich header hash.
+    ///
+    /// Only groups that contain known files are returned, the largest groups come first.
+    pub(crate) fn cluster_unknown_files(
+        &self,
+        snapshot: &SnapshotLatest,
+    ) -> anyhow::Result<Vec<FileCluster>> {
+        firestorm::profile_method!(cluster_unknown_files);
+
+        let mut unknown: BTreeMap<_, Vec<PathBuf>> = BTreeMap::new();
+        for entry in snapshot.root.walk() {
+            let fs::DirEntry::File(file) = &entry.entry.entry else {
+                continue;
+            };
+            if file.imphash.is_none() && file.rich_hash.is_none() {
+                continue;
+            }
+            if self.file_is_known(file)? {
+                continue;
+            }
+
+            for (kind, hash) in [
+                (ClusterHash::Imphash, &file.imphash),
+                (ClusterHash::RichHash, &file.rich_hash),
+            ] {
+                if let Some(hash) = hash {
+                    unknown
+                        .entry((kind, hash.clone()))
+                        .or_default()
+                        .push(entry.clone_path());
+                }
+            }
+        }
+
+        let mut clusters = Vec::new();
+        for ((kind, hash), unknown) in unknown {
+            let known = self
+                .hash_occurrences(kind, &hash)
+                .with_context(|| format!("Failed to get the files with the {kind} {hash}"))?;
+
+            if !known.paths.is_empty() {
+                clusters.push(FileCluster {
+                    kind,
+                    hash,
+                    unknown,
+                    known,
+                });
+            }
+        }
+        clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.unknown.len()));
+
+        Ok(clusters)
+    }
+
+    /// Returns the TLSH digests of all files in snapshots other than the current ones.
+    fn tlsh_digests(&self) -> anyhow::Result<&[(SqlId, fs::file::tlsh::Tlsh)]> {
+        firestorm::profile_method!(tlsh_digests);
+
+        if let Some(digests) = self.tlsh_digests.get() {
+            return Ok(digests);
+        }
+
+        let mut stmt = self
+            .connection
+            .prepare(
+                "SELECT DISTINCT
+                    Files.id AS id,
+                    tlsh
+                FROM
+                    Files,
+                    Records
+                WHERE
+                    tlsh IS NOT NULL AND
+                    file_id = Files.id AND
+                    snapshot_id IS NOT :main_id AND
+                    snapshot_id IS NOT :comparison_id",
+            )
+            .context("Failed to prepare statement for getting TLSH digests")?;
+
+        let digests = stmt
+            .query_map(
+                sql::named_params! {
+                    ":main_id": self.main_snapshot_id,
+                    ":comparison_id": self.comparison_snapshot_id,
+                },
+                |row| Ok((row.get("id")?, row.get::<_, String>("tlsh")?)),
+            )
+            .context("Failed to query TLSH digests")?
+            .filter_map(|row| {
+                row.map(|(id, tlsh)| Some((id, tlsh.parse().ok()?)))
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to get row in database")?;
+
+        Ok(self.tlsh_digests.get_or_init(|| digests))
+    }
+
+    /// Finds files in other snapshots that are similar to the given file, most similar first.
+    pub(crate) fn find_similar_files(&self, file: &fs::File) -> anyhow::Result<Vec<SimilarFile>> {
+        firestorm::profile_method!(find_similar_files);
+
+        let Some(tlsh) = &file.tlsh else {
+            return Ok(Vec::new());
+        };
+        let own_id = get_file_id(&self.connection, file)
+            .with_context(|| format!("Failed getting the file ID for {:?}", file.sha2_256_hash))?;
+
+        let mut candidates = self
+            .tlsh_digests()?
+            .iter()
+            .filter(|(id, _)| Some(*id) != own_id)
+            .map(|(id, other)| (tlsh.distance(other), *id))
+            .filter(|&(score, _)| score <= MAX_SIMILARITY_DISTANCE)
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+
+        let mut similar = Vec::new();
+        for (score, id) in candidates.into_iter().take(MAX_SIMILAR_FILES) {
+            let Some((snapshot, mut paths)) = self.file_id_occurrences(id)?.paths.pop_last() else {
+                continue;
+            };
+            paths.sort();
+
+            similar.push(SimilarFile {
+                snapshot,
+                path: paths.swap_remove(0),
+                score,
+            });
+        }
+
+        Ok(similar)
+    }
+
     /// Find all paths of files within the given snapshot.
     fn find_file_paths_in_snapshot(
         &self,
@@ -2,7 +2,13 @@
 
 use owo_colors::OwoColorize;
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, ffi::OsStr, fmt, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use crate::{
     database::Database,
@@ -10,14 +16,22 @@ use crate::{
         self, dir_entry::GenericDirEntry, dir_entry_type::DirEntryType, DirEntry, MetaDEntry,
         Metadata, OsStrExt as _,
     },
+    snapshot::SnapshotLatest,
 };
 
-use self::{file::display_file, filters::FilterContext, metadata::display_metadata};
+use self::{
+    file::{display_file, display_hard_links, files_equal},
+    filters::FilterContext,
+    hard_links::HardLinks,
+    metadata::display_metadata,
+};
 
 pub(crate) mod display_filters;
 mod file;
 pub(crate) mod filters;
+pub(crate) mod hard_links;
 pub(crate) mod metadata;
+pub(crate) mod registry;
 pub(crate) mod visualize;
 
 /// The possible metrics for measuring the size of diffs.
@@ -47,6 +61,19 @@ impl From<(&'static str, fn(&DiffTree) -> u64)> for SizeMetric {
     }
 }
 
+impl SizeMetric {
+    /// Returns the description of the metric in directory summaries.
+    ///
+    /// Returns `None` for metrics that are not shown in directory summaries.
+    fn summary_description(&self) -> Option<&'static str> {
+        match self.name? {
+            "allocated" => Some("allocated"),
+            "compression-savings" => Some("saved"),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Debug for SizeMetric {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("SizeMetric")
@@ -124,6 +151,20 @@ impl FromStr for SizeMetric {
                 DiffType::Changed { to } => std::cmp::max(entry.metadata.size, to.metadata.size),
                 _ => entry.metadata.size,
             }),
+            "allocated" => ("allocated", |entry| match &entry.context {
+                DiffType::Changed { to } => std::cmp::max(
+                    entry.metadata.allocated_size(),
+                    to.metadata.allocated_size(),
+                ),
+                _ => entry.metadata.allocated_size(),
+            }),
+            "compression-savings" => ("compression-savings", |entry| match &entry.context {
+                DiffType::Changed { to } => std::cmp::max(
+                    entry.metadata.compression_savings(),
+                    to.metadata.compression_savings(),
+                ),
+                _ => entry.metadata.compression_savings(),
+            }),
             _ => return Err("unrecognized size metric"),
         };
 
@@ -260,6 +301,7 @@ fn display_hexdump(
 
 /// The types of differences that occur.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum DiffType {
     /// Nothing is changed (recursively), except for possibly metadata.
     Unchanged {
@@ -280,6 +322,10 @@ pub(crate) enum DiffType {
     Added,
     /// The entry was removed.
     Removed,
+    /// The entry is different or missing in one of the snapshots, because it was excluded there.
+    Excluded,
+    /// The entry is missing in one of the snapshots, because it could not be read there.
+    Unreadable,
 }
 
 impl DiffType {
@@ -302,13 +348,63 @@ impl DiffType {
 pub(crate) type DiffTree = MetaDEntry<DiffType>;
 
 impl DiffTree {
-    /// Compute the difference tree between two directory entries.
-    pub(crate) fn compute(former: &MetaDEntry, latter: &MetaDEntry) -> Self {
-        if former.entry == latter.entry {
+    /// Compute the difference tree between two snapshots.
+    ///
+    /// Entries that were excluded from either snapshot are marked as excluded instead of changed.
+    /// Entries that are missing in one snapshot, because they could not be read when it was
+    /// created, are marked as unreadable instead of added or removed.
+    pub(crate) fn compute(
+        former: &SnapshotLatest,
+        latter: &SnapshotLatest,
+    ) -> anyhow::Result<Self> {
+        firestorm::profile_method!(compute);
+
+        let former_filter = former.exclusions.as_ref().map(|e| e.filter()).transpose()?;
+        let latter_filter = latter.exclusions.as_ref().map(|e| e.filter()).transpose()?;
+
+        let excluded = |path: &Path| {
+            [&former_filter, &latter_filter]
+                .into_iter()
+                .flatten()
+                .any(|filter| filter.is_excluded(path))
+        };
+        let unreadable =
+            crate::snapshot::errors::unreadable(former.errors.iter().chain(&latter.errors));
+
+        Ok(Self::compute_entries(
+            &former.root,
+            &latter.root,
+            &mut PathBuf::from("/"),
+            &excluded,
+            &unreadable,
+        ))
+    }
+
+    /// Compute the difference tree between two directory entries at the given `path`.
+    fn compute_entries(
+        former: &MetaDEntry,
+        latter: &MetaDEntry,
+        path: &mut PathBuf,
+        excluded: &impl Fn(&Path) -> bool,
+        unreadable: &impl Fn(&Path) -> bool,
+    ) -> Self {
+        let both_dirs = former.is_dir() && latter.is_dir();
+        if !both_dirs && former != latter && excluded(path) {
+            return former.with_context(&mut || DiffType::Excluded);
+        }
+
+        let entries_equal = match (&former.entry, &latter.entry) {
+            (fs::DirEntry::File(former_file), fs::DirEntry::File(latter_file)) => {
+                files_equal(former_file, latter_file)
+            }
+            (former_entry, latter_entry) => former_entry == latter_entry,
+        };
+
+        if entries_equal {
             let mut entry = former.with_context(&mut || DiffType::Unchanged {
                 metadata_changed_to: None,
             });
-            if former.metadata != latter.metadata {
+            if former.metadata != latter.metadata && !excluded(path) {
                 entry.context = DiffType::Unchanged {
                     metadata_changed_to: Some(latter.metadata.clone()),
                 }
@@ -324,29 +420,62 @@ impl DiffTree {
                     let mut entries = BTreeMap::new();
 
                     for (name, entry) in &former_dir.entries {
+                        path.push(name);
                         if let Some(latter_entry) = latter_dir.entries.get(name) {
-                            entries.insert(name.clone(), Self::compute(entry, latter_entry));
+                            entries.insert(
+                                name.clone(),
+                                Self::compute_entries(
+                                    entry,
+                                    latter_entry,
+                                    path,
+                                    excluded,
+                                    unreadable,
+                                ),
+                            );
+                        } else if excluded(path) {
+                            entries.insert(
+                                name.clone(),
+                                entry.with_context(&mut || DiffType::Excluded),
+                            );
+                        } else if unreadable(path) {
+                            entries.insert(
+                                name.clone(),
+                                entry.with_context(&mut || DiffType::Unreadable),
+                            );
                         } else {
                             entries.insert(
                                 name.clone(),
                                 entry.with_context(&mut || DiffType::Removed),
                             );
                         }
+                        path.pop();
                     }
 
                     for (name, entry) in &latter_dir.entries {
                         if !former_dir.entries.contains_key(name) {
+                            path.push(name);
+                            let context = if excluded(path) {
+                                DiffType::Excluded
+                            } else if unreadable(path) {
+                                DiffType::Unreadable
+                            } else {
+                                DiffType::Added
+                            };
                             entries
-                                .insert(name.clone(), entry.with_context(&mut || DiffType::Added));
+                                .insert(name.clone(), entry.with_context(&mut || context.clone()));
+                            path.pop();
                         }
                     }
 
+                    // The metadata of excluded directories may be missing in one of the
+                    // snapshots, if they only contain included entries
                     MetaDEntry {
                         entry: fs::DirEntry::Directory(fs::Directory { entries }),
                         metadata: former.metadata.clone(),
                         context: DiffType::ChildrenChanged {
-                            metadata_changed_to: (former.metadata != latter.metadata)
-                                .then(|| latter.metadata.clone()),
+                            metadata_changed_to: (former.metadata != latter.metadata
+                                && !excluded(path))
+                            .then(|| latter.metadata.clone()),
                         },
                     }
                 }
@@ -379,17 +508,24 @@ impl DiffTree {
             | DiffType::ChildrenChanged {
                 metadata_changed_to,
             } => metadata_changed_to.is_some(),
-            DiffType::Changed { .. } | DiffType::Added | DiffType::Removed => false,
+            DiffType::Changed { .. }
+            | DiffType::Added
+            | DiffType::Removed
+            | DiffType::Excluded
+            | DiffType::Unreadable => false,
         }
     }
 
     /// Formats the difference tree into the given formatter.
+    ///
+    /// The entry is displayed as `name` and located at `path` in the snapshot.
     fn recursive_tree_display(
         &self,
         f: &mut fmt::Formatter,
         prefix: &str,
         depth: u32,
         name: &OsStr,
+        path: &Path,
         ctx: DisplayContext<impl Fn(FilterContext) -> bool>,
     ) -> fmt::Result {
         firestorm::profile_section!(recursive_tree_display);
@@ -407,6 +543,8 @@ impl DiffTree {
             DiffType::Changed { .. } => write!(f, "{}", name.display().yellow())?,
             DiffType::Added => write!(f, "{}", name.display().green())?,
             DiffType::Removed => write!(f, "{}", name.display().red())?,
+            DiffType::Excluded => write!(f, "{}", name.display().cyan())?,
+            DiffType::Unreadable => write!(f, "{}", name.display().magenta())?,
         };
         if detailed
             && (ctx.summary_level != Some(0) || !matches!(self.entry, fs::DirEntry::Directory(_)))
@@ -429,6 +567,8 @@ impl DiffTree {
             } => (new_meta != &self.metadata).then_some(new_meta),
             DiffType::Added
             | DiffType::Removed
+            | DiffType::Excluded
+            | DiffType::Unreadable
             | DiffType::Unchanged {
                 metadata_changed_to: None,
             }
@@ -445,12 +585,15 @@ impl DiffTree {
                     DiffType::Unchanged { .. }
                     | DiffType::ChildrenChanged { .. }
                     | DiffType::Added
-                    | DiffType::Removed => {
+                    | DiffType::Removed
+                    | DiffType::Excluded
+                    | DiffType::Unreadable => {
                         if self.metadata.size == 0 && !detailed {
                             write!(f, " [empty file]")?;
                         } else {
                             display_file(
                                 f,
+                                name,
                                 file,
                                 None,
                                 &self.context,
@@ -464,6 +607,7 @@ impl DiffTree {
                         if let fs::DirEntry::File(latter_file) = &to.entry {
                             display_file(
                                 f,
+                                name,
                                 file,
                                 Some(latter_file),
                                 &self.context,
@@ -481,6 +625,7 @@ impl DiffTree {
                         }
                     }
                 }
+                display_hard_links(f, &ctx.hard_links.others(self, path), detailed)?;
                 display_meta(f)?;
                 if !detailed {
                     writeln!(f)?;
@@ -491,7 +636,9 @@ impl DiffTree {
                     DiffType::Unchanged { .. }
                     | DiffType::ChildrenChanged { .. }
                     | DiffType::Added
-                    | DiffType::Removed => {
+                    | DiffType::Removed
+                    | DiffType::Excluded
+                    | DiffType::Unreadable => {
                         write!(
                             f,
                             "{}{} to {}{}",
@@ -531,7 +678,7 @@ impl DiffTree {
                 }
             }
             fs::DirEntry::Directory(directory) => {
-                display_dir_summary(f, self, ctx.filter, ctx.database)?;
+                display_dir_summary(f, self, ctx.filter, ctx.size_metric, ctx.database)?;
                 if detailed {
                     writeln!(f)?;
                 }
@@ -576,7 +723,14 @@ impl DiffTree {
                         format!("{} ├─", stripped_prefix)
                     };
 
-                    entry.recursive_tree_display(f, &new_prefix, depth + 1, name, ctx)?;
+                    entry.recursive_tree_display(
+                        f,
+                        &new_prefix,
+                        depth + 1,
+                        name,
+                        &path.join(name),
+                        ctx,
+                    )?;
                 }
             }
             fs::DirEntry::Other(other) => {
@@ -590,23 +744,28 @@ impl DiffTree {
     }
 
     /// Displays the difference in a tree view.
+    ///
+    /// The entry is located at `path` in the snapshot.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn display_as_tree<'tree, F: Fn(FilterContext<'tree>) -> bool>(
         &'tree self,
-        name: &'tree OsStr,
+        path: &'tree Path,
         filter: F,
         summary_level: Option<u32>,
         size_metric: SizeMetric,
         show_hashes: bool,
         database: Option<&'tree Database>,
+        hard_links: &'tree HardLinks<'tree>,
     ) -> TreeDisplay<'tree, F> {
         TreeDisplay {
             entry: self,
-            name,
+            path,
             filter,
             summary_level,
             size_metric,
             show_hashes,
             database,
+            hard_links,
         }
     }
 }
@@ -616,8 +775,8 @@ impl DiffTree {
 pub(crate) struct TreeDisplay<'tree, F> {
     /// The entry which is displayed.
     entry: &'tree DiffTree,
-    /// The name of the entry.
-    name: &'tree OsStr,
+    /// The path of the entry.
+    path: &'tree Path,
     /// The filter to apply when displaying files.
     filter: F,
     /// Whether to display a tree or a summary.
@@ -628,6 +787,8 @@ pub(crate) struct TreeDisplay<'tree, F> {
     show_hashes: bool,
     /// The connection to the database.
     database: Option<&'tree Database>,
+    /// The hard links in the compared snapshots.
+    hard_links: &'tree HardLinks<'tree>,
 }
 
 impl<'tree, F: Fn(FilterContext) -> bool> fmt::Display for TreeDisplay<'tree, F> {
@@ -636,13 +797,15 @@ impl<'tree, F: Fn(FilterContext) -> bool> fmt::Display for TreeDisplay<'tree, F>
             f,
             "",
             0,
-            self.name,
+            self.path.as_os_str(),
+            self.path,
             DisplayContext {
                 filter: &self.filter,
                 summary_level: self.summary_level,
                 size_metric: self.size_metric,
                 show_hashes: self.show_hashes,
                 database: self.database,
+                hard_links: self.hard_links,
             },
         )
     }
@@ -660,6 +823,8 @@ struct DisplayContext<'tree, F: Fn(FilterContext<'tree>) -> bool> {
     show_hashes: bool,
     /// The connection to the database.
     database: Option<&'tree Database>,
+    /// The hard links in the compared snapshots.
+    hard_links: &'tree HardLinks<'tree>,
 }
 
 impl<'tree, F: Fn(FilterContext<'tree>) -> bool> Clone for DisplayContext<'tree, F> {
@@ -670,6 +835,7 @@ impl<'tree, F: Fn(FilterContext<'tree>) -> bool> Clone for DisplayContext<'tree,
             size_metric: self.size_metric,
             show_hashes: self.show_hashes,
             database: self.database,
+            hard_links: self.hard_links,
         }
     }
 }
@@ -681,6 +847,7 @@ fn display_dir_summary(
     f: &mut fmt::Formatter,
     dir: &DiffTree,
     filter: &impl Fn(FilterContext) -> bool,
+    size_metric: SizeMetric,
     database: Option<&Database>,
 ) -> fmt::Result {
     firestorm::profile_fn!(display_dir_summary);
@@ -690,6 +857,8 @@ fn display_dir_summary(
     let mut meta_only_changed = 0;
     let mut changed = 0;
     let mut unchanged = 0;
+    let mut excluded = 0;
+    let mut unreadable = 0;
 
     let total_size = dir.walk().map(|entry| entry.entry.metadata.size).sum();
 
@@ -713,11 +882,20 @@ fn display_dir_summary(
             DiffType::Changed { .. } => changed += 1,
             DiffType::Added => added += 1,
             DiffType::Removed => removed += 1,
+            DiffType::Excluded => excluded += 1,
+            DiffType::Unreadable => unreadable += 1,
         }
     }
 
     write!(f, " (")?;
     write!(f, "{}B", size_format::SizeFormatterBinary::new(total_size))?;
+    if let Some(description) = size_metric.summary_description() {
+        write!(
+            f,
+            ", {}B {description}",
+            size_format::SizeFormatterBinary::new(dir.size(size_metric))
+        )?;
+    }
     if removed != 0 {
         write!(f, " {}", removed.red())?;
     }
@@ -733,12 +911,25 @@ fn display_dir_summary(
     if meta_only_changed != 0 {
         write!(f, " {}{}", meta_only_changed.bright_black(), "M".yellow())?;
     }
+    if excluded != 0 {
+        write!(f, " {}{}", excluded.cyan(), "E".cyan())?;
+    }
+    if unreadable != 0 {
+        write!(f, " {}{}", unreadable.magenta(), "U".magenta())?;
+    }
     write!(f, ")")?;
 
     if let DirEntry::Directory(dir) = &dir.entry
         && dir.entries.is_empty() {
         write!(f, " (empty dir)")?;
-    } else if removed == 0 && added == 0 && changed == 0 && unchanged == 0 && meta_only_changed == 0 {
+    } else if removed == 0
+        && added == 0
+        && changed == 0
+        && unchanged == 0
+        && meta_only_changed == 0
+        && excluded == 0
+        && unreadable == 0
+    {
         write!(f, " (differences filtered out)")?;
     }
 
@@ -895,7 +1086,9 @@ pub(crate) fn compute_changeset(
             }
             | DiffType::ChildrenChanged {
                 metadata_changed_to: None,
-            } => None,
+            }
+            | DiffType::Excluded
+            | DiffType::Unreadable => None,
         };
         if let Some(diff) = diff {
             changes.insert(path.to_string_lossy().into_owned(), diff);
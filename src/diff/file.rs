@@ -1,17 +1,81 @@
 //! Display differences of files.
 
-use std::fmt;
+use std::{collections::BTreeSet, ffi::OsStr, fmt, path::Path};
 
 use owo_colors::OwoColorize as _;
 
 use crate::{
     database::Database,
-    fs::{file::FileFlags, File},
+    fs::{
+        file::{
+            authenticode::{Signature, Signer},
+            blocks::BlockHashes,
+            elf::ElfInfo,
+            file_type::FileType,
+            macho::MachOInfo,
+            pe::{PeInfo, Section, VersionInfo},
+            tlsh::Tlsh,
+            FileFlags,
+        },
+        File,
+    },
 };
 
 /// The width to use for the descriptions in detailed displays.
 const DETAILED_WIDTH: usize = 20;
 
+/// The maximum number of changed byte ranges of a file that are listed in detailed displays.
+const MAX_CHANGED_RANGES: usize = 16;
+
+/// Returns `true` if both files have the same contents and the same information about them.
+///
+/// Snapshots of older versions do not contain the information that was added to files since, so
+/// it is only compared if it is present for both files.
+/// Everything but the hashes is derived from the contents, so files with equal hashes only
+/// differ in it if one of them was recorded by an older version.
+pub(super) fn files_equal(former: &File, latter: &File) -> bool {
+    /// Returns `true` if the field is equal or absent in one of the files.
+    fn equal_if_present<T: PartialEq>(former: &Option<T>, latter: &Option<T>) -> bool {
+        former.is_none() || latter.is_none() || former == latter
+    }
+
+    let File {
+        sha2_256_hash,
+        md5_hash,
+        first_bytes,
+        flags,
+        entropy,
+        coff_header,
+        pe,
+        signature,
+        tlsh,
+        imphash,
+        rich_hash,
+        file_type,
+        elf,
+        macho,
+        block_hashes,
+    } = former;
+
+    // Recorded in every version
+    *sha2_256_hash == latter.sha2_256_hash
+        && *md5_hash == latter.md5_hash
+        && *first_bytes == latter.first_bytes
+        && *flags == latter.flags
+        && *entropy == latter.entropy
+        && *coff_header == latter.coff_header
+        // Added in later versions
+        && equal_if_present(pe, &latter.pe)
+        && equal_if_present(signature, &latter.signature)
+        && equal_if_present(tlsh, &latter.tlsh)
+        && equal_if_present(imphash, &latter.imphash)
+        && equal_if_present(rich_hash, &latter.rich_hash)
+        && equal_if_present(file_type, &latter.file_type)
+        && equal_if_present(elf, &latter.elf)
+        && equal_if_present(macho, &latter.macho)
+        && equal_if_present(block_hashes, &latter.block_hashes)
+}
+
 /// Displays a hash and possibly its change into the formatter.
 fn display_hash<H: fmt::Debug + Eq>(
     f: &mut fmt::Formatter,
@@ -43,6 +107,36 @@ fn display_hash<H: fmt::Debug + Eq>(
     }
 }
 
+/// Displays the TLSH digest and possibly its change into the formatter.
+fn display_tlsh(
+    f: &mut fmt::Formatter,
+    tlsh: Option<Tlsh>,
+    latter_tlsh: Option<Option<Tlsh>>,
+) -> fmt::Result {
+    let display = |tlsh: Option<Tlsh>| match tlsh {
+        Some(tlsh) => tlsh.to_string(),
+        None => "none".to_string(),
+    };
+
+    write!(f, "{:DETAILED_WIDTH$}", "TLSH:")?;
+
+    match latter_tlsh {
+        Some(latter_tlsh) if latter_tlsh != tlsh => {
+            write!(
+                f,
+                "{} -> {}",
+                display(tlsh).red(),
+                display(latter_tlsh).green()
+            )?;
+            if let (Some(tlsh), Some(latter_tlsh)) = (tlsh, latter_tlsh) {
+                write!(f, " (score {})", tlsh.distance(&latter_tlsh))?;
+            }
+            writeln!(f)
+        }
+        _ => writeln!(f, "{}", display(tlsh)),
+    }
+}
+
 /// Displays entropy and possibly its change into the formatter.
 fn display_entropy(
     f: &mut fmt::Formatter,
@@ -113,6 +207,59 @@ fn display_entropy(
     }
 }
 
+/// Displays which parts of a large file changed into the formatter.
+///
+/// Nothing is displayed unless both files have comparable block hashes.
+fn display_changed_blocks(
+    f: &mut fmt::Formatter,
+    block_hashes: Option<&BlockHashes>,
+    latter_block_hashes: Option<Option<&BlockHashes>>,
+    detailed: bool,
+) -> fmt::Result {
+    let (Some(hashes), Some(Some(latter_hashes))) = (block_hashes, latter_block_hashes) else {
+        return Ok(());
+    };
+    let Some(ranges) = hashes.changed_ranges(latter_hashes) else {
+        return Ok(());
+    };
+    if ranges.is_empty() {
+        return Ok(());
+    }
+
+    let changed = ranges
+        .iter()
+        .map(|range| range.end - range.start)
+        .sum::<u64>();
+    let percentage = changed as f64 * 100.0 / hashes.max_len(latter_hashes).max(1) as f64;
+
+    if !detailed {
+        return write!(f, ", {}", format!("{percentage:.2}% changed").yellow());
+    }
+
+    writeln!(
+        f,
+        "{:DETAILED_WIDTH$}{}B ({:.2}%) in {} ranges",
+        "changed bytes:",
+        size_format::SizeFormatterBinary::new(changed),
+        percentage.yellow(),
+        ranges.len()
+    )?;
+    for range in ranges.iter().take(MAX_CHANGED_RANGES) {
+        writeln!(
+            f,
+            "    {:08x}-{:08x}  ({}B)",
+            range.start,
+            range.end,
+            size_format::SizeFormatterBinary::new(range.end - range.start)
+        )?;
+    }
+    if ranges.len() > MAX_CHANGED_RANGES {
+        writeln!(f, "    ... and {} more", ranges.len() - MAX_CHANGED_RANGES)?;
+    }
+
+    Ok(())
+}
+
 /// Displays the first bytes and possibly their change into the formatter.
 fn display_first_bytes(
     f: &mut fmt::Formatter,
@@ -132,6 +279,49 @@ fn display_first_bytes(
     writeln!(f)
 }
 
+/// Displays the type of the file and possibly its change into the formatter.
+///
+/// Outside of detailed displays the type is only shown, if it contradicts the extension in `name`.
+fn display_file_type(
+    f: &mut fmt::Formatter,
+    name: &OsStr,
+    file_type: Option<FileType>,
+    latter_file_type: Option<Option<FileType>>,
+    detailed: bool,
+) -> fmt::Result {
+    let display = |file_type: Option<FileType>| match file_type {
+        Some(file_type) => file_type.to_string(),
+        None => "unknown".to_string(),
+    };
+    let current = latter_file_type.unwrap_or(file_type);
+    let mismatch = current.is_some_and(|file_type| file_type.contradicts_name(name));
+
+    if detailed {
+        write!(f, "{:DETAILED_WIDTH$}", "file type:")?;
+        match latter_file_type {
+            Some(latter_file_type) if latter_file_type != file_type => write!(
+                f,
+                "{} -> {}",
+                display(file_type).red(),
+                display(latter_file_type).green()
+            )?,
+            _ => write!(f, "{}", display(file_type))?,
+        }
+        if mismatch {
+            write!(f, " {}", "(contradicts the extension)".red())?;
+        }
+        writeln!(f)
+    } else if mismatch {
+        write!(
+            f,
+            ", {}",
+            format!("{} with wrong extension", display(current)).red()
+        )
+    } else {
+        Ok(())
+    }
+}
+
 /// Displays the COFF header and possibly its change into the formatter.
 fn display_coff_header(
     f: &mut fmt::Formatter,
@@ -196,6 +386,400 @@ fn display_coff_header(
     Ok(())
 }
 
+/// Displays a field of the PE structure and possibly its change into the formatter.
+///
+/// `None` means that the field does not exist in the file.
+fn display_pe_field<T: fmt::Display + PartialEq>(
+    f: &mut fmt::Formatter,
+    name: &str,
+    field: Option<T>,
+    latter_field: Option<Option<T>>,
+) -> fmt::Result {
+    let display = |field: &Option<T>| match field {
+        Some(field) => field.to_string(),
+        None => "-".to_string(),
+    };
+
+    match latter_field {
+        Some(latter_field) if latter_field != field => writeln!(
+            f,
+            "    {:DETAILED_WIDTH$}{} -> {}",
+            format!("{name}:"),
+            display(&field).red(),
+            display(&latter_field).green()
+        ),
+        _ if field.is_none() => Ok(()),
+        _ => writeln!(
+            f,
+            "    {:DETAILED_WIDTH$}{}",
+            format!("{name}:"),
+            display(&field)
+        ),
+    }
+}
+
+/// Displays a list of items of the PE structure and possibly its change into the formatter.
+///
+/// Without changes only the number of items is displayed.
+fn display_pe_list(
+    f: &mut fmt::Formatter,
+    name: &str,
+    items: BTreeSet<String>,
+    latter_items: Option<BTreeSet<String>>,
+) -> fmt::Result {
+    let changed = latter_items
+        .as_ref()
+        .is_some_and(|latter_items| *latter_items != items);
+    display_pe_field(
+        f,
+        name,
+        Some(items.len()),
+        latter_items.as_ref().map(|items| Some(items.len())),
+    )?;
+
+    if changed && let Some(latter_items) = latter_items {
+        for item in items.difference(&latter_items) {
+            writeln!(f, "        {}", format!("- {item}").red())?;
+        }
+        for item in latter_items.difference(&items) {
+            writeln!(f, "        {}", format!("+ {item}").green())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Displays the structure of a PE file and possibly its change into the formatter.
+fn display_pe(
+    f: &mut fmt::Formatter,
+    pe: Option<&PeInfo>,
+    latter_pe: Option<Option<&PeInfo>>,
+) -> fmt::Result {
+    match (pe, latter_pe) {
+        (Some(_), Some(None)) => writeln!(f, "{}", "PE:".red())?,
+        (None, Some(Some(_))) => writeln!(f, "{}", "PE:".green())?,
+        _ => writeln!(f, "PE:")?,
+    }
+
+    let field = |get: fn(&PeInfo) -> Option<String>| {
+        (
+            pe.and_then(get),
+            latter_pe.map(|latter_pe| latter_pe.and_then(get)),
+        )
+    };
+    /// Returns the string of the version information selected by `get`.
+    fn version_string(pe: &PeInfo, get: fn(&VersionInfo) -> Option<&str>) -> Option<String> {
+        pe.version_info.as_ref().and_then(get).map(str::to_string)
+    }
+
+    for (name, get) in [
+        (
+            "machine",
+            (|pe| Some(format!("{} ({:#06x})", pe.machine_name(), pe.machine)))
+                as fn(&PeInfo) -> Option<String>,
+        ),
+        ("subsystem", |pe| Some(pe.subsystem_name().to_string())),
+        ("type", |pe| {
+            Some(
+                match (pe.is_dll(), pe.is_64_bit) {
+                    (true, true) => "64-bit DLL",
+                    (true, false) => "32-bit DLL",
+                    (false, true) => "64-bit executable",
+                    (false, false) => "32-bit executable",
+                }
+                .to_string(),
+            )
+        }),
+        ("compiled", |pe| Some(format!("{:?}", pe.compile_time()))),
+        ("overlay size", |pe| {
+            (pe.overlay_size != 0).then(|| pe.overlay_size.to_string())
+        }),
+        ("company", |pe| {
+            version_string(pe, |info| info.company_name())
+        }),
+        ("product", |pe| {
+            version_string(pe, |info| info.product_name())
+        }),
+        ("original filename", |pe| {
+            version_string(pe, |info| info.original_filename())
+        }),
+        ("file version", |pe| {
+            pe.version_info
+                .as_ref()
+                .and_then(|info| info.file_version.clone())
+        }),
+    ] {
+        let (field, latter_field) = field(get);
+        display_pe_field(f, name, field, latter_field)?;
+    }
+
+    writeln!(f, "    sections:")?;
+    let sections = pe.map(|pe| &pe.sections[..]).unwrap_or_default();
+    let latter_sections =
+        latter_pe.map(|latter_pe| latter_pe.map(|pe| &pe.sections[..]).unwrap_or_default());
+    let Some(latter_sections) = latter_sections else {
+        for section in sections {
+            writeln!(f, "        {section}")?;
+        }
+        return display_pe_lists(f, pe, latter_pe);
+    };
+
+    // Sections with the same name are displayed next to each other
+    fn find<'pe>(sections: &'pe [Section], name: &str) -> Option<&'pe Section> {
+        sections.iter().find(|section| section.name == name)
+    }
+
+    let mut names = sections
+        .iter()
+        .map(|section| &section.name)
+        .collect::<Vec<_>>();
+    for section in latter_sections {
+        if !names.contains(&&section.name) {
+            names.push(&section.name);
+        }
+    }
+    for name in names {
+        match (find(sections, name), find(latter_sections, name)) {
+            // Sections are compared as displayed, like the entropy of files
+            (Some(section), Some(latter_section))
+                if section.to_string() == latter_section.to_string() =>
+            {
+                writeln!(f, "          {section}")?
+            }
+            (section, latter_section) => {
+                if let Some(section) = section {
+                    writeln!(f, "        {}", format!("- {section}").red())?;
+                }
+                if let Some(latter_section) = latter_section {
+                    writeln!(f, "        {}", format!("+ {latter_section}").green())?;
+                }
+            }
+        }
+    }
+
+    display_pe_lists(f, pe, latter_pe)
+}
+
+/// Displays the imports and exports of a PE file and possibly their change into the formatter.
+fn display_pe_lists(
+    f: &mut fmt::Formatter,
+    pe: Option<&PeInfo>,
+    latter_pe: Option<Option<&PeInfo>>,
+) -> fmt::Result {
+    let imports = |pe: Option<&PeInfo>| {
+        pe.into_iter()
+            .flat_map(|pe| &pe.imports)
+            .flat_map(|import| {
+                import
+                    .functions
+                    .iter()
+                    .map(move |function| format!("{}!{function}", import.dll))
+            })
+            .collect::<BTreeSet<_>>()
+    };
+    display_pe_list(f, "imports", imports(pe), latter_pe.map(imports))?;
+
+    let exports = |pe: Option<&PeInfo>| {
+        pe.into_iter()
+            .flat_map(|pe| pe.exports.iter().cloned())
+            .collect::<BTreeSet<_>>()
+    };
+    display_pe_list(f, "exports", exports(pe), latter_pe.map(exports))
+}
+
+/// Displays the structure of an ELF file and possibly its change into the formatter.
+fn display_elf(
+    f: &mut fmt::Formatter,
+    elf: Option<&ElfInfo>,
+    latter_elf: Option<Option<&ElfInfo>>,
+) -> fmt::Result {
+    match (elf, latter_elf) {
+        (Some(_), Some(None)) => writeln!(f, "{}", "ELF:".red())?,
+        (None, Some(Some(_))) => writeln!(f, "{}", "ELF:".green())?,
+        _ => writeln!(f, "ELF:")?,
+    }
+
+    for (name, get) in [
+        (
+            "machine",
+            (|elf| Some(format!("{} ({:#06x})", elf.machine_name(), elf.machine)))
+                as fn(&ElfInfo) -> Option<String>,
+        ),
+        ("type", |elf| {
+            Some(format!(
+                "{}-bit {} endian {}",
+                if elf.is_64_bit { 64 } else { 32 },
+                if elf.is_big_endian { "big" } else { "little" },
+                elf.type_name()
+            ))
+        }),
+        ("interpreter", |elf| elf.interpreter.clone()),
+        ("shared object name", |elf| elf.soname.clone()),
+        ("build ID", |elf| elf.build_id.clone()),
+        ("stripped", |elf| {
+            Some(if elf.stripped { "yes" } else { "no" }.to_string())
+        }),
+    ] {
+        display_pe_field(
+            f,
+            name,
+            elf.and_then(get),
+            latter_elf.map(|latter_elf| latter_elf.and_then(get)),
+        )?;
+    }
+
+    let needed = |elf: Option<&ElfInfo>| {
+        elf.into_iter()
+            .flat_map(|elf| elf.needed.iter().cloned())
+            .collect::<BTreeSet<_>>()
+    };
+    display_pe_list(f, "needed", needed(elf), latter_elf.map(needed))
+}
+
+/// Displays the structure of a Mach-O file and possibly its change into the formatter.
+fn display_macho(
+    f: &mut fmt::Formatter,
+    macho: Option<&MachOInfo>,
+    latter_macho: Option<Option<&MachOInfo>>,
+) -> fmt::Result {
+    match (macho, latter_macho) {
+        (Some(_), Some(None)) => writeln!(f, "{}", "Mach-O:".red())?,
+        (None, Some(Some(_))) => writeln!(f, "{}", "Mach-O:".green())?,
+        _ => writeln!(f, "Mach-O:")?,
+    }
+
+    for (name, get) in [
+        (
+            "architectures",
+            (|macho| Some(macho.cpu_names().join(", "))) as fn(&MachOInfo) -> Option<String>,
+        ),
+        ("type", |macho| Some(macho.type_name().to_string())),
+        ("UUID", |macho| macho.uuid.clone()),
+        ("code signature", |macho| {
+            Some(if macho.is_signed { "yes" } else { "no" }.to_string())
+        }),
+    ] {
+        display_pe_field(
+            f,
+            name,
+            macho.and_then(get),
+            latter_macho.map(|latter_macho| latter_macho.and_then(get)),
+        )?;
+    }
+
+    let libraries = |macho: Option<&MachOInfo>| {
+        macho
+            .into_iter()
+            .flat_map(|macho| macho.libraries.iter().cloned())
+            .collect::<BTreeSet<_>>()
+    };
+    display_pe_list(
+        f,
+        "libraries",
+        libraries(macho),
+        latter_macho.map(libraries),
+    )
+}
+
+/// Displays a marker for the executable format `name` and possibly its change into the formatter.
+fn display_executable_marker<T: PartialEq>(
+    f: &mut fmt::Formatter,
+    name: &str,
+    info: Option<&T>,
+    latter_info: Option<Option<&T>>,
+) -> fmt::Result {
+    match (info, latter_info) {
+        (Some(info), Some(Some(latter_info))) if info != latter_info => {
+            write!(f, ", {}", name.yellow())
+        }
+        (Some(_), Some(None)) => write!(f, ", {}", name.red()),
+        (None, Some(Some(_))) => write!(f, ", {}", name.green()),
+        (Some(_), _) => write!(f, ", {}", name.blue()),
+        (None, _) => Ok(()),
+    }
+}
+
+/// Displays the Authenticode signature and possibly its change into the formatter.
+fn display_signature(
+    f: &mut fmt::Formatter,
+    signature: Option<&Signature>,
+    latter_signature: Option<Option<&Signature>>,
+    detailed: bool,
+) -> fmt::Result {
+    let status = |signature: Option<&Signature>| match signature {
+        None => "unsigned",
+        Some(signature) if signature.signer.is_none() => "malformed signature",
+        Some(signature) if !signature.digest_matches => "digest mismatch",
+        Some(_) => "signed",
+    };
+
+    if !detailed {
+        let label = |signature: Option<&Signature>| match signature {
+            Some(signature) if signature.is_valid() => "signed",
+            Some(_) => "bad signature",
+            None => "",
+        };
+
+        return match (signature, latter_signature) {
+            (None, None | Some(None)) => Ok(()),
+            (Some(signature), None) => write!(f, ", {}", label(Some(signature)).blue()),
+            (Some(signature), Some(latter)) if Some(signature) == latter => {
+                write!(f, ", {}", label(Some(signature)).blue())
+            }
+            (Some(signature), Some(None)) => write!(f, ", {}", label(Some(signature)).red()),
+            (None, Some(latter)) => write!(f, ", {}", label(latter).green()),
+            (Some(_), Some(latter)) => write!(f, ", {}", label(latter).yellow()),
+        };
+    }
+
+    write!(f, "{:DETAILED_WIDTH$}", "signature:")?;
+    match latter_signature {
+        Some(latter_signature) if status(latter_signature) != status(signature) => writeln!(
+            f,
+            "{} -> {}",
+            status(signature).red(),
+            status(latter_signature).green()
+        )?,
+        _ => writeln!(f, "{}", status(signature))?,
+    }
+
+    let signer = |signature: Option<&Signature>| signature.and_then(|sig| sig.signer.clone());
+    let field = |get: fn(&Signer) -> Option<String>| {
+        (
+            signer(signature).as_ref().and_then(get),
+            latter_signature.map(|latter| signer(latter).as_ref().and_then(get)),
+        )
+    };
+    for (name, get) in [
+        (
+            "signer",
+            (|signer| signer.subject.clone()) as fn(&Signer) -> Option<String>,
+        ),
+        ("issuer", |signer| Some(signer.issuer.clone())),
+        ("serial", |signer| Some(signer.serial.clone())),
+    ] {
+        let (field, latter_field) = field(get);
+        display_pe_field(f, name, field, latter_field)?;
+    }
+
+    let signing_time = |signature: Option<&Signature>| {
+        signature
+            .and_then(|signature| signature.signing_time)
+            .map(|time| format!("{time:?}"))
+    };
+    display_pe_field(
+        f,
+        "signing time",
+        signing_time(signature),
+        latter_signature.map(signing_time),
+    )?;
+
+    let digest = |signature: Option<&Signature>| {
+        signature.and_then(|signature| signature.digest_algorithm.clone())
+    };
+    display_pe_field(f, "digest", digest(signature), latter_signature.map(digest))
+}
+
 /// Displays the file flags and possibly their change into the formatter.
 fn display_file_flags(
     f: &mut fmt::Formatter,
@@ -396,6 +980,48 @@ pub(super) fn display_occurrences(
     Ok(())
 }
 
+/// Displays files in other snapshots that are similar to the file, if it is unknown.
+pub(super) fn display_similar(
+    f: &mut fmt::Formatter,
+    former: &File,
+    latter: Option<&File>,
+    database: &Database,
+    detailed: bool,
+) -> fmt::Result {
+    firestorm::profile_fn!(display_similar);
+
+    let file = latter.unwrap_or(former);
+    if database.file_is_known(file).unwrap_or(true) {
+        return Ok(());
+    }
+
+    let similar = database.find_similar_files(file).unwrap_or_default();
+
+    if detailed {
+        if !similar.is_empty() {
+            writeln!(f, "Similar files found here:")?;
+
+            for similar in similar {
+                writeln!(
+                    f,
+                    "    {} {:?} (score {})",
+                    similar.snapshot, similar.path, similar.score
+                )?;
+            }
+        }
+    } else if let Some(similar) = similar.first() {
+        write!(
+            f,
+            " (similar to {:?} in {} (score {}))",
+            similar.path,
+            similar.snapshot.comment.blue(),
+            similar.score
+        )?;
+    }
+
+    Ok(())
+}
+
 /// Displays possible renames of the file.
 pub(super) fn display_renames(
     f: &mut fmt::Formatter,
@@ -458,9 +1084,40 @@ pub(super) fn display_renames(
     Ok(())
 }
 
+/// Displays the other paths of a hard-linked file.
+pub(super) fn display_hard_links(
+    f: &mut fmt::Formatter,
+    others: &[&Path],
+    detailed: bool,
+) -> fmt::Result {
+    if detailed {
+        match others {
+            [] => (),
+            [other] => writeln!(f, "Hard link of {:?}", other)?,
+            _ => {
+                writeln!(f, "Hard link of [")?;
+                for other in others {
+                    writeln!(f, "    {:?},", other)?;
+                }
+                writeln!(f, "]")?;
+            }
+        }
+    } else {
+        match others {
+            [] => (),
+            [other] => write!(f, " (hard link of {:?})", other)?,
+            [other, rest @ ..] => write!(f, " (hard link of {:?} and {} more)", other, rest.len())?,
+        }
+    }
+
+    Ok(())
+}
+
 /// Display a possible difference between the `former` and the `latter` file.
+#[allow(clippy::too_many_arguments)]
 pub(super) fn display_file(
     f: &mut fmt::Formatter,
+    name: &OsStr,
     former: &File,
     latter: Option<&File>,
     context: &super::DiffType,
@@ -491,24 +1148,85 @@ pub(super) fn display_file(
         )?;
     }
 
+    display_changed_blocks(
+        f,
+        former.block_hashes.as_deref(),
+        latter.map(|f| f.block_hashes.as_deref()),
+        detailed,
+    )?;
+
     if detailed {
+        display_tlsh(f, former.tlsh, latter.map(|f| f.tlsh))?;
         display_first_bytes(
             f,
             &former.first_bytes[..],
             latter.map(|f| &f.first_bytes[..]),
         )?;
     }
-
-    display_coff_header(
+    display_file_type(
         f,
-        former.coff_header.as_deref(),
-        latter.map(|f| f.coff_header.as_deref()),
+        name,
+        former.file_type,
+        latter.map(|f| f.file_type),
         detailed,
     )?;
+
+    let has_pe_info = former.pe.is_some() || latter.is_some_and(|latter| latter.pe.is_some());
+    if detailed && has_pe_info {
+        display_pe(
+            f,
+            former.pe.as_deref(),
+            latter.map(|latter| latter.pe.as_deref()),
+        )?;
+        display_pe_field(
+            f,
+            "import hash",
+            former.imphash.as_ref(),
+            latter.map(|latter| latter.imphash.as_ref()),
+        )?;
+        display_pe_field(
+            f,
+            "Rich header hash",
+            former.rich_hash.as_ref(),
+            latter.map(|latter| latter.rich_hash.as_ref()),
+        )?;
+    } else {
+        display_coff_header(
+            f,
+            former.coff_header.as_deref(),
+            latter.map(|f| f.coff_header.as_deref()),
+            detailed,
+        )?;
+    }
+    let elf = former.elf.as_deref();
+    let latter_elf = latter.map(|latter| latter.elf.as_deref());
+    let macho = former.macho.as_deref();
+    let latter_macho = latter.map(|latter| latter.macho.as_deref());
+    if !detailed {
+        display_executable_marker(f, "ELF", elf, latter_elf)?;
+        display_executable_marker(f, "Mach-O", macho, latter_macho)?;
+    } else {
+        if elf.is_some() || latter_elf.is_some_and(|elf| elf.is_some()) {
+            display_elf(f, elf, latter_elf)?;
+        }
+        if macho.is_some() || latter_macho.is_some_and(|macho| macho.is_some()) {
+            display_macho(f, macho, latter_macho)?;
+        }
+    }
+
+    if has_pe_info {
+        display_signature(
+            f,
+            former.signature.as_deref(),
+            latter.map(|latter| latter.signature.as_deref()),
+            detailed,
+        )?;
+    }
     display_file_flags(f, former.flags, latter.map(|f| f.flags), detailed)?;
 
     if let Some(database) = database {
         display_occurrences(f, former, latter, database, detailed)?;
+        display_similar(f, former, latter, database, detailed)?;
 
         if latter.is_none() {
             display_renames(f, former, context, database, detailed)?;
@@ -4,7 +4,16 @@ use std::ffi::OsStr;
 
 use crate::{
     database::Database,
-    fs::{self, Metadata, OsStrExt as _},
+    fs::{
+        self,
+        file::{file_type::FileType, pe::PeInfo},
+        metadata::{
+            reparse_point::{ReparsePoint, ReparseType},
+            security_descriptor::{SecurityDescriptor, Sid},
+            zone_identifier::MarkOfTheWeb,
+        },
+        Metadata, OsStrExt as _,
+    },
     timestamp::Timestamp,
 };
 
@@ -79,7 +88,10 @@ pub(crate) fn changes_only(include_metadata: bool) -> impl Fn(FilterContext) ->
         | DiffType::ChildrenChanged {
             metadata_changed_to,
         } => include_metadata && metadata_changed_to.is_some(),
-        DiffType::Changed { .. } | DiffType::Added | DiffType::Removed => true,
+        DiffType::Changed { .. } | DiffType::Added | DiffType::Removed | DiffType::Unreadable => {
+            true
+        }
+        DiffType::Excluded => false,
     }
 }
 
@@ -128,16 +140,189 @@ pub(crate) fn unknown_only(ctx: FilterContext) -> bool {
         return true;
     };
 
-    match db.file_is_known(file) {
-        // The file is known, ignore it
-        Ok(true) => false,
-        // The file is unknown, so let's include it
-        Ok(false) => true,
-        // If the database couldn't find a result, let's just include the file to be safe
-        Err(_) => true,
+    // Unknown data can also hide in the alternate data streams of a known file
+    let streams = ctx
+        .entry
+        .metadata
+        .streams
+        .iter()
+        .flat_map(|streams| streams.streams.values().flatten())
+        .map(|stream| &stream.file);
+
+    for file in std::iter::once(file).chain(streams) {
+        match db.file_is_known(file) {
+            // The file is known, check the next one
+            Ok(true) => (),
+            // The file is unknown, so let's include it
+            Ok(false) => return true,
+            // If the database couldn't find a result, let's just include the file to be safe
+            Err(_) => return true,
+        }
+    }
+
+    // All files are known, ignore the entry
+    false
+}
+
+/// Returns the file of the entry in the former snapshot and the file it was changed to.
+fn files<'a>(ctx: FilterContext<'a>) -> impl Iterator<Item = &'a fs::File> {
+    let former = match &ctx.entry.entry {
+        fs::DirEntry::File(file) => Some(file),
+        _ => None,
+    };
+    let latter = match &ctx.entry.context {
+        DiffType::Changed { to } => match &to.entry {
+            fs::DirEntry::File(file) => Some(file),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    former.into_iter().chain(latter)
+}
+
+/// Allows only PE files whose structure matches `predicate` in at least one of the snapshots.
+pub(crate) fn pe_matches(
+    predicate: impl Fn(&OsStr, &PeInfo) -> bool,
+) -> impl Fn(FilterContext) -> bool {
+    move |ctx| {
+        files(ctx)
+            .filter_map(|file| file.pe.as_deref())
+            .any(|pe| predicate(ctx.name, pe))
+    }
+}
+
+/// Allows only PE files whose original filename in the version information differs from their
+/// name.
+pub(crate) fn original_name_mismatch(name: &OsStr, pe: &PeInfo) -> bool {
+    let Some(original_name) = pe
+        .version_info
+        .as_ref()
+        .and_then(|info| info.original_filename())
+    else {
+        return false;
+    };
+
+    !original_name
+        .trim()
+        .eq_ignore_ascii_case(&name.to_string_lossy())
+}
+
+/// Allows only PE files with a company name in the version information containing `company`.
+pub(crate) fn company(company: &str) -> impl Fn(&OsStr, &PeInfo) -> bool {
+    let company = company.to_lowercase();
+
+    move |_, pe| {
+        pe.version_info
+            .as_ref()
+            .and_then(|info| info.company_name())
+            .is_some_and(|name| name.to_lowercase().contains(&company))
     }
 }
 
+/// Allows only PE files that are unsigned or whose signature does not match their contents in at
+/// least one of the snapshots.
+pub(crate) fn unsigned(ctx: FilterContext) -> bool {
+    files(ctx).any(|file| {
+        file.pe.is_some()
+            && !file
+                .signature
+                .as_ref()
+                .is_some_and(|signature| signature.is_valid())
+    })
+}
+
+/// Allows only files of one of the given types in at least one of the snapshots.
+pub(crate) fn file_types(types: Vec<FileType>) -> impl Fn(FilterContext) -> bool {
+    move |ctx| {
+        files(ctx)
+            .filter_map(|file| file.file_type)
+            .any(|ty| types.contains(&ty))
+    }
+}
+
+/// Allows only files whose extension contradicts their type in at least one of the snapshots.
+pub(crate) fn extension_mismatch(ctx: FilterContext) -> bool {
+    files(ctx)
+        .filter_map(|file| file.file_type)
+        .any(|ty| ty.contradicts_name(ctx.name))
+}
+
+/// Allows only ELF or Mach-O files loading a library containing `library` in at least one of the
+/// snapshots.
+pub(crate) fn needed(library: &str) -> impl Fn(FilterContext) -> bool {
+    let library = library.to_lowercase();
+
+    move |ctx| {
+        files(ctx).any(|file| {
+            let elf = file.elf.iter().flat_map(|elf| &elf.needed);
+            let macho = file.macho.iter().flat_map(|macho| &macho.libraries);
+
+            elf.chain(macho)
+                .any(|name| name.to_lowercase().contains(&library))
+        })
+    }
+}
+
+/// Allows only ELF files without a symbol table in at least one of the snapshots.
+pub(crate) fn stripped(ctx: FilterContext) -> bool {
+    files(ctx).any(|file| file.elf.as_ref().is_some_and(|elf| elf.stripped))
+}
+
+/// Returns the metadata of the entry in the former snapshot and the metadata it was changed to.
+fn metadata<'a>(ctx: FilterContext<'a>) -> impl Iterator<Item = &'a Metadata> {
+    let latter = match &ctx.entry.context {
+        DiffType::Unchanged {
+            metadata_changed_to,
+        }
+        | DiffType::ChildrenChanged {
+            metadata_changed_to,
+        } => metadata_changed_to.as_ref(),
+        DiffType::Changed { to } => Some(&to.metadata),
+        _ => None,
+    };
+
+    std::iter::once(&ctx.entry.metadata).chain(latter)
+}
+
+/// Returns the parsed security descriptors of the entry in both snapshots.
+fn security_descriptors(ctx: FilterContext<'_>) -> impl Iterator<Item = SecurityDescriptor> + '_ {
+    metadata(ctx)
+        .filter_map(|metadata| metadata.acl.as_deref())
+        .filter_map(SecurityDescriptor::parse)
+}
+
+/// Allows only entries owned by `owner` in at least one of the snapshots.
+pub(crate) fn owner(owner: Sid) -> impl Fn(FilterContext) -> bool {
+    move |ctx| security_descriptors(ctx).any(|sd| sd.owner.as_ref() == Some(&owner))
+}
+
+/// Allows only entries that a broad group such as `Everyone` may modify in at least one of the
+/// snapshots.
+pub(crate) fn world_writable(ctx: FilterContext) -> bool {
+    security_descriptors(ctx).any(|sd| sd.is_world_writable())
+}
+
+/// Allows only entries with a reparse point of one of the given types in at least one of the
+/// snapshots.
+pub(crate) fn reparse_types(types: Vec<ReparseType>) -> impl Fn(FilterContext) -> bool {
+    move |ctx| {
+        metadata(ctx)
+            .filter_map(|metadata| metadata.reparse_data.as_deref())
+            .filter_map(ReparsePoint::parse)
+            .any(|reparse_point| types.contains(&reparse_point.reparse_type()))
+    }
+}
+
+/// Allows only entries with a Mark-of-the-Web from the internet or a restricted site in at least
+/// one of the snapshots.
+pub(crate) fn downloaded(ctx: FilterContext) -> bool {
+    metadata(ctx)
+        .filter_map(|metadata| metadata.streams.as_ref())
+        .filter_map(MarkOfTheWeb::from_streams)
+        .any(|mark| mark.is_downloaded())
+}
+
 /// Allows only entries that match all the filters.
 pub(crate) fn all_of(filters: Vec<DynFilter>) -> impl Fn(FilterContext) -> bool + '_ {
     move |ctx| {
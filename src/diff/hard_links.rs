@@ -0,0 +1,80 @@
+//! Finds the other paths of hard-linked files in snapshots.
+//!
+//! Hard-linked files are stored once per path in a snapshot, the paths that refer to the same
+//! file are recorded in the snapshot while it is created.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{DiffTree, DiffType};
+
+/// The groups of paths of a snapshot that refer to the same file, with the index of the group of
+/// each path.
+struct LinkIndex<'snapshot> {
+    /// The groups of paths that refer to the same file.
+    groups: &'snapshot [Vec<PathBuf>],
+    /// The index of the group of each path.
+    by_path: HashMap<&'snapshot Path, usize>,
+}
+
+impl<'snapshot> LinkIndex<'snapshot> {
+    /// Indexes the given groups of paths.
+    fn new(groups: &'snapshot [Vec<PathBuf>]) -> Self {
+        let by_path = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, paths)| paths.iter().map(move |path| (path.as_path(), i)))
+            .collect();
+
+        Self { groups, by_path }
+    }
+}
+
+/// The hard links in the compared snapshots.
+pub(crate) struct HardLinks<'snapshot> {
+    /// The hard links in the former snapshot.
+    former: LinkIndex<'snapshot>,
+    /// The hard links in the latter snapshot, if snapshots are compared.
+    latter: Option<LinkIndex<'snapshot>>,
+}
+
+impl<'snapshot> HardLinks<'snapshot> {
+    /// Indexes the hard links recorded in the `former` and the `latter` snapshot.
+    ///
+    /// The hard links are recorded for the whole snapshot, so the other paths are known even if
+    /// only a part of the snapshot was loaded.
+    pub(crate) fn new(
+        former: &'snapshot [Vec<PathBuf>],
+        latter: Option<&'snapshot [Vec<PathBuf>]>,
+    ) -> Self {
+        firestorm::profile_fn!(index_hard_links);
+
+        Self {
+            former: LinkIndex::new(former),
+            latter: latter.map(LinkIndex::new),
+        }
+    }
+
+    /// Returns the other paths of the file at `path` in the difference tree.
+    ///
+    /// Removed files are looked up in the former snapshot, all others in the latter one.
+    pub(crate) fn others(&self, entry: &DiffTree, path: &Path) -> Vec<&'snapshot Path> {
+        let index = match (&self.latter, &entry.context) {
+            (Some(_), DiffType::Removed) | (None, _) => &self.former,
+            (Some(latter), _) => latter,
+        };
+
+        let path = Path::new("/").join(path);
+        let Some(&group) = index.by_path.get(path.as_path()) else {
+            return Vec::new();
+        };
+
+        index.groups[group]
+            .iter()
+            .filter(|other| **other != path)
+            .map(PathBuf::as_path)
+            .collect()
+    }
+}
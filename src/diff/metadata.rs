@@ -1,10 +1,24 @@
 //! Display differences of metadata.
 
-use std::{cmp::Ordering, collections::BTreeSet, ffi::OsString, fmt};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    fmt,
+};
 
 use owo_colors::OwoColorize as _;
+use serde::Serialize;
 
-use crate::{fs::metadata, timestamp::Timestamp};
+use crate::{
+    fs::metadata::{
+        self,
+        reparse_point::ReparsePoint,
+        security_descriptor::{self, Ace, SecurityDescriptor, Sid},
+        zone_identifier::MarkOfTheWeb,
+    },
+    timestamp::Timestamp,
+};
 
 use super::{compute_change, compute_maybe_change};
 
@@ -115,6 +129,33 @@ fn display_size(
     }
 }
 
+/// Display a possible difference between the `former` and the `latter` allocated size.
+///
+/// The allocated size is only shown in detailed displays, since it mostly follows the size.
+fn display_allocated_size(
+    f: &mut fmt::Formatter,
+    former: Option<u64>,
+    latter: Option<Option<u64>>,
+    detailed: bool,
+    use_sep: &mut bool,
+) -> fmt::Result {
+    if !detailed || (former.is_none() && latter.flatten().is_none()) {
+        return Ok(());
+    }
+
+    let format = |size: Option<u64>| {
+        size.map(|size| format!("{}B", size_format::SizeFormatterBinary::new(size)))
+    };
+    display_named_display(
+        f,
+        format(former),
+        latter.map(format),
+        "allocated",
+        detailed,
+        use_sep,
+    )
+}
+
 /// Display a possible difference between the `former` and the `latter` time stamp.
 fn display_timestamp(
     f: &mut fmt::Formatter,
@@ -297,6 +338,201 @@ fn display_byte_list(
     Ok(())
 }
 
+/// Display a possible difference between the `former` and the `latter` reparse points.
+///
+/// In detailed displays the reparse points are decoded into their type and targets.
+fn display_reparse_point(
+    f: &mut fmt::Formatter,
+    former: &Option<Vec<u8>>,
+    latter: Option<&Option<Vec<u8>>>,
+    detailed: bool,
+    use_sep: &mut bool,
+) -> fmt::Result {
+    let parse = |data: &Option<Vec<u8>>| data.as_deref().map(ReparsePoint::parse);
+    // Reparse points that cannot be parsed are displayed as bytes
+    if !detailed || parse(former) == Some(None) || latter.and_then(parse) == Some(None) {
+        return display_byte_list(f, former, latter, "reparse data", detailed, use_sep);
+    }
+
+    let former_rp = parse(former).flatten();
+    let latter_rp = latter.map(|latter| parse(latter).flatten());
+
+    match latter_rp {
+        Some(latter_rp) if former_rp != latter_rp => {
+            write_separator(f, detailed, use_sep)?;
+            write!(
+                f,
+                "reparse point: {} -> {}",
+                DisplayOption(former_rp).red(),
+                DisplayOption(latter_rp).green()
+            )?;
+        }
+        _ => {
+            if let Some(former_rp) = former_rp {
+                write_separator(f, detailed, use_sep)?;
+                write!(f, "reparse point: {former_rp}")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Display a possible difference between the `former` and the `latter` security descriptors.
+///
+/// In detailed displays the descriptors are shown in SDDL and changes are listed ACE by ACE.
+fn display_security_descriptor(
+    f: &mut fmt::Formatter,
+    former: &Option<Vec<u8>>,
+    latter: Option<&Option<Vec<u8>>>,
+    detailed: bool,
+    use_sep: &mut bool,
+) -> fmt::Result {
+    let parse = |acl: &Option<Vec<u8>>| acl.as_deref().map(SecurityDescriptor::parse);
+    // Descriptors that cannot be parsed are displayed as bytes
+    if !detailed || parse(former) == Some(None) || latter.and_then(parse) == Some(None) {
+        return display_byte_list(f, former, latter, "acl", detailed, use_sep);
+    }
+    let former_sd = parse(former).flatten();
+    let latter_sd = latter.and_then(parse).flatten();
+
+    let display = |sd: &Option<SecurityDescriptor>| match sd {
+        Some(sd) => sd.to_string(),
+        None => UNKOWN_TEXT.to_string(),
+    };
+
+    match latter {
+        Some(latter) if former != latter => {
+            write_separator(f, detailed, use_sep)?;
+            write!(
+                f,
+                "acl: {} -> {}",
+                display(&former_sd).red(),
+                display(&latter_sd).green()
+            )?;
+
+            for (name, get) in [
+                (
+                    "owner",
+                    (|sd| sd.owner.as_ref()) as fn(&SecurityDescriptor) -> Option<&Sid>,
+                ),
+                ("group", |sd| sd.group.as_ref()),
+            ] {
+                let sid = former_sd.as_ref().and_then(get);
+                let latter_sid = latter_sd.as_ref().and_then(get);
+                if sid != latter_sid {
+                    let display = |sid: Option<&Sid>| match sid {
+                        Some(sid) => sid.display_name(),
+                        None => UNKOWN_TEXT.to_string(),
+                    };
+                    write!(
+                        f,
+                        "\n        {name}: {} -> {}",
+                        display(sid).red(),
+                        display(latter_sid).green()
+                    )?;
+                }
+            }
+
+            for (name, get) in [
+                (
+                    "DACL",
+                    (|sd| sd.dacl.as_deref()) as fn(&SecurityDescriptor) -> Option<&[Ace]>,
+                ),
+                ("SACL", |sd| sd.sacl.as_deref()),
+            ] {
+                display_ace_changes(
+                    f,
+                    name,
+                    former_sd.as_ref().and_then(get),
+                    latter_sd.as_ref().and_then(get),
+                )?;
+            }
+        }
+        _ => {
+            if let Some(former_sd) = former_sd {
+                write_separator(f, detailed, use_sep)?;
+                write!(f, "acl: {former_sd}")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Displays the ACEs that were added, removed or changed between the two ACLs.
+///
+/// ACEs of the same type for the same user or group with the same flags are matched, so changed
+/// access rights are shown as gained and lost rights.
+fn display_ace_changes(
+    f: &mut fmt::Formatter,
+    name: &str,
+    former: Option<&[Ace]>,
+    latter: Option<&[Ace]>,
+) -> fmt::Result {
+    /// Collects the access rights of the ACEs by their type, trustee and flags.
+    fn rights(aces: Option<&[Ace]>) -> BTreeMap<(&'static str, String, String), u32> {
+        let mut rights = BTreeMap::new();
+        for ace in aces.unwrap_or_default() {
+            *rights
+                .entry((ace.type_name(), ace.trustee(), ace.sddl_flags()))
+                .or_default() |= ace.mask;
+        }
+
+        rights
+    }
+
+    let former = rights(former);
+    let latter = rights(latter);
+    let keys = former.keys().chain(latter.keys()).collect::<BTreeSet<_>>();
+    let display_ace = |(ty, trustee, flags): &(&str, String, String)| {
+        if flags.is_empty() {
+            format!("{name}: {ty} {trustee}")
+        } else {
+            format!("{name}: {ty} {trustee} [{flags}]")
+        }
+    };
+
+    for key in keys {
+        match (former.get(key).copied(), latter.get(key).copied()) {
+            (Some(mask), Some(latter_mask)) if mask != latter_mask => {
+                write!(f, "\n          {}", display_ace(key).yellow())?;
+                let gained = security_descriptor::rights_names(latter_mask & !mask);
+                if latter_mask & !mask != 0 {
+                    write!(f, " gained {}", gained.join(", ").green())?;
+                }
+                let lost = security_descriptor::rights_names(mask & !latter_mask);
+                if mask & !latter_mask != 0 {
+                    write!(f, " lost {}", lost.join(", ").red())?;
+                }
+            }
+            (Some(mask), None) => write!(
+                f,
+                "\n        {}",
+                format!(
+                    "- {}: {}",
+                    display_ace(key),
+                    security_descriptor::rights_names(mask).join(", ")
+                )
+                .red()
+            )?,
+            (None, Some(mask)) => write!(
+                f,
+                "\n        {}",
+                format!(
+                    "+ {}: {}",
+                    display_ace(key),
+                    security_descriptor::rights_names(mask).join(", ")
+                )
+                .green()
+            )?,
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
 /// Display a possible difference between the `former` and the `latter` alternate data streams.
 fn display_ads(
     f: &mut fmt::Formatter,
@@ -349,12 +585,98 @@ fn display_ads(
             }
 
             write!(f, ")")?;
+
+            if detailed {
+                let display = |stream: &Option<metadata::AlternateDataStream>| match stream {
+                    Some(stream) => stream.to_string(),
+                    None => UNKOWN_TEXT.to_string(),
+                };
+
+                for name in removed {
+                    let stream = display(&former_streams[name]);
+                    write!(f, "\n        {}", format!("- {name:?}: {stream}").red())?;
+                }
+                for name in added {
+                    let stream = display(&latter_streams[name]);
+                    write!(f, "\n        {}", format!("+ {name:?}: {stream}").green())?;
+                }
+                for name in changed {
+                    write!(
+                        f,
+                        "\n        {}: {} -> {}",
+                        format!("{name:?}").yellow(),
+                        display(&former_streams[name]).red(),
+                        display(&latter_streams[name]).green()
+                    )?;
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+/// Display a possible difference between the `former` and the `latter` Mark-of-the-Web.
+///
+/// Unlike most other metadata, the Mark-of-the-Web is also shown in compact displays when it did
+/// not change, because where a file came from is often the first thing to know about it.
+fn display_mark_of_the_web(
+    f: &mut fmt::Formatter,
+    former: &Option<metadata::AlternateDataStreams>,
+    latter: Option<&Option<metadata::AlternateDataStreams>>,
+    detailed: bool,
+    use_sep: &mut bool,
+) -> fmt::Result {
+    let parse = |streams: &Option<metadata::AlternateDataStreams>| {
+        streams.as_ref().and_then(MarkOfTheWeb::from_streams)
+    };
+    let former = parse(former);
+    let latter = latter.map(parse);
+    let name = if detailed { "mark of the web" } else { "MotW" };
+
+    match (former, latter) {
+        (former, Some(latter)) if former != latter => {
+            write_separator(f, detailed, use_sep)?;
+            if detailed {
+                let display = |mark: Option<MarkOfTheWeb>| match mark {
+                    Some(mark) => mark.to_string(),
+                    None => String::from("none"),
+                };
+                write!(
+                    f,
+                    "{name}: {} -> {}",
+                    display(former).red(),
+                    display(latter).green()
+                )?;
+            } else {
+                match (former, latter) {
+                    (Some(_), Some(latter)) => {
+                        write!(f, "{}", format!("{name}: {}", latter.zone_name()).yellow())?
+                    }
+                    (Some(former), None) => {
+                        write!(f, "{}", format!("{name}: {}", former.zone_name()).red())?
+                    }
+                    (None, Some(latter)) => {
+                        write!(f, "{}", format!("{name}: {}", latter.zone_name()).green())?
+                    }
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (Some(former), _) => {
+            write_separator(f, detailed, use_sep)?;
+            if detailed {
+                write!(f, "{name}: {former}")?;
+            } else {
+                write!(f, "{name}: {}", former.zone_name())?;
+            }
+        }
+        (None, _) => (),
+    }
+
+    Ok(())
+}
+
 /// Display a possible difference between the `former` and the `latter` property
 /// implementing `fmt::Display`.
 fn display_named_display<T: fmt::Display + Eq>(
@@ -386,6 +708,46 @@ fn display_named_display<T: fmt::Display + Eq>(
     Ok(())
 }
 
+/// Display a possible difference between the `former` and the `latter` number of hard links.
+///
+/// Compact displays show how many hard links the file gained or lost.
+fn display_nlink(
+    f: &mut fmt::Formatter,
+    former: Option<u64>,
+    latter: Option<Option<u64>>,
+    detailed: bool,
+    use_sep: &mut bool,
+) -> fmt::Result {
+    match (former, latter) {
+        (Some(former), Some(Some(latter))) if !detailed && former != latter => {
+            write_separator(f, detailed, use_sep)?;
+
+            let plural = |count: u64| if count == 1 { "" } else { "s" };
+            if latter > former {
+                let gained = latter - former;
+                write!(
+                    f,
+                    "{}",
+                    format!("+{gained} hard link{}", plural(gained)).green()
+                )?;
+            } else {
+                let lost = former - latter;
+                write!(f, "{}", format!("-{lost} hard link{}", plural(lost)).red())?;
+            }
+
+            Ok(())
+        }
+        _ => display_named_display(
+            f,
+            former,
+            latter,
+            if detailed { "hard links" } else { "nlink" },
+            detailed,
+            use_sep,
+        ),
+    }
+}
+
 /// Displays the metadata and possible difference between `former` and `latter` into `f`.
 pub(super) fn display_metadata(
     f: &mut fmt::Formatter,
@@ -411,6 +773,13 @@ pub(super) fn display_metadata(
         detailed,
         &mut use_sep,
     )?;
+    display_allocated_size(
+        f,
+        former.allocated_size,
+        latter.map(|m| m.allocated_size),
+        detailed,
+        &mut use_sep,
+    )?;
     if detailed {
         display_timestamp(
             f,
@@ -469,11 +838,10 @@ pub(super) fn display_metadata(
         detailed,
         &mut use_sep,
     )?;
-    display_named_display(
+    display_nlink(
         f,
         former.nlink,
         latter.map(|m| m.nlink),
-        if detailed { "hard links" } else { "nlink" },
         detailed,
         &mut use_sep,
     )?;
@@ -493,19 +861,17 @@ pub(super) fn display_metadata(
         detailed,
         &mut use_sep,
     )?;
-    display_byte_list(
+    display_reparse_point(
         f,
         &former.reparse_data,
         latter.map(|m| &m.reparse_data),
-        "reparse data",
         detailed,
         &mut use_sep,
     )?;
-    display_byte_list(
+    display_security_descriptor(
         f,
         &former.acl,
         latter.map(|m| &m.acl),
-        "acl",
         detailed,
         &mut use_sep,
     )?;
@@ -548,6 +914,13 @@ pub(super) fn display_metadata(
         detailed,
         &mut use_sep,
     )?;
+    display_mark_of_the_web(
+        f,
+        &former.streams,
+        latter.map(|m| &m.streams),
+        detailed,
+        &mut use_sep,
+    )?;
     display_named_display(
         f,
         former.inode,
@@ -571,6 +944,36 @@ pub(super) fn display_metadata(
 
 use sniff_interop as interop;
 
+/// A decoded reparse point as it is carried in changesets.
+#[derive(Serialize)]
+struct ChangesetReparsePoint<'point> {
+    /// The reparse tag.
+    tag: u32,
+    /// The decoded reparse point.
+    #[serde(flatten)]
+    point: &'point ReparsePoint,
+}
+
+/// Returns the reparse data as it is carried in changesets.
+///
+/// The changeset format only carries bytes, so decoded reparse points are encoded as JSON with
+/// their tag, type and targets.
+/// Reparse points of unknown types and malformed ones are carried as their raw reparse data.
+fn changeset_reparse_data(data: &Option<Vec<u8>>) -> Option<Vec<u8>> {
+    let data = data.as_deref()?;
+
+    match ReparsePoint::parse(data) {
+        Some(ReparsePoint::Other { .. }) | None => Some(data.to_vec()),
+        Some(point) => Some(
+            serde_json::to_vec(&ChangesetReparsePoint {
+                tag: u32::from_le_bytes(data[..4].try_into().unwrap()),
+                point: &point,
+            })
+            .expect("reparse points can always be serialized"),
+        ),
+    }
+}
+
 /// Computes the difference between the two given metadata instances.
 pub(crate) fn compute_meta_info_change(
     old: &crate::fs::Metadata,
@@ -612,7 +1015,10 @@ pub(crate) fn compute_meta_info_change(
     if let Some(change) = compute_change(&old.gid, &new.gid) {
         changes.push(interop::MetadataChange::Gid(change));
     }
-    if let Some(change) = compute_change(&old.reparse_data, &new.reparse_data) {
+    if let Some(change) = compute_change(
+        &changeset_reparse_data(&old.reparse_data),
+        &changeset_reparse_data(&new.reparse_data),
+    ) {
         changes.push(interop::MetadataChange::NamedStream(
             interop::NamedStreamType::ReparseData,
             change,
@@ -653,41 +1059,39 @@ pub(crate) fn compute_meta_info_change(
     fn get_from_streams<'a>(
         name: &std::ffi::OsStr,
         streams: &'a Option<metadata::AlternateDataStreams>,
-    ) -> &'a Option<Vec<u8>> {
-        if let Some(streams) = streams {
-            if let Some(stream) = streams.streams.get(name) {
-                stream
-            } else {
-                &None
-            }
-        } else {
-            &None
-        }
+    ) -> Option<&'a metadata::AlternateDataStream> {
+        streams.as_ref()?.streams.get(name)?.as_ref()
     }
 
-    if let Some(streams) = &old.streams {
-        for (name, ads) in &streams.streams {
-            if let Some(change) = compute_change(ads, get_from_streams(name, &new.streams)) {
-                changes.push(interop::MetadataChange::NamedStream(
-                    interop::NamedStreamType::AlternateDataStream {
-                        name: name.to_string_lossy().into_owned(),
-                    },
-                    change,
-                ));
-            }
-        }
+    /// Returns the value of the stream in the changeset.
+    ///
+    /// Streams whose contents are not stored are represented by their SHA-256 hash.
+    fn changeset_value(stream: Option<&metadata::AlternateDataStream>) -> Option<Vec<u8>> {
+        stream.map(|stream| match &stream.content {
+            Some(content) => content.clone(),
+            None => stream.file.sha2_256_hash.bytes.to_vec(),
+        })
     }
 
-    if let Some(streams) = &new.streams {
-        for (name, ads) in &streams.streams {
-            if let Some(change) = compute_change(get_from_streams(name, &old.streams), ads) {
-                changes.push(interop::MetadataChange::NamedStream(
-                    interop::NamedStreamType::AlternateDataStream {
-                        name: name.to_string_lossy().into_owned(),
-                    },
-                    change,
-                ));
-            }
+    let names = old
+        .streams
+        .iter()
+        .chain(&new.streams)
+        .flat_map(|streams| streams.streams.keys())
+        .collect::<BTreeSet<_>>();
+    for name in names {
+        let old_stream = get_from_streams(name, &old.streams);
+        let new_stream = get_from_streams(name, &new.streams);
+        if old_stream != new_stream {
+            changes.push(interop::MetadataChange::NamedStream(
+                interop::NamedStreamType::AlternateDataStream {
+                    name: name.to_string_lossy().into_owned(),
+                },
+                interop::Change {
+                    from: changeset_value(old_stream),
+                    to: changeset_value(new_stream),
+                },
+            ));
         }
     }
 
@@ -0,0 +1,336 @@
+//! Computing and displaying differences between the registry hives of snapshots.
+
+use owo_colors::OwoColorize as _;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::snapshot::registry::{Hive, Key, Value};
+
+/// The difference between two versions of a registry key or value.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Change<'hive, T> {
+    /// The item is the same in both snapshots.
+    Unchanged(&'hive T),
+    /// The item only exists in the latter snapshot.
+    Added(&'hive T),
+    /// The item only exists in the former snapshot.
+    Removed(&'hive T),
+    /// The item exists in both snapshots, but differs.
+    Changed(&'hive T, &'hive T),
+}
+
+impl<'hive, T> Change<'hive, T> {
+    /// Returns the difference between the item in the `former` and the `latter` snapshot.
+    ///
+    /// `changed` decides whether an item existing in both snapshots differs.
+    fn compute(
+        former: Option<&'hive T>,
+        latter: Option<&'hive T>,
+        changed: impl FnOnce(&T, &T) -> bool,
+    ) -> Option<Self> {
+        Some(match (former, latter) {
+            (Some(former), Some(latter)) if changed(former, latter) => {
+                Change::Changed(former, latter)
+            }
+            (Some(former), Some(_)) => Change::Unchanged(former),
+            (Some(former), None) => Change::Removed(former),
+            (None, Some(latter)) => Change::Added(latter),
+            (None, None) => return None,
+        })
+    }
+
+    /// Returns `true` if the item is unchanged.
+    fn is_unchanged(&self) -> bool {
+        matches!(self, Change::Unchanged(_))
+    }
+}
+
+/// The difference between a registry key in two snapshots.
+#[derive(Debug)]
+pub(crate) struct KeyDiff<'hive> {
+    /// The difference of the key itself, which is changed if its values changed.
+    change: Change<'hive, Key>,
+    /// The differences of the values of the key.
+    values: BTreeMap<&'hive str, Change<'hive, Value>>,
+    /// The differences of the subkeys of the key.
+    subkeys: BTreeMap<&'hive str, KeyDiff<'hive>>,
+}
+
+impl<'hive> KeyDiff<'hive> {
+    /// Computes the difference between the key in the `former` and the `latter` snapshot.
+    ///
+    /// Returns `None` if the key exists in neither snapshot.
+    pub(crate) fn compute(former: Option<&'hive Key>, latter: Option<&'hive Key>) -> Option<Self> {
+        firestorm::profile_fn!(registry_diff_computation);
+
+        let mut values = BTreeMap::new();
+        let mut subkeys = BTreeMap::new();
+
+        for key in former.into_iter().chain(latter) {
+            for name in key.values.keys() {
+                values.entry(&name[..]).or_insert_with(|| {
+                    Change::compute(
+                        former.and_then(|key| key.values.get(name)),
+                        latter.and_then(|key| key.values.get(name)),
+                        |former, latter| former != latter,
+                    )
+                    .expect("the value exists in one of the keys")
+                });
+            }
+
+            for name in key.subkeys.keys() {
+                // Key names are case insensitive, so a key only differing in case is the same
+                if subkeys
+                    .keys()
+                    .any(|other: &&str| other.eq_ignore_ascii_case(name))
+                {
+                    continue;
+                }
+
+                let diff = KeyDiff::compute(
+                    former.and_then(|key| key.subkey(name)),
+                    latter.and_then(|key| key.subkey(name)),
+                )
+                .expect("the subkey exists in one of the keys");
+                subkeys.insert(&name[..], diff);
+            }
+        }
+
+        let values_changed = values.values().any(|value| !value.is_unchanged());
+        let change = Change::compute(former, latter, |_, _| values_changed)?;
+
+        Some(Self {
+            change,
+            values,
+            subkeys,
+        })
+    }
+
+    /// Returns `true` if anything changed in the key or below it.
+    pub(crate) fn has_changes(&self) -> bool {
+        !self.change.is_unchanged() || self.subkeys.values().any(KeyDiff::has_changes)
+    }
+
+    /// Counts the added, removed and changed keys and values below this key, including itself.
+    fn count_changes(&self, counts: &mut ChangeCounts) {
+        counts.add(&self.change);
+        for value in self.values.values() {
+            counts.add(value);
+        }
+        for subkey in self.subkeys.values() {
+            subkey.count_changes(counts);
+        }
+    }
+
+    /// Formats the difference tree of the key into the given formatter.
+    fn recursive_tree_display(
+        &self,
+        f: &mut fmt::Formatter,
+        prefix: &str,
+        depth: u32,
+        name: &str,
+        ctx: &RegistryDisplay,
+    ) -> fmt::Result {
+        write!(f, "{prefix} 🗁 ")?;
+        match self.change {
+            Change::Unchanged(_) if self.has_changes() => write!(f, "{name}")?,
+            Change::Unchanged(_) => write!(f, "{}", name.bright_black())?,
+            Change::Added(_) => write!(f, "{}", name.green())?,
+            Change::Removed(_) => write!(f, "{}", name.red())?,
+            Change::Changed(..) => write!(f, "{}", name.yellow())?,
+        }
+
+        let mut counts = ChangeCounts::default();
+        self.count_changes(&mut counts);
+        writeln!(f, "{counts}")?;
+
+        if let Some(level) = ctx.summary_level
+            && depth == level
+        {
+            return Ok(());
+        }
+
+        let stripped_prefix = if let Some(prefix) = prefix.strip_suffix(" └─") {
+            format!("{}   ", prefix)
+        } else if let Some(prefix) = prefix.strip_suffix(" ├─") {
+            format!("{} │ ", prefix)
+        } else {
+            prefix.to_string()
+        };
+
+        let values = self
+            .values
+            .iter()
+            .filter(|(_, value)| ctx.show_unchanged || !value.is_unchanged())
+            .collect::<Vec<_>>();
+        let subkeys = self
+            .subkeys
+            .iter()
+            .filter(|(_, subkey)| ctx.show_unchanged || subkey.has_changes())
+            .collect::<Vec<_>>();
+
+        for (i, (name, value)) in values.iter().enumerate() {
+            let last = i + 1 == values.len() && subkeys.is_empty();
+            let branch = if last { " └─" } else { " ├─" };
+            let name = if name.is_empty() { "(Default)" } else { name };
+
+            write!(f, "{stripped_prefix}{branch} ")?;
+            match value {
+                Change::Unchanged(value) => {
+                    writeln!(f, "{}", format!("{name} = {value}").bright_black())?
+                }
+                Change::Added(value) => writeln!(f, "{}", format!("{name} = {value}").green())?,
+                Change::Removed(value) => writeln!(f, "{}", format!("{name} = {value}").red())?,
+                Change::Changed(former, latter) => writeln!(
+                    f,
+                    "{} = {} -> {}",
+                    name.yellow(),
+                    former.red(),
+                    latter.green()
+                )?,
+            }
+        }
+
+        for (i, (name, subkey)) in subkeys.iter().enumerate() {
+            let branch = if i + 1 == subkeys.len() {
+                " └─"
+            } else {
+                " ├─"
+            };
+
+            subkey.recursive_tree_display(
+                f,
+                &format!("{stripped_prefix}{branch}"),
+                depth + 1,
+                name,
+                ctx,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Displays the difference in a tree view.
+    ///
+    /// Unchanged keys and values are only shown if `show_unchanged` is set.
+    pub(crate) fn display_as_tree<'diff>(
+        &'diff self,
+        name: &'diff str,
+        summary_level: Option<u32>,
+        show_unchanged: bool,
+    ) -> RegistryDisplay<'diff, 'hive> {
+        RegistryDisplay {
+            diff: self,
+            name,
+            summary_level,
+            show_unchanged,
+        }
+    }
+}
+
+/// The number of added, removed and changed keys and values.
+#[derive(Debug, Default)]
+struct ChangeCounts {
+    /// The number of added keys and values.
+    added: usize,
+    /// The number of removed keys and values.
+    removed: usize,
+    /// The number of changed keys and values.
+    changed: usize,
+    /// The number of unchanged keys and values.
+    unchanged: usize,
+}
+
+impl ChangeCounts {
+    /// Counts the given change.
+    fn add<T>(&mut self, change: &Change<T>) {
+        match change {
+            Change::Unchanged(_) => self.unchanged += 1,
+            Change::Added(_) => self.added += 1,
+            Change::Removed(_) => self.removed += 1,
+            Change::Changed(..) => self.changed += 1,
+        }
+    }
+}
+
+impl fmt::Display for ChangeCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, " (")?;
+        let mut first = true;
+        for (count, color) in [
+            (self.removed, owo_colors::AnsiColors::Red),
+            (self.added, owo_colors::AnsiColors::Green),
+            (self.changed, owo_colors::AnsiColors::Yellow),
+            (self.unchanged, owo_colors::AnsiColors::BrightBlack),
+        ] {
+            if count != 0 {
+                write!(f, "{}{}", if first { "" } else { " " }, count.color(color))?;
+                first = false;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+/// A helper struct implementing `Display` used for `KeyDiff::display_as_tree`.
+pub(crate) struct RegistryDisplay<'diff, 'hive> {
+    /// The difference that is displayed.
+    diff: &'diff KeyDiff<'hive>,
+    /// The name of the key.
+    name: &'diff str,
+    /// The depth at which the tree is summarized.
+    summary_level: Option<u32>,
+    /// Whether to show unchanged keys and values.
+    show_unchanged: bool,
+}
+
+impl fmt::Display for RegistryDisplay<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.diff.recursive_tree_display(f, "", 0, self.name, self)
+    }
+}
+
+/// Splits the given path into the path of a hive file in `hives` and the path of a key in it.
+///
+/// Returns `None` if the path is not at or below one of the hives.
+pub(crate) fn locate<'hive>(
+    path: &Path,
+    hives: impl IntoIterator<Item = &'hive PathBuf>,
+) -> Option<(&'hive Path, Vec<String>)> {
+    let names = |path: &Path| {
+        path.components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let path = names(path);
+
+    hives.into_iter().find_map(|hive| {
+        let hive_names = names(hive);
+        let is_prefix = hive_names.len() <= path.len()
+            && hive_names
+                .iter()
+                .zip(&path)
+                .all(|(hive, path)| hive.eq_ignore_ascii_case(path));
+
+        is_prefix.then(|| (hive.as_path(), path[hive_names.len()..].to_vec()))
+    })
+}
+
+/// Returns the key at `key_path` in the hive at `hive_path` in the given hives, if it exists.
+pub(crate) fn find_key<'hive>(
+    hives: &'hive BTreeMap<PathBuf, Hive>,
+    hive_path: &Path,
+    key_path: &[String],
+) -> Option<&'hive Key> {
+    hives
+        .iter()
+        .find(|(path, _)| path.as_os_str().eq_ignore_ascii_case(hive_path.as_os_str()))
+        .and_then(|(_, hive)| hive.root.get(key_path.iter().map(String::as_str)))
+}
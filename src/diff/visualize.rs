@@ -348,7 +348,9 @@ fn color_from_diff_type(diff_type: &super::DiffType) -> Rgb<u8> {
         }
         | super::DiffType::ChildrenChanged {
             metadata_changed_to: None,
-        } => UNCHANGED,
+        }
+        | super::DiffType::Excluded
+        | super::DiffType::Unreadable => UNCHANGED,
         super::DiffType::Unchanged {
             metadata_changed_to: Some(_),
         }
@@ -68,13 +68,121 @@ pub(crate) type MetaDEntryV1<Context = ()> =
 
 /// The directory entry of version 1.
 pub(crate) type DEntryV1<Context = ()> =
-    DirEntry<metadata::MetadataV1, File, Symlink, dir_entry_type::DirEntryType, Context>;
+    DirEntry<metadata::MetadataV1, file::FileV1, Symlink, dir_entry_type::DirEntryType, Context>;
 
 /// The directory type of version 1.
 pub(crate) type DirectoryV1<Context = ()> =
     Directory<DEntryV1<Context>, metadata::MetadataV1, Context>;
 
-impl<Context> From<MetaDEntryV1<Context>> for MetaDEntry<Context> {
+/// The type of a directory entry with metadata of version 2.
+pub(crate) type MetaDEntryV2<Context = ()> =
+    MetaDirEntry<DEntryV2<Context>, metadata::MetadataV2, Context>;
+
+/// The directory entry of version 2.
+pub(crate) type DEntryV2<Context = ()> =
+    DirEntry<metadata::MetadataV2, file::FileV1, Symlink, dir_entry_type::DirEntryType, Context>;
+
+/// The directory type of version 2.
+pub(crate) type DirectoryV2<Context = ()> =
+    Directory<DEntryV2<Context>, metadata::MetadataV2, Context>;
+
+/// The type of a directory entry with metadata of version 3.
+pub(crate) type MetaDEntryV3<Context = ()> =
+    MetaDirEntry<DEntryV3<Context>, metadata::MetadataV2, Context>;
+
+/// The directory entry of version 3.
+pub(crate) type DEntryV3<Context = ()> =
+    DirEntry<metadata::MetadataV2, file::FileV2, Symlink, dir_entry_type::DirEntryType, Context>;
+
+/// The directory type of version 3.
+pub(crate) type DirectoryV3<Context = ()> =
+    Directory<DEntryV3<Context>, metadata::MetadataV2, Context>;
+
+/// The type of a directory entry with metadata of version 4.
+pub(crate) type MetaDEntryV4<Context = ()> =
+    MetaDirEntry<DEntryV4<Context>, metadata::MetadataV2, Context>;
+
+/// The directory entry of version 4.
+pub(crate) type DEntryV4<Context = ()> =
+    DirEntry<metadata::MetadataV2, file::FileV3, Symlink, dir_entry_type::DirEntryType, Context>;
+
+/// The directory type of version 4.
+pub(crate) type DirectoryV4<Context = ()> =
+    Directory<DEntryV4<Context>, metadata::MetadataV2, Context>;
+
+/// The type of a directory entry with metadata of version 5.
+pub(crate) type MetaDEntryV5<Context = ()> =
+    MetaDirEntry<DEntryV5<Context>, metadata::MetadataV2, Context>;
+
+/// The directory entry of version 5.
+pub(crate) type DEntryV5<Context = ()> =
+    DirEntry<metadata::MetadataV2, file::FileV4, Symlink, dir_entry_type::DirEntryType, Context>;
+
+/// The directory type of version 5.
+pub(crate) type DirectoryV5<Context = ()> =
+    Directory<DEntryV5<Context>, metadata::MetadataV2, Context>;
+
+/// The type of a directory entry with metadata of version 6.
+pub(crate) type MetaDEntryV6<Context = ()> =
+    MetaDirEntry<DEntryV6<Context>, metadata::MetadataV2, Context>;
+
+/// The directory entry of version 6.
+pub(crate) type DEntryV6<Context = ()> =
+    DirEntry<metadata::MetadataV2, file::FileV5, Symlink, dir_entry_type::DirEntryType, Context>;
+
+/// The directory type of version 6.
+pub(crate) type DirectoryV6<Context = ()> =
+    Directory<DEntryV6<Context>, metadata::MetadataV2, Context>;
+
+/// The type of a directory entry with metadata of version 7.
+pub(crate) type MetaDEntryV7<Context = ()> =
+    MetaDirEntry<DEntryV7<Context>, metadata::MetadataV2, Context>;
+
+/// The directory entry of version 7.
+pub(crate) type DEntryV7<Context = ()> =
+    DirEntry<metadata::MetadataV2, file::FileV6, Symlink, dir_entry_type::DirEntryType, Context>;
+
+/// The directory type of version 7.
+pub(crate) type DirectoryV7<Context = ()> =
+    Directory<DEntryV7<Context>, metadata::MetadataV2, Context>;
+
+/// The type of a directory entry with metadata of version 8.
+pub(crate) type MetaDEntryV8<Context = ()> =
+    MetaDirEntry<DEntryV8<Context>, metadata::MetadataV2, Context>;
+
+/// The directory entry of version 8.
+pub(crate) type DEntryV8<Context = ()> =
+    DirEntry<metadata::MetadataV2, file::FileV7, Symlink, dir_entry_type::DirEntryType, Context>;
+
+/// The directory type of version 8.
+pub(crate) type DirectoryV8<Context = ()> =
+    Directory<DEntryV8<Context>, metadata::MetadataV2, Context>;
+
+/// The type of a directory entry with metadata of version 9.
+pub(crate) type MetaDEntryV9<Context = ()> =
+    MetaDirEntry<DEntryV9<Context>, metadata::MetadataV2, Context>;
+
+/// The directory entry of version 9.
+pub(crate) type DEntryV9<Context = ()> =
+    DirEntry<metadata::MetadataV2, File, Symlink, dir_entry_type::DirEntryType, Context>;
+
+/// The directory type of version 9.
+pub(crate) type DirectoryV9<Context = ()> =
+    Directory<DEntryV9<Context>, metadata::MetadataV2, Context>;
+
+/// The type of a directory entry with metadata of version 10.
+pub(crate) type MetaDEntryV10<Context = ()> =
+    MetaDirEntry<DEntryV10<Context>, metadata::MetadataV3, Context>;
+
+/// The directory entry of version 10.
+pub(crate) type DEntryV10<Context = ()> =
+    DirEntry<metadata::MetadataV3, File, Symlink, dir_entry_type::DirEntryType, Context>;
+
+/// The directory type of version 10.
+pub(crate) type DirectoryV10<Context = ()> =
+    Directory<DEntryV10<Context>, metadata::MetadataV3, Context>;
+
+impl<Context> From<MetaDEntryV1<Context>> for MetaDEntryV2<Context> {
     fn from(entry: MetaDEntryV1<Context>) -> Self {
         firestorm::profile_section!(v1_snapshot_conversion);
         Self {
@@ -85,7 +193,7 @@ impl<Context> From<MetaDEntryV1<Context>> for MetaDEntry<Context> {
     }
 }
 
-impl<Context> From<DEntryV1<Context>> for DEntry<Context> {
+impl<Context> From<DEntryV1<Context>> for DEntryV2<Context> {
     fn from(entry: DEntryV1<Context>) -> Self {
         match entry {
             DirEntry::File(file) => DirEntry::File(file),
@@ -96,7 +204,7 @@ impl<Context> From<DEntryV1<Context>> for DEntry<Context> {
     }
 }
 
-impl<Context> From<DirectoryV1<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+impl<Context> From<DirectoryV1<Context>> for DirectoryV2<Context> {
     fn from(dir: DirectoryV1<Context>) -> Self {
         let mut entries = std::collections::BTreeMap::new();
 
@@ -108,6 +216,312 @@ impl<Context> From<DirectoryV1<Context>> for Directory<DEntry<Context>, Metadata
     }
 }
 
+impl<Context> From<MetaDEntryV2<Context>> for MetaDEntry<Context> {
+    fn from(entry: MetaDEntryV2<Context>) -> Self {
+        firestorm::profile_section!(v2_snapshot_conversion);
+        Self {
+            entry: entry.entry.into(),
+            metadata: entry.metadata.into(),
+            context: entry.context,
+        }
+    }
+}
+
+impl<Context> From<DEntryV2<Context>> for DEntry<Context> {
+    fn from(entry: DEntryV2<Context>) -> Self {
+        match entry {
+            DirEntry::File(file) => DirEntry::File(file.into()),
+            DirEntry::Symlink(symlink) => DirEntry::Symlink(symlink),
+            DirEntry::Directory(directory) => DirEntry::Directory(directory.into()),
+            DirEntry::Other(ty) => DirEntry::Other(ty),
+        }
+    }
+}
+
+impl<Context> From<DirectoryV2<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+    fn from(dir: DirectoryV2<Context>) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for (name, entry) in dir.entries {
+            entries.insert(name, entry.into());
+        }
+
+        Self { entries }
+    }
+}
+
+impl<Context> From<MetaDEntryV3<Context>> for MetaDEntry<Context> {
+    fn from(entry: MetaDEntryV3<Context>) -> Self {
+        firestorm::profile_section!(v3_snapshot_conversion);
+        Self {
+            entry: entry.entry.into(),
+            metadata: entry.metadata.into(),
+            context: entry.context,
+        }
+    }
+}
+
+impl<Context> From<DEntryV3<Context>> for DEntry<Context> {
+    fn from(entry: DEntryV3<Context>) -> Self {
+        match entry {
+            DirEntry::File(file) => DirEntry::File(file.into()),
+            DirEntry::Symlink(symlink) => DirEntry::Symlink(symlink),
+            DirEntry::Directory(directory) => DirEntry::Directory(directory.into()),
+            DirEntry::Other(ty) => DirEntry::Other(ty),
+        }
+    }
+}
+
+impl<Context> From<DirectoryV3<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+    fn from(dir: DirectoryV3<Context>) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for (name, entry) in dir.entries {
+            entries.insert(name, entry.into());
+        }
+
+        Self { entries }
+    }
+}
+
+impl<Context> From<MetaDEntryV4<Context>> for MetaDEntry<Context> {
+    fn from(entry: MetaDEntryV4<Context>) -> Self {
+        firestorm::profile_section!(v4_snapshot_conversion);
+        Self {
+            entry: entry.entry.into(),
+            metadata: entry.metadata.into(),
+            context: entry.context,
+        }
+    }
+}
+
+impl<Context> From<DEntryV4<Context>> for DEntry<Context> {
+    fn from(entry: DEntryV4<Context>) -> Self {
+        match entry {
+            DirEntry::File(file) => DirEntry::File(file.into()),
+            DirEntry::Symlink(symlink) => DirEntry::Symlink(symlink),
+            DirEntry::Directory(directory) => DirEntry::Directory(directory.into()),
+            DirEntry::Other(ty) => DirEntry::Other(ty),
+        }
+    }
+}
+
+impl<Context> From<DirectoryV4<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+    fn from(dir: DirectoryV4<Context>) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for (name, entry) in dir.entries {
+            entries.insert(name, entry.into());
+        }
+
+        Self { entries }
+    }
+}
+
+impl<Context> From<MetaDEntryV5<Context>> for MetaDEntry<Context> {
+    fn from(entry: MetaDEntryV5<Context>) -> Self {
+        firestorm::profile_section!(v5_snapshot_conversion);
+        Self {
+            entry: entry.entry.into(),
+            metadata: entry.metadata.into(),
+            context: entry.context,
+        }
+    }
+}
+
+impl<Context> From<DEntryV5<Context>> for DEntry<Context> {
+    fn from(entry: DEntryV5<Context>) -> Self {
+        match entry {
+            DirEntry::File(file) => DirEntry::File(file.into()),
+            DirEntry::Symlink(symlink) => DirEntry::Symlink(symlink),
+            DirEntry::Directory(directory) => DirEntry::Directory(directory.into()),
+            DirEntry::Other(ty) => DirEntry::Other(ty),
+        }
+    }
+}
+
+impl<Context> From<DirectoryV5<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+    fn from(dir: DirectoryV5<Context>) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for (name, entry) in dir.entries {
+            entries.insert(name, entry.into());
+        }
+
+        Self { entries }
+    }
+}
+
+impl<Context> From<MetaDEntryV6<Context>> for MetaDEntry<Context> {
+    fn from(entry: MetaDEntryV6<Context>) -> Self {
+        firestorm::profile_section!(v6_snapshot_conversion);
+        Self {
+            entry: entry.entry.into(),
+            metadata: entry.metadata.into(),
+            context: entry.context,
+        }
+    }
+}
+
+impl<Context> From<DEntryV6<Context>> for DEntry<Context> {
+    fn from(entry: DEntryV6<Context>) -> Self {
+        match entry {
+            DirEntry::File(file) => DirEntry::File(file.into()),
+            DirEntry::Symlink(symlink) => DirEntry::Symlink(symlink),
+            DirEntry::Directory(directory) => DirEntry::Directory(directory.into()),
+            DirEntry::Other(ty) => DirEntry::Other(ty),
+        }
+    }
+}
+
+impl<Context> From<DirectoryV6<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+    fn from(dir: DirectoryV6<Context>) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for (name, entry) in dir.entries {
+            entries.insert(name, entry.into());
+        }
+
+        Self { entries }
+    }
+}
+
+impl<Context> From<MetaDEntryV7<Context>> for MetaDEntry<Context> {
+    fn from(entry: MetaDEntryV7<Context>) -> Self {
+        firestorm::profile_section!(v7_snapshot_conversion);
+        Self {
+            entry: entry.entry.into(),
+            metadata: entry.metadata.into(),
+            context: entry.context,
+        }
+    }
+}
+
+impl<Context> From<DEntryV7<Context>> for DEntry<Context> {
+    fn from(entry: DEntryV7<Context>) -> Self {
+        match entry {
+            DirEntry::File(file) => DirEntry::File(file.into()),
+            DirEntry::Symlink(symlink) => DirEntry::Symlink(symlink),
+            DirEntry::Directory(directory) => DirEntry::Directory(directory.into()),
+            DirEntry::Other(ty) => DirEntry::Other(ty),
+        }
+    }
+}
+
+impl<Context> From<DirectoryV7<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+    fn from(dir: DirectoryV7<Context>) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for (name, entry) in dir.entries {
+            entries.insert(name, entry.into());
+        }
+
+        Self { entries }
+    }
+}
+
+impl<Context> From<MetaDEntryV8<Context>> for MetaDEntry<Context> {
+    fn from(entry: MetaDEntryV8<Context>) -> Self {
+        firestorm::profile_section!(v8_snapshot_conversion);
+        Self {
+            entry: entry.entry.into(),
+            metadata: entry.metadata.into(),
+            context: entry.context,
+        }
+    }
+}
+
+impl<Context> From<DEntryV8<Context>> for DEntry<Context> {
+    fn from(entry: DEntryV8<Context>) -> Self {
+        match entry {
+            DirEntry::File(file) => DirEntry::File(file.into()),
+            DirEntry::Symlink(symlink) => DirEntry::Symlink(symlink),
+            DirEntry::Directory(directory) => DirEntry::Directory(directory.into()),
+            DirEntry::Other(ty) => DirEntry::Other(ty),
+        }
+    }
+}
+
+impl<Context> From<DirectoryV8<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+    fn from(dir: DirectoryV8<Context>) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for (name, entry) in dir.entries {
+            entries.insert(name, entry.into());
+        }
+
+        Self { entries }
+    }
+}
+
+impl<Context> From<MetaDEntryV9<Context>> for MetaDEntry<Context> {
+    fn from(entry: MetaDEntryV9<Context>) -> Self {
+        firestorm::profile_section!(v9_snapshot_conversion);
+        Self {
+            entry: entry.entry.into(),
+            metadata: entry.metadata.into(),
+            context: entry.context,
+        }
+    }
+}
+
+impl<Context> From<DEntryV9<Context>> for DEntry<Context> {
+    fn from(entry: DEntryV9<Context>) -> Self {
+        match entry {
+            DirEntry::File(file) => DirEntry::File(file),
+            DirEntry::Symlink(symlink) => DirEntry::Symlink(symlink),
+            DirEntry::Directory(directory) => DirEntry::Directory(directory.into()),
+            DirEntry::Other(ty) => DirEntry::Other(ty),
+        }
+    }
+}
+
+impl<Context> From<DirectoryV9<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+    fn from(dir: DirectoryV9<Context>) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for (name, entry) in dir.entries {
+            entries.insert(name, entry.into());
+        }
+
+        Self { entries }
+    }
+}
+
+impl<Context> From<MetaDEntryV10<Context>> for MetaDEntry<Context> {
+    fn from(entry: MetaDEntryV10<Context>) -> Self {
+        firestorm::profile_section!(v10_snapshot_conversion);
+        Self {
+            entry: entry.entry.into(),
+            metadata: entry.metadata.into(),
+            context: entry.context,
+        }
+    }
+}
+
+impl<Context> From<DEntryV10<Context>> for DEntry<Context> {
+    fn from(entry: DEntryV10<Context>) -> Self {
+        match entry {
+            DirEntry::File(file) => DirEntry::File(file),
+            DirEntry::Symlink(symlink) => DirEntry::Symlink(symlink),
+            DirEntry::Directory(directory) => DirEntry::Directory(directory.into()),
+            DirEntry::Other(ty) => DirEntry::Other(ty),
+        }
+    }
+}
+
+impl<Context> From<DirectoryV10<Context>> for Directory<DEntry<Context>, Metadata, Context> {
+    fn from(dir: DirectoryV10<Context>) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for (name, entry) in dir.entries {
+            entries.insert(name, entry.into());
+        }
+
+        Self { entries }
+    }
+}
+
 impl<Context> DEntry<Context> {
     /// Clones this entry, annotating each node with the context given to it by `ctx`.
     pub(crate) fn with_context<NewContext>(
@@ -5,14 +5,38 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use std::{
     fmt, fs,
-    io::{self, Read},
+    io::{self, Read, Seek, SeekFrom},
     mem,
     path::Path,
 };
 
+pub(crate) mod authenticode;
+pub(crate) mod blocks;
+pub(crate) mod elf;
+pub(crate) mod file_type;
+pub(crate) mod macho;
+pub(crate) mod pe;
+pub(crate) mod tlsh;
+
 /// The number of bytes that will be stored in the `first_bytes` field.
 const FIRST_BYTES_LEN: usize = 16;
 
+/// The maximum size of an executable file whose structure is parsed.
+///
+/// Larger executables are usually installers with a large overlay and are not kept in memory.
+const MAX_EXECUTABLE_LEN: usize = 0x1000_0000;
+
+/// The magic bytes of the executable formats whose structure is parsed.
+const EXECUTABLE_MAGICS: &[&[u8]] = &[
+    b"MZ",
+    b"\x7fELF",
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+    b"\xca\xfe\xba\xbe",
+];
+
 /// Stores information about a file.
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub(crate) struct File {
@@ -33,26 +57,398 @@ pub(crate) struct File {
     /// documentation](https://docs.microsoft.com/en-us/windows/win32/debug/pe-format) for more
     /// details.
     pub(crate) coff_header: Option<Vec<u8>>,
+    /// The structure of the file, if it is a PE file.
+    pub(crate) pe: Option<Box<pe::PeInfo>>,
+    /// The Authenticode signature of the file, if it is a signed PE file.
+    pub(crate) signature: Option<Box<authenticode::Signature>>,
+    /// The TLSH digest of the file, if it is large and varied enough.
+    pub(crate) tlsh: Option<tlsh::Tlsh>,
+    /// The import hash of the file, if it is a PE file with imports.
+    pub(crate) imphash: Option<Md5Hash>,
+    /// The MD5 hash of the decoded Rich header of the file, if it is a PE file with one.
+    pub(crate) rich_hash: Option<Md5Hash>,
+    /// The type of the file identified by its contents, if it is known.
+    pub(crate) file_type: Option<file_type::FileType>,
+    /// The structure of the file, if it is an ELF file.
+    pub(crate) elf: Option<Box<elf::ElfInfo>>,
+    /// The structure of the file, if it is a Mach-O file.
+    pub(crate) macho: Option<Box<macho::MachOInfo>>,
+    /// The hashes of the blocks of the file, if it is large.
+    pub(crate) block_hashes: Option<Box<blocks::BlockHashes>>,
+}
+
+/// Stores information about a file, as in snapshots of version 16.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct FileV7 {
+    /// The SHA2-256 hash of the files contents.
+    pub(crate) sha2_256_hash: Sha256Hash,
+    /// The MD5 hash of the files contents.
+    pub(crate) md5_hash: Md5Hash,
+    /// The first bytes of the file.
+    pub(crate) first_bytes: ArrayVec<u8, FIRST_BYTES_LEN>,
+    /// The flags stored about a file.
+    pub(crate) flags: FileFlags,
+    /// The entropy of the file.
+    pub(crate) entropy: f32,
+    /// The COFF-header of the file, if it exists.
+    pub(crate) coff_header: Option<Vec<u8>>,
+    /// The structure of the file, if it is a PE file.
+    pub(crate) pe: Option<Box<pe::PeInfo>>,
+    /// The Authenticode signature of the file, if it is a signed PE file.
+    pub(crate) signature: Option<Box<authenticode::Signature>>,
+    /// The TLSH digest of the file, if it is large and varied enough.
+    pub(crate) tlsh: Option<tlsh::Tlsh>,
+    /// The import hash of the file, if it is a PE file with imports.
+    pub(crate) imphash: Option<Md5Hash>,
+    /// The MD5 hash of the decoded Rich header of the file, if it is a PE file with one.
+    pub(crate) rich_hash: Option<Md5Hash>,
+    /// The type of the file identified by its contents, if it is known.
+    pub(crate) file_type: Option<file_type::FileType>,
+    /// The structure of the file, if it is an ELF file.
+    pub(crate) elf: Option<Box<elf::ElfInfo>>,
+    /// The structure of the file, if it is a Mach-O file.
+    pub(crate) macho: Option<Box<macho::MachOInfo>>,
+}
+
+impl From<FileV7> for File {
+    fn from(old: FileV7) -> Self {
+        Self {
+            sha2_256_hash: old.sha2_256_hash,
+            md5_hash: old.md5_hash,
+            first_bytes: old.first_bytes,
+            flags: old.flags,
+            entropy: old.entropy,
+            coff_header: old.coff_header,
+            pe: old.pe,
+            signature: old.signature,
+            tlsh: old.tlsh,
+            imphash: old.imphash,
+            rich_hash: old.rich_hash,
+            file_type: old.file_type,
+            elf: old.elf,
+            macho: old.macho,
+            block_hashes: None,
+        }
+    }
+}
+
+/// Stores information about a file, as in snapshots of version 15.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct FileV6 {
+    /// The SHA2-256 hash of the files contents.
+    pub(crate) sha2_256_hash: Sha256Hash,
+    /// The MD5 hash of the files contents.
+    pub(crate) md5_hash: Md5Hash,
+    /// The first bytes of the file.
+    pub(crate) first_bytes: ArrayVec<u8, FIRST_BYTES_LEN>,
+    /// The flags stored about a file.
+    pub(crate) flags: FileFlags,
+    /// The entropy of the file.
+    pub(crate) entropy: f32,
+    /// The COFF-header of the file, if it exists.
+    pub(crate) coff_header: Option<Vec<u8>>,
+    /// The structure of the file, if it is a PE file.
+    pub(crate) pe: Option<Box<pe::PeInfo>>,
+    /// The Authenticode signature of the file, if it is a signed PE file.
+    pub(crate) signature: Option<Box<authenticode::Signature>>,
+    /// The TLSH digest of the file, if it is large and varied enough.
+    pub(crate) tlsh: Option<tlsh::Tlsh>,
+    /// The import hash of the file, if it is a PE file with imports.
+    pub(crate) imphash: Option<Md5Hash>,
+    /// The MD5 hash of the decoded Rich header of the file, if it is a PE file with one.
+    pub(crate) rich_hash: Option<Md5Hash>,
+    /// The type of the file identified by its contents, if it is known.
+    pub(crate) file_type: Option<file_type::FileType>,
+}
+
+impl From<FileV6> for File {
+    fn from(old: FileV6) -> Self {
+        Self {
+            sha2_256_hash: old.sha2_256_hash,
+            md5_hash: old.md5_hash,
+            first_bytes: old.first_bytes,
+            flags: old.flags,
+            entropy: old.entropy,
+            coff_header: old.coff_header,
+            pe: old.pe,
+            signature: old.signature,
+            tlsh: old.tlsh,
+            imphash: old.imphash,
+            rich_hash: old.rich_hash,
+            file_type: old.file_type,
+            elf: None,
+            macho: None,
+            block_hashes: None,
+        }
+    }
+}
+
+/// Stores information about a file, as in snapshots of version 14.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct FileV5 {
+    /// The SHA2-256 hash of the files contents.
+    pub(crate) sha2_256_hash: Sha256Hash,
+    /// The MD5 hash of the files contents.
+    pub(crate) md5_hash: Md5Hash,
+    /// The first bytes of the file.
+    pub(crate) first_bytes: ArrayVec<u8, FIRST_BYTES_LEN>,
+    /// The flags stored about a file.
+    pub(crate) flags: FileFlags,
+    /// The entropy of the file.
+    pub(crate) entropy: f32,
+    /// The COFF-header of the file, if it exists.
+    pub(crate) coff_header: Option<Vec<u8>>,
+    /// The structure of the file, if it is a PE file.
+    pub(crate) pe: Option<Box<pe::PeInfo>>,
+    /// The Authenticode signature of the file, if it is a signed PE file.
+    pub(crate) signature: Option<Box<authenticode::Signature>>,
+    /// The TLSH digest of the file, if it is large and varied enough.
+    pub(crate) tlsh: Option<tlsh::Tlsh>,
+    /// The import hash of the file, if it is a PE file with imports.
+    pub(crate) imphash: Option<Md5Hash>,
+    /// The MD5 hash of the decoded Rich header of the file, if it is a PE file with one.
+    pub(crate) rich_hash: Option<Md5Hash>,
+}
+
+impl From<FileV5> for File {
+    fn from(old: FileV5) -> Self {
+        Self {
+            // older versions did not record the type, but it can mostly be identified anyway
+            file_type: file_type::FileType::detect(
+                &old.first_bytes,
+                old.flags,
+                old.coff_header.is_some(),
+            ),
+            sha2_256_hash: old.sha2_256_hash,
+            md5_hash: old.md5_hash,
+            first_bytes: old.first_bytes,
+            flags: old.flags,
+            entropy: old.entropy,
+            coff_header: old.coff_header,
+            pe: old.pe,
+            signature: old.signature,
+            tlsh: old.tlsh,
+            imphash: old.imphash,
+            rich_hash: old.rich_hash,
+            elf: None,
+            macho: None,
+            block_hashes: None,
+        }
+    }
+}
+
+/// Stores information about a file, as in snapshots of version 13.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct FileV4 {
+    /// The SHA2-256 hash of the files contents.
+    pub(crate) sha2_256_hash: Sha256Hash,
+    /// The MD5 hash of the files contents.
+    pub(crate) md5_hash: Md5Hash,
+    /// The first bytes of the file.
+    pub(crate) first_bytes: ArrayVec<u8, FIRST_BYTES_LEN>,
+    /// The flags stored about a file.
+    pub(crate) flags: FileFlags,
+    /// The entropy of the file.
+    pub(crate) entropy: f32,
+    /// The COFF-header of the file, if it exists.
+    pub(crate) coff_header: Option<Vec<u8>>,
+    /// The structure of the file, if it is a PE file.
+    pub(crate) pe: Option<Box<pe::PeInfo>>,
+    /// The Authenticode signature of the file, if it is a signed PE file.
+    pub(crate) signature: Option<Box<authenticode::Signature>>,
+    /// The TLSH digest of the file, if it is large and varied enough.
+    pub(crate) tlsh: Option<tlsh::Tlsh>,
+}
+
+impl From<FileV4> for File {
+    fn from(old: FileV4) -> Self {
+        Self {
+            file_type: file_type::FileType::detect(
+                &old.first_bytes,
+                old.flags,
+                old.coff_header.is_some(),
+            ),
+            sha2_256_hash: old.sha2_256_hash,
+            md5_hash: old.md5_hash,
+            first_bytes: old.first_bytes,
+            flags: old.flags,
+            entropy: old.entropy,
+            coff_header: old.coff_header,
+            pe: old.pe,
+            signature: old.signature,
+            tlsh: old.tlsh,
+            imphash: None,
+            rich_hash: None,
+            elf: None,
+            macho: None,
+            block_hashes: None,
+        }
+    }
+}
+
+/// Stores information about a file, as in snapshots of version 12.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct FileV3 {
+    /// The SHA2-256 hash of the files contents.
+    pub(crate) sha2_256_hash: Sha256Hash,
+    /// The MD5 hash of the files contents.
+    pub(crate) md5_hash: Md5Hash,
+    /// The first bytes of the file.
+    pub(crate) first_bytes: ArrayVec<u8, FIRST_BYTES_LEN>,
+    /// The flags stored about a file.
+    pub(crate) flags: FileFlags,
+    /// The entropy of the file.
+    pub(crate) entropy: f32,
+    /// The COFF-header of the file, if it exists.
+    pub(crate) coff_header: Option<Vec<u8>>,
+    /// The structure of the file, if it is a PE file.
+    pub(crate) pe: Option<Box<pe::PeInfo>>,
+    /// The Authenticode signature of the file, if it is a signed PE file.
+    pub(crate) signature: Option<Box<authenticode::Signature>>,
+}
+
+impl From<FileV3> for File {
+    fn from(old: FileV3) -> Self {
+        Self {
+            file_type: file_type::FileType::detect(
+                &old.first_bytes,
+                old.flags,
+                old.coff_header.is_some(),
+            ),
+            sha2_256_hash: old.sha2_256_hash,
+            md5_hash: old.md5_hash,
+            first_bytes: old.first_bytes,
+            flags: old.flags,
+            entropy: old.entropy,
+            coff_header: old.coff_header,
+            pe: old.pe,
+            signature: old.signature,
+            tlsh: None,
+            imphash: None,
+            rich_hash: None,
+            elf: None,
+            macho: None,
+            block_hashes: None,
+        }
+    }
+}
+
+/// Stores information about a file, as in snapshots of version 11.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct FileV2 {
+    /// The SHA2-256 hash of the files contents.
+    pub(crate) sha2_256_hash: Sha256Hash,
+    /// The MD5 hash of the files contents.
+    pub(crate) md5_hash: Md5Hash,
+    /// The first bytes of the file.
+    pub(crate) first_bytes: ArrayVec<u8, FIRST_BYTES_LEN>,
+    /// The flags stored about a file.
+    pub(crate) flags: FileFlags,
+    /// The entropy of the file.
+    pub(crate) entropy: f32,
+    /// The COFF-header of the file, if it exists.
+    pub(crate) coff_header: Option<Vec<u8>>,
+    /// The structure of the file, if it is a PE file.
+    pub(crate) pe: Option<Box<pe::PeInfo>>,
+}
+
+impl From<FileV2> for File {
+    fn from(old: FileV2) -> Self {
+        Self {
+            file_type: file_type::FileType::detect(
+                &old.first_bytes,
+                old.flags,
+                old.coff_header.is_some(),
+            ),
+            sha2_256_hash: old.sha2_256_hash,
+            md5_hash: old.md5_hash,
+            first_bytes: old.first_bytes,
+            flags: old.flags,
+            entropy: old.entropy,
+            coff_header: old.coff_header,
+            pe: old.pe,
+            signature: None,
+            tlsh: None,
+            imphash: None,
+            rich_hash: None,
+            elf: None,
+            macho: None,
+            block_hashes: None,
+        }
+    }
+}
+
+/// Stores information about a file, as in snapshots before version 11.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct FileV1 {
+    /// The SHA2-256 hash of the files contents.
+    pub(crate) sha2_256_hash: Sha256Hash,
+    /// The MD5 hash of the files contents.
+    pub(crate) md5_hash: Md5Hash,
+    /// The first bytes of the file.
+    pub(crate) first_bytes: ArrayVec<u8, FIRST_BYTES_LEN>,
+    /// The flags stored about a file.
+    pub(crate) flags: FileFlags,
+    /// The entropy of the file.
+    pub(crate) entropy: f32,
+    /// The COFF-header of the file, if it exists.
+    pub(crate) coff_header: Option<Vec<u8>>,
+}
+
+impl From<FileV1> for File {
+    fn from(old: FileV1) -> Self {
+        Self {
+            file_type: file_type::FileType::detect(
+                &old.first_bytes,
+                old.flags,
+                old.coff_header.is_some(),
+            ),
+            sha2_256_hash: old.sha2_256_hash,
+            md5_hash: old.md5_hash,
+            first_bytes: old.first_bytes,
+            flags: old.flags,
+            entropy: old.entropy,
+            coff_header: old.coff_header,
+            pe: None,
+            signature: None,
+            tlsh: None,
+            imphash: None,
+            rich_hash: None,
+            elf: None,
+            macho: None,
+            block_hashes: None,
+        }
+    }
 }
 
 /// A common trait that all implementations of files should fulfill.
 pub(crate) trait GenericFile: Serialize + DeserializeOwned + Clone + Sized + Send {
     /// Reads the file information from the specified path.
-    fn from_path(path: impl AsRef<Path>) -> io::Result<Self>;
+    fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_reader(fs::File::open(path)?)
+    }
+
+    /// Reads the file information from the contents of the given reader.
+    fn from_reader(reader: impl Read + Seek) -> io::Result<Self>;
 }
 
 impl GenericFile for File {
-    /// Reads the file information from the specified path.
-    fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
-        firestorm::profile_fn!(file_from_path);
+    /// Reads the file information from the contents of the given reader.
+    fn from_reader(mut reader: impl Read + Seek) -> io::Result<Self> {
+        firestorm::profile_fn!(file_from_reader);
         use sha2::Digest as _;
 
-        let path = path.as_ref();
+        // The length is needed in advance to choose the size of the blocks
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.rewind()?;
 
         let mut first_bytes = ArrayVec::new();
+        let mut header = ArrayVec::<u8, { file_type::HEADER_LEN }>::new();
         let mut sha256hasher = sha2::Sha256::new();
         let mut md5hasher = md5::Md5::new();
         let mut byte_occurrences = [0u64; 256];
+        let mut tlsh_hasher = tlsh::TlshHasher::new();
+        let mut block_hasher = blocks::BlockHasher::new(len);
 
         let mut last_utf8_bytes = ArrayVec::<u8, 4>::new();
         let mut last_utf16le_bytes = ArrayVec::<u8, 4>::new();
@@ -61,11 +457,13 @@ impl GenericFile for File {
 
         let mut flags = FileFlags::UTF_ENCODING;
 
-        let file = fs::File::open(path)?;
-        let mut buf_reader = io::BufReader::new(&file);
+        let mut buf_reader = io::BufReader::new(reader);
 
         let mut buf = vec![0; 0x10000];
 
+        // The contents of executables are kept to parse their structure afterwards
+        let mut contents = Some(Vec::new());
+
         while let Ok(n) = buf_reader.read(&mut buf) {
             if n == 0 {
                 break;
@@ -73,10 +471,29 @@ impl GenericFile for File {
 
             sha256hasher.update(&buf[..n]);
             md5hasher.update(&buf[..n]);
+            tlsh_hasher.update(&buf[..n]);
+            if let Some(block_hasher) = &mut block_hasher {
+                block_hasher.update(&buf[..n]);
+            }
 
             for &byte in &buf[..first_bytes.remaining_capacity().min(n)] {
                 first_bytes.try_push(byte).ok();
             }
+            for &byte in &buf[..header.remaining_capacity().min(n)] {
+                header.try_push(byte).ok();
+            }
+
+            if let Some(data) = &mut contents {
+                if EXECUTABLE_MAGICS
+                    .iter()
+                    .any(|magic| first_bytes.starts_with(magic))
+                    && data.len() + n <= MAX_EXECUTABLE_LEN
+                {
+                    data.extend_from_slice(&buf[..n]);
+                } else {
+                    contents = None;
+                }
+            }
 
             if flags.contains(FileFlags::UTF8) {
                 let mut start = 0;
@@ -210,7 +627,18 @@ impl GenericFile for File {
             bytes: md5hasher.finalize().into(),
         };
 
-        let coff_header = extract_coff_header(&file).ok().flatten();
+        let coff_header = extract_coff_header(&mut buf_reader.into_inner())
+            .ok()
+            .flatten();
+
+        let pe_contents = contents.as_deref().filter(|_| coff_header.is_some());
+        let pe = pe_contents.and_then(pe::parse).map(Box::new);
+        let signature = pe_contents.and_then(authenticode::parse).map(Box::new);
+        let imphash = pe.as_ref().and_then(|pe| pe::imphash(&pe.imports));
+        let rich_hash = pe_contents.and_then(pe::rich_header_hash);
+        let elf = contents.as_deref().and_then(elf::parse).map(Box::new);
+        let macho = contents.as_deref().and_then(macho::parse).map(Box::new);
+        let file_type = file_type::FileType::detect(&header, flags, coff_header.is_some());
 
         Ok(Self {
             sha2_256_hash,
@@ -219,6 +647,15 @@ impl GenericFile for File {
             flags,
             entropy: entropy as f32,
             coff_header,
+            pe,
+            signature,
+            tlsh: tlsh_hasher.finish(),
+            imphash,
+            rich_hash,
+            file_type,
+            elf,
+            macho,
+            block_hashes: block_hasher.map(|hasher| Box::new(hasher.finish())),
         })
     }
 }
@@ -270,7 +707,7 @@ impl fmt::Display for Sha256Hash {
 }
 
 /// Represents an MD5 hash.
-#[derive(PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
 pub(crate) struct Md5Hash {
     /// The bytes of the hash.
     pub(crate) bytes: [u8; 16],
@@ -286,10 +723,14 @@ impl fmt::Debug for Md5Hash {
     }
 }
 
-/// Extracts the PE header from the given file.
-fn extract_coff_header(file: &fs::File) -> io::Result<Option<Vec<u8>>> {
-    use std::os::unix::prelude::FileExt as _;
+impl fmt::Display for Md5Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self, f)
+    }
+}
 
+/// Extracts the PE header from the given file.
+fn extract_coff_header(file: &mut (impl Read + Seek)) -> io::Result<Option<Vec<u8>>> {
     /// The offset into a MZ-header where the offset of the PE-header is listed.
     const MZ_PE_OFFSET: u64 = 0x3c;
 
@@ -310,25 +751,30 @@ fn extract_coff_header(file: &fs::File) -> io::Result<Option<Vec<u8>>> {
     let mut u16_buf = [0; mem::size_of::<u16>()];
     let mut u32_buf = [0; mem::size_of::<u32>()];
 
+    let mut read_exact_at = |buf: &mut [u8], offset: u64| {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)
+    };
+
     assert_eq!(u16_buf.len(), MZ_MAGIC.len());
-    file.read_exact_at(&mut u16_buf, 0)?;
+    read_exact_at(&mut u16_buf, 0)?;
     if u16_buf != MZ_MAGIC {
         return Ok(None);
     }
 
-    file.read_exact_at(&mut u32_buf, MZ_PE_OFFSET)?;
+    read_exact_at(&mut u32_buf, MZ_PE_OFFSET)?;
     let pe_offset = u32::from_le_bytes(u32_buf);
 
     assert_eq!(u32_buf.len(), PE_MAGIC.len());
-    file.read_exact_at(&mut u32_buf, pe_offset.into())?;
+    read_exact_at(&mut u32_buf, pe_offset.into())?;
     if u32_buf != PE_MAGIC {
         return Ok(None);
     }
 
     let header_start = u64::from(pe_offset) + PE_MAGIC.len() as u64;
-    file.read_exact_at(&mut u16_buf, header_start + 16)?;
+    read_exact_at(&mut u16_buf, header_start + 16)?;
     let optional_header_len = u16::from_le_bytes(u16_buf).clamp(0, COFF_OPTIONAL_HEADER_MAX_LEN);
     let mut total_headers = vec![0; COFF_HEADER_LEN + usize::from(optional_header_len)];
-    file.read_exact_at(&mut total_headers, header_start)?;
+    read_exact_at(&mut total_headers, header_start)?;
     Ok(Some(total_headers))
 }
@@ -0,0 +1,314 @@
+//! Reads the Authenticode signatures of PE files.
+//!
+//! The signature is a PKCS#7 `SignedData` structure stored in the
+//! [certificate table](https://learn.microsoft.com/en-us/windows/win32/debug/pe-format#the-attribute-certificate-table-image-only)
+//! of the file.
+//! Only the digest of the file embedded in the signature is checked.
+//! Neither the certificate chain nor the cryptographic signature itself are verified, since this
+//! would require trusted root certificates and revocation information.
+
+use std::ops::Range;
+
+use cms::{
+    cert::{
+        x509::{
+            der::{
+                asn1::{AnyRef, GeneralizedTime, ObjectIdentifier, OctetStringRef},
+                Any, Decode as _, Encode as _, Reader as _, SliceReader,
+            },
+            spki::AlgorithmIdentifierOwned,
+            time::Time,
+        },
+        CertificateChoices,
+    },
+    content_info::ContentInfo,
+    signed_data::{SignedData, SignerIdentifier, SignerInfo},
+};
+use serde::{Deserialize, Serialize};
+
+use super::pe::PeFile;
+use crate::timestamp::Timestamp;
+
+/// The type of certificates in the certificate table that contain PKCS#7 `SignedData`.
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 2;
+
+/// The content type of PKCS#7 `SignedData`.
+const SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+
+/// The attribute containing the signing time in PKCS#9.
+const SIGNING_TIME: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.5");
+
+/// The attribute containing a counter signature in PKCS#9, used for legacy timestamps.
+const COUNTER_SIGNATURE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.6");
+
+/// The attribute containing an RFC 3161 timestamp token in Authenticode signatures.
+const TIMESTAMP_TOKEN: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.3.3.1");
+
+/// The digest algorithm SHA-1.
+const SHA1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.14.3.2.26");
+
+/// The digest algorithm SHA2-256.
+const SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+
+/// The digest algorithm SHA2-384.
+const SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.2");
+
+/// The digest algorithm SHA2-512.
+const SHA512: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.3");
+
+/// The digest algorithm MD5.
+const MD5: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.2.5");
+
+/// The Authenticode signature of a PE file.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct Signature {
+    /// The certificate that signed the file, `None` if the signature could not be parsed.
+    pub(crate) signer: Option<Signer>,
+    /// The time the file was signed, if the signature contains a timestamp.
+    pub(crate) signing_time: Option<Timestamp>,
+    /// The name of the algorithm of the digest, `None` if it is unknown.
+    pub(crate) digest_algorithm: Option<String>,
+    /// Whether the digest in the signature matches the contents of the file.
+    pub(crate) digest_matches: bool,
+}
+
+impl Signature {
+    /// The signature of a file whose certificate table could not be parsed.
+    const MALFORMED: Signature = Signature {
+        signer: None,
+        signing_time: None,
+        digest_algorithm: None,
+        digest_matches: false,
+    };
+
+    /// Returns `true` if the signature could be parsed and its digest matches the file.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.signer.is_some() && self.digest_matches
+    }
+}
+
+/// The certificate that signed a file.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct Signer {
+    /// The subject of the certificate, if the certificate is contained in the signature.
+    pub(crate) subject: Option<String>,
+    /// The issuer of the certificate.
+    pub(crate) issuer: String,
+    /// The serial number of the certificate in hexadecimal.
+    pub(crate) serial: String,
+}
+
+/// Reads the Authenticode signature of the PE file with the given contents.
+///
+/// Returns `None` if the file is not a PE file or if it is unsigned.
+pub(crate) fn parse(data: &[u8]) -> Option<Signature> {
+    firestorm::profile_fn!(parse_authenticode);
+
+    let file = PeFile::parse(data)?;
+    let (offset, size) = file.certificate_table()?;
+
+    let table = usize::try_from(offset)
+        .ok()
+        .and_then(|offset| data.get(offset..))
+        .unwrap_or_default();
+    let table = &table[..table.len().min(size as usize)];
+
+    Some(read_signature(&file, table).unwrap_or(Signature::MALFORMED))
+}
+
+/// Reads the first PKCS#7 signature in the given certificate table.
+fn read_signature(file: &PeFile, table: &[u8]) -> Option<Signature> {
+    let mut certificate = table;
+    let content = loop {
+        // Each entry consists of its length, revision and type followed by its data
+        let len = u32::from_le_bytes(certificate.get(..4)?.try_into().ok()?) as usize;
+        let ty = u16::from_le_bytes(certificate.get(6..8)?.try_into().ok()?);
+        let data = certificate.get(8..len)?;
+
+        if ty == WIN_CERT_TYPE_PKCS_SIGNED_DATA {
+            break data;
+        }
+
+        // Entries are aligned to 8 bytes
+        certificate = certificate.get(len.checked_add(7)? & !7..)?;
+    };
+
+    // The data may be followed by padding, so it is not required to end with the structure
+    let content_info = ContentInfo::decode(&mut SliceReader::new(content).ok()?).ok()?;
+    if content_info.content_type != SIGNED_DATA {
+        return None;
+    }
+    let signed_data = content_info.content.decode_as::<SignedData>().ok()?;
+    let signer_info = signed_data.signer_infos.0.iter().next()?;
+
+    let (digest_algorithm, digest) = signed_data
+        .encap_content_info
+        .econtent
+        .as_ref()?
+        .sequence(|reader| {
+            // The `SpcIndirectDataContent` consists of some data and the digest of the file
+            AnyRef::decode(reader)?;
+            reader.sequence(|reader| {
+                let algorithm = AlgorithmIdentifierOwned::decode(reader)?;
+                let digest = OctetStringRef::decode(reader)?;
+
+                Ok((algorithm.oid, digest.as_bytes().to_vec()))
+            })
+        })
+        .ok()?;
+
+    let computed_digest = file
+        .authenticode_ranges()
+        .and_then(|ranges| compute_digest(digest_algorithm, file.data, ranges));
+
+    Some(Signature {
+        signer: Some(signer(&signed_data, signer_info)?),
+        signing_time: signing_time(signer_info),
+        digest_algorithm: digest_name(digest_algorithm).map(str::to_string),
+        digest_matches: computed_digest.is_some_and(|computed| computed == digest),
+    })
+}
+
+/// Returns the certificate that created the given signer info in the signed data.
+fn signer(signed_data: &SignedData, signer_info: &SignerInfo) -> Option<Signer> {
+    let SignerIdentifier::IssuerAndSerialNumber(id) = &signer_info.sid else {
+        return None;
+    };
+
+    let subject = signed_data
+        .certificates
+        .iter()
+        .flat_map(|certificates| certificates.0.iter())
+        .find_map(|certificate| match certificate {
+            CertificateChoices::Certificate(certificate)
+                if certificate.tbs_certificate.issuer == id.issuer
+                    && certificate.tbs_certificate.serial_number == id.serial_number =>
+            {
+                Some(certificate.tbs_certificate.subject.to_string())
+            }
+            _ => None,
+        });
+
+    Some(Signer {
+        subject,
+        issuer: id.issuer.to_string(),
+        serial: hex::encode(id.serial_number.as_bytes()),
+    })
+}
+
+/// Returns the time the signer info was created.
+///
+/// Timestamps of a timestamping authority are preferred over the time claimed by the signer.
+fn signing_time(signer_info: &SignerInfo) -> Option<Timestamp> {
+    let unsigned_attrs = signer_info
+        .unsigned_attrs
+        .iter()
+        .flat_map(|attrs| attrs.iter());
+
+    for attr in unsigned_attrs {
+        let Some(value) = attr.values.iter().next() else {
+            continue;
+        };
+
+        let time = match attr.oid {
+            COUNTER_SIGNATURE => value
+                .decode_as::<SignerInfo>()
+                .ok()
+                .and_then(|counter_signature| claimed_signing_time(&counter_signature)),
+            TIMESTAMP_TOKEN => timestamp_token_time(value),
+            _ => None,
+        };
+
+        if time.is_some() {
+            return time;
+        }
+    }
+
+    claimed_signing_time(signer_info)
+}
+
+/// Returns the signing time attribute of the given signer info.
+fn claimed_signing_time(signer_info: &SignerInfo) -> Option<Timestamp> {
+    let value = signer_info
+        .signed_attrs
+        .iter()
+        .flat_map(|attrs| attrs.iter())
+        .find(|attr| attr.oid == SIGNING_TIME)?
+        .values
+        .iter()
+        .next()?;
+    let time = Time::from_der(&value.to_der().ok()?).ok()?;
+
+    Some((std::time::UNIX_EPOCH + time.to_unix_duration()).into())
+}
+
+/// Returns the time of the RFC 3161 timestamp token in the given attribute value.
+fn timestamp_token_time(value: &Any) -> Option<Timestamp> {
+    let signed_data = value
+        .decode_as::<ContentInfo>()
+        .ok()?
+        .content
+        .decode_as::<SignedData>()
+        .ok()?;
+    let tst_info = signed_data.encap_content_info.econtent?;
+
+    // The `TSTInfo` is stored in an octet string
+    let time = AnyRef::from_der(tst_info.value())
+        .ok()?
+        .sequence(|reader| {
+            // Skip the version, policy, message imprint and serial number
+            for _ in 0..4 {
+                AnyRef::decode(reader)?;
+            }
+            let time = GeneralizedTime::decode(reader)?;
+
+            // Skip the optional fields after the time
+            while !reader.is_finished() {
+                AnyRef::decode(reader)?;
+            }
+
+            Ok(time)
+        })
+        .ok()?;
+
+    Some((std::time::UNIX_EPOCH + time.to_unix_duration()).into())
+}
+
+/// Returns the name of the given digest algorithm, if it is known.
+fn digest_name(algorithm: ObjectIdentifier) -> Option<&'static str> {
+    Some(match algorithm {
+        SHA1 => "SHA-1",
+        SHA256 => "SHA2-256",
+        SHA384 => "SHA2-384",
+        SHA512 => "SHA2-512",
+        MD5 => "MD5",
+        _ => return None,
+    })
+}
+
+/// Computes the digest of the given ranges of `data` with the given algorithm.
+///
+/// Returns `None` if the algorithm is not supported.
+fn compute_digest(
+    algorithm: ObjectIdentifier,
+    data: &[u8],
+    ranges: [Range<usize>; 4],
+) -> Option<Vec<u8>> {
+    fn digest<D: sha2::Digest>(data: &[u8], ranges: [Range<usize>; 4]) -> Vec<u8> {
+        let mut hasher = D::new();
+        for range in ranges {
+            hasher.update(&data[range]);
+        }
+
+        hasher.finalize().to_vec()
+    }
+
+    Some(match algorithm {
+        SHA1 => digest::<sha1::Sha1>(data, ranges),
+        SHA256 => digest::<sha2::Sha256>(data, ranges),
+        SHA384 => digest::<sha2::Sha384>(data, ranges),
+        SHA512 => digest::<sha2::Sha512>(data, ranges),
+        MD5 => digest::<md5::Md5>(data, ranges),
+        _ => return None,
+    })
+}
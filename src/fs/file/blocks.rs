@@ -0,0 +1,128 @@
+//! Hashes fixed-size blocks of large files to find the parts that changed between snapshots.
+//!
+//! The hashes of whole files only tell that a file changed, which is of little use for large
+//! files such as databases, virtual disks or registry hives, where usually only a small part
+//! changes.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// The minimum size of a file for which block hashes are recorded.
+const MIN_FILE_LEN: u64 = 0x100_0000;
+
+/// The minimum size of a block.
+const MIN_BLOCK_SIZE: u64 = 0x10_0000;
+
+/// The maximum number of blocks of a file, larger files use larger blocks.
+const MAX_BLOCKS: u64 = 0x1000;
+
+/// The hashes of the blocks of a file.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct BlockHashes {
+    /// The size of each block except for the last one, which may be smaller.
+    block_size: u64,
+    /// The length of the file.
+    len: u64,
+    /// The CRC32 checksums of the blocks.
+    hashes: Vec<u32>,
+}
+
+impl BlockHashes {
+    /// Returns the byte ranges that differ between the two files.
+    ///
+    /// Adjacent changed blocks are merged into one range and bytes that only exist in one of the
+    /// files are considered changed.
+    /// Returns `None` if the blocks of the files have different sizes and cannot be compared.
+    pub(crate) fn changed_ranges(&self, other: &BlockHashes) -> Option<Vec<Range<u64>>> {
+        if self.block_size != other.block_size {
+            return None;
+        }
+
+        let len = self.len.max(other.len);
+        let mut ranges = Vec::<Range<u64>>::new();
+        for i in 0..self.hashes.len().max(other.hashes.len()) {
+            if self.hashes.get(i) == other.hashes.get(i) {
+                continue;
+            }
+
+            let start = i as u64 * self.block_size;
+            let end = (start + self.block_size).min(len);
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+
+        Some(ranges)
+    }
+
+    /// Returns the length of the larger of the two files.
+    pub(crate) fn max_len(&self, other: &BlockHashes) -> u64 {
+        self.len.max(other.len)
+    }
+}
+
+/// Computes the block hashes of data that is passed in in pieces.
+#[derive(Debug, Clone)]
+pub(crate) struct BlockHasher {
+    /// The size of each block.
+    block_size: u64,
+    /// The number of bytes of the current block that were processed so far.
+    block_len: u64,
+    /// The hasher of the current block.
+    hasher: crc32fast::Hasher,
+    /// The hashes of the completed blocks.
+    hashes: Vec<u32>,
+}
+
+impl BlockHasher {
+    /// Creates a hasher for a file of length `len`.
+    ///
+    /// Returns `None` if the file is too small for block hashes to be worthwhile.
+    pub(crate) fn new(len: u64) -> Option<Self> {
+        if len < MIN_FILE_LEN {
+            return None;
+        }
+
+        Some(Self {
+            block_size: len
+                .div_ceil(MAX_BLOCKS)
+                .next_power_of_two()
+                .max(MIN_BLOCK_SIZE),
+            block_len: 0,
+            hasher: crc32fast::Hasher::new(),
+            hashes: Vec::new(),
+        })
+    }
+
+    /// Processes the next piece of the data.
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let remaining = (self.block_size - self.block_len).min(data.len() as u64) as usize;
+            self.hasher.update(&data[..remaining]);
+            self.block_len += remaining as u64;
+            data = &data[remaining..];
+
+            if self.block_len == self.block_size {
+                let hasher = std::mem::take(&mut self.hasher);
+                self.hashes.push(hasher.finalize());
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Returns the hashes of all processed blocks.
+    pub(crate) fn finish(mut self) -> BlockHashes {
+        let len = self.hashes.len() as u64 * self.block_size + self.block_len;
+        if self.block_len != 0 {
+            self.hashes.push(self.hasher.finalize());
+        }
+
+        BlockHashes {
+            block_size: self.block_size,
+            len,
+            hashes: self.hashes,
+        }
+    }
+}
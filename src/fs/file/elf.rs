@@ -0,0 +1,372 @@
+//! Parses the structure of ELF files.
+//!
+//! The layouts are described in the
+//! [System V ABI](https://refspecs.linuxfoundation.org/elf/gabi4+/contents.html).
+//! Like for PE files, everything except for the headers is parsed on a best effort basis.
+
+use serde::{Deserialize, Serialize};
+
+use super::pe::c_string;
+
+/// The magic bytes of an ELF file.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// The program header type of loadable segments.
+const PT_LOAD: u32 = 1;
+
+/// The program header type of the dynamic linking information.
+const PT_DYNAMIC: u32 = 2;
+
+/// The program header type of the path of the interpreter.
+const PT_INTERP: u32 = 3;
+
+/// The program header type of notes.
+const PT_NOTE: u32 = 4;
+
+/// The section type of the symbol table.
+const SHT_SYMTAB: u32 = 2;
+
+/// The dynamic entry of a needed library.
+const DT_NEEDED: u64 = 1;
+
+/// The dynamic entry of the address of the string table.
+const DT_STRTAB: u64 = 5;
+
+/// The dynamic entry of the name of a shared object.
+const DT_SONAME: u64 = 14;
+
+/// The note type of the GNU build ID.
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// The maximum number of entries read from any table, to limit the effect of corrupted files.
+const MAX_TABLE_ENTRIES: usize = 0x10000;
+
+/// The structure of an ELF file.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct ElfInfo {
+    /// Whether the file is a 64-bit file.
+    pub(crate) is_64_bit: bool,
+    /// Whether the file is stored in big endian byte order.
+    pub(crate) is_big_endian: bool,
+    /// The machine type the file was compiled for.
+    pub(crate) machine: u16,
+    /// The type of the file, such as executable or shared object.
+    pub(crate) elf_type: u16,
+    /// The path of the program interpreter (dynamic linker), if the file has one.
+    pub(crate) interpreter: Option<String>,
+    /// The name of the file as a shared object, if it has one.
+    pub(crate) soname: Option<String>,
+    /// The libraries needed by the file.
+    pub(crate) needed: Vec<String>,
+    /// The GNU build ID in hexadecimal, if the file has one.
+    pub(crate) build_id: Option<String>,
+    /// Whether the symbol table was removed from the file.
+    pub(crate) stripped: bool,
+}
+
+impl ElfInfo {
+    /// Returns a human readable name of the machine type.
+    pub(crate) fn machine_name(&self) -> &'static str {
+        match self.machine {
+            2 => "SPARC",
+            3 => "x86",
+            8 => "MIPS",
+            20 => "PowerPC",
+            21 => "PowerPC64",
+            22 => "S/390",
+            40 => "ARM",
+            43 => "SPARCv9",
+            62 => "x86-64",
+            183 => "AArch64",
+            243 => "RISC-V",
+            258 => "LoongArch",
+            _ => "unknown",
+        }
+    }
+
+    /// Returns a human readable name of the type of the file.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self.elf_type {
+            1 => "relocatable",
+            2 => "executable",
+            // Position independent executables are shared objects with an interpreter
+            3 if self.interpreter.is_some() => "position independent executable",
+            3 => "shared object",
+            4 => "core dump",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Reads values from an ELF file in its byte order and class.
+struct Reader<'data> {
+    /// The contents of the file.
+    data: &'data [u8],
+    /// Whether the file is a 64-bit file.
+    is_64_bit: bool,
+    /// Whether the file is stored in big endian byte order.
+    is_big_endian: bool,
+}
+
+impl<'data> Reader<'data> {
+    /// Reads the bytes at the given offset.
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.data
+            .get(offset..offset.checked_add(N)?)?
+            .try_into()
+            .ok()
+    }
+
+    /// Reads a `u16` at the given offset.
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.bytes(offset)?;
+
+        Some(if self.is_big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    /// Reads a `u32` at the given offset.
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.bytes(offset)?;
+
+        Some(if self.is_big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// Reads a word of the class of the file at the given offset.
+    fn word(&self, offset: usize) -> Option<u64> {
+        if !self.is_64_bit {
+            return self.u32(offset).map(u64::from);
+        }
+
+        let bytes = self.bytes(offset)?;
+
+        Some(if self.is_big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    /// Returns the size of a word of the class of the file.
+    fn word_size(&self) -> usize {
+        if self.is_64_bit {
+            8
+        } else {
+            4
+        }
+    }
+}
+
+/// A program header of an ELF file.
+#[derive(Debug, Clone, Copy)]
+struct ProgramHeader {
+    /// The type of the segment.
+    ty: u32,
+    /// The offset of the segment in the file.
+    offset: u64,
+    /// The address of the segment in memory.
+    address: u64,
+    /// The size of the segment in the file.
+    size: u64,
+}
+
+impl ProgramHeader {
+    /// Returns the data of the segment.
+    fn data<'data>(&self, data: &'data [u8]) -> Option<&'data [u8]> {
+        let start = usize::try_from(self.offset).ok()?;
+        let end = start.checked_add(usize::try_from(self.size).ok()?)?;
+
+        data.get(start..end)
+    }
+}
+
+/// Reads the program headers of the file.
+fn program_headers(reader: &Reader) -> Option<Vec<ProgramHeader>> {
+    let (offset, entry_size, count) = if reader.is_64_bit {
+        (reader.word(32)?, reader.u16(54)?, reader.u16(56)?)
+    } else {
+        (reader.word(28)?, reader.u16(42)?, reader.u16(44)?)
+    };
+    let offset = usize::try_from(offset).ok()?;
+
+    (0..usize::from(count))
+        .map(|i| {
+            let header = offset + i * usize::from(entry_size);
+
+            Some(if reader.is_64_bit {
+                ProgramHeader {
+                    ty: reader.u32(header)?,
+                    offset: reader.word(header + 8)?,
+                    address: reader.word(header + 16)?,
+                    size: reader.word(header + 32)?,
+                }
+            } else {
+                ProgramHeader {
+                    ty: reader.u32(header)?,
+                    offset: reader.word(header + 4)?,
+                    address: reader.word(header + 8)?,
+                    size: reader.word(header + 16)?,
+                }
+            })
+        })
+        .collect()
+}
+
+/// Returns the offset in the file of the given address in memory.
+fn address_to_offset(headers: &[ProgramHeader], address: u64) -> Option<usize> {
+    headers
+        .iter()
+        .filter(|header| header.ty == PT_LOAD)
+        .find(|header| header.address <= address && address - header.address < header.size)
+        .and_then(|header| usize::try_from(address - header.address + header.offset).ok())
+}
+
+/// Reads the needed libraries and the name of the shared object from the dynamic segment.
+fn dynamic_entries(
+    reader: &Reader,
+    headers: &[ProgramHeader],
+) -> Option<(Vec<String>, Option<String>)> {
+    let dynamic = headers
+        .iter()
+        .find(|header| header.ty == PT_DYNAMIC)?
+        .data(reader.data)?;
+    let entries = Reader {
+        data: dynamic,
+        ..*reader
+    };
+
+    let mut string_table = None;
+    let mut needed = Vec::new();
+    let mut soname = None;
+    for i in 0..MAX_TABLE_ENTRIES {
+        let entry = i * 2 * reader.word_size();
+        let Some(tag) = entries.word(entry) else {
+            break;
+        };
+        let value = entries.word(entry + reader.word_size())?;
+
+        match tag {
+            0 => break,
+            DT_STRTAB => string_table = Some(value),
+            DT_NEEDED => needed.push(value),
+            DT_SONAME => soname = Some(value),
+            _ => (),
+        }
+    }
+
+    let strings = reader
+        .data
+        .get(address_to_offset(headers, string_table?)?..)?;
+    let string = |offset: u64| c_string(strings, usize::try_from(offset).ok()?);
+
+    Some((
+        needed.into_iter().filter_map(string).collect(),
+        soname.and_then(string),
+    ))
+}
+
+/// Reads the GNU build ID from the notes of the file.
+fn build_id(reader: &Reader, headers: &[ProgramHeader]) -> Option<String> {
+    /// Rounds the length up to the alignment of notes.
+    fn align(len: usize) -> Option<usize> {
+        Some(len.checked_add(3)? & !3)
+    }
+
+    for header in headers.iter().filter(|header| header.ty == PT_NOTE) {
+        let notes = Reader {
+            data: header.data(reader.data)?,
+            ..*reader
+        };
+
+        let mut offset = 0;
+        while let (Some(name_size), Some(desc_size), Some(ty)) = (
+            notes.u32(offset),
+            notes.u32(offset + 4),
+            notes.u32(offset + 8),
+        ) {
+            let name = offset + 12;
+            let desc = name + align(name_size as usize)?;
+            let end = desc + align(desc_size as usize)?;
+
+            if ty == NT_GNU_BUILD_ID && notes.data.get(name..name + name_size as usize)? == b"GNU\0"
+            {
+                return Some(hex::encode(
+                    notes.data.get(desc..desc + desc_size as usize)?,
+                ));
+            }
+
+            offset = end;
+        }
+    }
+
+    None
+}
+
+/// Returns `true` if the file does not contain a symbol table.
+fn is_stripped(reader: &Reader) -> Option<bool> {
+    let (offset, entry_size, count) = if reader.is_64_bit {
+        (reader.word(40)?, reader.u16(58)?, reader.u16(60)?)
+    } else {
+        (reader.word(32)?, reader.u16(46)?, reader.u16(48)?)
+    };
+    let offset = usize::try_from(offset).ok()?;
+
+    for i in 0..usize::from(count) {
+        if reader.u32(offset + i * usize::from(entry_size) + 4)? == SHT_SYMTAB {
+            return Some(false);
+        }
+    }
+
+    Some(true)
+}
+
+/// Parses the structure of the ELF file with the given contents.
+///
+/// Returns `None` if the file is not an ELF file.
+pub(crate) fn parse(data: &[u8]) -> Option<ElfInfo> {
+    firestorm::profile_fn!(parse_elf);
+
+    if !data.starts_with(ELF_MAGIC) {
+        return None;
+    }
+
+    let reader = Reader {
+        data,
+        is_64_bit: match data.get(4)? {
+            1 => false,
+            2 => true,
+            _ => return None,
+        },
+        is_big_endian: match data.get(5)? {
+            1 => false,
+            2 => true,
+            _ => return None,
+        },
+    };
+
+    let headers = program_headers(&reader).unwrap_or_default();
+    let interpreter = headers
+        .iter()
+        .find(|header| header.ty == PT_INTERP)
+        .and_then(|header| c_string(header.data(data)?, 0));
+    let (needed, soname) = dynamic_entries(&reader, &headers).unwrap_or_default();
+
+    Some(ElfInfo {
+        is_64_bit: reader.is_64_bit,
+        is_big_endian: reader.is_big_endian,
+        machine: reader.u16(18)?,
+        elf_type: reader.u16(16)?,
+        interpreter,
+        soname,
+        needed,
+        build_id: build_id(&reader, &headers),
+        stripped: is_stripped(&reader).unwrap_or(true),
+    })
+}
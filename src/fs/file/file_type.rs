@@ -0,0 +1,360 @@
+//! Identifies the type of files by the signatures at their start.
+
+use std::{ffi::OsStr, fmt, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::FileFlags;
+
+/// The number of bytes at the start of a file that are used to identify its type.
+pub(crate) const HEADER_LEN: usize = 512;
+
+/// The type of the contents of a file.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum FileType {
+    /// A Windows executable or DLL.
+    Pe,
+    /// A DOS executable without a PE-header.
+    Dos,
+    /// A Linux or other UNIX executable or shared object.
+    Elf,
+    /// A macOS executable or library.
+    MachO,
+    /// A compiled Java class.
+    JavaClass,
+    /// A ZIP archive (including JAR, APK and similar formats).
+    Zip,
+    /// An Office Open XML document, such as a `.docx` file.
+    Ooxml,
+    /// A compound file, such as an old Office document or an MSI installer.
+    Ole,
+    /// A PDF document.
+    Pdf,
+    /// An RTF document.
+    Rtf,
+    /// A Windows shortcut.
+    Lnk,
+    /// A script starting with an interpreter line.
+    Script,
+    /// An HTML document.
+    Html,
+    /// An XML document.
+    Xml,
+    /// Any other text.
+    Text,
+    /// A PNG image.
+    Png,
+    /// A JPEG image.
+    Jpeg,
+    /// A GIF image.
+    Gif,
+    /// A bitmap image.
+    Bmp,
+    /// An icon or cursor.
+    Ico,
+    /// A TIFF image.
+    Tiff,
+    /// A WebP image.
+    WebP,
+    /// A gzip compressed file.
+    Gzip,
+    /// A bzip2 compressed file.
+    Bzip2,
+    /// An XZ compressed file.
+    Xz,
+    /// A Zstandard compressed file.
+    Zstd,
+    /// A 7-Zip archive.
+    SevenZip,
+    /// A RAR archive.
+    Rar,
+    /// A cabinet archive.
+    Cab,
+    /// A tar archive.
+    Tar,
+    /// An SQLite database.
+    Sqlite,
+    /// A Windows registry hive.
+    RegistryHive,
+    /// A Windows event log.
+    Evtx,
+}
+
+/// The names and usual extensions of all file types.
+///
+/// Extensions that are shared by many types (such as `.dat`) are not listed, so that they are never
+/// reported as mismatching.
+const TYPES: &[(FileType, &str, &[&str])] = &[
+    (
+        FileType::Pe,
+        "pe",
+        &[
+            "exe", "dll", "sys", "ocx", "scr", "cpl", "drv", "efi", "mui", "ax", "acm", "pyd",
+            "node", "winmd", "com",
+        ],
+    ),
+    (FileType::Dos, "dos", &["exe", "com"]),
+    (FileType::Elf, "elf", &["so", "ko", "o", "elf"]),
+    (FileType::MachO, "mach-o", &["dylib", "bundle"]),
+    (FileType::JavaClass, "java-class", &["class"]),
+    (
+        FileType::Zip,
+        "zip",
+        &[
+            "zip", "jar", "war", "apk", "xpi", "crx", "nupkg", "whl", "vsix", "appx", "msix",
+            "odt", "ods", "odp", "epub", "docx", "xlsx", "pptx", "docm", "xlsm", "pptm", "kmz",
+        ],
+    ),
+    (
+        FileType::Ooxml,
+        "ooxml",
+        &[
+            "docx", "xlsx", "pptx", "docm", "xlsm", "pptm", "dotx", "dotm", "xltx", "xltm", "potx",
+            "potm", "ppsx", "ppsm", "vsdx", "appx", "msix", "nupkg",
+        ],
+    ),
+    (
+        FileType::Ole,
+        "ole",
+        &[
+            "doc", "xls", "ppt", "dot", "xlt", "pot", "pps", "msi", "msp", "msm", "msg", "vsd",
+        ],
+    ),
+    (FileType::Pdf, "pdf", &["pdf"]),
+    (FileType::Rtf, "rtf", &["rtf", "doc"]),
+    (FileType::Lnk, "lnk", &["lnk"]),
+    (
+        FileType::Script,
+        "script",
+        &["sh", "bash", "zsh", "py", "pl", "rb", "php", "js"],
+    ),
+    (
+        FileType::Html,
+        "html",
+        &["html", "htm", "hta", "xhtml", "mht"],
+    ),
+    (
+        FileType::Xml,
+        "xml",
+        &[
+            "xml", "xsd", "xsl", "xslt", "manifest", "config", "svg", "plist", "xaml",
+        ],
+    ),
+    (
+        FileType::Text,
+        "text",
+        &[
+            "txt", "log", "csv", "ini", "inf", "cfg", "conf", "json", "md", "yml", "yaml", "toml",
+            "ps1", "psm1", "psd1", "bat", "cmd", "vbs", "js", "reg", "c", "h", "cpp", "rs", "py",
+        ],
+    ),
+    (FileType::Png, "png", &["png"]),
+    (FileType::Jpeg, "jpeg", &["jpg", "jpeg", "jpe", "jfif"]),
+    (FileType::Gif, "gif", &["gif"]),
+    (FileType::Bmp, "bmp", &["bmp", "dib"]),
+    (FileType::Ico, "ico", &["ico", "cur"]),
+    (FileType::Tiff, "tiff", &["tif", "tiff"]),
+    (FileType::WebP, "webp", &["webp"]),
+    (FileType::Gzip, "gzip", &["gz", "tgz"]),
+    (FileType::Bzip2, "bzip2", &["bz2", "tbz2"]),
+    (FileType::Xz, "xz", &["xz", "txz"]),
+    (FileType::Zstd, "zstd", &["zst"]),
+    (FileType::SevenZip, "7z", &["7z"]),
+    (FileType::Rar, "rar", &["rar"]),
+    (FileType::Cab, "cab", &["cab"]),
+    (FileType::Tar, "tar", &["tar"]),
+    (FileType::Sqlite, "sqlite", &["sqlite", "sqlite3"]),
+    (FileType::RegistryHive, "registry-hive", &[]),
+    (FileType::Evtx, "evtx", &["evtx"]),
+];
+
+impl FileType {
+    /// Returns the name and the usual extensions of the file type.
+    fn info(self) -> (&'static str, &'static [&'static str]) {
+        TYPES
+            .iter()
+            .find(|(ty, _, _)| *ty == self)
+            .map(|&(_, name, extensions)| (name, extensions))
+            .expect("every file type is listed in `TYPES`")
+    }
+
+    /// Returns the name of the file type.
+    pub(crate) fn name(self) -> &'static str {
+        self.info().0
+    }
+
+    /// Returns `true` if the type stores text.
+    fn is_text(self) -> bool {
+        matches!(
+            self,
+            FileType::Script | FileType::Html | FileType::Xml | FileType::Text | FileType::Rtf
+        )
+    }
+
+    /// Returns `true` if the extension of the given file name contradicts the file type.
+    ///
+    /// Extensions that are not usual for any file type never contradict it.
+    pub(crate) fn contradicts_name(self, name: &OsStr) -> bool {
+        let Some(extension) = Path::new(name).extension().and_then(OsStr::to_str) else {
+            return false;
+        };
+        let extension = extension.to_lowercase();
+        let mut claimed_by = TYPES
+            .iter()
+            .filter(|(_, _, extensions)| extensions.contains(&extension.as_str()))
+            .map(|&(ty, _, _)| ty)
+            .peekable();
+
+        if claimed_by.peek().is_none() {
+            return false;
+        }
+
+        // Text can always be interpreted as one of the other text formats
+        !claimed_by.any(|ty| ty == self || (ty.is_text() && self.is_text()))
+    }
+
+    /// Identifies the type of a file from its first bytes.
+    ///
+    /// `header` should contain the first `HEADER_LEN` bytes of the file.
+    pub(crate) fn detect(header: &[u8], flags: FileFlags, has_coff_header: bool) -> Option<Self> {
+        /// The signatures of file types at the start of files.
+        const MAGIC: &[(&[u8], FileType)] = &[
+            (b"\x7fELF", FileType::Elf),
+            (b"\xfe\xed\xfa\xce", FileType::MachO),
+            (b"\xfe\xed\xfa\xcf", FileType::MachO),
+            (b"\xce\xfa\xed\xfe", FileType::MachO),
+            (b"\xcf\xfa\xed\xfe", FileType::MachO),
+            (b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", FileType::Ole),
+            (b"%PDF-", FileType::Pdf),
+            (b"{\\rtf", FileType::Rtf),
+            (b"L\0\0\0\x01\x14\x02\0", FileType::Lnk),
+            (b"\x89PNG\r\n\x1a\n", FileType::Png),
+            (b"\xff\xd8\xff", FileType::Jpeg),
+            (b"GIF87a", FileType::Gif),
+            (b"GIF89a", FileType::Gif),
+            (b"II*\0", FileType::Tiff),
+            (b"MM\0*", FileType::Tiff),
+            (b"\x1f\x8b", FileType::Gzip),
+            (b"BZh", FileType::Bzip2),
+            (b"\xfd7zXZ\0", FileType::Xz),
+            (b"\x28\xb5\x2f\xfd", FileType::Zstd),
+            (b"7z\xbc\xaf\x27\x1c", FileType::SevenZip),
+            (b"Rar!\x1a\x07", FileType::Rar),
+            (b"MSCF\0\0\0\0", FileType::Cab),
+            (b"SQLite format 3\0", FileType::Sqlite),
+            (b"regf", FileType::RegistryHive),
+            (b"ElfFile\0", FileType::Evtx),
+            (b"#!", FileType::Script),
+        ];
+
+        if header.is_empty() {
+            return None;
+        }
+
+        if header.starts_with(b"MZ") {
+            return Some(if has_coff_header {
+                FileType::Pe
+            } else {
+                FileType::Dos
+            });
+        }
+
+        if let Some(&(_, ty)) = MAGIC.iter().find(|(magic, _)| header.starts_with(magic)) {
+            return Some(ty);
+        }
+
+        if header.starts_with(b"\xca\xfe\xba\xbe") {
+            // Universal Mach-O files and Java classes share their magic, but the number of
+            // architectures is much smaller than the version of Java classes
+            let value = u32::from_be_bytes(header.get(4..8)?.try_into().ok()?);
+            return Some(if value < 30 {
+                FileType::MachO
+            } else {
+                FileType::JavaClass
+            });
+        }
+
+        if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            // The first entry of Office Open XML documents is the list of content types
+            return Some(
+                if header
+                    .get(30..)
+                    .is_some_and(|name| name.starts_with(b"[Content_Types].xml"))
+                {
+                    FileType::Ooxml
+                } else {
+                    FileType::Zip
+                },
+            );
+        }
+
+        if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+            return Some(FileType::WebP);
+        }
+
+        // These signatures are short, so some additional reserved fields are checked
+        if header.starts_with(b"BM") && header.get(6..10) == Some(&[0; 4]) {
+            return Some(FileType::Bmp);
+        }
+        if (header.starts_with(b"\0\0\x01\0") || header.starts_with(b"\0\0\x02\0"))
+            && header.get(4..6).is_some_and(|count| count != [0, 0])
+            && header.get(9) == Some(&0)
+        {
+            return Some(FileType::Ico);
+        }
+
+        if header.get(257..262) == Some(b"ustar") {
+            return Some(FileType::Tar);
+        }
+
+        let is_utf16 = (header.starts_with(b"\xff\xfe") || header.starts_with(b"\xfe\xff"))
+            && flags.intersects(FileFlags::UTF16LE | FileFlags::UTF16BE);
+        if !flags.contains(FileFlags::UTF8) && !is_utf16 {
+            return None;
+        }
+
+        let text = header.strip_prefix(b"\xef\xbb\xbf").unwrap_or(header);
+        let start = text
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())
+            .map(|start| text[start..].to_ascii_lowercase())
+            .unwrap_or_default();
+
+        Some(if text.starts_with(b"#!") {
+            FileType::Script
+        } else if start.starts_with(b"<!doctype html") || start.starts_with(b"<html") {
+            FileType::Html
+        } else if start.starts_with(b"<?xml") {
+            FileType::Xml
+        } else {
+            FileType::Text
+        })
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for FileType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TYPES
+            .iter()
+            .find(|(_, name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(ty, _, _)| ty)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown file type `{s}`, expected one of {}",
+                    TYPES
+                        .iter()
+                        .map(|(_, name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
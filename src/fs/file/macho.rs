@@ -0,0 +1,197 @@
+//! Parses the structure of Mach-O files.
+//!
+//! The layouts are described in Apple's `mach-o/loader.h` and `mach-o/fat.h` headers.
+//! Only the basic information that is useful to recognize a binary is extracted.
+
+use serde::{Deserialize, Serialize};
+
+use super::pe::c_string;
+
+/// The magic number of a 32-bit Mach-O file.
+const MH_MAGIC: u32 = 0xfeed_face;
+
+/// The magic number of a 64-bit Mach-O file.
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+
+/// The magic number of a universal binary containing Mach-O files for multiple architectures.
+const FAT_MAGIC: u32 = 0xcafe_babe;
+
+/// The maximum number of architectures in a universal binary.
+///
+/// Java class files share the magic number of universal binaries, but store their version where
+/// universal binaries store the number of architectures, which is at least 45.
+const MAX_FAT_ARCHS: u32 = 30;
+
+/// The load command of a needed library.
+const LC_LOAD_DYLIB: u32 = 0xc;
+
+/// The load command of a library that may be missing at runtime.
+const LC_LOAD_WEAK_DYLIB: u32 = 0x8000_0018;
+
+/// The load command of a library whose symbols are exported again.
+const LC_REEXPORT_DYLIB: u32 = 0x8000_001f;
+
+/// The load command of a library that is loaded on first use.
+const LC_LAZY_LOAD_DYLIB: u32 = 0x20;
+
+/// The load command of a library that depends on the file itself.
+const LC_LOAD_UPWARD_DYLIB: u32 = 0x8000_0023;
+
+/// The load command of the UUID of the file.
+const LC_UUID: u32 = 0x1b;
+
+/// The load command of the code signature of the file.
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+
+/// The maximum number of load commands that are read, to limit the effect of corrupted files.
+const MAX_LOAD_COMMANDS: u32 = 0x1000;
+
+/// The structure of a Mach-O file.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct MachOInfo {
+    /// The CPU types the file contains code for.
+    ///
+    /// Universal binaries contain more than one.
+    pub(crate) cpu_types: Vec<u32>,
+    /// The type of the file, such as executable or dynamic library.
+    pub(crate) file_type: u32,
+    /// The libraries loaded by the file.
+    pub(crate) libraries: Vec<String>,
+    /// The UUID of the file in hexadecimal, if it has one.
+    pub(crate) uuid: Option<String>,
+    /// Whether the file contains a code signature.
+    pub(crate) is_signed: bool,
+}
+
+impl MachOInfo {
+    /// Returns human readable names of the CPU types.
+    pub(crate) fn cpu_names(&self) -> Vec<&'static str> {
+        self.cpu_types
+            .iter()
+            .map(|cpu_type| match cpu_type {
+                0x7 => "x86",
+                0x0100_0007 => "x86-64",
+                0xc => "ARM",
+                0x0100_000c => "ARM64",
+                0x0200_000c => "ARM64_32",
+                0x12 => "PowerPC",
+                0x0100_0012 => "PowerPC64",
+                _ => "unknown",
+            })
+            .collect()
+    }
+
+    /// Returns a human readable name of the type of the file.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self.file_type {
+            0x1 => "object",
+            0x2 => "executable",
+            0x4 => "core dump",
+            0x6 => "dynamic library",
+            0x7 => "dynamic linker",
+            0x8 => "bundle",
+            0x9 => "dynamic library stub",
+            0xa => "debug symbols",
+            0xb => "kernel extension",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Reads a `u32` at the given offset in the given byte order.
+fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+/// Parses a single Mach-O file that is not a universal binary.
+fn parse_single(data: &[u8]) -> Option<MachOInfo> {
+    let (is_64_bit, big_endian) = match read_u32(data, 0, true)? {
+        MH_MAGIC => (false, true),
+        MH_MAGIC_64 => (true, true),
+        magic if magic.swap_bytes() == MH_MAGIC => (false, false),
+        magic if magic.swap_bytes() == MH_MAGIC_64 => (true, false),
+        _ => return None,
+    };
+    let u32_at = |offset| read_u32(data, offset, big_endian);
+
+    let mut info = MachOInfo {
+        cpu_types: vec![u32_at(4)?],
+        file_type: u32_at(12)?,
+        libraries: Vec::new(),
+        uuid: None,
+        is_signed: false,
+    };
+
+    let mut command = if is_64_bit { 32 } else { 28 };
+    for _ in 0..u32_at(16)?.min(MAX_LOAD_COMMANDS) {
+        let (Some(ty), Some(size)) = (u32_at(command), u32_at(command + 4)) else {
+            break;
+        };
+
+        match ty {
+            LC_LOAD_DYLIB | LC_LOAD_WEAK_DYLIB | LC_REEXPORT_DYLIB | LC_LAZY_LOAD_DYLIB
+            | LC_LOAD_UPWARD_DYLIB => {
+                if let Some(name) =
+                    u32_at(command + 8).and_then(|offset| c_string(data, command + offset as usize))
+                {
+                    info.libraries.push(name);
+                }
+            }
+            LC_UUID => {
+                info.uuid = data.get(command + 8..command + 24).map(hex::encode);
+            }
+            LC_CODE_SIGNATURE => info.is_signed = true,
+            _ => (),
+        }
+
+        if size < 8 {
+            break;
+        }
+        command += size as usize;
+    }
+
+    Some(info)
+}
+
+/// Parses the structure of the Mach-O file with the given contents.
+///
+/// For universal binaries, all CPU types are recorded, but everything else is taken from the
+/// first contained file.
+///
+/// Returns `None` if the file is not a Mach-O file.
+pub(crate) fn parse(data: &[u8]) -> Option<MachOInfo> {
+    firestorm::profile_fn!(parse_macho);
+
+    if read_u32(data, 0, true)? != FAT_MAGIC {
+        return parse_single(data);
+    }
+
+    let count = read_u32(data, 4, true)?;
+    if count == 0 || count >= MAX_FAT_ARCHS {
+        return None;
+    }
+
+    let archs = (0..count as usize)
+        .map(|i| {
+            let arch = 8 + i * 20;
+
+            Some((
+                read_u32(data, arch, true)?,
+                read_u32(data, arch + 8, true)? as usize,
+                read_u32(data, arch + 12, true)? as usize,
+            ))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let (_, offset, size) = archs[0];
+    let mut info = parse_single(data.get(offset..offset.checked_add(size)?)?)?;
+    info.cpu_types = archs.iter().map(|&(cpu_type, _, _)| cpu_type).collect();
+
+    Some(info)
+}
@@ -0,0 +1,820 @@
+//! Parses the structure of PE files.
+//!
+//! The layouts are described in the
+//! [PE format documentation](https://learn.microsoft.com/en-us/windows/win32/debug/pe-format).
+//! Everything except for the headers is parsed on a best effort basis, so that a malformed import
+//! table for example does not hide the rest of the information.
+
+use std::{collections::BTreeMap, fmt, ops::Range};
+
+use serde::{Deserialize, Serialize};
+
+use super::Md5Hash;
+
+/// The offset into a MZ-header where the offset of the PE-header is listed.
+const MZ_PE_OFFSET: usize = 0x3c;
+
+/// The magic bytes of an MZ-file.
+const MZ_MAGIC: &[u8] = b"MZ";
+
+/// The magic bytes of a PE-file.
+const PE_MAGIC: &[u8] = b"PE\x00\x00";
+
+/// The length of the standard COFF-header.
+const COFF_HEADER_LEN: usize = 20;
+
+/// The magic of the optional header of 32-bit PE files.
+const PE32_MAGIC: u16 = 0x10b;
+
+/// The magic of the optional header of 64-bit PE files.
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+
+/// The length of an entry in the section table.
+const SECTION_HEADER_LEN: usize = 40;
+
+/// The index of the export table in the data directories.
+const EXPORT_DIRECTORY: usize = 0;
+
+/// The index of the import table in the data directories.
+const IMPORT_DIRECTORY: usize = 1;
+
+/// The index of the resource table in the data directories.
+const RESOURCE_DIRECTORY: usize = 2;
+
+/// The index of the certificate table in the data directories.
+const SECURITY_DIRECTORY: usize = 4;
+
+/// The resource type of version information.
+const RT_VERSION: u32 = 16;
+
+/// The signature of the fixed file information in the version information.
+const FIXED_FILE_INFO_SIGNATURE: u32 = 0xfeef_04bd;
+
+/// The maximum number of entries read from any table, to limit the effect of corrupted files.
+const MAX_TABLE_ENTRIES: usize = 0x10000;
+
+/// The maximum length of names read from the file.
+const MAX_NAME_LEN: usize = 0x1000;
+
+/// The structure of a PE file.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct PeInfo {
+    /// The machine type the file was compiled for.
+    pub(crate) machine: u16,
+    /// The subsystem required to run the file.
+    pub(crate) subsystem: u16,
+    /// The characteristics flags of the COFF header.
+    pub(crate) characteristics: u16,
+    /// The DLL characteristics flags of the optional header.
+    pub(crate) dll_characteristics: u16,
+    /// Whether the file is a 64-bit (PE32+) file.
+    pub(crate) is_64_bit: bool,
+    /// The compile timestamp in seconds since the unix epoch.
+    ///
+    /// For reproducible builds this is a hash of the file instead.
+    pub(crate) timestamp: u32,
+    /// The sections of the file.
+    pub(crate) sections: Vec<Section>,
+    /// The imported functions, grouped by the DLL they are imported from.
+    pub(crate) imports: Vec<Import>,
+    /// The names of the exported functions.
+    pub(crate) exports: Vec<String>,
+    /// The number of bytes appended after the last section, excluding the certificate table.
+    pub(crate) overlay_size: u64,
+    /// The version information resource of the file, if it has one.
+    pub(crate) version_info: Option<VersionInfo>,
+}
+
+/// A section of a PE file.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct Section {
+    /// The name of the section.
+    pub(crate) name: String,
+    /// The size of the section when loaded into memory.
+    pub(crate) virtual_size: u32,
+    /// The size of the section in the file.
+    pub(crate) raw_size: u32,
+    /// The characteristics flags of the section.
+    pub(crate) characteristics: u32,
+    /// The entropy of the data of the section in the file.
+    pub(crate) entropy: f32,
+}
+
+/// The functions imported from a single DLL.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct Import {
+    /// The name of the DLL.
+    pub(crate) dll: String,
+    /// The names of the imported functions, functions imported by ordinal are named `#ordinal`.
+    pub(crate) functions: Vec<String>,
+}
+
+/// The version information resource of a PE file.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct VersionInfo {
+    /// The file version in the fixed file information.
+    pub(crate) file_version: Option<String>,
+    /// The product version in the fixed file information.
+    pub(crate) product_version: Option<String>,
+    /// The strings of the string file information, such as `CompanyName` or `OriginalFilename`.
+    pub(crate) strings: BTreeMap<String, String>,
+}
+
+impl VersionInfo {
+    /// Returns the company name of the file.
+    pub(crate) fn company_name(&self) -> Option<&str> {
+        self.strings.get("CompanyName").map(String::as_str)
+    }
+
+    /// Returns the product name of the file.
+    pub(crate) fn product_name(&self) -> Option<&str> {
+        self.strings.get("ProductName").map(String::as_str)
+    }
+
+    /// Returns the name the file was originally created with.
+    pub(crate) fn original_filename(&self) -> Option<&str> {
+        self.strings.get("OriginalFilename").map(String::as_str)
+    }
+}
+
+impl PeInfo {
+    /// Returns a human readable name of the machine type.
+    pub(crate) fn machine_name(&self) -> &'static str {
+        match self.machine {
+            0x014c => "x86",
+            0x8664 => "x64",
+            0x01c0 => "ARM",
+            0x01c4 => "ARMv7",
+            0xaa64 => "ARM64",
+            0xa641 => "ARM64EC",
+            0x0200 => "IA-64",
+            0x0ebc => "EFI byte code",
+            0x0000 => "any",
+            _ => "unknown",
+        }
+    }
+
+    /// Returns a human readable name of the subsystem.
+    pub(crate) fn subsystem_name(&self) -> &'static str {
+        match self.subsystem {
+            1 => "native",
+            2 => "Windows GUI",
+            3 => "Windows console",
+            5 => "OS/2 console",
+            7 => "POSIX console",
+            9 => "Windows CE GUI",
+            10 => "EFI application",
+            11 => "EFI boot service driver",
+            12 => "EFI runtime driver",
+            13 => "EFI ROM",
+            14 => "Xbox",
+            16 => "Windows boot application",
+            _ => "unknown",
+        }
+    }
+
+    /// Returns the compile time of the file.
+    pub(crate) fn compile_time(&self) -> crate::timestamp::Timestamp {
+        (std::time::UNIX_EPOCH + std::time::Duration::from_secs(self.timestamp.into())).into()
+    }
+
+    /// Returns `true` if the file is a DLL.
+    pub(crate) fn is_dll(&self) -> bool {
+        /// The characteristics flag of DLLs.
+        const IMAGE_FILE_DLL: u16 = 0x2000;
+
+        self.characteristics & IMAGE_FILE_DLL != 0
+    }
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// The characteristics flag of executable sections.
+        const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+        /// The characteristics flag of readable sections.
+        const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+        /// The characteristics flag of writable sections.
+        const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+        let flag = |flag, c| {
+            if self.characteristics & flag != 0 {
+                c
+            } else {
+                '-'
+            }
+        };
+
+        write!(
+            f,
+            "{:8} {}{}{} virtual {:#x}, raw {:#x}, entropy {:.2}",
+            self.name,
+            flag(IMAGE_SCN_MEM_READ, 'r'),
+            flag(IMAGE_SCN_MEM_WRITE, 'w'),
+            flag(IMAGE_SCN_MEM_EXECUTE, 'x'),
+            self.virtual_size,
+            self.raw_size,
+            self.entropy
+        )
+    }
+}
+
+/// Computes the entropy of the given data in bits per byte.
+pub(crate) fn entropy(data: &[u8]) -> f32 {
+    let mut byte_occurrences = [0u64; 256];
+    for &byte in data {
+        byte_occurrences[byte as usize] += 1;
+    }
+
+    let total_bytes = data.len() as f64;
+    let entropy = -byte_occurrences
+        .into_iter()
+        .filter(|&num| num != 0)
+        .map(|num| {
+            let p = num as f64 / total_bytes;
+
+            p * p.log2()
+        })
+        .sum::<f64>();
+
+    entropy.abs() as f32
+}
+
+/// Reads a little endian `u16` at the given offset.
+fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+
+/// Reads a little endian `u32` at the given offset.
+fn le_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+/// Reads a little endian `u64` at the given offset.
+fn le_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset.checked_add(8)?)?.try_into().ok()?,
+    ))
+}
+
+/// Reads the null terminated string at the given offset.
+pub(super) fn c_string(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let bytes = &bytes[..bytes.len().min(MAX_NAME_LEN)];
+    let len = bytes.iter().position(|&byte| byte == 0)?;
+
+    Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+/// The location of a section in the file and in memory.
+#[derive(Debug, Clone, Copy)]
+struct SectionLocation {
+    /// The address of the section in memory, relative to the image base.
+    virtual_address: u32,
+    /// The size of the section in memory.
+    virtual_size: u32,
+    /// The offset of the section data in the file.
+    raw_offset: u32,
+    /// The size of the section data in the file.
+    raw_size: u32,
+}
+
+/// The parsed headers of a PE file, used to find data by its virtual address.
+pub(crate) struct PeFile<'data> {
+    /// The contents of the file.
+    pub(crate) data: &'data [u8],
+    /// The offset of the optional header.
+    pub(crate) optional_header: usize,
+    /// Whether the file is a 64-bit (PE32+) file.
+    pub(crate) is_64_bit: bool,
+    /// The size of the headers in the file.
+    size_of_headers: u32,
+    /// The locations of the sections.
+    sections: Vec<SectionLocation>,
+}
+
+impl<'data> PeFile<'data> {
+    /// Parses the headers of the PE file with the given contents.
+    ///
+    /// Returns `None` if the file is not a valid PE file.
+    pub(crate) fn parse(data: &'data [u8]) -> Option<Self> {
+        if !data.starts_with(MZ_MAGIC) {
+            return None;
+        }
+
+        let pe_offset = le_u32(data, MZ_PE_OFFSET)? as usize;
+        if data.get(pe_offset..pe_offset.checked_add(PE_MAGIC.len())?)? != PE_MAGIC {
+            return None;
+        }
+
+        let coff_header = pe_offset + PE_MAGIC.len();
+        let section_count = le_u16(data, coff_header + 2)?;
+        let optional_header_len = le_u16(data, coff_header + 16)?;
+        let optional_header = coff_header + COFF_HEADER_LEN;
+
+        let is_64_bit = match le_u16(data, optional_header)? {
+            PE32_MAGIC => false,
+            PE32_PLUS_MAGIC => true,
+            _ => return None,
+        };
+
+        let section_table = optional_header + usize::from(optional_header_len);
+        let sections = (0..usize::from(section_count))
+            .map(|i| {
+                let header = section_table + i * SECTION_HEADER_LEN;
+
+                Some(SectionLocation {
+                    virtual_size: le_u32(data, header + 8)?,
+                    virtual_address: le_u32(data, header + 12)?,
+                    raw_size: le_u32(data, header + 16)?,
+                    raw_offset: le_u32(data, header + 20)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            data,
+            optional_header,
+            is_64_bit,
+            size_of_headers: le_u32(data, optional_header + 60)?,
+            sections,
+        })
+    }
+
+    /// Returns the offset of the COFF header.
+    fn coff_header(&self) -> usize {
+        self.optional_header - COFF_HEADER_LEN
+    }
+
+    /// Returns the offset of the section table.
+    fn section_table(&self) -> Option<usize> {
+        let optional_header_len = le_u16(self.data, self.coff_header() + 16)?;
+
+        Some(self.optional_header + usize::from(optional_header_len))
+    }
+
+    /// Returns the offset of the entry of the data directory with the given index in the file.
+    fn data_directory_entry(&self, index: usize) -> Option<usize> {
+        let (count_offset, directories_offset) = if self.is_64_bit { (108, 112) } else { (92, 96) };
+
+        let count = le_u32(self.data, self.optional_header + count_offset)?;
+        if index >= count as usize {
+            return None;
+        }
+
+        Some(self.optional_header + directories_offset + index * 8)
+    }
+
+    /// Returns the address and size of the data directory with the given index.
+    pub(crate) fn data_directory(&self, index: usize) -> Option<(u32, u32)> {
+        let directory = self.data_directory_entry(index)?;
+        let address = le_u32(self.data, directory)?;
+        let size = le_u32(self.data, directory + 4)?;
+
+        (address != 0 && size != 0).then_some((address, size))
+    }
+
+    /// Converts the given address relative to the image base to an offset in the file.
+    pub(crate) fn rva_to_offset(&self, rva: u32) -> Option<usize> {
+        if rva < self.size_of_headers {
+            return Some(rva as usize);
+        }
+
+        self.sections.iter().find_map(|section| {
+            let size = section.virtual_size.max(section.raw_size);
+            let offset_in_section = rva.checked_sub(section.virtual_address)?;
+
+            (offset_in_section < size && offset_in_section < section.raw_size)
+                .then(|| section.raw_offset as usize + offset_in_section as usize)
+        })
+    }
+
+    /// Returns the data at the given address relative to the image base.
+    pub(crate) fn data_at(&self, rva: u32) -> Option<&'data [u8]> {
+        self.data.get(self.rva_to_offset(rva)?..)
+    }
+
+    /// Returns the offset in the file where the data of the sections ends.
+    pub(crate) fn sections_end(&self) -> u64 {
+        self.sections
+            .iter()
+            .filter(|section| section.raw_size != 0)
+            .map(|section| u64::from(section.raw_offset) + u64::from(section.raw_size))
+            .max()
+            .unwrap_or(u64::from(self.size_of_headers))
+    }
+
+    /// Returns the offset and size of the certificate table in the file, if it has one.
+    ///
+    /// Unlike other data directories, the certificate table is addressed by its file offset.
+    pub(crate) fn certificate_table(&self) -> Option<(u64, u64)> {
+        let (offset, size) = self.data_directory(SECURITY_DIRECTORY)?;
+
+        Some((u64::from(offset), u64::from(size)))
+    }
+
+    /// Returns the ranges of the file that are covered by its Authenticode digest.
+    ///
+    /// These are all bytes except for the checksum, the entry of the certificate table in the data
+    /// directories and the certificate table itself.
+    pub(crate) fn authenticode_ranges(&self) -> Option<[Range<usize>; 4]> {
+        let checksum = self.optional_header + 64;
+        let directory = self.data_directory_entry(SECURITY_DIRECTORY)?;
+        let (offset, size) = self.certificate_table()?;
+
+        let len = self.data.len();
+        let table_start = usize::try_from(offset).ok()?.min(len);
+        let table_end = usize::try_from(offset + size).ok()?.min(len);
+
+        (checksum + 4 <= directory && directory + 8 <= table_start).then_some([
+            0..checksum,
+            checksum + 4..directory,
+            directory + 8..table_start,
+            table_end..len,
+        ])
+    }
+
+    /// Reads the section table with the entropy of each section.
+    fn section_table_info(&self) -> Option<Vec<Section>> {
+        let section_table = self.section_table()?;
+
+        self.sections
+            .iter()
+            .enumerate()
+            .map(|(i, location)| {
+                let header = section_table + i * SECTION_HEADER_LEN;
+                let name = self.data.get(header..header + 8)?;
+                let name_len = name.iter().position(|&byte| byte == 0).unwrap_or(8);
+
+                let start = (location.raw_offset as usize).min(self.data.len());
+                let end = start
+                    .saturating_add(location.raw_size as usize)
+                    .min(self.data.len());
+
+                Some(Section {
+                    name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+                    virtual_size: location.virtual_size,
+                    raw_size: location.raw_size,
+                    characteristics: le_u32(self.data, header + 36)?,
+                    entropy: entropy(&self.data[start..end]),
+                })
+            })
+            .collect()
+    }
+
+    /// Reads the import table.
+    pub(crate) fn imports(&self) -> Option<Vec<Import>> {
+        /// The length of an import descriptor.
+        const IMPORT_DESCRIPTOR_LEN: usize = 20;
+
+        let (address, _) = self.data_directory(IMPORT_DIRECTORY)?;
+        let descriptors = self.data_at(address)?;
+
+        let mut imports = Vec::new();
+        for i in 0..MAX_TABLE_ENTRIES {
+            let descriptor = i * IMPORT_DESCRIPTOR_LEN;
+            let original_first_thunk = le_u32(descriptors, descriptor)?;
+            let name = le_u32(descriptors, descriptor + 12)?;
+            let first_thunk = le_u32(descriptors, descriptor + 16)?;
+
+            if original_first_thunk == 0 && name == 0 && first_thunk == 0 {
+                break;
+            }
+
+            let dll = c_string(self.data_at(name)?, 0)?;
+            let thunks = if original_first_thunk != 0 {
+                original_first_thunk
+            } else {
+                first_thunk
+            };
+
+            imports.push(Import {
+                dll,
+                functions: self.imported_functions(thunks).unwrap_or_default(),
+            });
+        }
+
+        Some(imports)
+    }
+
+    /// Reads the names of the functions in the import thunk table at the given address.
+    fn imported_functions(&self, address: u32) -> Option<Vec<String>> {
+        let thunks = self.data_at(address)?;
+        let (thunk_len, ordinal_flag) = if self.is_64_bit {
+            (8, 1 << 63)
+        } else {
+            (4, 1 << 31)
+        };
+
+        let mut functions = Vec::new();
+        for i in 0..MAX_TABLE_ENTRIES {
+            let thunk = if self.is_64_bit {
+                le_u64(thunks, i * thunk_len)?
+            } else {
+                u64::from(le_u32(thunks, i * thunk_len)?)
+            };
+
+            if thunk == 0 {
+                break;
+            }
+
+            if thunk & ordinal_flag != 0 {
+                functions.push(format!("#{}", thunk & 0xffff));
+            } else {
+                // The name follows a 2 byte hint
+                let hint_name = self.data_at(thunk as u32)?;
+                functions.push(c_string(hint_name, 2)?);
+            }
+        }
+
+        Some(functions)
+    }
+
+    /// Reads the names of the exported functions.
+    pub(crate) fn exports(&self) -> Option<Vec<String>> {
+        let (address, _) = self.data_directory(EXPORT_DIRECTORY)?;
+        let directory = self.data_at(address)?;
+
+        let name_count = le_u32(directory, 24)? as usize;
+        let names = self.data_at(le_u32(directory, 32)?)?;
+
+        (0..name_count.min(MAX_TABLE_ENTRIES))
+            .map(|i| c_string(self.data_at(le_u32(names, i * 4)?)?, 0))
+            .collect()
+    }
+
+    /// Reads the version information resource.
+    fn version_info(&self) -> Option<VersionInfo> {
+        let (address, _) = self.data_directory(RESOURCE_DIRECTORY)?;
+        let resources = self.data_at(address)?;
+
+        // The resource tree has the levels type, name and language
+        let names = resource_subdirectory(resources, 0, |id| id == RT_VERSION)?;
+        let languages = resource_subdirectory(resources, names, |_| true)?;
+        let data_entry = resource_entry(resources, languages, |_| true)?;
+
+        let data_address = le_u32(resources, data_entry)?;
+        let size = le_u32(resources, data_entry + 4)? as usize;
+        let data = self.data_at(data_address)?;
+
+        parse_version_info(&data[..size.min(data.len())])
+    }
+}
+
+/// Finds the entry in the resource directory at `directory` with an ID matching `matches`.
+///
+/// Returns the offset of the data the entry points to.
+fn resource_entry(
+    resources: &[u8],
+    directory: usize,
+    matches: impl Fn(u32) -> bool,
+) -> Option<usize> {
+    /// The flag in the offset of resource entries that marks them as subdirectories.
+    const SUBDIRECTORY_FLAG: u32 = 0x8000_0000;
+
+    let named_count = usize::from(le_u16(resources, directory + 12)?);
+    let id_count = usize::from(le_u16(resources, directory + 14)?);
+
+    (0..named_count + id_count).find_map(|i| {
+        let entry = directory + 16 + i * 8;
+        let id = le_u32(resources, entry)?;
+        let offset = le_u32(resources, entry + 4)?;
+
+        matches(id).then_some((offset & !SUBDIRECTORY_FLAG) as usize)
+    })
+}
+
+/// Finds the subdirectory in the resource directory at `directory` with an ID matching `matches`.
+fn resource_subdirectory(
+    resources: &[u8],
+    directory: usize,
+    matches: impl Fn(u32) -> bool,
+) -> Option<usize> {
+    let subdirectory = resource_entry(resources, directory, matches)?;
+
+    // Refuse to follow references to the same directory
+    (subdirectory != directory).then_some(subdirectory)
+}
+
+/// A block in the version information, consisting of a key, a value and children.
+struct VersionBlock<'data> {
+    /// The key of the block.
+    key: String,
+    /// The value of the block.
+    value: &'data [u8],
+    /// Whether the value is text or binary data.
+    is_text: bool,
+    /// The data of the children of the block.
+    children: &'data [u8],
+}
+
+impl<'data> VersionBlock<'data> {
+    /// Parses the block at the start of the given data.
+    ///
+    /// Returns the block and the remaining data after it.
+    fn parse(data: &'data [u8]) -> Option<(Self, &'data [u8])> {
+        let align = |offset: usize| (offset + 3) & !3;
+
+        let len = usize::from(le_u16(data, 0)?);
+        let value_len = usize::from(le_u16(data, 2)?);
+        let is_text = le_u16(data, 4)? == 1;
+        if len < 6 {
+            return None;
+        }
+        let block = data.get(..len)?;
+
+        let key_units = block[6..]
+            .chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect::<Vec<_>>();
+        let key = String::from_utf16_lossy(&key_units);
+
+        let value_start = align(6 + (key_units.len() + 1) * 2).min(len);
+        // The length of text values is given in characters
+        let value_len = if is_text { value_len * 2 } else { value_len };
+        let value_end = (value_start + value_len).min(len);
+        let children_start = align(value_end).min(len);
+
+        Some((
+            Self {
+                key,
+                value: &block[value_start..value_end],
+                is_text,
+                children: &block[children_start..],
+            },
+            data.get(align(len)..).unwrap_or_default(),
+        ))
+    }
+
+    /// Returns the blocks of the children of this block.
+    fn children(&self) -> impl Iterator<Item = VersionBlock<'data>> + 'data {
+        let mut remaining = self.children;
+
+        std::iter::from_fn(move || {
+            let (block, rest) = VersionBlock::parse(remaining)?;
+            remaining = rest;
+
+            Some(block)
+        })
+    }
+
+    /// Returns the text value of this block.
+    fn text(&self) -> String {
+        let units = self
+            .value
+            .chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect::<Vec<_>>();
+
+        String::from_utf16_lossy(&units)
+    }
+}
+
+/// Parses the `VS_VERSIONINFO` structure in the given data.
+fn parse_version_info(data: &[u8]) -> Option<VersionInfo> {
+    let (root, _) = VersionBlock::parse(data)?;
+    if root.key != "VS_VERSION_INFO" {
+        return None;
+    }
+
+    let version =
+        |ms: u32, ls: u32| format!("{}.{}.{}.{}", ms >> 16, ms & 0xffff, ls >> 16, ls & 0xffff);
+    let fixed = (!root.is_text && le_u32(root.value, 0) == Some(FIXED_FILE_INFO_SIGNATURE))
+        .then_some(root.value);
+    let file_version = fixed.and_then(|fixed| Some(version(le_u32(fixed, 8)?, le_u32(fixed, 12)?)));
+    let product_version =
+        fixed.and_then(|fixed| Some(version(le_u32(fixed, 16)?, le_u32(fixed, 20)?)));
+
+    let mut strings = BTreeMap::new();
+    for string_file_info in root
+        .children()
+        .filter(|child| child.key == "StringFileInfo")
+    {
+        for table in string_file_info.children() {
+            for string in table.children() {
+                // If there are multiple languages, the first one is used
+                strings
+                    .entry(string.key.clone())
+                    .or_insert_with(|| string.text());
+            }
+        }
+    }
+
+    Some(VersionInfo {
+        file_version,
+        product_version,
+        strings,
+    })
+}
+
+/// Parses the structure of the PE file with the given contents.
+///
+/// Returns `None` if the file is not a PE file.
+pub(crate) fn parse(data: &[u8]) -> Option<PeInfo> {
+    firestorm::profile_fn!(parse_pe);
+
+    let file = PeFile::parse(data)?;
+    let coff_header = file.coff_header();
+
+    let sections_end = file.sections_end();
+    let overlay_end = match file.certificate_table() {
+        Some((offset, _)) if offset >= sections_end => offset,
+        _ => data.len() as u64,
+    };
+
+    Some(PeInfo {
+        machine: le_u16(data, coff_header)?,
+        subsystem: le_u16(data, file.optional_header + 68)?,
+        characteristics: le_u16(data, coff_header + 18)?,
+        dll_characteristics: le_u16(data, file.optional_header + 70)?,
+        is_64_bit: file.is_64_bit,
+        timestamp: le_u32(data, coff_header + 4)?,
+        sections: file.section_table_info().unwrap_or_default(),
+        imports: file.imports().unwrap_or_default(),
+        exports: file.exports().unwrap_or_default(),
+        overlay_size: overlay_end.saturating_sub(sections_end),
+        version_info: file.version_info(),
+    })
+}
+
+/// Computes the import hash of the given imports, as introduced by Mandiant.
+///
+/// Unlike other implementations imports by ordinal are never resolved to their names.
+/// Returns `None` if there are no imports.
+pub(crate) fn imphash(imports: &[Import]) -> Option<Md5Hash> {
+    use md5::Digest as _;
+
+    let mut hasher = md5::Md5::new();
+    let mut first = true;
+
+    for import in imports {
+        let dll = import.dll.to_lowercase();
+        let dll = match dll.rsplit_once('.') {
+            Some((name, "dll" | "ocx" | "sys")) => name,
+            _ => &dll,
+        };
+
+        for function in &import.functions {
+            if !first {
+                hasher.update(b",");
+            }
+            first = false;
+
+            let function = match function.strip_prefix('#') {
+                Some(ordinal) => format!("ord{ordinal}"),
+                None => function.to_lowercase(),
+            };
+            hasher.update(format!("{dll}.{function}"));
+        }
+    }
+
+    (!first).then(|| Md5Hash {
+        bytes: hasher.finalize().into(),
+    })
+}
+
+/// Computes the MD5 hash of the decoded Rich header of the PE file with the given contents.
+///
+/// The Rich header is an undocumented structure between the MZ-header and the PE-header, which
+/// records the tools used to build the file.
+/// Returns `None` if the file does not have a Rich header.
+pub(crate) fn rich_header_hash(data: &[u8]) -> Option<Md5Hash> {
+    use md5::Digest as _;
+
+    /// The marker at the end of the Rich header.
+    const RICH_MAGIC: &[u8] = b"Rich";
+
+    /// The marker at the start of the Rich header, after decoding.
+    const DANS_MAGIC: u32 = u32::from_le_bytes(*b"DanS");
+
+    let pe_offset = le_u32(data, MZ_PE_OFFSET)? as usize;
+    let stub = data.get(..pe_offset)?;
+
+    let end = stub
+        .windows(RICH_MAGIC.len())
+        .rposition(|window| window == RICH_MAGIC)?;
+    let key = le_u32(stub, end + RICH_MAGIC.len())?;
+
+    // The header consists of 4 byte values that are encoded with the key
+    let mut start = end;
+    loop {
+        start = start.checked_sub(4)?;
+        if le_u32(stub, start)? ^ key == DANS_MAGIC {
+            break;
+        }
+    }
+
+    let mut hasher = md5::Md5::new();
+    for offset in (start..end).step_by(4) {
+        hasher.update((le_u32(stub, offset)? ^ key).to_le_bytes());
+    }
+
+    Some(Md5Hash {
+        bytes: hasher.finalize().into(),
+    })
+}
@@ -0,0 +1,369 @@
+//! Computes [TLSH](https://tlsh.org) digests, which allow finding similar files.
+//!
+//! This implements the standard variant with 128 buckets and a checksum of one byte, so the
+//! digests are compatible with the ones of the reference implementation.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// The minimum number of bytes required to compute a digest.
+const MIN_DATA_LEN: u64 = 50;
+
+/// The number of buckets that are used for the digest.
+const EFFECTIVE_BUCKETS: usize = 128;
+
+/// The number of bytes in the body of the digest.
+const CODE_SIZE: usize = EFFECTIVE_BUCKETS / 4;
+
+/// The size of the window that slides over the data.
+const WINDOW_SIZE: usize = 5;
+
+/// The permutation of bytes used for Pearson hashing.
+#[rustfmt::skip]
+const PEARSON_TABLE: [u8; 256] = [
+    1, 87, 49, 12, 176, 178, 102, 166, 121, 193, 6, 84, 249, 230, 44, 163,
+    14, 197, 213, 181, 161, 85, 218, 80, 64, 239, 24, 226, 236, 142, 38, 200,
+    110, 177, 104, 103, 141, 253, 255, 50, 77, 101, 81, 18, 45, 96, 31, 222,
+    25, 107, 190, 70, 86, 237, 240, 34, 72, 242, 20, 214, 244, 227, 149, 235,
+    97, 234, 57, 22, 60, 250, 82, 175, 208, 5, 127, 199, 111, 62, 135, 248,
+    174, 169, 211, 58, 66, 154, 106, 195, 245, 171, 17, 187, 182, 179, 0, 243,
+    132, 56, 148, 75, 128, 133, 158, 100, 130, 126, 91, 13, 153, 246, 216, 219,
+    119, 68, 223, 78, 83, 88, 201, 99, 122, 11, 92, 32, 136, 114, 52, 10,
+    138, 30, 48, 183, 156, 35, 61, 26, 143, 74, 251, 94, 129, 162, 63, 152,
+    170, 7, 115, 167, 241, 206, 3, 150, 55, 59, 151, 220, 90, 53, 23, 131,
+    125, 173, 15, 238, 79, 95, 89, 16, 105, 137, 225, 224, 217, 160, 37, 123,
+    118, 73, 2, 157, 46, 116, 9, 145, 134, 228, 207, 212, 202, 215, 69, 229,
+    27, 188, 67, 124, 168, 252, 42, 4, 29, 108, 21, 247, 19, 205, 39, 203,
+    233, 40, 186, 147, 198, 192, 155, 33, 164, 191, 98, 204, 165, 180, 117, 76,
+    140, 36, 210, 172, 41, 54, 159, 8, 185, 232, 113, 196, 231, 47, 146, 120,
+    51, 65, 28, 144, 254, 221, 93, 189, 194, 139, 112, 43, 71, 109, 184, 209,
+];
+
+/// A TLSH digest of the contents of a file.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct Tlsh {
+    /// The checksum of the data.
+    checksum: u8,
+    /// The logarithmically encoded length of the data.
+    lvalue: u8,
+    /// The ratio of the first to the third quartile of the buckets.
+    q1_ratio: u8,
+    /// The ratio of the second to the third quartile of the buckets.
+    q2_ratio: u8,
+    /// The quartile each bucket belongs to, with two bits per bucket.
+    code: [u8; CODE_SIZE],
+}
+
+impl Tlsh {
+    /// Returns the distance between the two digests.
+    ///
+    /// A distance of zero means that the files are very likely identical, distances below about
+    /// 100 indicate similar files.
+    pub(crate) fn distance(&self, other: &Tlsh) -> u32 {
+        /// Returns the distance of `a` and `b` in a circular range of values.
+        fn mod_diff(a: u8, b: u8, range: u32) -> u32 {
+            let diff = u32::from(a.abs_diff(b));
+
+            diff.min(range - diff)
+        }
+
+        let mut distance = match mod_diff(self.lvalue, other.lvalue, 256) {
+            diff @ (0 | 1) => diff,
+            diff => diff * 12,
+        };
+
+        for (a, b) in [
+            (self.q1_ratio, other.q1_ratio),
+            (self.q2_ratio, other.q2_ratio),
+        ] {
+            distance += match mod_diff(a, b, 16) {
+                diff @ (0 | 1) => diff,
+                diff => (diff - 1) * 12,
+            };
+        }
+
+        if self.checksum != other.checksum {
+            distance += 1;
+        }
+
+        for (&a, &b) in self.code.iter().zip(&other.code) {
+            for shift in (0..8).step_by(2) {
+                distance += match ((a >> shift) & 0b11).abs_diff((b >> shift) & 0b11) {
+                    3 => 6,
+                    diff => u32::from(diff),
+                };
+            }
+        }
+
+        distance
+    }
+
+    /// Returns the bytes of the digest in the order of the reference implementation.
+    fn to_bytes(self) -> [u8; 3 + CODE_SIZE] {
+        let mut bytes = [0; 3 + CODE_SIZE];
+
+        bytes[0] = self.checksum.rotate_left(4);
+        bytes[1] = self.lvalue.rotate_left(4);
+        bytes[2] = (self.q1_ratio << 4) | self.q2_ratio;
+        for (byte, &code) in bytes[3..].iter_mut().zip(self.code.iter().rev()) {
+            *byte = code;
+        }
+
+        bytes
+    }
+
+    /// Reads a digest from its bytes in the order of the reference implementation.
+    fn from_bytes(bytes: [u8; 3 + CODE_SIZE]) -> Self {
+        let mut code = [0; CODE_SIZE];
+        for (code, &byte) in code.iter_mut().rev().zip(&bytes[3..]) {
+            *code = byte;
+        }
+
+        Self {
+            checksum: bytes[0].rotate_left(4),
+            lvalue: bytes[1].rotate_left(4),
+            q1_ratio: bytes[2] >> 4,
+            q2_ratio: bytes[2] & 0xf,
+            code,
+        }
+    }
+}
+
+impl fmt::Display for Tlsh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "T1")?;
+
+        for byte in self.to_bytes() {
+            write!(f, "{byte:02X}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Tlsh {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digest = s
+            .strip_prefix("T1")
+            .ok_or_else(|| anyhow::anyhow!("unsupported TLSH digest version"))?;
+
+        let mut bytes = [0; 3 + CODE_SIZE];
+        hex::decode_to_slice(digest, &mut bytes)?;
+
+        Ok(Self::from_bytes(bytes))
+    }
+}
+
+/// Computes a TLSH digest of data that is passed in in pieces.
+#[derive(Debug, Clone)]
+pub(crate) struct TlshHasher {
+    /// The number of triplets that were mapped to each bucket.
+    buckets: [u32; 256],
+    /// The last bytes of the data.
+    window: [u8; WINDOW_SIZE],
+    /// The number of bytes processed so far.
+    len: u64,
+    /// The checksum of the data processed so far.
+    checksum: u8,
+}
+
+impl TlshHasher {
+    /// Creates a new hasher without any data.
+    pub(crate) fn new() -> Self {
+        Self {
+            buckets: [0; 256],
+            window: [0; WINDOW_SIZE],
+            len: 0,
+            checksum: 0,
+        }
+    }
+
+    /// Processes the next piece of the data.
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        /// Maps a salt and three bytes to a bucket using Pearson hashing.
+        fn pearson(salt: u8, a: u8, b: u8, c: u8) -> u8 {
+            let mut hash = PEARSON_TABLE[salt as usize];
+            hash = PEARSON_TABLE[(hash ^ a) as usize];
+            hash = PEARSON_TABLE[(hash ^ b) as usize];
+            PEARSON_TABLE[(hash ^ c) as usize]
+        }
+
+        for &byte in data {
+            self.window.copy_within(1.., 0);
+            self.window[WINDOW_SIZE - 1] = byte;
+            self.len += 1;
+
+            if self.len < WINDOW_SIZE as u64 {
+                continue;
+            }
+
+            let [w4, w3, w2, w1, w0] = self.window;
+
+            self.checksum = pearson(0, w0, w1, self.checksum);
+
+            for (salt, a, b) in [
+                (2, w1, w2),
+                (3, w1, w3),
+                (5, w2, w3),
+                (7, w2, w4),
+                (11, w1, w4),
+                (13, w3, w4),
+            ] {
+                self.buckets[pearson(salt, w0, a, b) as usize] += 1;
+            }
+        }
+    }
+
+    /// Computes the digest of all processed data.
+    ///
+    /// Returns `None` if there is too little data or the data is too uniform to compute a
+    /// meaningful digest.
+    pub(crate) fn finish(&self) -> Option<Tlsh> {
+        if self.len < MIN_DATA_LEN {
+            return None;
+        }
+
+        let buckets = &self.buckets[..EFFECTIVE_BUCKETS];
+
+        let mut sorted = [0; EFFECTIVE_BUCKETS];
+        sorted.copy_from_slice(buckets);
+        sorted.sort_unstable();
+        let q1 = sorted[EFFECTIVE_BUCKETS / 4 - 1];
+        let q2 = sorted[EFFECTIVE_BUCKETS / 2 - 1];
+        let q3 = sorted[EFFECTIVE_BUCKETS * 3 / 4 - 1];
+
+        let non_zero = buckets.iter().filter(|&&count| count > 0).count();
+        if q3 == 0 || non_zero <= EFFECTIVE_BUCKETS / 2 {
+            return None;
+        }
+
+        let mut code = [0; CODE_SIZE];
+        for (byte, counts) in code.iter_mut().zip(buckets.chunks_exact(4)) {
+            for (i, &count) in counts.iter().enumerate() {
+                let quartile = if count > q3 {
+                    3
+                } else if count > q2 {
+                    2
+                } else if count > q1 {
+                    1
+                } else {
+                    0
+                };
+
+                *byte |= quartile << (i * 2);
+            }
+        }
+
+        Some(Tlsh {
+            checksum: self.checksum,
+            lvalue: length_value(self.len),
+            q1_ratio: ((u64::from(q1) * 100 / u64::from(q3)) % 16) as u8,
+            q2_ratio: ((u64::from(q2) * 100 / u64::from(q3)) % 16) as u8,
+            code,
+        })
+    }
+}
+
+/// Encodes the length of the data logarithmically into a single byte.
+fn length_value(len: u64) -> u8 {
+    let len = (len as f64).ln();
+
+    let value = if len <= 656f64.ln() {
+        len / 1.5f64.ln()
+    } else if len <= 3199f64.ln() {
+        len / 1.3f64.ln() - 8.72777
+    } else {
+        len / 1.1f64.ln() - 62.5472
+    };
+
+    (value.floor() as i64 & 0xff) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Generates deterministic pseudo-random data with the linear congruential generator of ANSI C.
+    fn pseudo_random(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345) & 0x7fff_ffff;
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    /// Computes the digest of `data`, feeding it to the hasher in pieces of `piece_len` bytes.
+    fn digest(data: &[u8], piece_len: usize) -> Option<Tlsh> {
+        let mut hasher = TlshHasher::new();
+        for piece in data.chunks(piece_len) {
+            hasher.update(piece);
+        }
+
+        hasher.finish()
+    }
+
+    /// The pseudo-random data with every 50th byte inverted.
+    fn modified() -> Vec<u8> {
+        let mut data = pseudo_random(1000);
+        for byte in data.iter_mut().step_by(50) {
+            *byte ^= 0xff;
+        }
+
+        data
+    }
+
+    /// A short sentence repeated to 900 bytes of text.
+    fn text() -> Vec<u8> {
+        b"The quick brown fox jumps over the lazy dog. ".repeat(20)
+    }
+
+    // The expected digests were produced by a direct port of the reference implementation.
+
+    #[test]
+    fn known_digests() {
+        for (data, expected) in [
+            (
+                pseudo_random(1000),
+                "T1D71198D7171DD7C30188165823F51568B7597773DBEC311F40200960EEF0B9780AD169",
+            ),
+            (
+                modified(),
+                "T1381198D72729E7C30198665923B41578F3157773EAEC325F40300D54E6B0B9741AD15A",
+            ),
+            (
+                text(),
+                "T16811024A311C1794658A1888438D95B2D2C9C910612114116570604219482359CD8551",
+            ),
+        ] {
+            for piece_len in [1, 7, data.len()] {
+                let tlsh = digest(&data, piece_len).unwrap();
+                assert_eq!(tlsh.to_string(), expected);
+                assert_eq!(expected.parse::<Tlsh>().unwrap(), tlsh);
+            }
+        }
+    }
+
+    #[test]
+    fn known_distances() {
+        let random = digest(&pseudo_random(1000), 1000).unwrap();
+        let modified = digest(&modified(), 1000).unwrap();
+        let text = digest(&text(), 900).unwrap();
+
+        assert_eq!(random.distance(&random), 0);
+        assert_eq!(random.distance(&modified), 31);
+        assert_eq!(modified.distance(&random), 31);
+        assert_eq!(random.distance(&text), 309);
+    }
+
+    #[test]
+    fn too_little_data() {
+        assert_eq!(digest(&pseudo_random(49), 49), None);
+        assert_eq!(digest(&[0; 1000], 1000), None);
+    }
+}
@@ -2,9 +2,22 @@
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use std::{collections::BTreeMap, ffi::OsString, io, path::Path};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt,
+    io::{self, Read, Seek},
+    path::Path,
+};
 
-use crate::timestamp::Timestamp;
+use crate::{
+    fs::file::{File, GenericFile as _},
+    timestamp::Timestamp,
+};
+
+pub(crate) mod reparse_point;
+pub(crate) mod security_descriptor;
+pub(crate) mod zone_identifier;
 
 /// Stores filesystem metadata about objects.
 ///
@@ -14,6 +27,10 @@ use crate::timestamp::Timestamp;
 pub(crate) struct Metadata {
     /// The size of the entry in bytes.
     pub(crate) size: u64,
+    /// The number of bytes allocated for the entry on disk.
+    ///
+    /// This is smaller than the size for sparse and compressed files.
+    pub(crate) allocated_size: Option<u64>,
     /// The time the entry was created.
     pub(crate) created: Option<Timestamp>,
     /// The time the entry was last modified.
@@ -52,6 +69,20 @@ pub(crate) struct Metadata {
     pub(crate) inode: Option<u64>,
 }
 
+impl Metadata {
+    /// Returns the number of bytes allocated for the entry on disk.
+    ///
+    /// If the allocated size was not recorded, the size is returned instead.
+    pub(crate) fn allocated_size(&self) -> u64 {
+        self.allocated_size.unwrap_or(self.size)
+    }
+
+    /// Returns the number of bytes that are not allocated on disk due to sparseness or compression.
+    pub(crate) fn compression_savings(&self) -> u64 {
+        self.size.saturating_sub(self.allocated_size())
+    }
+}
+
 /// A common trait that all implementations of metadata should fulfill.
 pub(crate) trait GenericMetadata:
     Serialize + DeserializeOwned + Clone + Sized + Send
@@ -111,10 +142,12 @@ impl GenericMetadata for Metadata {
 
         let streams = AlternateDataStreams::from_path(path).ok();
 
-        let (unix_permissions, nlink, uid, gid, inode);
+        let (allocated_size, unix_permissions, nlink, uid, gid, inode);
         #[cfg(unix)]
         {
             use std::os::unix::fs::MetadataExt;
+            // `st_blocks` is always counted in 512 byte units
+            allocated_size = Some(metadata.blocks() * 512);
             unix_permissions = Some(metadata.mode());
             nlink = Some(metadata.nlink());
             uid = Some(metadata.uid());
@@ -123,6 +156,7 @@ impl GenericMetadata for Metadata {
         }
         #[cfg(not(unix))]
         {
+            allocated_size = None;
             unix_permissions = None;
             nlink = None;
             uid = None;
@@ -132,6 +166,7 @@ impl GenericMetadata for Metadata {
 
         Ok(Self {
             size,
+            allocated_size,
             created,
             modified,
             accessed,
@@ -155,6 +190,7 @@ impl GenericMetadata for Metadata {
     fn meaningless() -> Self {
         Self {
             size: 0,
+            allocated_size: None,
             created: None,
             modified: None,
             accessed: None,
@@ -203,15 +239,86 @@ bitflags::bitflags! {
     }
 }
 
+/// The default size up to which the contents of alternate data streams are stored in snapshots.
+pub(crate) const DEFAULT_MAX_INLINE_STREAM_LEN: u64 = 0x1000;
+
+/// An alternate data stream of a filesystem object.
+#[derive(Debug, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct AlternateDataStream {
+    /// The size of the stream in bytes.
+    pub(crate) size: u64,
+    /// The information about the contents of the stream, such as its hashes and entropy.
+    ///
+    /// Streams are recorded like regular files, so that they can be looked up in the database.
+    pub(crate) file: File,
+    /// The contents of the stream, if it is small enough to be stored in the snapshot.
+    pub(crate) content: Option<Vec<u8>>,
+}
+
+impl AlternateDataStream {
+    /// Records the stream with the contents of the given reader.
+    ///
+    /// The contents are only stored if the stream is at most `max_inline_len` bytes large.
+    pub(crate) fn from_reader(
+        mut reader: impl Read + Seek,
+        max_inline_len: u64,
+    ) -> io::Result<Self> {
+        let size = reader.seek(io::SeekFrom::End(0))?;
+        reader.rewind()?;
+
+        let content = if size <= max_inline_len {
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content)?;
+            reader.rewind()?;
+
+            Some(content)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            size,
+            file: File::from_reader(reader)?,
+            content,
+        })
+    }
+}
+
+impl PartialEq for AlternateDataStream {
+    fn eq(&self, other: &Self) -> bool {
+        // Whether the contents are stored depends only on the options of the snapshot
+        self.size == other.size && self.file == other.file
+    }
+}
+
+impl fmt::Display for AlternateDataStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes, entropy {:.2}, SHA-256 {}",
+            self.size, self.file.entropy, self.file.sha2_256_hash
+        )?;
+        if self.content.is_none() {
+            write!(f, ", contents not stored")?;
+        }
+
+        Ok(())
+    }
+}
+
 /// Alternate data streams of filesystem objects.
 #[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub(crate) struct AlternateDataStreams {
     /// The alternate data streams associated with a filesystem object.
-    pub(crate) streams: BTreeMap<OsString, Option<Vec<u8>>>,
+    ///
+    /// Streams that could not be read are `None`.
+    pub(crate) streams: BTreeMap<OsString, Option<AlternateDataStream>>,
 }
 
 impl AlternateDataStreams {
     /// Reads the alternate data stream for the specified path.
+    ///
+    /// The contents of all streams are kept, they can be removed with `limit_inline_len`.
     pub(crate) fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
         let path = path.as_ref();
 
@@ -220,15 +327,195 @@ impl AlternateDataStreams {
         let iter = xattr::list(path)?;
 
         for attr in iter {
-            if let Ok(Some(val)) = xattr::get(path, &attr) {
-                streams.insert(attr, Some(val));
-            } else {
-                streams.insert(attr, None);
-            }
+            let stream = xattr::get(path, &attr).ok().flatten().and_then(|val| {
+                AlternateDataStream::from_reader(io::Cursor::new(val), u64::MAX).ok()
+            });
+            streams.insert(attr, stream);
         }
 
         Ok(Self { streams })
     }
+
+    /// Removes the contents of the streams larger than `max_inline_len` bytes.
+    pub(crate) fn limit_inline_len(&mut self, max_inline_len: u64) {
+        for stream in self.streams.values_mut().flatten() {
+            if stream.size > max_inline_len {
+                stream.content = None;
+            }
+        }
+    }
+
+    /// Returns the contents of the stream with the given name, if they are stored.
+    pub(crate) fn content(&self, name: impl AsRef<std::ffi::OsStr>) -> Option<&[u8]> {
+        self.streams
+            .get(name.as_ref())?
+            .as_ref()?
+            .content
+            .as_deref()
+    }
+}
+
+/// Alternate data streams of filesystem objects, as in snapshots up to version 17.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct AlternateDataStreamsV1 {
+    /// The alternate data streams associated with a filesystem object.
+    pub(crate) streams: BTreeMap<OsString, Option<Vec<u8>>>,
+}
+
+impl From<AlternateDataStreamsV1> for AlternateDataStreams {
+    fn from(old: AlternateDataStreamsV1) -> Self {
+        Self {
+            streams: old
+                .streams
+                .into_iter()
+                .map(|(name, content)| {
+                    let stream = content.and_then(|content| {
+                        AlternateDataStream::from_reader(io::Cursor::new(content), u64::MAX).ok()
+                    });
+
+                    (name, stream)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Stores filesystem metadata about objects, as in snapshots of version 18.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct MetadataV3 {
+    /// The size of the entry in bytes.
+    pub(crate) size: u64,
+    /// The time the entry was created.
+    pub(crate) created: Option<Timestamp>,
+    /// The time the entry was last modified.
+    pub(crate) modified: Option<Timestamp>,
+    /// The time the entry was last accessed.
+    pub(crate) accessed: Option<Timestamp>,
+    /// The time the MFT entry was last modified.
+    ///
+    /// This means any changes to the file including changes to metadata.
+    pub(crate) mft_modified: Option<Timestamp>,
+    /// The NTFS attributes of the entry.
+    pub(crate) ntfs_attributes: Option<NtfsAttributes>,
+    /// The UNIX permissions of the file.
+    pub(crate) unix_permissions: Option<u32>,
+    /// The number of hard links to this file.
+    pub(crate) nlink: Option<u64>,
+    /// The UNIX user ID of the file.
+    pub(crate) uid: Option<u32>,
+    /// The UNIX group ID of the file.
+    pub(crate) gid: Option<u32>,
+    /// The NTFS reparse data.
+    pub(crate) reparse_data: Option<Vec<u8>>,
+    /// The NTFS access control list.
+    pub(crate) acl: Option<Vec<u8>>,
+    /// The NTFS dos name.
+    pub(crate) dos_name: Option<Vec<u8>>,
+    /// The NTFS object id.
+    pub(crate) object_id: Option<Vec<u8>>,
+    /// The NTFS encryption information.
+    pub(crate) efs_info: Option<Vec<u8>>,
+    /// The NTFS extended attributes.
+    pub(crate) ea: Option<Vec<u8>>,
+    /// The NTFS alternate data streams.
+    pub(crate) streams: Option<AlternateDataStreams>,
+    /// The inode of the entry.
+    pub(crate) inode: Option<u64>,
+}
+
+impl From<MetadataV3> for Metadata {
+    fn from(old: MetadataV3) -> Self {
+        Self {
+            size: old.size,
+            allocated_size: None,
+            created: old.created,
+            modified: old.modified,
+            accessed: old.accessed,
+            mft_modified: old.mft_modified,
+            ntfs_attributes: old.ntfs_attributes,
+            unix_permissions: old.unix_permissions,
+            nlink: old.nlink,
+            uid: old.uid,
+            gid: old.gid,
+            reparse_data: old.reparse_data,
+            acl: old.acl,
+            dos_name: old.dos_name,
+            object_id: old.object_id,
+            efs_info: old.efs_info,
+            ea: old.ea,
+            streams: old.streams,
+            inode: old.inode,
+        }
+    }
+}
+
+/// Stores filesystem metadata about objects, as in snapshots of versions 2 to 17.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct MetadataV2 {
+    /// The size of the entry in bytes.
+    pub(crate) size: u64,
+    /// The time the entry was created.
+    pub(crate) created: Option<Timestamp>,
+    /// The time the entry was last modified.
+    pub(crate) modified: Option<Timestamp>,
+    /// The time the entry was last accessed.
+    pub(crate) accessed: Option<Timestamp>,
+    /// The time the MFT entry was last modified.
+    ///
+    /// This means any changes to the file including changes to metadata.
+    pub(crate) mft_modified: Option<Timestamp>,
+    /// The NTFS attributes of the entry.
+    pub(crate) ntfs_attributes: Option<NtfsAttributes>,
+    /// The UNIX permissions of the file.
+    pub(crate) unix_permissions: Option<u32>,
+    /// The number of hard links to this file.
+    pub(crate) nlink: Option<u64>,
+    /// The UNIX user ID of the file.
+    pub(crate) uid: Option<u32>,
+    /// The UNIX group ID of the file.
+    pub(crate) gid: Option<u32>,
+    /// The NTFS reparse data.
+    pub(crate) reparse_data: Option<Vec<u8>>,
+    /// The NTFS access control list.
+    pub(crate) acl: Option<Vec<u8>>,
+    /// The NTFS dos name.
+    pub(crate) dos_name: Option<Vec<u8>>,
+    /// The NTFS object id.
+    pub(crate) object_id: Option<Vec<u8>>,
+    /// The NTFS encryption information.
+    pub(crate) efs_info: Option<Vec<u8>>,
+    /// The NTFS extended attributes.
+    pub(crate) ea: Option<Vec<u8>>,
+    /// The NTFS alternate data streams.
+    pub(crate) streams: Option<AlternateDataStreamsV1>,
+    /// The inode of the entry.
+    pub(crate) inode: Option<u64>,
+}
+
+impl From<MetadataV2> for Metadata {
+    fn from(old: MetadataV2) -> Self {
+        Self {
+            size: old.size,
+            allocated_size: None,
+            created: old.created,
+            modified: old.modified,
+            accessed: old.accessed,
+            mft_modified: old.mft_modified,
+            ntfs_attributes: old.ntfs_attributes,
+            unix_permissions: old.unix_permissions,
+            nlink: old.nlink,
+            uid: old.uid,
+            gid: old.gid,
+            reparse_data: old.reparse_data,
+            acl: old.acl,
+            dos_name: old.dos_name,
+            object_id: old.object_id,
+            efs_info: old.efs_info,
+            ea: old.ea,
+            streams: old.streams.map(AlternateDataStreams::from),
+            inode: old.inode,
+        }
+    }
 }
 
 /// Stores filesystem metadata about objects.
@@ -264,12 +551,12 @@ pub(crate) struct MetadataV1 {
     /// The NTFS extended attributes.
     pub(crate) ea: Option<Vec<u8>>,
     /// The NTFS alternate data streams.
-    pub(crate) streams: AlternateDataStreams,
+    pub(crate) streams: AlternateDataStreamsV1,
     /// The inode of the entry.
     pub(crate) inode: u64,
 }
 
-impl From<MetadataV1> for Metadata {
+impl From<MetadataV1> for MetadataV2 {
     fn from(old: MetadataV1) -> Self {
         Self {
             size: old.size,
@@ -0,0 +1,410 @@
+//! Decodes NTFS reparse points into the links, placeholders and compressed files they represent.
+//!
+//! The layouts of the reparse data buffers are described in the
+//! [MS-FSCC specification](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/c8e77b37-3909-4fe6-a4ea-2b9d423b1ee4).
+//! Reparse points of unknown types are kept with just their tag.
+
+use std::{fmt, str::FromStr};
+
+use serde::Serialize;
+
+/// The reparse tag of junctions and mount points.
+const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xa000_0003;
+
+/// The reparse tag of symbolic links.
+const IO_REPARSE_TAG_SYMLINK: u32 = 0xa000_000c;
+
+/// The reparse tag of files that were deduplicated.
+const IO_REPARSE_TAG_DEDUP: u32 = 0x8000_0013;
+
+/// The reparse tag of files compressed or backed by the Windows Overlay Filter.
+const IO_REPARSE_TAG_WOF: u32 = 0x8000_0017;
+
+/// The reparse tag of cloud file placeholders, such as those of OneDrive.
+///
+/// The bits in [`CLOUD_TAG_FLAGS`] may be set in the tags of placeholders.
+const IO_REPARSE_TAG_CLOUD: u32 = 0x9000_001a;
+
+/// The bits of the cloud file reparse tags that vary between placeholders.
+const CLOUD_TAG_FLAGS: u32 = 0x0000_f000;
+
+/// The reparse tag of app execution aliases of Windows Store apps.
+const IO_REPARSE_TAG_APPEXECLINK: u32 = 0x8000_001b;
+
+/// The reparse tag of symbolic links created by WSL.
+const IO_REPARSE_TAG_LX_SYMLINK: u32 = 0xa000_001d;
+
+/// The WOF provider of files backed by a WIM image.
+const WOF_PROVIDER_WIM: u32 = 1;
+
+/// The WOF provider of individually compressed files.
+const WOF_PROVIDER_FILE: u32 = 2;
+
+/// The names of reparse tags that are not decoded further.
+const OTHER_TAGS: &[(u32, &str)] = &[
+    (0xc000_0004, "HSM"),
+    (0x8000_0007, "single instance storage"),
+    (0x8000_000a, "DFS"),
+    (0x8000_0012, "DFS-R"),
+    (0x8000_0021, "OneDrive"),
+    (0x8000_0023, "unix socket"),
+    (0x8000_0024, "WSL FIFO"),
+    (0x8000_0025, "WSL character device"),
+    (0x8000_0026, "WSL block device"),
+    (0x9000_001c, "projected file system"),
+];
+
+/// The maximum number of strings read from an app execution alias.
+const MAX_APPEXECLINK_STRINGS: usize = 4;
+
+/// Reads a little endian `u16` at the given offset.
+fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+
+/// Reads a little endian `u32` at the given offset.
+fn le_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+/// Decodes UTF-16LE data, replacing invalid characters.
+fn utf16(data: &[u8]) -> String {
+    char::decode_utf16(
+        data.chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]])),
+    )
+    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    .collect()
+}
+
+/// The compression algorithm or backing of a file handled by the Windows Overlay Filter.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WofAlgorithm {
+    /// The file is backed by a WIM image, as done by WIMBoot.
+    Wim,
+    /// The file is compressed with XPRESS in 4KiB chunks.
+    Xpress4K,
+    /// The file is compressed with LZX in 32KiB chunks.
+    Lzx,
+    /// The file is compressed with XPRESS in 8KiB chunks.
+    Xpress8K,
+    /// The file is compressed with XPRESS in 16KiB chunks.
+    Xpress16K,
+    /// An unknown provider or algorithm.
+    Unknown {
+        /// The WOF provider.
+        provider: u32,
+        /// The algorithm of the provider.
+        algorithm: u32,
+    },
+}
+
+impl fmt::Display for WofAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WofAlgorithm::Wim => write!(f, "WIM"),
+            WofAlgorithm::Xpress4K => write!(f, "XPRESS4K"),
+            WofAlgorithm::Lzx => write!(f, "LZX"),
+            WofAlgorithm::Xpress8K => write!(f, "XPRESS8K"),
+            WofAlgorithm::Xpress16K => write!(f, "XPRESS16K"),
+            WofAlgorithm::Unknown {
+                provider,
+                algorithm,
+            } => write!(f, "unknown (provider {provider}, algorithm {algorithm})"),
+        }
+    }
+}
+
+/// A decoded reparse point.
+///
+/// It is serialized with its type in the `type` field, as carried in changesets.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ReparsePoint {
+    /// A junction or a mount point of a volume.
+    MountPoint {
+        /// The target of the link as used by the system.
+        substitute_name: String,
+        /// The target of the link as shown to users.
+        print_name: String,
+    },
+    /// A symbolic link.
+    Symlink {
+        /// The target of the link as used by the system.
+        substitute_name: String,
+        /// The target of the link as shown to users.
+        print_name: String,
+        /// Whether the target is relative to the directory of the link.
+        relative: bool,
+    },
+    /// A symbolic link created by WSL.
+    LxSymlink {
+        /// The target of the link.
+        target: String,
+    },
+    /// A file compressed or backed by the Windows Overlay Filter.
+    Wof {
+        /// The compression algorithm or backing of the file.
+        algorithm: WofAlgorithm,
+    },
+    /// A file whose contents were moved to the deduplication chunk store.
+    Dedup,
+    /// A placeholder of a file synchronized with a cloud service, such as OneDrive.
+    Cloud {
+        /// The reparse tag, which differs between kinds of placeholders.
+        tag: u32,
+    },
+    /// An app execution alias of a Windows Store app.
+    AppExecLink {
+        /// The package family name of the app.
+        package: String,
+        /// The application user model ID of the app.
+        app_id: String,
+        /// The executable that is started.
+        target: String,
+    },
+    /// A reparse point of another type.
+    Other {
+        /// The reparse tag.
+        tag: u32,
+    },
+}
+
+impl ReparsePoint {
+    /// Decodes the reparse data buffer, starting with the reparse tag.
+    ///
+    /// Returns `None` if the data of a known reparse tag is malformed.
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        let tag = le_u32(data, 0)?;
+        let len = usize::from(le_u16(data, 4)?);
+        let payload = data.get(8..8 + len)?;
+
+        let names = |buffer_start: usize| -> Option<(String, String)> {
+            let name = |offset: usize| {
+                let start = buffer_start + usize::from(le_u16(payload, offset)?);
+                let len = usize::from(le_u16(payload, offset + 2)?);
+
+                Some(utf16(payload.get(start..start.checked_add(len)?)?))
+            };
+
+            Some((name(0)?, name(4)?))
+        };
+
+        Some(match tag {
+            IO_REPARSE_TAG_MOUNT_POINT => {
+                let (substitute_name, print_name) = names(8)?;
+
+                ReparsePoint::MountPoint {
+                    substitute_name,
+                    print_name,
+                }
+            }
+            IO_REPARSE_TAG_SYMLINK => {
+                let (substitute_name, print_name) = names(12)?;
+
+                ReparsePoint::Symlink {
+                    substitute_name,
+                    print_name,
+                    relative: le_u32(payload, 8)? & 1 != 0,
+                }
+            }
+            IO_REPARSE_TAG_LX_SYMLINK => ReparsePoint::LxSymlink {
+                target: String::from_utf8_lossy(payload.get(4..)?).into_owned(),
+            },
+            IO_REPARSE_TAG_WOF => {
+                let provider = le_u32(payload, 4)?;
+                let algorithm = if provider == WOF_PROVIDER_FILE {
+                    le_u32(payload, 12)?
+                } else {
+                    0
+                };
+
+                ReparsePoint::Wof {
+                    algorithm: match (provider, algorithm) {
+                        (WOF_PROVIDER_WIM, _) => WofAlgorithm::Wim,
+                        (WOF_PROVIDER_FILE, 0) => WofAlgorithm::Xpress4K,
+                        (WOF_PROVIDER_FILE, 1) => WofAlgorithm::Lzx,
+                        (WOF_PROVIDER_FILE, 2) => WofAlgorithm::Xpress8K,
+                        (WOF_PROVIDER_FILE, 3) => WofAlgorithm::Xpress16K,
+                        (provider, algorithm) => WofAlgorithm::Unknown {
+                            provider,
+                            algorithm,
+                        },
+                    },
+                }
+            }
+            IO_REPARSE_TAG_DEDUP => ReparsePoint::Dedup,
+            tag if tag & !CLOUD_TAG_FLAGS == IO_REPARSE_TAG_CLOUD => ReparsePoint::Cloud { tag },
+            IO_REPARSE_TAG_APPEXECLINK => {
+                let strings = payload
+                    .get(4..)?
+                    .chunks_exact(2)
+                    .collect::<Vec<_>>()
+                    .split(|unit| *unit == [0, 0])
+                    .take(MAX_APPEXECLINK_STRINGS)
+                    .map(|units| utf16(&units.concat()))
+                    .collect::<Vec<_>>();
+                let [package, app_id, target, ..] = strings.as_slice() else {
+                    return None;
+                };
+
+                ReparsePoint::AppExecLink {
+                    package: package.clone(),
+                    app_id: app_id.clone(),
+                    target: target.clone(),
+                }
+            }
+            tag => ReparsePoint::Other { tag },
+        })
+    }
+
+    /// Returns the type of the reparse point.
+    pub(crate) fn reparse_type(&self) -> ReparseType {
+        match self {
+            ReparsePoint::MountPoint { .. } => ReparseType::MountPoint,
+            ReparsePoint::Symlink { .. } => ReparseType::Symlink,
+            ReparsePoint::LxSymlink { .. } => ReparseType::LxSymlink,
+            ReparsePoint::Wof { .. } => ReparseType::Wof,
+            ReparsePoint::Dedup => ReparseType::Dedup,
+            ReparsePoint::Cloud { .. } => ReparseType::Cloud,
+            ReparsePoint::AppExecLink { .. } => ReparseType::AppExecLink,
+            ReparsePoint::Other { .. } => ReparseType::Other,
+        }
+    }
+}
+
+impl fmt::Display for ReparsePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Writes the names of a link, omitting the print name if it is the same.
+        fn write_names(
+            f: &mut fmt::Formatter<'_>,
+            substitute_name: &str,
+            print_name: &str,
+        ) -> fmt::Result {
+            write!(f, "\"{substitute_name}\"")?;
+            if !print_name.is_empty() && print_name != substitute_name {
+                write!(f, " (shown as \"{print_name}\")")?;
+            }
+
+            Ok(())
+        }
+
+        match self {
+            ReparsePoint::MountPoint {
+                substitute_name,
+                print_name,
+            } => {
+                write!(f, "mount point to ")?;
+                write_names(f, substitute_name, print_name)
+            }
+            ReparsePoint::Symlink {
+                substitute_name,
+                print_name,
+                relative,
+            } => {
+                if *relative {
+                    write!(f, "relative ")?;
+                }
+                write!(f, "symlink to ")?;
+                write_names(f, substitute_name, print_name)
+            }
+            ReparsePoint::LxSymlink { target } => write!(f, "WSL symlink to \"{target}\""),
+            ReparsePoint::Wof {
+                algorithm: WofAlgorithm::Wim,
+            } => write!(f, "WOF backed by a WIM image"),
+            ReparsePoint::Wof { algorithm } => write!(f, "WOF compressed with {algorithm}"),
+            ReparsePoint::Dedup => write!(f, "deduplicated"),
+            ReparsePoint::Cloud { tag } => write!(f, "cloud file placeholder (tag {tag:#010x})"),
+            ReparsePoint::AppExecLink {
+                package,
+                app_id,
+                target,
+            } => write!(
+                f,
+                "app execution alias to \"{target}\" (app \"{app_id}\" in package \"{package}\")"
+            ),
+            ReparsePoint::Other { tag } => {
+                match OTHER_TAGS.iter().find(|(known, _)| known == tag) {
+                    Some((_, name)) => write!(f, "{name} (tag {tag:#010x})"),
+                    None => write!(f, "unknown (tag {tag:#010x})"),
+                }
+            }
+        }
+    }
+}
+
+/// The type of a reparse point, used to filter by it.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub(crate) enum ReparseType {
+    /// A junction or a mount point of a volume.
+    MountPoint,
+    /// A symbolic link.
+    Symlink,
+    /// A symbolic link created by WSL.
+    LxSymlink,
+    /// A file compressed or backed by the Windows Overlay Filter.
+    Wof,
+    /// A deduplicated file.
+    Dedup,
+    /// A cloud file placeholder.
+    Cloud,
+    /// An app execution alias.
+    AppExecLink,
+    /// Any other reparse point.
+    Other,
+}
+
+/// The names of all reparse types.
+const TYPES: &[(ReparseType, &str)] = &[
+    (ReparseType::MountPoint, "mount-point"),
+    (ReparseType::Symlink, "symlink"),
+    (ReparseType::LxSymlink, "lx-symlink"),
+    (ReparseType::Wof, "wof"),
+    (ReparseType::Dedup, "dedup"),
+    (ReparseType::Cloud, "cloud"),
+    (ReparseType::AppExecLink, "app-exec-link"),
+    (ReparseType::Other, "other"),
+];
+
+impl fmt::Display for ReparseType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = TYPES
+            .iter()
+            .find(|(ty, _)| ty == self)
+            .map(|(_, name)| *name)
+            .expect("every reparse type is listed in `TYPES`");
+
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for ReparseType {
+    type Err = anyhow::Error;
+
+    /// Parses a reparse type from its name, also accepting "junction" for mount points.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("junction") {
+            return Ok(ReparseType::MountPoint);
+        }
+
+        TYPES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+            .map(|&(ty, _)| ty)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown reparse type `{s}`, expected one of {}",
+                    TYPES
+                        .iter()
+                        .map(|(_, name)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
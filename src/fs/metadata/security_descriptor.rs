@@ -0,0 +1,541 @@
+//! Parses NTFS security descriptors into their owner, group and access control lists.
+//!
+//! The self-relative format is described in the
+//! [MS-DTYP specification](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-dtyp/7d4dac05-9cef-4563-a058-f108abecce1d).
+
+use std::{fmt, str::FromStr};
+
+/// The control flag indicating that the DACL is present.
+const SE_DACL_PRESENT: u16 = 0x0004;
+
+/// The control flag indicating that the SACL is present.
+const SE_SACL_PRESENT: u16 = 0x0010;
+
+/// The control flag indicating that the DACL was created with automatic inheritance.
+const SE_DACL_AUTO_INHERITED: u16 = 0x0400;
+
+/// The control flag indicating that the SACL was created with automatic inheritance.
+const SE_SACL_AUTO_INHERITED: u16 = 0x0800;
+
+/// The control flag indicating that the DACL does not inherit ACEs from the parent.
+const SE_DACL_PROTECTED: u16 = 0x1000;
+
+/// The control flag indicating that the SACL does not inherit ACEs from the parent.
+const SE_SACL_PROTECTED: u16 = 0x2000;
+
+/// The ACE flag indicating that the ACE applies only to children and not the entry itself.
+const INHERIT_ONLY_ACE: u8 = 0x08;
+
+/// The object ACE flag indicating that the object type GUID is present.
+const ACE_OBJECT_TYPE_PRESENT: u32 = 0x1;
+
+/// The object ACE flag indicating that the inherited object type GUID is present.
+const ACE_INHERITED_OBJECT_TYPE_PRESENT: u32 = 0x2;
+
+/// The maximum number of ACEs read from an ACL, to limit the effect of corrupted descriptors.
+const MAX_ACES: u16 = 0x400;
+
+/// The access rights that allow modifying an entry, its metadata or its permissions.
+const WRITE_RIGHTS: u32 = 0x0000_0002 // WRITE_DATA/ADD_FILE
+    | 0x0000_0004 // APPEND_DATA/ADD_SUBDIRECTORY
+    | 0x0000_0010 // WRITE_EA
+    | 0x0000_0040 // DELETE_CHILD
+    | 0x0000_0100 // WRITE_ATTRIBUTES
+    | 0x0001_0000 // DELETE
+    | 0x0004_0000 // WRITE_DAC
+    | 0x0008_0000 // WRITE_OWNER
+    | 0x1000_0000 // GENERIC_ALL
+    | 0x4000_0000; // GENERIC_WRITE
+
+/// The names of the access rights for files and directories, by their bit.
+const RIGHTS: &[(u32, &str)] = &[
+    (0x0000_0001, "READ_DATA"),
+    (0x0000_0002, "WRITE_DATA"),
+    (0x0000_0004, "APPEND_DATA"),
+    (0x0000_0008, "READ_EA"),
+    (0x0000_0010, "WRITE_EA"),
+    (0x0000_0020, "EXECUTE"),
+    (0x0000_0040, "DELETE_CHILD"),
+    (0x0000_0080, "READ_ATTRIBUTES"),
+    (0x0000_0100, "WRITE_ATTRIBUTES"),
+    (0x0001_0000, "DELETE"),
+    (0x0002_0000, "READ_CONTROL"),
+    (0x0004_0000, "WRITE_DAC"),
+    (0x0008_0000, "WRITE_OWNER"),
+    (0x0010_0000, "SYNCHRONIZE"),
+    (0x0100_0000, "ACCESS_SYSTEM_SECURITY"),
+    (0x1000_0000, "GENERIC_ALL"),
+    (0x2000_0000, "GENERIC_EXECUTE"),
+    (0x4000_0000, "GENERIC_WRITE"),
+    (0x8000_0000, "GENERIC_READ"),
+];
+
+/// The common combinations of access rights, with their SDDL abbreviation and name.
+const RIGHT_COMBINATIONS: &[(u32, Option<&str>, &str)] = &[
+    (0x001f_01ff, Some("FA"), "full control"),
+    (0x0013_01bf, None, "modify"),
+    (0x0012_00a9, None, "read & execute"),
+    (0x0012_0089, Some("FR"), "read"),
+    (0x0012_0116, Some("FW"), "write"),
+    (0x0012_00a0, Some("FX"), "execute"),
+    (0x1000_0000, Some("GA"), "generic all"),
+    (0xa000_0000, None, "generic read & execute"),
+];
+
+/// The well-known SIDs, with their SDDL abbreviation and name.
+const WELL_KNOWN_SIDS: &[(&str, Option<&str>, &str)] = &[
+    ("S-1-1-0", Some("WD"), "Everyone"),
+    ("S-1-3-0", Some("CO"), "CREATOR OWNER"),
+    ("S-1-3-1", Some("CG"), "CREATOR GROUP"),
+    ("S-1-5-2", Some("NU"), "NETWORK"),
+    ("S-1-5-4", Some("IU"), "INTERACTIVE"),
+    ("S-1-5-6", Some("SU"), "SERVICE"),
+    ("S-1-5-7", Some("AN"), "ANONYMOUS LOGON"),
+    ("S-1-5-11", Some("AU"), "Authenticated Users"),
+    ("S-1-5-18", Some("SY"), "SYSTEM"),
+    ("S-1-5-19", Some("LS"), "LOCAL SERVICE"),
+    ("S-1-5-20", Some("NS"), "NETWORK SERVICE"),
+    ("S-1-5-32-544", Some("BA"), "Administrators"),
+    ("S-1-5-32-545", Some("BU"), "Users"),
+    ("S-1-5-32-546", Some("BG"), "Guests"),
+    ("S-1-5-32-547", Some("PU"), "Power Users"),
+    ("S-1-15-2-1", Some("AC"), "ALL APPLICATION PACKAGES"),
+    ("S-1-15-2-2", None, "ALL RESTRICTED APPLICATION PACKAGES"),
+    ("S-1-16-4096", Some("LW"), "Low Mandatory Level"),
+    ("S-1-16-8192", Some("ME"), "Medium Mandatory Level"),
+    ("S-1-16-12288", Some("HI"), "High Mandatory Level"),
+    ("S-1-16-16384", Some("SI"), "System Mandatory Level"),
+    (
+        "S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464",
+        None,
+        "TrustedInstaller",
+    ),
+];
+
+/// The SIDs of groups that (almost) every user is a member of.
+const BROAD_GROUPS: &[&str] = &["S-1-1-0", "S-1-5-7", "S-1-5-11", "S-1-5-32-545"];
+
+/// Reads a little endian `u16` at the given offset.
+fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+
+/// Reads a little endian `u32` at the given offset.
+fn le_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+/// A security identifier of a user or group.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub(crate) struct Sid {
+    /// The revision of the SID format.
+    revision: u8,
+    /// The authority that issued the SID.
+    authority: u64,
+    /// The relative identifiers below the authority.
+    sub_authorities: Vec<u32>,
+}
+
+impl Sid {
+    /// Parses the SID at the given offset.
+    fn parse(data: &[u8], offset: usize) -> Option<Self> {
+        let revision = *data.get(offset)?;
+        let count = *data.get(offset + 1)?;
+        let authority = data.get(offset + 2..offset + 8)?;
+
+        Some(Self {
+            revision,
+            authority: authority
+                .iter()
+                .fold(0, |authority, &byte| (authority << 8) | u64::from(byte)),
+            sub_authorities: (0..usize::from(count))
+                .map(|i| le_u32(data, offset + 8 + i * 4))
+                .collect::<Option<_>>()?,
+        })
+    }
+
+    /// Returns the entry of the SID in the list of well-known SIDs.
+    fn well_known(&self) -> Option<(Option<&'static str>, &'static str)> {
+        let sid = self.to_string();
+
+        WELL_KNOWN_SIDS
+            .iter()
+            .find(|(known, _, _)| *known == sid)
+            .map(|&(_, alias, name)| (alias, name))
+    }
+
+    /// Returns the name of the SID, if it is well-known.
+    pub(crate) fn name(&self) -> Option<&'static str> {
+        self.well_known().map(|(_, name)| name)
+    }
+
+    /// Returns `true` if the SID is a group that (almost) every user is a member of.
+    pub(crate) fn is_broad_group(&self) -> bool {
+        BROAD_GROUPS.contains(&&*self.to_string())
+    }
+
+    /// Returns the SID as used in SDDL strings.
+    fn sddl(&self) -> String {
+        match self.well_known() {
+            Some((Some(alias), _)) => alias.to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Returns the SID with its name, if it is well-known.
+    pub(crate) fn display_name(&self) -> String {
+        match self.name() {
+            Some(name) => format!("{name} ({self})"),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S-{}-", self.revision)?;
+        if self.authority >> 32 == 0 {
+            write!(f, "{}", self.authority)?;
+        } else {
+            write!(f, "{:#014x}", self.authority)?;
+        }
+
+        for sub_authority in &self.sub_authorities {
+            write!(f, "-{sub_authority}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Sid {
+    type Err = anyhow::Error;
+
+    /// Parses a SID from its string form, its SDDL abbreviation or its well-known name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = WELL_KNOWN_SIDS
+            .iter()
+            .find(|(_, alias, name)| *alias == Some(s) || name.eq_ignore_ascii_case(s))
+            .map(|(sid, _, _)| *sid)
+            .unwrap_or(s);
+
+        let mut parts = s
+            .strip_prefix("S-")
+            .or_else(|| s.strip_prefix("s-"))
+            .ok_or_else(|| anyhow::anyhow!("a SID must start with \"S-\""))?
+            .split('-');
+        let revision = parts
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("the SID has no revision"))?
+            .parse()?;
+        let authority = parts
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("the SID has no authority"))?;
+        let authority = match authority.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16)?,
+            None => authority.parse()?,
+        };
+
+        Ok(Self {
+            revision,
+            authority,
+            sub_authorities: parts.map(str::parse).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// An access control entry, granting, denying or auditing access rights of a user or group.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct Ace {
+    /// The type of the ACE.
+    pub(crate) ace_type: u8,
+    /// The inheritance and audit flags of the ACE.
+    pub(crate) flags: u8,
+    /// The access rights the ACE applies to.
+    pub(crate) mask: u32,
+    /// The user or group the ACE applies to, if the type of the ACE is known.
+    pub(crate) sid: Option<Sid>,
+}
+
+impl Ace {
+    /// Parses the ACE at the given offset, returning it with its size.
+    fn parse(data: &[u8], offset: usize) -> Option<(Self, usize)> {
+        let ace_type = *data.get(offset)?;
+        let flags = *data.get(offset + 1)?;
+        let size = usize::from(le_u16(data, offset + 2)?);
+        let mask = le_u32(data, offset + 4)?;
+
+        let sid = match ace_type {
+            // Access allowed, denied, audit, alarm and their callback variants
+            0x0..=0x4 | 0x9 | 0xa | 0xd | 0xe | 0x11..=0x13 => Sid::parse(data, offset + 8),
+            // Object ACEs, which are followed by optional GUIDs
+            0x5..=0x8 | 0xb | 0xc | 0xf | 0x10 => {
+                let object_flags = le_u32(data, offset + 8)?;
+                let mut sid = offset + 12;
+                if object_flags & ACE_OBJECT_TYPE_PRESENT != 0 {
+                    sid += 16;
+                }
+                if object_flags & ACE_INHERITED_OBJECT_TYPE_PRESENT != 0 {
+                    sid += 16;
+                }
+
+                Sid::parse(data, sid)
+            }
+            _ => None,
+        };
+
+        Some((
+            Self {
+                ace_type,
+                flags,
+                mask,
+                sid,
+            },
+            size,
+        ))
+    }
+
+    /// Returns `true` if the ACE grants access rights.
+    pub(crate) fn is_allow(&self) -> bool {
+        matches!(self.ace_type, 0x0 | 0x5 | 0x9 | 0xb)
+    }
+
+    /// Returns `true` if the ACE applies to the entry itself and not only to its children.
+    pub(crate) fn applies_to_self(&self) -> bool {
+        self.flags & INHERIT_ONLY_ACE == 0
+    }
+
+    /// Returns a human readable name of the type of the ACE.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self.ace_type {
+            0x0 | 0x5 | 0x9 | 0xb => "allow",
+            0x1 | 0x6 | 0xa | 0xc => "deny",
+            0x2 | 0x7 | 0xd | 0xf => "audit",
+            0x3 | 0x8 | 0xe | 0x10 => "alarm",
+            0x11 => "label",
+            0x12 => "resource attribute",
+            0x13 => "scoped policy",
+            _ => "unknown",
+        }
+    }
+
+    /// Returns the type of the ACE as used in SDDL strings.
+    fn sddl_type(&self) -> String {
+        match self.ace_type {
+            0x0 => "A",
+            0x1 => "D",
+            0x2 => "AU",
+            0x3 => "AL",
+            0x5 => "OA",
+            0x6 => "OD",
+            0x7 => "OU",
+            0x8 => "OL",
+            0x9 => "XA",
+            0xa => "XD",
+            0xd => "XU",
+            0x11 => "ML",
+            0x12 => "RA",
+            0x13 => "SP",
+            ty => return format!("{ty:#x}"),
+        }
+        .to_string()
+    }
+
+    /// Returns the flags of the ACE as used in SDDL strings.
+    pub(crate) fn sddl_flags(&self) -> String {
+        [
+            (0x01, "OI"),
+            (0x02, "CI"),
+            (0x04, "NP"),
+            (0x08, "IO"),
+            (0x10, "ID"),
+            (0x40, "SA"),
+            (0x80, "FA"),
+        ]
+        .iter()
+        .filter(|&&(flag, _)| self.flags & flag != 0)
+        .map(|&(_, name)| name)
+        .collect()
+    }
+
+    /// Returns the name of the user or group of the ACE.
+    pub(crate) fn trustee(&self) -> String {
+        match &self.sid {
+            Some(sid) => sid.display_name(),
+            None => "<unknown>".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Ace {
+    /// Formats the ACE as an SDDL string.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rights = match RIGHT_COMBINATIONS
+            .iter()
+            .find(|&&(mask, alias, _)| mask == self.mask && alias.is_some())
+        {
+            Some((_, Some(alias), _)) => alias.to_string(),
+            _ => format!("{:#x}", self.mask),
+        };
+
+        write!(
+            f,
+            "({};{};{rights};;;{})",
+            self.sddl_type(),
+            self.sddl_flags(),
+            self.sid.as_ref().map(Sid::sddl).unwrap_or_default()
+        )
+    }
+}
+
+/// Returns the names of the access rights in `mask`.
+///
+/// Common combinations are named as a whole.
+pub(crate) fn rights_names(mask: u32) -> Vec<String> {
+    if let Some((_, _, name)) = RIGHT_COMBINATIONS
+        .iter()
+        .find(|(known, _, _)| *known == mask)
+    {
+        return vec![name.to_string()];
+    }
+
+    let mut names = RIGHTS
+        .iter()
+        .filter(|&&(right, _)| mask & right != 0)
+        .map(|(_, name)| name.to_string())
+        .collect::<Vec<_>>();
+    let unknown = RIGHTS.iter().fold(mask, |mask, (right, _)| mask & !right);
+    if unknown != 0 {
+        names.push(format!("{unknown:#x}"));
+    }
+
+    names
+}
+
+/// A self-relative security descriptor of a filesystem entry.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct SecurityDescriptor {
+    /// The control flags of the descriptor.
+    control: u16,
+    /// The owner of the entry.
+    pub(crate) owner: Option<Sid>,
+    /// The primary group of the entry.
+    pub(crate) group: Option<Sid>,
+    /// The discretionary access control list, which grants and denies access rights.
+    ///
+    /// `None` means that the DACL is missing, which grants everyone full access.
+    pub(crate) dacl: Option<Vec<Ace>>,
+    /// The system access control list, which controls auditing and integrity levels.
+    pub(crate) sacl: Option<Vec<Ace>>,
+}
+
+impl SecurityDescriptor {
+    /// Parses the self-relative security descriptor.
+    ///
+    /// Returns `None` if the descriptor is malformed.
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        firestorm::profile_fn!(parse_security_descriptor);
+
+        if *data.first()? != 1 {
+            return None;
+        }
+
+        let control = le_u16(data, 2)?;
+        let sid = |offset| match le_u32(data, offset)? {
+            0 => None,
+            offset => Sid::parse(data, offset as usize),
+        };
+        let acl = |offset, present| match le_u32(data, offset)? {
+            offset if offset != 0 && control & present != 0 => {
+                Some(parse_acl(data, offset as usize))
+            }
+            _ => None,
+        };
+
+        Some(Self {
+            control,
+            owner: sid(4),
+            group: sid(8),
+            sacl: acl(12, SE_SACL_PRESENT),
+            dacl: acl(16, SE_DACL_PRESENT),
+        })
+    }
+
+    /// Returns `true` if a broad group such as `Everyone` may modify the entry.
+    pub(crate) fn is_world_writable(&self) -> bool {
+        let Some(dacl) = &self.dacl else {
+            return true;
+        };
+
+        dacl.iter().any(|ace| {
+            ace.is_allow()
+                && ace.applies_to_self()
+                && ace.mask & WRITE_RIGHTS != 0
+                && ace.sid.as_ref().is_some_and(Sid::is_broad_group)
+        })
+    }
+}
+
+impl fmt::Display for SecurityDescriptor {
+    /// Formats the descriptor as an SDDL string.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(owner) = &self.owner {
+            write!(f, "O:{}", owner.sddl())?;
+        }
+        if let Some(group) = &self.group {
+            write!(f, "G:{}", group.sddl())?;
+        }
+
+        for (name, acl, protected, auto_inherited) in [
+            ("D", &self.dacl, SE_DACL_PROTECTED, SE_DACL_AUTO_INHERITED),
+            ("S", &self.sacl, SE_SACL_PROTECTED, SE_SACL_AUTO_INHERITED),
+        ] {
+            let Some(acl) = acl else {
+                if name == "D" {
+                    write!(f, "D:NO_ACCESS_CONTROL")?;
+                }
+                continue;
+            };
+
+            write!(f, "{name}:")?;
+            if self.control & protected != 0 {
+                write!(f, "P")?;
+            }
+            if self.control & auto_inherited != 0 {
+                write!(f, "AI")?;
+            }
+            for ace in acl {
+                write!(f, "{ace}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the ACEs of the ACL at the given offset.
+///
+/// Parsing stops at the first malformed ACE.
+fn parse_acl(data: &[u8], offset: usize) -> Vec<Ace> {
+    let Some(count) = le_u16(data, offset + 4) else {
+        return Vec::new();
+    };
+
+    let mut aces = Vec::new();
+    let mut ace = offset + 8;
+    for _ in 0..count.min(MAX_ACES) {
+        match Ace::parse(data, ace) {
+            Some((parsed, size)) if size >= 8 => {
+                aces.push(parsed);
+                ace += size;
+            }
+            _ => break,
+        }
+    }
+
+    aces
+}
@@ -0,0 +1,121 @@
+//! Parses the `Zone.Identifier` alternate data stream, also known as the Mark-of-the-Web.
+//!
+//! Browsers, mail clients and other applications attach this stream to files they download, so it
+//! tells where a file came from.
+//! The stream is an INI file with a `[ZoneTransfer]` section.
+
+use std::{ffi::OsStr, fmt};
+
+use super::AlternateDataStreams;
+
+/// The name of the stream in the file system, as exposed by `ntfs-3g`.
+const STREAM_NAME: &str = "user.Zone.Identifier";
+
+/// The zone ID of files downloaded from the internet.
+const URLZONE_INTERNET: u32 = 3;
+
+/// The names of the URL security zones.
+const ZONES: &[(u32, &str)] = &[
+    (0, "Local Machine"),
+    (1, "Local Intranet"),
+    (2, "Trusted Sites"),
+    (3, "Internet"),
+    (4, "Restricted Sites"),
+];
+
+/// The Mark-of-the-Web of a file.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub(crate) struct MarkOfTheWeb {
+    /// The URL security zone the file came from.
+    pub(crate) zone_id: Option<u32>,
+    /// The URL of the page the file was downloaded from.
+    pub(crate) referrer_url: Option<String>,
+    /// The URL of the file itself.
+    pub(crate) host_url: Option<String>,
+    /// The package of the application that downloaded the file, if it is a Windows Store app.
+    pub(crate) application: Option<String>,
+}
+
+impl MarkOfTheWeb {
+    /// Parses the contents of a `Zone.Identifier` stream.
+    ///
+    /// Returns `None` if the stream does not contain a `[ZoneTransfer]` section.
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        let text = match data {
+            [0xff, 0xfe, rest @ ..] => char::decode_utf16(
+                rest.chunks_exact(2)
+                    .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]])),
+            )
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+            _ => String::from_utf8_lossy(data).into_owned(),
+        };
+
+        let mut mark = None;
+        let mut in_section = false;
+        for line in text.lines().map(str::trim) {
+            if line.starts_with('[') {
+                in_section = line.eq_ignore_ascii_case("[ZoneTransfer]");
+                if in_section {
+                    mark.get_or_insert_with(MarkOfTheWeb::default);
+                }
+                continue;
+            }
+            if !in_section {
+                continue;
+            }
+            let (Some(mark), Some((key, value))) = (mark.as_mut(), line.split_once('=')) else {
+                continue;
+            };
+
+            let value = Some(value.trim().to_string());
+            match key.trim() {
+                "ZoneId" => mark.zone_id = value.and_then(|value| value.parse().ok()),
+                "ReferrerUrl" => mark.referrer_url = value,
+                "HostUrl" => mark.host_url = value,
+                "LastWriterPackageFamilyName" => mark.application = value,
+                _ => (),
+            }
+        }
+
+        mark
+    }
+
+    /// Returns the Mark-of-the-Web of a file with the given alternate data streams.
+    pub(crate) fn from_streams(streams: &AlternateDataStreams) -> Option<Self> {
+        streams
+            .content(OsStr::new(STREAM_NAME))
+            .and_then(MarkOfTheWeb::parse)
+    }
+
+    /// Returns the name of the zone the file came from.
+    pub(crate) fn zone_name(&self) -> &'static str {
+        self.zone_id
+            .and_then(|zone_id| ZONES.iter().find(|(id, _)| *id == zone_id))
+            .map(|(_, name)| *name)
+            .unwrap_or("unknown zone")
+    }
+
+    /// Returns `true` if the file was downloaded from the internet or a restricted site.
+    pub(crate) fn is_downloaded(&self) -> bool {
+        self.zone_id
+            .is_some_and(|zone_id| zone_id >= URLZONE_INTERNET)
+    }
+}
+
+impl fmt::Display for MarkOfTheWeb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.zone_name())?;
+        if let Some(host_url) = &self.host_url {
+            write!(f, " from {host_url}")?;
+        }
+        if let Some(referrer_url) = &self.referrer_url {
+            write!(f, " via {referrer_url}")?;
+        }
+        if let Some(application) = &self.application {
+            write!(f, " by {application}")?;
+        }
+
+        Ok(())
+    }
+}
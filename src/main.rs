@@ -34,6 +34,60 @@ enum Config {
         /// a comment to attach to the snapshot in the database
         #[structopt(short = "C", long)]
         comment: Option<String>,
+        /// the partitions of a disk image to snapshot
+        ///
+        /// either "largest" for the largest NTFS partition, "all" for all NTFS partitions (each
+        /// stored under `/partition<number>`), the number of a partition (such as "2"), the GUID
+        /// of a GPT partition (such as "{6A2F8C4E-1D3B-4F70-9E5A-B2C81D7F0A93}") or the label of a
+        /// GPT partition (such as "label:Windows")
+        ///
+        /// the "label:" prefix is optional, unless the label is "largest", "all", a number or a GUID
+        #[structopt(short = "p", long, default_value = "largest")]
+        partition: snapshot::partition::PartitionSelector,
+        /// a previous snapshot of the same system to reuse file hashes from
+        ///
+        /// the information about a file is reused if its size, modification times and inode
+        /// are unchanged
+        #[structopt(short = "r", long)]
+        reuse: Option<PathBuf>,
+        /// when reusing file hashes, read a random sample of the reused files again to verify them
+        #[structopt(long, requires = "reuse")]
+        paranoid: bool,
+        /// a glob pattern of paths to exclude from the snapshot
+        ///
+        /// patterns are matched against the absolute path in the snapshot, patterns not starting
+        /// with `/` match at any depth
+        #[structopt(short = "e", long, number_of_values = 1)]
+        exclude: Vec<String>,
+        /// a file containing patterns of paths to exclude from the snapshot, one per line
+        #[structopt(long)]
+        exclude_from: Option<PathBuf>,
+        /// a glob pattern of paths to include in the snapshot, even if a parent is excluded
+        #[structopt(short = "i", long, number_of_values = 1)]
+        include: Vec<String>,
+        /// what to do with excluded paths
+        ///
+        /// either "skip" to leave them out entirely, "metadata" to only store their metadata or
+        /// "hash" to store them completely, but report them as excluded in differences
+        #[structopt(long, default_value = "skip")]
+        excluded: snapshot::exclude::ExcludedMode,
+        /// the compression of the snapshot file, either "zstd", "gzip" or "none"
+        #[structopt(long, default_value = "zstd")]
+        compression: snapshot::compression::Compression,
+        /// a tag to attach to the snapshot in the form `key=value`, such as a case number
+        #[structopt(short = "t", long, number_of_values = 1, parse(try_from_str = parse_tag))]
+        tag: Vec<(String, String)>,
+        /// do not compute the SHA2-256 hash of the source image or device
+        #[structopt(long)]
+        skip_source_hash: bool,
+        /// do not parse the key and value trees of the registry hives in the snapshot
+        #[structopt(long)]
+        skip_registry: bool,
+        /// the size in bytes up to which the contents of alternate data streams are stored
+        ///
+        /// larger streams are only recorded with their size, hashes, entropy and first bytes
+        #[structopt(long, default_value = "4096")]
+        max_inline_stream_len: u64,
     },
     /// lists the contents of `entry` in `snapshot`
     Ls {
@@ -86,14 +140,54 @@ enum Config {
         #[structopt(short = "E", long)]
         ignore_extensions: Option<String>,
         /// the size metric to use for visualizations and sorting
+        ///
+        /// one of size-on-disk, allocated, compression-savings, size-of-change, number-of-files,
+        /// number-of-changed-files, number-of-added-files, number-of-removed-files or
+        /// number-of-changes
         #[structopt(short = "m", long, default_value = "size-on-disk")]
         size_metric: diff::SizeMetric,
         /// only include entries that have the given string as a substring
         #[structopt(short = "g", long)]
         grep: Option<String>,
+        /// only include PE files whose original filename differs from their name
+        #[structopt(long)]
+        original_name_mismatch: bool,
+        /// only include PE files whose company name contains the given string
+        #[structopt(long)]
+        company: Option<String>,
+        /// only include PE files that are unsigned or whose signature does not match their contents
+        #[structopt(long)]
+        unsigned: bool,
+        /// only include files of the given comma separated types, such as "pe,elf,script"
+        #[structopt(long = "type")]
+        file_types: Option<String>,
+        /// only include files whose extension contradicts their contents
+        #[structopt(long)]
+        extension_mismatch: bool,
+        /// only include ELF or Mach-O files loading a library whose name contains the given string
+        #[structopt(long)]
+        needed: Option<String>,
+        /// only include ELF files whose symbol table was removed
+        #[structopt(long)]
+        stripped: bool,
+        /// only include entries owned by the given SID, such as "S-1-5-18", "BA" or "SYSTEM"
+        #[structopt(long)]
+        owner: Option<String>,
+        /// only include entries that Everyone, Authenticated Users or Users may modify
+        #[structopt(long)]
+        world_writable: bool,
+        /// only include entries with reparse points of the given comma separated types, such as "wof,cloud"
+        #[structopt(long)]
+        reparse_type: Option<String>,
+        /// only include entries with a Mark-of-the-Web from the internet or a restricted site
+        #[structopt(long)]
+        downloaded: bool,
         /// a path to the database to use during the analysis
         #[structopt(short = "D", long)]
         database: Option<PathBuf>,
+        /// list the errors that occurred while creating the snapshots
+        #[structopt(long)]
+        show_errors: bool,
     },
     /// compute changesets between all adjacent snapshots in a folder
     Changesets {
@@ -111,6 +205,14 @@ enum Config {
         source: PathBuf,
         /// the folder where the updated snapshots are stored
         target: PathBuf,
+        /// the compression of the updated snapshot files, either "zstd", "gzip" or "none"
+        #[structopt(long, default_value = "zstd")]
+        compression: snapshot::compression::Compression,
+    },
+    /// checks the integrity of the given snapshot or all snapshots in the given folder
+    VerifySnapshot {
+        /// the file or folder of the snapshot(s) to verify
+        file_or_folder: PathBuf,
     },
     /// inserts all snapshots in the given folder into the database
     InsertIntoDatabase {
@@ -136,6 +238,47 @@ enum Config {
         #[structopt(short = "i", long)]
         ignore_unknown_hashes: bool,
     },
+    /// groups the unknown PE files in the given snapshot with known files that share their import
+    /// hash or Rich header hash
+    ClusterUnknown {
+        /// the snapshot with the unknown files
+        snapshot: PathBuf,
+        /// a path to the database to use during the analysis
+        #[structopt(short = "D", long)]
+        database: PathBuf,
+    },
+}
+
+/// Parses a tag in the form `key=value`.
+fn parse_tag(tag: &str) -> anyhow::Result<(String, String)> {
+    let (key, value) = tag
+        .split_once('=')
+        .context("tags must be in the form `key=value`")?;
+    anyhow::ensure!(!key.is_empty(), "the key of a tag must not be empty");
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Prints the tags, the source hash and the errors of the given snapshot.
+///
+/// The errors are only listed individually if `show_errors` is `true`.
+fn print_snapshot_info(snapshot: &snapshot::SnapshotLatest, show_errors: bool) {
+    if let Some(source_hash) = &snapshot.source_hash {
+        println!("Source SHA2-256: {source_hash}");
+    }
+    for (key, value) in &snapshot.tags {
+        println!("Tag {key}: {value}");
+    }
+    match snapshot.errors.len() {
+        0 => (),
+        1 => println!("1 error occurred while creating the snapshot"),
+        len => println!("{len} errors occurred while creating the snapshot"),
+    }
+    if show_errors {
+        for error in &snapshot.errors {
+            println!("  {error}");
+        }
+    }
 }
 
 /// The main function that executes when the program is launched.
@@ -166,11 +309,77 @@ fn run(config: Config) -> anyhow::Result<()> {
             out_dir,
             database,
             comment,
+            partition,
+            reuse,
+            paranoid,
+            mut exclude,
+            exclude_from,
+            include,
+            excluded,
+            compression,
+            tag,
+            skip_source_hash,
+            skip_registry,
+            max_inline_stream_len,
         } => {
             let time = std::time::Instant::now();
 
+            if let Some(exclude_from) = exclude_from {
+                exclude.extend(
+                    snapshot::exclude::ExcludeFilter::read_patterns(&exclude_from).with_context(
+                        || {
+                            format!(
+                                "Could not read exclude patterns from {}",
+                                exclude_from.display()
+                            )
+                        },
+                    )?,
+                );
+            }
+            let exclude = snapshot::exclude::ExcludeFilter::new(exclude, include, excluded)
+                .context("Could not parse exclude patterns")?;
+
+            let reuse = reuse
+                .map(|reuse| {
+                    snapshot::Snapshot::file_version(&reuse)
+                        .and_then(|version| {
+                            let previous = snapshot::Snapshot::from_file(&reuse)?;
+                            Ok(snapshot::reuse::Reuse::new(
+                                previous.root,
+                                version,
+                                paranoid,
+                            ))
+                        })
+                        .with_context(|| {
+                            format!("Could not read snapshot from file {}", reuse.display())
+                        })
+                })
+                .transpose()?;
+
+            let options = snapshot::CreateOptions {
+                partitions: partition,
+                reuse,
+                exclude,
+                tags: tag.into_iter().collect(),
+                skip_source_hash,
+                skip_registry,
+                max_inline_stream_len,
+            };
+
             eprintln!("Creating snapshot of {}", path.display());
-            let snapshot = snapshot::Snapshot::create(path).context("Could not create snapshot")?;
+            let snapshot =
+                snapshot::Snapshot::create(path, &options).context("Could not create snapshot")?;
+
+            if let Some(reuse) = &options.reuse {
+                let stats = reuse.stats();
+                eprintln!("Reused the information of {} files", stats.reused);
+                if paranoid {
+                    eprintln!(
+                        "Verified {} reused files, {} of them had changed",
+                        stats.verified, stats.mismatched
+                    );
+                }
+            }
 
             let file_name = format!("{:?}.snp", Timestamp::now())
                 .replace(' ', "_")
@@ -182,7 +391,7 @@ fn run(config: Config) -> anyhow::Result<()> {
             };
 
             snapshot
-                .to_file(&out_file)
+                .to_file(&out_file, compression)
                 .with_context(|| format!("Could not write snapshot file {}", out_file.display()))?;
 
             if let Some(database) = database {
@@ -221,13 +430,38 @@ fn run(config: Config) -> anyhow::Result<()> {
             ignore_extensions,
             size_metric,
             grep,
+            original_name_mismatch,
+            company,
+            unsigned,
+            file_types,
+            extension_mismatch,
+            needed,
+            stripped,
+            owner,
+            world_writable,
+            reparse_type,
+            downloaded,
             database,
+            show_errors,
         } => {
+            // The database checks the whole snapshot to identify it, so only the requested entry
+            // can be loaded without one
+            let load_entry = if database.is_none() {
+                entry.as_deref()
+            } else {
+                None
+            };
+
+            let former_digest = snapshot::Snapshot::file_digest(&former).ok().flatten();
+            let latter_digest = latter
+                .as_ref()
+                .and_then(|latter| snapshot::Snapshot::file_digest(latter).ok().flatten());
+
             let (former, latter) = std::thread::scope(|s| {
                 firestorm::profile_section!(load_snapshots);
 
                 let former = s.spawn(|| {
-                    snapshot::Snapshot::from_file(&former).with_context(|| {
+                    snapshot::Snapshot::from_file_at(&former, load_entry).with_context(|| {
                         format!("Could not read snapshot from file {}", former.display())
                     })
                 });
@@ -235,9 +469,14 @@ fn run(config: Config) -> anyhow::Result<()> {
                 let latter = s.spawn(|| {
                     latter
                         .map(|latter| {
-                            snapshot::Snapshot::from_file(&latter).with_context(|| {
-                                format!("Could not read snapshot from file {}", latter.display())
-                            })
+                            snapshot::Snapshot::from_file_at(&latter, load_entry).with_context(
+                                || {
+                                    format!(
+                                        "Could not read snapshot from file {}",
+                                        latter.display()
+                                    )
+                                },
+                            )
                         })
                         .transpose()
                 });
@@ -261,11 +500,56 @@ fn run(config: Config) -> anyhow::Result<()> {
                 None
             };
 
+            // Paths inside of registry hives are shown as a tree of keys and values instead
+            if let Some(entry) = &entry
+                && let Some((hive_path, key_path)) = diff::registry::locate(
+                    entry,
+                    former
+                        .hives
+                        .keys()
+                        .chain(latter.iter().flat_map(|latter| latter.hives.keys())),
+                )
+            {
+                let former_key = diff::registry::find_key(&former.hives, hive_path, &key_path);
+                let latter_key = match &latter {
+                    Some(latter) => diff::registry::find_key(&latter.hives, hive_path, &key_path),
+                    None => former_key,
+                };
+                let diff = diff::registry::KeyDiff::compute(former_key, latter_key)
+                    .with_context(|| format!("Could not find key {}", entry.display()))?;
+
+                println!(
+                    "Registry hive {} in snapshot of {} taken at {:?}",
+                    hive_path.display(),
+                    former.source,
+                    former.timestamp
+                );
+                if let Some(latter) = &latter {
+                    println!(
+                        "Compared to snapshot of {} taken at {:?}",
+                        latter.source, latter.timestamp
+                    );
+                }
+                println!();
+
+                let name = key_path
+                    .last()
+                    .map(String::as_str)
+                    .or_else(|| hive_path.file_name().and_then(|name| name.to_str()))
+                    .unwrap_or_default();
+                print!(
+                    "{}",
+                    diff.display_as_tree(name, summary_depth, show_unchanged || latter.is_none())
+                );
+
+                return Ok(());
+            }
+
             let full_diff = {
                 firestorm::profile_section!(diff_computation);
 
                 if let Some(ref latter) = latter {
-                    diff::DiffTree::compute(&former.root, &latter.root)
+                    diff::DiffTree::compute(&former, latter)?
                 } else {
                     diff::DiffTree::unchanged(&former.root)
                 }
@@ -316,6 +600,65 @@ fn run(config: Config) -> anyhow::Result<()> {
                 }));
             }
 
+            if original_name_mismatch {
+                filters.push(Box::new(diff::filters::pe_matches(
+                    diff::filters::original_name_mismatch,
+                )));
+            }
+
+            if let Some(company) = &company {
+                filters.push(Box::new(diff::filters::pe_matches(diff::filters::company(
+                    company,
+                ))));
+            }
+
+            if unsigned {
+                filters.push(Box::new(diff::filters::unsigned));
+            }
+
+            if let Some(file_types) = &file_types {
+                let file_types = file_types
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<anyhow::Result<_>>()
+                    .context("Could not parse the file types")?;
+                filters.push(Box::new(diff::filters::file_types(file_types)));
+            }
+
+            if extension_mismatch {
+                filters.push(Box::new(diff::filters::extension_mismatch));
+            }
+
+            if let Some(needed) = &needed {
+                filters.push(Box::new(diff::filters::needed(needed)));
+            }
+
+            if stripped {
+                filters.push(Box::new(diff::filters::stripped));
+            }
+
+            if let Some(owner) = &owner {
+                let owner = owner.parse().context("Could not parse the owner SID")?;
+                filters.push(Box::new(diff::filters::owner(owner)));
+            }
+
+            if world_writable {
+                filters.push(Box::new(diff::filters::world_writable));
+            }
+
+            if let Some(reparse_types) = &reparse_type {
+                let reparse_types = reparse_types
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<anyhow::Result<_>>()
+                    .context("Could not parse the reparse types")?;
+                filters.push(Box::new(diff::filters::reparse_types(reparse_types)));
+            }
+
+            if downloaded {
+                filters.push(Box::new(diff::filters::downloaded));
+            }
+
             if !show_known {
                 filters.push(Box::new(diff::filters::unknown_only));
             }
@@ -341,15 +684,52 @@ fn run(config: Config) -> anyhow::Result<()> {
 
                 println!("{}", serde_json::to_string_pretty(&changeset)?);
             } else {
+                println!(
+                    "Snapshot of {} taken at {:?}",
+                    former.source, former.timestamp
+                );
+                if let Some(digest) = &former_digest {
+                    println!("Digest: {digest}");
+                }
+                print_snapshot_info(&former, show_errors);
+                if let Some(table) = &former.partition_table {
+                    println!("{table}");
+                }
+                if let Some(exclusions) = &former.exclusions {
+                    println!("{exclusions}");
+                }
+                if let Some(latter) = &latter {
+                    println!(
+                        "Compared to snapshot of {} taken at {:?}",
+                        latter.source, latter.timestamp
+                    );
+                    if let Some(digest) = &latter_digest {
+                        println!("Digest: {digest}");
+                    }
+                    print_snapshot_info(latter, show_errors);
+                    if let Some(table) = &latter.partition_table {
+                        println!("{table}");
+                    }
+                    if let Some(exclusions) = &latter.exclusions {
+                        println!("{exclusions}");
+                    }
+                }
+                println!();
+
+                let hard_links = diff::hard_links::HardLinks::new(
+                    &former.hard_links,
+                    latter.as_ref().map(|latter| &latter.hard_links[..]),
+                );
                 print!(
                     "{}",
                     diff.display_as_tree(
-                        path.as_os_str(),
+                        path,
                         &filter,
                         summary_depth,
                         size_metric,
                         show_hashes,
                         database.as_ref(),
+                        &hard_links,
                     )
                 );
             }
@@ -472,7 +852,7 @@ fn run(config: Config) -> anyhow::Result<()> {
                 };
 
                 if let Some(prev_snapshot) = last_iter_snapshot {
-                    let diff = diff::DiffTree::compute(&prev_snapshot.root, &new_snapshot.root);
+                    let diff = diff::DiffTree::compute(&prev_snapshot, &new_snapshot)?;
 
                     let changeset = diff::compute_changeset(
                         "/",
@@ -499,7 +879,11 @@ fn run(config: Config) -> anyhow::Result<()> {
                 std::io::stdout().write_all(&encoded)?;
             };
         }
-        Config::UpdateSnapshots { source, target } => {
+        Config::UpdateSnapshots {
+            source,
+            target,
+            compression,
+        } => {
             let dir_iter = std::fs::read_dir(&source)
                 .with_context(|| format!("Failed to read directory {}", source.display()))?;
 
@@ -530,7 +914,7 @@ fn run(config: Config) -> anyhow::Result<()> {
                     }
                 };
 
-                match snapshot.to_file(&out_path) {
+                match snapshot.to_file(&out_path, compression) {
                     Ok(()) => (),
                     Err(err) => {
                         eprintln!(
@@ -545,6 +929,48 @@ fn run(config: Config) -> anyhow::Result<()> {
                 eprintln!("DONE");
             }
         }
+        Config::VerifySnapshot { file_or_folder } => {
+            let paths = if file_or_folder.is_file() {
+                vec![file_or_folder]
+            } else {
+                let dir_iter = std::fs::read_dir(&file_or_folder).with_context(|| {
+                    format!("Failed to read directory {}", file_or_folder.display())
+                })?;
+
+                let mut paths = Vec::new();
+                for entry in dir_iter {
+                    match entry {
+                        Ok(entry) if entry.path().is_file() => paths.push(entry.path()),
+                        Ok(_) => (),
+                        Err(err) => eprintln!("error getting directory entry: {}", err),
+                    }
+                }
+                paths.sort();
+
+                paths
+            };
+
+            let mut num_failed = 0;
+            for path in &paths {
+                let result = snapshot::Snapshot::verify_file(path);
+
+                match result {
+                    Ok(Some(digest)) => println!("{}: OK, digest {digest}", path.display()),
+                    Ok(None) => println!(
+                        "{}: OK, but created by an older version without a digest",
+                        path.display()
+                    ),
+                    Err(err) => {
+                        num_failed += 1;
+                        println!("{}: FAILED: {err:#}", path.display());
+                    }
+                }
+            }
+
+            if num_failed > 0 {
+                anyhow::bail!("{num_failed}/{} snapshots failed verification", paths.len());
+            }
+        }
         Config::InsertIntoDatabase {
             file_or_folder,
             database,
@@ -668,6 +1094,22 @@ fn run(config: Config) -> anyhow::Result<()> {
                 println!("{}", result);
             }
         }
+        Config::ClusterUnknown { snapshot, database } => {
+            let mut db = database::Database::open(&database).context("Could not open database")?;
+            let snapshot = snapshot::Snapshot::from_file(&snapshot).with_context(|| {
+                format!("Could not read snapshot from file {}", snapshot.display())
+            })?;
+
+            db.main_snapshot(&snapshot)
+                .context("Could not communicate with database")?;
+
+            for cluster in db
+                .cluster_unknown_files(&snapshot)
+                .context("Could not cluster the unknown files")?
+            {
+                println!("{}", cluster);
+            }
+        }
     }
 
     Ok(())
@@ -4,6 +4,8 @@ use anyhow::Context;
 use serde::{Deserialize, Serialize};
 
 use std::{
+    collections::{hash_map, BTreeMap, BTreeSet, HashMap},
+    fmt,
     fs::File,
     io,
     path::{Path, PathBuf},
@@ -11,12 +13,21 @@ use std::{
 
 use crate::{
     autoruns::Autoruns,
-    fs::{DEntry, MetaDirEntry, Metadata},
+    fs::{file::Sha256Hash, DEntry, DirEntry, MetaDEntry, MetaDirEntry, Metadata},
     timestamp::Timestamp,
     updates::Updates,
 };
 
-mod vdi_mount;
+mod chunks;
+pub(crate) mod compression;
+pub(crate) mod errors;
+pub(crate) mod exclude;
+mod image;
+mod ntfs;
+pub(crate) mod partition;
+mod read_at;
+pub(crate) mod registry;
+pub(crate) mod reuse;
 
 /// The magic string that's used to identify snapshot files.
 const MAGIC_STR: &str = "snpsht";
@@ -35,7 +46,69 @@ const MAGIC_STR: &str = "snpsht";
 /// ### Version 2
 /// - Made all metadata fields except for size optional
 /// - Added fields for UNIX metadata (permissions, uid, gid, nlink)
-const CURRENT_SNAPSHOT_VERSION: u8 = 2;
+///
+/// ### Version 3
+/// - Added the partition table of the source disk
+///
+/// ### Version 4
+/// - Added the record of excluded paths
+///
+/// ### Version 5
+/// - Stored the directory tree in independently compressed chunks with an index, so that parts
+///   of it can be read without decoding everything
+///
+/// ### Version 6
+/// - Recorded the compression codec of the chunks in the file header
+///
+/// ### Version 7
+/// - Added a SHA2-256 digest of the data after the header to the file header, to detect corrupted
+///   and truncated files
+///
+/// ### Version 8
+/// - Added user defined tags and the SHA2-256 hash of the source image
+///
+/// ### Version 9
+/// - Added the log of errors that occurred while creating the snapshot
+///
+/// ### Version 10
+/// - Added the key and value trees of the registry hives, stored in their own chunks
+///
+/// ### Version 11
+/// - Added the structure of PE files (sections, imports, exports, overlay and version
+///   information)
+///
+/// ### Version 12
+/// - Added the Authenticode signatures of PE files
+///
+/// ### Version 13
+/// - Added TLSH digests of files
+///
+/// ### Version 14
+/// - Added the import hash and the Rich header hash of PE files
+///
+/// ### Version 15
+/// - Added the type of files identified by their contents
+///
+/// ### Version 16
+/// - Added the structure of ELF and Mach-O files
+///
+/// ### Version 17
+/// - Added hashes of the blocks of large files
+///
+/// ### Version 18
+/// - Recorded alternate data streams with their size, hashes, entropy and first bytes and stored
+///   their contents only up to a configurable size
+///
+/// ### Version 19
+/// - Added the allocated size of entries
+///
+/// ### Version 20
+/// - Added SHA2-256 digests of each chunk and of the index, so that only the decoded chunks need
+///   to be hashed when reading a snapshot
+///
+/// ### Version 21
+/// - Added the groups of paths that are hard links to the same file
+const CURRENT_SNAPSHOT_VERSION: u8 = 21;
 
 /// The header of a snapshot file with version information, to allow backwards compatible changes.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -44,6 +117,14 @@ struct SnapshotFileHeader {
     magic: String,
     /// The version of the snapshot data structure that is used.
     version: u8,
+    /// The compression codec used for the data after the header.
+    ///
+    /// This is only stored in the file since version 6, older versions always use gzip.
+    compression: compression::Compression,
+    /// The digest of the data after the header.
+    ///
+    /// This is only stored in the file since version 7.
+    digest: Option<Sha256Hash>,
 }
 
 impl Default for SnapshotFileHeader {
@@ -51,6 +132,8 @@ impl Default for SnapshotFileHeader {
         SnapshotFileHeader {
             magic: MAGIC_STR.to_string(),
             version: CURRENT_SNAPSHOT_VERSION,
+            compression: compression::Compression::default(),
+            digest: None,
         }
     }
 }
@@ -65,16 +148,31 @@ impl SnapshotFileHeader {
             .allow_trailing_bytes()
     }
 
-    /// Writes the header with the most recent version to a file.
-    fn write_to_file(file: &mut impl io::Write) -> anyhow::Result<()> {
-        Self::default().write_self_to_file(file)
+    /// Returns the header with the most recent version, the given compression and the given
+    /// digest of the data.
+    fn new(compression: compression::Compression, digest: Sha256Hash) -> Self {
+        Self {
+            compression,
+            digest: Some(digest),
+            ..Self::default()
+        }
     }
 
     /// Writes the header to a file.
     fn write_self_to_file(&self, file: &mut impl io::Write) -> anyhow::Result<()> {
         use bincode::Options as _;
 
-        Self::bincode().serialize_into(file, &self)?;
+        Self::bincode().serialize_into(&mut *file, &(&self.magic, self.version))?;
+        if self.version >= 6 {
+            Self::bincode().serialize_into(&mut *file, &self.compression)?;
+        }
+        if self.version >= 7 {
+            let digest = self
+                .digest
+                .as_ref()
+                .context("snapshot files of version 7 and later require a digest")?;
+            Self::bincode().serialize_into(&mut *file, digest)?;
+        }
 
         Ok(())
     }
@@ -83,13 +181,79 @@ impl SnapshotFileHeader {
     fn read_from_file(file: &mut impl io::Read) -> anyhow::Result<Self> {
         use bincode::Options as _;
 
-        let header: Self = Self::bincode().deserialize_from(file)?;
+        let (magic, version): (String, u8) = Self::bincode()
+            .deserialize_from(&mut *file)
+            .context("could not read the snapshot file header, the file may be truncated")?;
 
-        if header.magic != MAGIC_STR {
-            anyhow::bail!("unexpected magic header string: {}", header.magic);
+        if magic != MAGIC_STR {
+            anyhow::bail!("unexpected magic header string: {magic}");
         }
 
-        Ok(header)
+        let compression = if version >= 6 {
+            Self::bincode()
+                .deserialize_from(&mut *file)
+                .context("unknown compression codec")?
+        } else {
+            compression::Compression::Gzip
+        };
+
+        let digest = if version >= 7 {
+            Some(
+                Self::bincode()
+                    .deserialize_from(&mut *file)
+                    .context("could not read the digest in the snapshot file header")?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Self {
+            magic,
+            version,
+            compression,
+            digest,
+        })
+    }
+
+    /// Checks that the data remaining in `file` matches the digest in the header.
+    ///
+    /// Headers of versions without a digest are always accepted.
+    fn verify(&self, file: &mut impl io::Read) -> anyhow::Result<()> {
+        firestorm::profile_method!(verify);
+
+        use sha2::Digest as _;
+
+        let Some(expected) = &self.digest else {
+            return Ok(());
+        };
+
+        let mut hasher = sha2::Sha256::new();
+        io::copy(file, &mut hasher).context("could not read the snapshot data")?;
+        let found = Sha256Hash {
+            bytes: hasher.finalize().into(),
+        };
+
+        if &found != expected {
+            anyhow::bail!(
+                "the snapshot file is corrupted or truncated: its data has the digest {found}, \
+                but {expected} was recorded"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the context for errors while decoding the snapshot data after the header.
+    fn undetected_corruption(&self) -> String {
+        if self.digest.is_some() {
+            "could not decode the snapshot data".to_string()
+        } else {
+            format!(
+                "could not decode the snapshot data, the file may be corrupted or truncated \
+                (version {} snapshots contain no digest to detect this)",
+                self.version
+            )
+        }
     }
 }
 
@@ -100,6 +264,143 @@ pub(crate) enum Source {
     Directory(PathBuf),
     /// The snapshot was created from the given VDI image.
     VdiImage(PathBuf),
+    /// The snapshot was created from the given NTFS volume image or device.
+    NtfsVolume(PathBuf),
+    /// The snapshot was created from the given VMDK image.
+    VmdkImage(PathBuf),
+    /// The snapshot was created from the given VHD image.
+    VhdImage(PathBuf),
+    /// The snapshot was created from the given VHDX image.
+    VhdxImage(PathBuf),
+    /// The snapshot was created from the given QCOW2 image.
+    Qcow2Image(PathBuf),
+    /// The snapshot was created from the given raw disk image or device.
+    RawImage(PathBuf),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, path) = match self {
+            Source::Directory(path) => ("directory", path),
+            Source::VdiImage(path) => ("VDI image", path),
+            Source::NtfsVolume(path) => ("NTFS volume", path),
+            Source::VmdkImage(path) => ("VMDK image", path),
+            Source::VhdImage(path) => ("VHD image", path),
+            Source::VhdxImage(path) => ("VHDX image", path),
+            Source::Qcow2Image(path) => ("QCOW2 image", path),
+            Source::RawImage(path) => ("raw disk image", path),
+        };
+
+        write!(f, "{kind} {}", path.display())
+    }
+}
+
+/// Options that control how a snapshot is created.
+pub(crate) struct CreateOptions {
+    /// Selects which partitions of a disk image are included in the snapshot.
+    pub(crate) partitions: partition::PartitionSelector,
+    /// The file information of a previous snapshot that is reused for unchanged files.
+    pub(crate) reuse: Option<reuse::Reuse>,
+    /// Decides which paths are excluded from the snapshot.
+    pub(crate) exclude: exclude::ExcludeFilter,
+    /// The tags to attach to the snapshot.
+    pub(crate) tags: BTreeMap<String, String>,
+    /// Whether to skip hashing the source image or device.
+    pub(crate) skip_source_hash: bool,
+    /// Whether to skip parsing the registry hives.
+    pub(crate) skip_registry: bool,
+    /// The size up to which the contents of alternate data streams are stored.
+    pub(crate) max_inline_stream_len: u64,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        Self {
+            partitions: Default::default(),
+            reuse: None,
+            exclude: Default::default(),
+            tags: BTreeMap::new(),
+            skip_source_hash: false,
+            skip_registry: false,
+            max_inline_stream_len: crate::fs::metadata::DEFAULT_MAX_INLINE_STREAM_LEN,
+        }
+    }
+}
+
+/// Returns `true` if the given path refers to a block device.
+fn is_block_device(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt as _;
+
+    std::fs::metadata(path)
+        .map(|metadata| metadata.file_type().is_block_device())
+        .unwrap_or(false)
+}
+
+/// Returns the device and inode of the walked file, if it has more than one hard link.
+fn hard_link_key(entry: &walkdir::DirEntry) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt as _;
+
+    if !entry.file_type().is_file() {
+        return None;
+    }
+
+    let metadata = entry.metadata().ok()?;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+/// Computes the SHA2-256 hash of the image or device at the given path.
+fn hash_source(path: &Path) -> io::Result<Sha256Hash> {
+    firestorm::profile_fn!(hash_source);
+
+    use sha2::Digest as _;
+
+    let mut reader = io::BufReader::with_capacity(1 << 20, File::open(path)?);
+    let mut hasher = sha2::Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+
+    Ok(Sha256Hash {
+        bytes: hasher.finalize().into(),
+    })
+}
+
+/// Parses the registry hive files in the directory tree `root`, which is placed at
+/// `snapshot_root` in the snapshot.
+///
+/// The contents of the hive files are read with `read`, given their path in `root`.
+/// Hive files that cannot be read or parsed are skipped with a warning.
+fn read_hives(
+    root: &MetaDEntry,
+    snapshot_root: &Path,
+    read: impl Fn(&Path) -> anyhow::Result<Vec<u8>> + Sync,
+) -> BTreeMap<PathBuf, registry::Hive> {
+    firestorm::profile_fn!(read_hives);
+
+    use rayon::prelude::*;
+
+    let paths = root
+        .walk()
+        .filter(|entry| match &entry.entry.entry {
+            DirEntry::File(file) => registry::is_hive(&file.first_bytes),
+            _ => false,
+        })
+        .map(|entry| entry.clone_path())
+        .collect::<Vec<_>>();
+
+    paths
+        .into_par_iter()
+        .filter_map(|path| {
+            let hive = read(&path).and_then(|data| registry::parse(&data));
+            let path = snapshot_root.join(path.strip_prefix("/").unwrap_or(&path));
+
+            match hive {
+                Ok(hive) => hive.map(|hive| (path, hive)),
+                Err(err) => {
+                    eprintln!("could not parse registry hive {}: {err:#}", path.display());
+                    None
+                }
+            }
+        })
+        .collect()
 }
 
 /// Represents a snapshot of a directory.
@@ -109,6 +410,21 @@ pub(crate) struct Snapshot<DirEntry, Metadata, Autoruns, Updates> {
     pub(crate) root: crate::fs::MetaDirEntry<DirEntry, Metadata, ()>,
     /// The source of the snapshot.
     pub(crate) source: Source,
+    /// The SHA2-256 hash of the source image or device, computed while creating the snapshot.
+    pub(crate) source_hash: Option<Sha256Hash>,
+    /// The partition table of the source disk, if it had one.
+    pub(crate) partition_table: Option<partition::PartitionTable>,
+    /// The paths that were excluded from the snapshot, if any patterns were given.
+    pub(crate) exclusions: Option<exclude::Exclusions>,
+    /// User defined tags of the snapshot, such as case numbers or examiner names.
+    pub(crate) tags: BTreeMap<String, String>,
+    /// The errors that occurred while creating the snapshot.
+    ///
+    /// The entries at the paths of these errors are missing or incomplete in the snapshot.
+    pub(crate) errors: Vec<errors::AcquisitionError>,
+    /// The groups of paths that refer to the same file, for files with more than one hard link in
+    /// the snapshot.
+    pub(crate) hard_links: Vec<Vec<PathBuf>>,
     /// The creation time of the snapshot.
     pub(crate) timestamp: Timestamp,
     /// The version of the system in question.
@@ -119,6 +435,65 @@ pub(crate) struct Snapshot<DirEntry, Metadata, Autoruns, Updates> {
     pub(crate) autoruns: Option<Autoruns>,
     /// Data about the updates installed on the system.
     pub(crate) updates: Option<Updates>,
+    /// The key and value trees of the registry hive files in the snapshot, by their path.
+    pub(crate) hives: BTreeMap<PathBuf, registry::Hive>,
+}
+
+/// Represents a snapshot of a directory in versions 1 and 2 of the snapshot format.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct SnapshotV2<DirEntry, Metadata, Autoruns, Updates> {
+    /// The root directory of the snapshot.
+    root: crate::fs::MetaDirEntry<DirEntry, Metadata, ()>,
+    /// The source of the snapshot.
+    source: Source,
+    /// The creation time of the snapshot.
+    timestamp: Timestamp,
+    /// The version of the system in question.
+    version: Option<String>,
+    /// Data about the autoruns on the system.
+    autoruns: Option<Autoruns>,
+    /// Data about the updates installed on the system.
+    updates: Option<Updates>,
+}
+
+/// Represents a snapshot of a directory in version 3 of the snapshot format.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct SnapshotV3<DirEntry, Metadata, Autoruns, Updates> {
+    /// The root directory of the snapshot.
+    root: crate::fs::MetaDirEntry<DirEntry, Metadata, ()>,
+    /// The source of the snapshot.
+    source: Source,
+    /// The partition table of the source disk, if it had one.
+    partition_table: Option<partition::PartitionTable>,
+    /// The creation time of the snapshot.
+    timestamp: Timestamp,
+    /// The version of the system in question.
+    version: Option<String>,
+    /// Data about the autoruns on the system.
+    autoruns: Option<Autoruns>,
+    /// Data about the updates installed on the system.
+    updates: Option<Updates>,
+}
+
+/// Represents a snapshot of a directory in version 4 of the snapshot format.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct SnapshotV4<DirEntry, Metadata, Autoruns, Updates> {
+    /// The root directory of the snapshot.
+    root: crate::fs::MetaDirEntry<DirEntry, Metadata, ()>,
+    /// The source of the snapshot.
+    source: Source,
+    /// The partition table of the source disk, if it had one.
+    partition_table: Option<partition::PartitionTable>,
+    /// The paths that were excluded from the snapshot, if any patterns were given.
+    exclusions: Option<exclude::Exclusions>,
+    /// The creation time of the snapshot.
+    timestamp: Timestamp,
+    /// The version of the system in question.
+    version: Option<String>,
+    /// Data about the autoruns on the system.
+    autoruns: Option<Autoruns>,
+    /// Data about the updates installed on the system.
+    updates: Option<Updates>,
 }
 
 /// The latest version of the snapshot format.
@@ -126,46 +501,269 @@ pub(crate) type SnapshotLatest = Snapshot<DEntry, Metadata, Autoruns, Updates>;
 
 impl SnapshotLatest {
     /// Creates a new snapshot of the specified location.
-    pub(crate) fn create(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    pub(crate) fn create(path: impl AsRef<Path>, options: &CreateOptions) -> anyhow::Result<Self> {
         let path = path.as_ref();
 
-        if path.is_dir() {
-            Ok(Self::create_from_dir(path))
-        } else if path.is_file() {
-            let mut file = io::BufReader::new(std::fs::File::open(path)?);
+        let mut snapshot = if path.is_dir() {
+            Self::create_from_dir(path, options)
+        } else if path.is_file() || is_block_device(path) {
+            // The source is hashed in parallel to creating the snapshot, so that the snapshot
+            // doesn't take much longer than reading the source once
+            let (snapshot, source_hash) = std::thread::scope(|scope| {
+                let source_hash = (!options.skip_source_hash).then(|| {
+                    scope.spawn(|| {
+                        hash_source(path)
+                            .with_context(|| format!("could not hash {}", path.display()))
+                    })
+                });
+                let snapshot = Self::create_from_file(path, options);
+
+                (snapshot, source_hash.map(|handle| handle.join().unwrap()))
+            });
 
-            use io::BufRead as _;
+            let mut snapshot = snapshot?;
+            snapshot.source_hash = source_hash.transpose()?;
+            snapshot
+        } else {
+            anyhow::bail!(
+                "snapshot cannot be created from something that isn't a directory, disk image or NTFS volume"
+            )
+        };
 
-            let mut line = String::new();
-            file.read_line(&mut line)?;
+        snapshot.tags = options.tags.clone();
 
-            if line == "<<< Oracle VM VirtualBox Disk Image >>>\n" {
-                Self::create_from_vdi(path)
-            } else {
-                Err(anyhow::anyhow!("provided file is not a VDI image"))
-            }
-        } else {
-            Err(anyhow::anyhow!(
-                "snapshot cannot be created from something that isn't a directory or VDI image"
-            ))
+        Ok(snapshot)
+    }
+
+    /// Creates a new snapshot of the disk image or NTFS volume at the specified path.
+    fn create_from_file(path: &Path, options: &CreateOptions) -> anyhow::Result<Self> {
+        let device = read_at::HostFile::open(path)
+            .with_context(|| format!("could not open {}", path.display()))?;
+
+        // A volume without a partition table may look like a raw disk image, so it is checked
+        // for first
+        if ntfs::is_ntfs_volume(&device) {
+            return Self::create_from_ntfs(
+                device,
+                Source::NtfsVolume(path.to_path_buf()),
+                options,
+                Path::new("/"),
+            );
         }
+
+        let format = image::ImageFormat::detect(&device)
+            .with_context(|| format!("could not read {}", path.display()))?
+            .context("provided file is neither a known disk image format nor an NTFS volume")?;
+
+        Self::create_from_image(path, format, options)
     }
 
-    /// Creates a new snapshot of the largest partition in the specified VDI image.
-    pub(crate) fn create_from_vdi(path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let path = path.as_ref();
+    /// Creates a new snapshot of the selected partitions in the specified disk image.
+    fn create_from_image(
+        path: &Path,
+        format: image::ImageFormat,
+        options: &CreateOptions,
+    ) -> anyhow::Result<Self> {
+        let image = format
+            .open(path)
+            .with_context(|| format!("could not open {format} image {}", path.display()))?;
+
+        Self::create_from_disk(image, format.source(path.to_path_buf()), options)
+    }
 
-        let mount = vdi_mount::VDIMount::new(path)?;
+    /// Creates a new snapshot of the selected partitions on the given disk.
+    ///
+    /// If the disk has no partition table, it is read as a single volume.
+    fn create_from_disk(
+        disk: impl read_at::ReadAt,
+        source: Source,
+        options: &CreateOptions,
+    ) -> anyhow::Result<Self> {
+        use partition::PartitionSelector;
+
+        let selector = &options.partitions;
+
+        let Some(mut table) = partition::read_partition_table(&disk)? else {
+            if ntfs::is_ntfs_volume(&disk) {
+                return Self::create_from_ntfs(disk, source, options, Path::new("/"));
+            }
+
+            anyhow::bail!("the disk contains neither a partition table nor an NTFS volume");
+        };
+
+        let volume = |partition: &partition::Partition| {
+            read_at::Slice::new(&disk, partition.offset, partition.size)
+        };
+
+        let supported = table
+            .partitions
+            .iter()
+            .enumerate()
+            .filter(|(_, partition)| ntfs::is_ntfs_volume(&volume(partition)))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        let selected = match selector {
+            PartitionSelector::Largest => supported
+                .iter()
+                .copied()
+                .max_by_key(|&i| table.partitions[i].size)
+                .into_iter()
+                .collect(),
+            PartitionSelector::All => supported,
+            _ => {
+                let i = table
+                    .partitions
+                    .iter()
+                    .position(|partition| selector.matches(partition))
+                    .with_context(|| format!("no partition matches {selector}\n{table}"))?;
+
+                if !supported.contains(&i) {
+                    anyhow::bail!(
+                        "partition {} does not contain an NTFS volume",
+                        table.partitions[i].index
+                    );
+                }
+
+                vec![i]
+            }
+        };
+
+        let mut snapshot = match &selected[..] {
+            [] => anyhow::bail!("could not find a partition containing an NTFS volume\n{table}"),
+            &[i] => {
+                let partition = &mut table.partitions[i];
+                partition.root = Some(PathBuf::from("/"));
 
-        let mut snapshot = Self::create_from_dir(mount.path());
+                Self::create_from_ntfs(volume(partition), source, options, Path::new("/"))
+                    .with_context(|| format!("could not read partition {}", partition.index))?
+            }
+            selected => {
+                use crate::fs::{dir_entry::GenericDirEntry as _, metadata::GenericMetadata as _};
+
+                let mut combined = Self {
+                    root: MetaDirEntry {
+                        metadata: Metadata::meaningless(),
+                        entry: DEntry::empty_dir(),
+                        context: (),
+                    },
+                    source,
+                    source_hash: None,
+                    partition_table: None,
+                    exclusions: None,
+                    tags: BTreeMap::new(),
+                    errors: Vec::new(),
+                    hard_links: Vec::new(),
+                    timestamp: Timestamp::now(),
+                    version: None,
+                    autoruns: None,
+                    updates: None,
+                    hives: BTreeMap::new(),
+                };
+
+                for &i in selected {
+                    let partition = &mut table.partitions[i];
+                    let root = PathBuf::from(format!("/partition{}", partition.index));
+
+                    let snapshot = Self::create_from_ntfs(
+                        volume(partition),
+                        combined.source.clone(),
+                        options,
+                        &root,
+                    )
+                    .with_context(|| format!("could not read partition {}", partition.index))?;
+
+                    combined.root.insert(&root, snapshot.root);
+                    combined.exclusions = match (combined.exclusions, snapshot.exclusions) {
+                        (Some(mut combined), Some(exclusions)) => {
+                            combined.paths.extend(exclusions.paths);
+                            Some(combined)
+                        }
+                        (combined, exclusions) => combined.or(exclusions),
+                    };
+                    combined.errors.extend(snapshot.errors);
+                    combined.hard_links.extend(snapshot.hard_links);
+                    combined.version = combined.version.or(snapshot.version);
+                    combined.autoruns = combined.autoruns.or(snapshot.autoruns);
+                    combined.updates = combined.updates.or(snapshot.updates);
+                    combined.hives.extend(snapshot.hives);
+                    partition.root = Some(root);
+                }
+
+                combined
+            }
+        };
 
-        snapshot.source = Source::VdiImage(path.to_path_buf());
+        snapshot.partition_table = Some(table);
 
         Ok(snapshot)
     }
 
+    /// Creates a new snapshot of the NTFS volume stored on the given device.
+    ///
+    /// `root` is the path in the snapshot at which the volume will be placed.
+    fn create_from_ntfs(
+        device: impl read_at::ReadAt,
+        source: Source,
+        options: &CreateOptions,
+        root: &Path,
+    ) -> anyhow::Result<Self> {
+        let volume = ntfs::NtfsVolume::open(device).context("could not open NTFS volume")?;
+
+        let version = volume
+            .read_file("/sniff/version")
+            .ok()
+            .and_then(|(data, _)| String::from_utf8(data).ok())
+            .map(|version| version.trim().to_string());
+
+        let autoruns = volume
+            .read_file("/sniff/autoruns.csv")
+            .ok()
+            .and_then(|(data, modified)| Autoruns::from_reader(&data[..], modified).ok());
+
+        let updates = volume
+            .read_file("/sniff/updates.csv")
+            .ok()
+            .and_then(|(data, modified)| Updates::from_reader(&data[..], modified).ok());
+
+        // Ignore the `sniff` root directory, since it isn't really part of the normal system.
+        let mut excluded = BTreeSet::new();
+        let mut errors = Vec::new();
+        let mut hard_links = Vec::new();
+        let tree = volume.read_tree(
+            |path| path.starts_with("/sniff"),
+            options,
+            root,
+            &mut excluded,
+            &mut errors,
+            &mut hard_links,
+        );
+
+        let hives = if options.skip_registry {
+            BTreeMap::new()
+        } else {
+            read_hives(&tree, root, |path| Ok(volume.read_file(path)?.0))
+        };
+
+        Ok(Self {
+            root: tree,
+            source,
+            source_hash: None,
+            partition_table: None,
+            exclusions: (!options.exclude.is_empty()).then(|| options.exclude.record(excluded)),
+            tags: BTreeMap::new(),
+            errors,
+            hard_links,
+            timestamp: Timestamp::now(),
+            version,
+            autoruns,
+            updates,
+            hives,
+        })
+    }
+
     /// Creates a new snapshot of the specified directory.
-    pub(crate) fn create_from_dir(root_path: impl AsRef<Path>) -> Self {
+    pub(crate) fn create_from_dir(root_path: impl AsRef<Path>, options: &CreateOptions) -> Self {
         let root_path = root_path.as_ref();
 
         let version = if let Ok(mut file) = File::open(root_path.join("sniff/version")) {
@@ -183,13 +781,55 @@ impl SnapshotLatest {
 
         let updates = Updates::from_path(root_path.join("sniff/updates.csv")).ok();
 
+        let exclude = &options.exclude;
+        let mut excluded = BTreeSet::new();
+
+        // Returns whether the contents of the given path should be read, or `None` if it is
+        // skipped entirely
+        let mut read_contents = |path: &Path| {
+            let snapshot_path = Path::new("/").join(path.strip_prefix(root_path).ok()?);
+            if !exclude.is_excluded(&snapshot_path) {
+                return Some(true);
+            }
+
+            if exclude.is_exclusion_root(&snapshot_path) {
+                excluded.insert(snapshot_path);
+            }
+
+            match exclude.mode {
+                exclude::ExcludedMode::Skip => None,
+                exclude::ExcludedMode::Metadata => Some(false),
+                exclude::ExcludedMode::Hash => Some(true),
+            }
+        };
+
         let mut paths = Vec::new();
+        let mut errors = Vec::new();
 
-        for entry in walkdir::WalkDir::new(root_path) {
+        let mut walker = walkdir::WalkDir::new(root_path).into_iter();
+        while let Some(entry) = walker.next() {
             let entry = match entry {
                 Ok(entry) => entry,
                 Err(err) => {
                     eprintln!("could not read path information: {}", err);
+                    if let Some(path) = err
+                        .path()
+                        .and_then(|path| path.strip_prefix(root_path).ok())
+                    {
+                        let path = Path::new("/").join(path);
+
+                        // Errors for directories occur while listing their contents
+                        let operation = if err.path().is_some_and(Path::is_dir) {
+                            errors::Operation::ListDirectory
+                        } else {
+                            errors::Operation::ReadEntry
+                        };
+                        let err = match err.into_io_error() {
+                            Some(err) => anyhow::Error::from(err),
+                            None => anyhow::anyhow!("file system loop"),
+                        };
+                        errors.push(errors::AcquisitionError::new(path, operation, err));
+                    }
                     continue;
                 }
             };
@@ -200,25 +840,66 @@ impl SnapshotLatest {
                 .path()
                 .strip_prefix(root_path)
                 .map(|path| path.starts_with("sniff"))
-                != Ok(true)
+                == Ok(true)
             {
-                paths.push(entry.path().to_path_buf());
+                continue;
+            }
+
+            match read_contents(entry.path()) {
+                Some(read_contents) => paths.push((
+                    entry.path().to_path_buf(),
+                    read_contents,
+                    hard_link_key(&entry),
+                )),
+                None => {
+                    let snapshot_path = entry.path().strip_prefix(root_path).unwrap();
+                    if entry.file_type().is_dir()
+                        && exclude.skips_dir(&Path::new("/").join(snapshot_path))
+                    {
+                        walker.skip_current_dir();
+                    }
+                }
             }
         }
 
         // Due to a bug in glibc the $MFT file may not be listed, but can still be accessed (source:
         // man 8 ntfs-3g)
         let mft_path = root_path.join("$MFT");
-        if !paths.contains(&mft_path) {
-            paths.push(mft_path);
+        if !paths.iter().any(|(path, _, _)| path == &mft_path)
+            && mft_path.exists()
+            && let Some(read_contents) = read_contents(&mft_path)
+        {
+            paths.push((mft_path, read_contents, None));
+        }
+
+        // All hard links of a file are read only once, the contents are read if at least one of
+        // the paths is not excluded
+        let mut linked_paths = Vec::<(Vec<PathBuf>, bool)>::new();
+        let mut groups_by_inode = HashMap::<(u64, u64), usize>::new();
+        for (path, read_contents, key) in paths {
+            match key.map(|key| groups_by_inode.entry(key)) {
+                Some(hash_map::Entry::Occupied(group)) => {
+                    let (paths, read) = &mut linked_paths[*group.get()];
+                    paths.push(path);
+                    *read |= read_contents;
+                }
+                Some(hash_map::Entry::Vacant(group)) => {
+                    group.insert(linked_paths.len());
+                    linked_paths.push((vec![path], read_contents));
+                }
+                None => linked_paths.push((vec![path], read_contents)),
+            }
         }
 
         use crate::fs::{dir_entry::GenericDirEntry as _, metadata::GenericMetadata as _};
-        let metadata = if let Ok(metadata) = Metadata::from_path(root_path) {
+        let mut metadata = if let Ok(metadata) = Metadata::from_path(root_path) {
             metadata
         } else {
             Metadata::meaningless()
         };
+        if let Some(streams) = &mut metadata.streams {
+            streams.limit_inline_len(options.max_inline_stream_len);
+        }
         let mut root = MetaDirEntry {
             metadata,
             entry: DEntry::empty_dir(),
@@ -226,24 +907,41 @@ impl SnapshotLatest {
         };
 
         crossbeam_utils::thread::scope(|s| {
-            let (sender, receiver) =
-                crossbeam_channel::bounded::<(PathBuf, MetaDirEntry<DEntry, Metadata, ()>)>(100);
+            let (sender, receiver) = crossbeam_channel::bounded::<
+                Result<(PathBuf, MetaDirEntry<DEntry, Metadata, ()>), errors::AcquisitionError>,
+            >(100);
 
             s.spawn(|_| {
-                for (path, entry) in receiver {
-                    root.insert(path, entry);
+                for entry in receiver {
+                    match entry {
+                        Ok((path, entry)) => root.insert(path, entry),
+                        Err(err) => errors.push(err),
+                    }
                 }
             });
 
             use rayon::prelude::*;
-            paths.par_iter().for_each(|path| {
-                let entry = match MetaDirEntry::from_path(path, |symlink_path| {
+            linked_paths.par_iter().for_each(|(paths, read_contents)| {
+                let trim_path =
+                    |path: &Path| Path::new("/").join(path.strip_prefix(root_path).unwrap());
+                let path = &paths[0];
+                let trimmed_path = trim_path(path);
+                let trim_symlink_path = |symlink_path: PathBuf| {
                     if let Ok(path) = symlink_path.strip_prefix(root_path) {
                         Path::new("/").join(path)
                     } else {
                         symlink_path
                     }
-                }) {
+                };
+
+                let entry = if !read_contents {
+                    Self::dir_entry_without_contents(path, trim_symlink_path)
+                } else if let Some(reuse) = &options.reuse {
+                    Self::dir_entry_reusing(path, &trimmed_path, reuse, trim_symlink_path)
+                } else {
+                    MetaDirEntry::from_path(path, trim_symlink_path)
+                };
+                let mut entry = match entry {
                     Ok(entry) => entry,
                     Err(err) => {
                         eprintln!(
@@ -251,26 +949,126 @@ impl SnapshotLatest {
                             path.display(),
                             err
                         );
+                        let error = errors::AcquisitionError::new(
+                            trimmed_path,
+                            errors::Operation::ReadEntry,
+                            err,
+                        );
+                        for path in &paths[1..] {
+                            sender
+                                .send(Err(errors::AcquisitionError {
+                                    path: trim_path(path),
+                                    ..error.clone()
+                                }))
+                                .unwrap();
+                        }
+                        sender.send(Err(error)).unwrap();
                         return;
                     }
                 };
 
-                let trimmed_path = Path::new("/").join(path.strip_prefix(root_path).unwrap());
-                sender.send((trimmed_path, entry)).unwrap();
+                if let Some(streams) = &mut entry.metadata.streams {
+                    streams.limit_inline_len(options.max_inline_stream_len);
+                }
+
+                for path in &paths[1..] {
+                    sender.send(Ok((trim_path(path), entry.clone()))).unwrap();
+                }
+                sender.send(Ok((trimmed_path, entry))).unwrap();
             });
         })
         .unwrap();
 
+        let trim_path = |path: &Path| Path::new("/").join(path.strip_prefix(root_path).unwrap());
+        let hard_links = linked_paths
+            .iter()
+            .filter(|(paths, _)| paths.len() > 1)
+            .map(|(paths, _)| paths.iter().map(|path| trim_path(path)).collect())
+            .collect();
+
+        let hives = if options.skip_registry {
+            BTreeMap::new()
+        } else {
+            read_hives(&root, Path::new("/"), |path| {
+                Ok(std::fs::read(root_path.join(path.strip_prefix("/")?))?)
+            })
+        };
+
         Self {
             root,
             source: Source::Directory(root_path.to_path_buf()),
+            source_hash: None,
+            partition_table: None,
+            exclusions: (!exclude.is_empty()).then(|| exclude.record(excluded)),
+            tags: BTreeMap::new(),
+            errors,
+            hard_links,
             timestamp: Timestamp::now(),
             version,
             autoruns,
             updates,
+            hives,
         }
     }
 
+    /// Reads the directory entry at `path`, which is stored at `snapshot_path` in the snapshot.
+    ///
+    /// The file information of the previous snapshot is reused if the file is unchanged.
+    fn dir_entry_reusing(
+        path: &Path,
+        snapshot_path: &Path,
+        reuse: &reuse::Reuse,
+        trim_symlink_path: impl FnMut(PathBuf) -> PathBuf,
+    ) -> io::Result<MetaDirEntry<DEntry, Metadata, ()>> {
+        use crate::fs::{
+            dir_entry::GenericDirEntry as _, file::GenericFile as _,
+            metadata::GenericMetadata as _,
+        };
+
+        let metadata = Metadata::from_path(path)?;
+
+        let entry = if std::fs::symlink_metadata(path)?.is_file() {
+            DirEntry::File(reuse.file_or_read(snapshot_path, (&metadata).into(), || {
+                crate::fs::File::from_path(path)
+            })?)
+        } else {
+            DEntry::from_path(path, trim_symlink_path)?
+        };
+
+        Ok(MetaDirEntry {
+            metadata,
+            entry,
+            context: (),
+        })
+    }
+
+    /// Reads the directory entry at `path` without reading the contents of files.
+    ///
+    /// Files are stored as entries of the file type instead.
+    fn dir_entry_without_contents(
+        path: &Path,
+        trim_symlink_path: impl FnMut(PathBuf) -> PathBuf,
+    ) -> io::Result<MetaDirEntry<DEntry, Metadata, ()>> {
+        use crate::fs::{
+            dir_entry::GenericDirEntry as _, dir_entry_type::DirEntryType,
+            metadata::GenericMetadata as _,
+        };
+
+        let metadata = Metadata::from_path(path)?;
+
+        let entry = if std::fs::symlink_metadata(path)?.is_file() {
+            DirEntry::Other(DirEntryType::File)
+        } else {
+            DEntry::from_path(path, trim_symlink_path)?
+        };
+
+        Ok(MetaDirEntry {
+            metadata,
+            entry,
+            context: (),
+        })
+    }
+
     /// The specific bincode configuration used for serialization.
     fn bincode() -> impl bincode::Options {
         use bincode::Options as _;
@@ -280,57 +1078,152 @@ impl SnapshotLatest {
             .reject_trailing_bytes()
     }
 
-    /// Writes the snapshot to the specified path.
-    pub(crate) fn to_file(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    /// Writes the snapshot to the specified path, using the given compression.
+    pub(crate) fn to_file(
+        &self,
+        path: impl AsRef<Path>,
+        compression: compression::Compression,
+    ) -> anyhow::Result<()> {
+        use io::{Seek as _, Write as _};
+
         let path = path.as_ref();
 
-        let mut out_file = std::fs::File::create(path)
+        let out_file = std::fs::File::create(path)
             .with_context(|| format!("failed creating file at {}", path.display()))?;
-
-        SnapshotFileHeader::write_to_file(&mut out_file).context("failed writing file header")?;
-
-        let out_file_compressed =
-            flate2::write::GzEncoder::new(out_file, flate2::Compression::best());
-
-        use bincode::Options as _;
-        Self::bincode()
-            .serialize_into(std::io::BufWriter::new(out_file_compressed), &self)
-            .context("failed serialization into compressed file")?;
+        let mut out_file = io::BufWriter::new(out_file);
+
+        // The digest in the header has a fixed size, so a placeholder header is written before the
+        // data and replaced once the digest of the data is known
+        let mut header = Vec::new();
+        SnapshotFileHeader::new(compression, Sha256Hash { bytes: [0; 32] })
+            .write_self_to_file(&mut header)?;
+        out_file
+            .write_all(&header)
+            .context("failed writing file header")?;
+
+        let digest = chunks::write(self, &mut out_file, header.len() as u64, compression)?;
+
+        let mut out_file = out_file
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)
+            .context("failed writing snapshot data")?;
+
+        header.clear();
+        SnapshotFileHeader::new(compression, digest).write_self_to_file(&mut header)?;
+        out_file.rewind().context("failed writing file header")?;
+        out_file
+            .write_all(&header)
+            .context("failed writing file header")?;
 
         Ok(())
     }
 
     /// Reads the snapshot from the specified path.
     pub(crate) fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_file_at(path, None)
+    }
+
+    /// Reads the snapshot from the specified path.
+    ///
+    /// If `entry` is given, the snapshot may only contain the entries on the way to `entry` and
+    /// the entries below it.
+    /// For snapshots stored in chunks, this avoids decoding the chunks of unrelated directories.
+    pub(crate) fn from_file_at(
+        path: impl AsRef<Path>,
+        entry: Option<&Path>,
+    ) -> anyhow::Result<Self> {
         firestorm::profile_method!(from_file);
 
         use std::io::Read as _;
 
+        let path = path.as_ref();
+
         let (header, data) = {
             firestorm::profile_section!(reading_file);
             let mut in_file = std::fs::File::open(path)?;
 
             let header = SnapshotFileHeader::read_from_file(&mut in_file)?;
 
+            anyhow::ensure!(
+                header.version != 0,
+                "version 0 snapshots are no longer supported"
+            );
+            anyhow::ensure!(
+                header.version <= CURRENT_SNAPSHOT_VERSION,
+                "unknown version: {}",
+                header.version
+            );
+
+            // Since version 20, the chunks are checked against their own digests when decoded
+            if header.version < 20 {
+                header.verify(&mut in_file)?;
+            }
+
+            if header.version >= 5 {
+                let read_chunks = || -> anyhow::Result<_> {
+                    let file =
+                        chunks::ChunkedFile::open(in_file, header.version, header.compression)?;
+                    let root = match entry {
+                        Some(entry) => file.read_subtree(entry)?,
+                        None => file.read_tree()?,
+                    };
+                    let hives = file.read_hives(entry)?;
+
+                    Ok((root, hives, file.index))
+                };
+                let (root, hives, index) = read_chunks()
+                    .or_else(|err| {
+                        // Corruption outside of the chunks, such as a truncated file, is only
+                        // detected by the digest of the whole file
+                        if header.version >= 20 {
+                            Self::verify_data(path)?;
+                        }
+
+                        Err(err)
+                    })
+                    .with_context(|| header.undetected_corruption())?;
+
+                return Ok(Self {
+                    root,
+                    source: index.source,
+                    source_hash: index.source_hash,
+                    partition_table: index.partition_table,
+                    exclusions: index.exclusions,
+                    tags: index.tags,
+                    errors: index.errors,
+                    hard_links: index.hard_links,
+                    timestamp: index.timestamp,
+                    version: index.version,
+                    autoruns: index.autoruns,
+                    updates: index.updates,
+                    hives,
+                });
+            }
+
             let mut data = Vec::new();
             in_file.read_to_end(&mut data)?;
 
             (header, data)
         };
 
-        match header.version {
-            0 => Err(anyhow::anyhow!(
-                "version 0 snapshots are no longer supported"
-            )),
+        Self::decode_unchunked(header.version, &data)
+            .with_context(|| header.undetected_corruption())
+    }
+
+    /// Decodes the data of a snapshot file of the given version before chunks were introduced.
+    fn decode_unchunked(version: u8, data: &[u8]) -> anyhow::Result<Self> {
+        use std::io::Read as _;
+
+        match version {
             1 => {
                 let data = {
                     firestorm::profile_section!(decompressing_file);
                     let mut decoded_data = Vec::new();
-                    flate2::read::GzDecoder::new(&data[..]).read_to_end(&mut decoded_data)?;
+                    flate2::read::GzDecoder::new(data).read_to_end(&mut decoded_data)?;
                     decoded_data
                 };
 
-                let v1: Snapshot<
+                let v1: SnapshotV2<
                     crate::fs::DEntryV1<()>,
                     crate::fs::metadata::MetadataV1,
                     Autoruns,
@@ -341,30 +1234,167 @@ impl SnapshotLatest {
                 };
 
                 Ok(Self {
-                    root: v1.root.into(),
+                    root: crate::fs::MetaDEntryV2::from(v1.root).into(),
                     source: v1.source,
+                    source_hash: None,
+                    partition_table: None,
+                    exclusions: None,
+                    tags: BTreeMap::new(),
+                    errors: Vec::new(),
+                    hard_links: Vec::new(),
                     timestamp: v1.timestamp,
                     version: v1.version,
                     autoruns: v1.autoruns,
                     updates: v1.updates,
+                    hives: BTreeMap::new(),
                 })
             }
             2 => {
                 let data = {
                     firestorm::profile_section!(decompressing_file);
                     let mut decoded_data = Vec::new();
-                    flate2::read::GzDecoder::new(&data[..]).read_to_end(&mut decoded_data)?;
+                    flate2::read::GzDecoder::new(data).read_to_end(&mut decoded_data)?;
                     decoded_data
                 };
 
-                let v2: SnapshotLatest = {
+                let v2: SnapshotV2<
+                    crate::fs::DEntryV2,
+                    crate::fs::metadata::MetadataV2,
+                    Autoruns,
+                    Updates,
+                > = {
                     firestorm::profile_section!(deserializing_file);
                     bincode::Options::deserialize_from(Self::bincode(), &data[..])?
                 };
 
-                Ok(v2)
+                Ok(Self {
+                    root: v2.root.into(),
+                    source: v2.source,
+                    source_hash: None,
+                    partition_table: None,
+                    exclusions: None,
+                    tags: BTreeMap::new(),
+                    errors: Vec::new(),
+                    hard_links: Vec::new(),
+                    timestamp: v2.timestamp,
+                    version: v2.version,
+                    autoruns: v2.autoruns,
+                    updates: v2.updates,
+                    hives: BTreeMap::new(),
+                })
             }
-            _ => Err(anyhow::anyhow!("unknown version: {}", header.version)),
+            3 => {
+                let data = {
+                    firestorm::profile_section!(decompressing_file);
+                    let mut decoded_data = Vec::new();
+                    flate2::read::GzDecoder::new(data).read_to_end(&mut decoded_data)?;
+                    decoded_data
+                };
+
+                let v3: SnapshotV3<
+                    crate::fs::DEntryV2,
+                    crate::fs::metadata::MetadataV2,
+                    Autoruns,
+                    Updates,
+                > = {
+                    firestorm::profile_section!(deserializing_file);
+                    bincode::Options::deserialize_from(Self::bincode(), &data[..])?
+                };
+
+                Ok(Self {
+                    root: v3.root.into(),
+                    source: v3.source,
+                    source_hash: None,
+                    partition_table: v3.partition_table,
+                    exclusions: None,
+                    tags: BTreeMap::new(),
+                    errors: Vec::new(),
+                    hard_links: Vec::new(),
+                    timestamp: v3.timestamp,
+                    version: v3.version,
+                    autoruns: v3.autoruns,
+                    updates: v3.updates,
+                    hives: BTreeMap::new(),
+                })
+            }
+            4 => {
+                let data = {
+                    firestorm::profile_section!(decompressing_file);
+                    let mut decoded_data = Vec::new();
+                    flate2::read::GzDecoder::new(data).read_to_end(&mut decoded_data)?;
+                    decoded_data
+                };
+
+                let v4: SnapshotV4<
+                    crate::fs::DEntryV2,
+                    crate::fs::metadata::MetadataV2,
+                    Autoruns,
+                    Updates,
+                > = {
+                    firestorm::profile_section!(deserializing_file);
+                    bincode::Options::deserialize_from(Self::bincode(), &data[..])?
+                };
+
+                Ok(Self {
+                    root: v4.root.into(),
+                    source: v4.source,
+                    source_hash: None,
+                    partition_table: v4.partition_table,
+                    exclusions: v4.exclusions,
+                    tags: BTreeMap::new(),
+                    errors: Vec::new(),
+                    hard_links: Vec::new(),
+                    timestamp: v4.timestamp,
+                    version: v4.version,
+                    autoruns: v4.autoruns,
+                    updates: v4.updates,
+                    hives: BTreeMap::new(),
+                })
+            }
+            _ => Err(anyhow::anyhow!("unknown version: {version}")),
         }
     }
+
+    /// Checks the integrity of the snapshot file at the specified path by hashing all of its data
+    /// and decoding it completely.
+    ///
+    /// Returns the digest of the data, which is only stored in snapshots created with version 7 or
+    /// later.
+    pub(crate) fn verify_file(path: impl AsRef<Path>) -> anyhow::Result<Option<Sha256Hash>> {
+        firestorm::profile_fn!(verify_file);
+
+        let path = path.as_ref();
+
+        let header = Self::verify_data(path)?;
+        Self::from_file(path)?;
+
+        Ok(header.digest)
+    }
+
+    /// Checks all data in the snapshot file at the specified path against the digest in its
+    /// header, returning the header.
+    fn verify_data(path: &Path) -> anyhow::Result<SnapshotFileHeader> {
+        let mut in_file = std::fs::File::open(path)?;
+        let header = SnapshotFileHeader::read_from_file(&mut in_file)?;
+        header.verify(&mut in_file)?;
+
+        Ok(header)
+    }
+
+    /// Reads the digest of the data in the snapshot file at the specified path.
+    ///
+    /// The digest identifies the snapshot file, but is only stored in snapshots created with
+    /// version 7 or later.
+    pub(crate) fn file_digest(path: impl AsRef<Path>) -> anyhow::Result<Option<Sha256Hash>> {
+        let mut in_file = std::fs::File::open(path)?;
+
+        Ok(SnapshotFileHeader::read_from_file(&mut in_file)?.digest)
+    }
+
+    /// Reads the version of the snapshot format of the snapshot file at the specified path.
+    pub(crate) fn file_version(path: impl AsRef<Path>) -> anyhow::Result<u8> {
+        let mut in_file = std::fs::File::open(path)?;
+
+        Ok(SnapshotFileHeader::read_from_file(&mut in_file)?.version)
+    }
 }
@@ -0,0 +1,835 @@
+//! Stores the directory tree of a snapshot in independently compressed chunks.
+//!
+//! Large directories are split off into their own chunks, leaving only an empty directory with
+//! their metadata in the parent chunk.
+//! An index at the end of the file records the path and location of each chunk, so that only the
+//! chunks on the way to a requested path and below it need to be decoded.
+//!
+//! The layout of a chunked snapshot file is as follows:
+//! - the `SnapshotFileHeader`
+//! - the compressed chunks, starting with the root directory
+//! - the compressed registry hives, one per chunk
+//! - the compressed `SnapshotIndex`
+//! - the SHA2-256 digest of the compressed index (since version 20)
+//! - the offset of the index as a little endian `u64`
+//!
+//! Since version 20, the index also records the digest of each compressed chunk, so corrupted
+//! chunks are detected when they are decoded, without hashing the whole file.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    os::unix::fs::FileExt as _,
+    path::{Component, Components, Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    compression::Compression, errors::AcquisitionError, exclude::Exclusions,
+    partition::PartitionTable, registry::Hive, SnapshotLatest, Source,
+};
+use crate::{
+    autoruns::Autoruns,
+    fs::{file::Sha256Hash, DirEntry, Directory, MetaDEntry},
+    timestamp::Timestamp,
+    updates::Updates,
+};
+
+/// The number of entries above which subdirectories are split off into their own chunks.
+const MAX_CHUNK_ENTRIES: usize = 4096;
+
+/// The minimum number of entries in a directory tree for it to be stored in its own chunk.
+const MIN_CHUNK_ENTRIES: usize = 256;
+
+/// The number of chunks that are compressed in parallel before they are written.
+///
+/// Only one batch of compressed chunks is kept in memory at a time.
+const WRITE_BATCH_LEN: usize = 64;
+
+/// The location of a chunk in a snapshot file.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct Chunk {
+    /// The path of the directory or registry hive stored in the chunk.
+    path: PathBuf,
+    /// The offset of the compressed chunk in the file.
+    offset: u64,
+    /// The length of the compressed chunk in bytes.
+    len: u64,
+    /// The SHA2-256 digest of the compressed chunk.
+    ///
+    /// This is only recorded since version 20.
+    digest: Option<Sha256Hash>,
+}
+
+/// The location of a chunk in a snapshot file in versions 5 to 19 of the snapshot format.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct ChunkV5 {
+    /// The path of the directory or registry hive stored in the chunk.
+    path: PathBuf,
+    /// The offset of the compressed chunk in the file.
+    offset: u64,
+    /// The length of the compressed chunk in bytes.
+    len: u64,
+}
+
+impl From<ChunkV5> for Chunk {
+    fn from(chunk: ChunkV5) -> Self {
+        Self {
+            path: chunk.path,
+            offset: chunk.offset,
+            len: chunk.len,
+            digest: None,
+        }
+    }
+}
+
+/// The index of a chunked snapshot file, storing everything except for the directory tree.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(super) struct SnapshotIndex {
+    /// The source of the snapshot.
+    pub(super) source: Source,
+    /// The SHA2-256 hash of the source image or device.
+    pub(super) source_hash: Option<Sha256Hash>,
+    /// The partition table of the source disk, if it had one.
+    pub(super) partition_table: Option<PartitionTable>,
+    /// The paths that were excluded from the snapshot, if any patterns were given.
+    pub(super) exclusions: Option<Exclusions>,
+    /// User defined tags of the snapshot.
+    pub(super) tags: BTreeMap<String, String>,
+    /// The errors that occurred while creating the snapshot.
+    pub(super) errors: Vec<AcquisitionError>,
+    /// The groups of paths that refer to the same file.
+    pub(super) hard_links: Vec<Vec<PathBuf>>,
+    /// The creation time of the snapshot.
+    pub(super) timestamp: Timestamp,
+    /// The version of the system in question.
+    pub(super) version: Option<String>,
+    /// Data about the autoruns on the system.
+    pub(super) autoruns: Option<Autoruns>,
+    /// Data about the updates installed on the system.
+    pub(super) updates: Option<Updates>,
+    /// The chunks of the directory tree, parents are always stored before their children.
+    chunks: Vec<Chunk>,
+    /// The chunks of the registry hives, each storing a single hive.
+    hives: Vec<Chunk>,
+}
+
+/// The index of a chunked snapshot file in versions 5 to 7 of the snapshot format.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct SnapshotIndexV5 {
+    /// The source of the snapshot.
+    source: Source,
+    /// The partition table of the source disk, if it had one.
+    partition_table: Option<PartitionTable>,
+    /// The paths that were excluded from the snapshot, if any patterns were given.
+    exclusions: Option<Exclusions>,
+    /// The creation time of the snapshot.
+    timestamp: Timestamp,
+    /// The version of the system in question.
+    version: Option<String>,
+    /// Data about the autoruns on the system.
+    autoruns: Option<Autoruns>,
+    /// Data about the updates installed on the system.
+    updates: Option<Updates>,
+    /// The chunks of the directory tree, parents are always stored before their children.
+    chunks: Vec<ChunkV5>,
+}
+
+impl From<SnapshotIndexV5> for SnapshotIndex {
+    fn from(index: SnapshotIndexV5) -> Self {
+        Self {
+            source: index.source,
+            source_hash: None,
+            partition_table: index.partition_table,
+            exclusions: index.exclusions,
+            tags: BTreeMap::new(),
+            errors: Vec::new(),
+            hard_links: Vec::new(),
+            timestamp: index.timestamp,
+            version: index.version,
+            autoruns: index.autoruns,
+            updates: index.updates,
+            chunks: index.chunks.into_iter().map(Chunk::from).collect(),
+            hives: Vec::new(),
+        }
+    }
+}
+
+/// The index of a chunked snapshot file in version 8 of the snapshot format.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct SnapshotIndexV8 {
+    /// The source of the snapshot.
+    source: Source,
+    /// The SHA2-256 hash of the source image or device.
+    source_hash: Option<Sha256Hash>,
+    /// The partition table of the source disk, if it had one.
+    partition_table: Option<PartitionTable>,
+    /// The paths that were excluded from the snapshot, if any patterns were given.
+    exclusions: Option<Exclusions>,
+    /// User defined tags of the snapshot.
+    tags: BTreeMap<String, String>,
+    /// The creation time of the snapshot.
+    timestamp: Timestamp,
+    /// The version of the system in question.
+    version: Option<String>,
+    /// Data about the autoruns on the system.
+    autoruns: Option<Autoruns>,
+    /// Data about the updates installed on the system.
+    updates: Option<Updates>,
+    /// The chunks of the directory tree, parents are always stored before their children.
+    chunks: Vec<ChunkV5>,
+}
+
+impl From<SnapshotIndexV8> for SnapshotIndex {
+    fn from(index: SnapshotIndexV8) -> Self {
+        Self {
+            source: index.source,
+            source_hash: index.source_hash,
+            partition_table: index.partition_table,
+            exclusions: index.exclusions,
+            tags: index.tags,
+            errors: Vec::new(),
+            hard_links: Vec::new(),
+            timestamp: index.timestamp,
+            version: index.version,
+            autoruns: index.autoruns,
+            updates: index.updates,
+            chunks: index.chunks.into_iter().map(Chunk::from).collect(),
+            hives: Vec::new(),
+        }
+    }
+}
+
+/// The index of a chunked snapshot file in version 9 of the snapshot format.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct SnapshotIndexV9 {
+    /// The source of the snapshot.
+    source: Source,
+    /// The SHA2-256 hash of the source image or device.
+    source_hash: Option<Sha256Hash>,
+    /// The partition table of the source disk, if it had one.
+    partition_table: Option<PartitionTable>,
+    /// The paths that were excluded from the snapshot, if any patterns were given.
+    exclusions: Option<Exclusions>,
+    /// User defined tags of the snapshot.
+    tags: BTreeMap<String, String>,
+    /// The errors that occurred while creating the snapshot.
+    errors: Vec<AcquisitionError>,
+    /// The creation time of the snapshot.
+    timestamp: Timestamp,
+    /// The version of the system in question.
+    version: Option<String>,
+    /// Data about the autoruns on the system.
+    autoruns: Option<Autoruns>,
+    /// Data about the updates installed on the system.
+    updates: Option<Updates>,
+    /// The chunks of the directory tree, parents are always stored before their children.
+    chunks: Vec<ChunkV5>,
+}
+
+impl From<SnapshotIndexV9> for SnapshotIndex {
+    fn from(index: SnapshotIndexV9) -> Self {
+        Self {
+            source: index.source,
+            source_hash: index.source_hash,
+            partition_table: index.partition_table,
+            exclusions: index.exclusions,
+            tags: index.tags,
+            errors: index.errors,
+            hard_links: Vec::new(),
+            timestamp: index.timestamp,
+            version: index.version,
+            autoruns: index.autoruns,
+            updates: index.updates,
+            chunks: index.chunks.into_iter().map(Chunk::from).collect(),
+            hives: Vec::new(),
+        }
+    }
+}
+
+/// The index of a chunked snapshot file in versions 10 to 19 of the snapshot format.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct SnapshotIndexV10 {
+    /// The source of the snapshot.
+    source: Source,
+    /// The SHA2-256 hash of the source image or device.
+    source_hash: Option<Sha256Hash>,
+    /// The partition table of the source disk, if it had one.
+    partition_table: Option<PartitionTable>,
+    /// The paths that were excluded from the snapshot, if any patterns were given.
+    exclusions: Option<Exclusions>,
+    /// User defined tags of the snapshot.
+    tags: BTreeMap<String, String>,
+    /// The errors that occurred while creating the snapshot.
+    errors: Vec<AcquisitionError>,
+    /// The creation time of the snapshot.
+    timestamp: Timestamp,
+    /// The version of the system in question.
+    version: Option<String>,
+    /// Data about the autoruns on the system.
+    autoruns: Option<Autoruns>,
+    /// Data about the updates installed on the system.
+    updates: Option<Updates>,
+    /// The chunks of the directory tree, parents are always stored before their children.
+    chunks: Vec<ChunkV5>,
+    /// The chunks of the registry hives, each storing a single hive.
+    hives: Vec<ChunkV5>,
+}
+
+impl From<SnapshotIndexV10> for SnapshotIndex {
+    fn from(index: SnapshotIndexV10) -> Self {
+        Self {
+            source: index.source,
+            source_hash: index.source_hash,
+            partition_table: index.partition_table,
+            exclusions: index.exclusions,
+            tags: index.tags,
+            errors: index.errors,
+            hard_links: Vec::new(),
+            timestamp: index.timestamp,
+            version: index.version,
+            autoruns: index.autoruns,
+            updates: index.updates,
+            chunks: index.chunks.into_iter().map(Chunk::from).collect(),
+            hives: index.hives.into_iter().map(Chunk::from).collect(),
+        }
+    }
+}
+
+/// The index of a chunked snapshot file in version 20 of the snapshot format.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct SnapshotIndexV20 {
+    /// The source of the snapshot.
+    source: Source,
+    /// The SHA2-256 hash of the source image or device.
+    source_hash: Option<Sha256Hash>,
+    /// The partition table of the source disk, if it had one.
+    partition_table: Option<PartitionTable>,
+    /// The paths that were excluded from the snapshot, if any patterns were given.
+    exclusions: Option<Exclusions>,
+    /// User defined tags of the snapshot.
+    tags: BTreeMap<String, String>,
+    /// The errors that occurred while creating the snapshot.
+    errors: Vec<AcquisitionError>,
+    /// The creation time of the snapshot.
+    timestamp: Timestamp,
+    /// The version of the system in question.
+    version: Option<String>,
+    /// Data about the autoruns on the system.
+    autoruns: Option<Autoruns>,
+    /// Data about the updates installed on the system.
+    updates: Option<Updates>,
+    /// The chunks of the directory tree, parents are always stored before their children.
+    chunks: Vec<Chunk>,
+    /// The chunks of the registry hives, each storing a single hive.
+    hives: Vec<Chunk>,
+}
+
+impl From<SnapshotIndexV20> for SnapshotIndex {
+    fn from(index: SnapshotIndexV20) -> Self {
+        Self {
+            source: index.source,
+            source_hash: index.source_hash,
+            partition_table: index.partition_table,
+            exclusions: index.exclusions,
+            tags: index.tags,
+            errors: index.errors,
+            hard_links: Vec::new(),
+            timestamp: index.timestamp,
+            version: index.version,
+            autoruns: index.autoruns,
+            updates: index.updates,
+            chunks: index.chunks,
+            hives: index.hives,
+        }
+    }
+}
+
+/// A writer that computes the SHA2-256 digest of everything written through it.
+struct HashingWriter<W> {
+    /// The writer that the data is written to.
+    inner: W,
+    /// The hasher of the written data.
+    hasher: sha2::Sha256,
+}
+
+impl<W: io::Write> io::Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        use sha2::Digest as _;
+
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes the given snapshot in chunks to `out`, which is stored at `offset` in the file.
+///
+/// The chunks are compressed in parallel batches using the given `compression` and written as
+/// soon as their batch is done.
+/// Returns the digest of all data written to `out`.
+pub(super) fn write(
+    snapshot: &SnapshotLatest,
+    out: &mut impl io::Write,
+    mut offset: u64,
+    compression: Compression,
+) -> anyhow::Result<Sha256Hash> {
+    firestorm::profile_fn!(write_chunks);
+
+    use sha2::Digest as _;
+
+    let mut out = HashingWriter {
+        inner: out,
+        hasher: sha2::Sha256::new(),
+    };
+
+    let mut chunk_paths = BTreeSet::from([PathBuf::from("/")]);
+    choose_chunks(&snapshot.root, &mut PathBuf::from("/"), &mut chunk_paths);
+
+    // Paths sort before all paths below them, so parents are written before their children
+    let chunk_paths = Vec::from_iter(chunk_paths);
+
+    let chunks = write_batched(&mut out, &mut offset, &chunk_paths, |path| {
+        let entry = snapshot
+            .root
+            .get(path)
+            .expect("chunk paths are taken from the snapshot");
+        let chunk = chunk_entry(entry, &mut path.to_path_buf(), &|path| {
+            chunk_paths.binary_search(&path.to_path_buf()).is_ok()
+        });
+
+        compress(&chunk, compression)
+    })?;
+
+    let hive_paths = Vec::from_iter(snapshot.hives.keys().cloned());
+    let hives = write_batched(&mut out, &mut offset, &hive_paths, |path| {
+        compress(&snapshot.hives[path], compression)
+    })?;
+
+    let index = SnapshotIndex {
+        source: snapshot.source.clone(),
+        source_hash: snapshot.source_hash.clone(),
+        partition_table: snapshot.partition_table.clone(),
+        exclusions: snapshot.exclusions.clone(),
+        tags: snapshot.tags.clone(),
+        errors: snapshot.errors.clone(),
+        hard_links: snapshot.hard_links.clone(),
+        timestamp: snapshot.timestamp,
+        version: snapshot.version.clone(),
+        autoruns: snapshot.autoruns.clone(),
+        updates: snapshot.updates.clone(),
+        chunks,
+        hives,
+    };
+
+    let index = compress(&index, compression)?;
+    let trailer = [
+        index.as_slice(),
+        &digest(&index).bytes,
+        &offset.to_le_bytes(),
+    ]
+    .concat();
+    io::Write::write_all(&mut out, &trailer).context("failed writing snapshot index")?;
+
+    Ok(Sha256Hash {
+        bytes: out.hasher.finalize().into(),
+    })
+}
+
+/// Encodes the chunk at each of the given `paths` with `encode` and writes it to `out`.
+///
+/// The chunks are encoded in parallel batches, each batch is written before the next one is
+/// encoded.
+/// Returns the locations of the chunks, the first of which is written at `offset`.
+fn write_batched(
+    out: &mut impl io::Write,
+    offset: &mut u64,
+    paths: &[PathBuf],
+    encode: impl Fn(&Path) -> anyhow::Result<Vec<u8>> + Sync,
+) -> anyhow::Result<Vec<Chunk>> {
+    use rayon::prelude::*;
+
+    let mut chunks = Vec::with_capacity(paths.len());
+    for batch in paths.chunks(WRITE_BATCH_LEN) {
+        let encoded = batch
+            .par_iter()
+            .map(|path| encode(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for (path, data) in batch.iter().zip(encoded) {
+            out.write_all(&data)
+                .context("failed writing snapshot data")?;
+
+            chunks.push(Chunk {
+                path: path.clone(),
+                offset: *offset,
+                len: data.len() as u64,
+                digest: Some(digest(&data)),
+            });
+            *offset += data.len() as u64;
+        }
+    }
+
+    Ok(chunks)
+}
+
+/// Selects the directories below `entry` at `path` that are stored in their own chunks.
+///
+/// Returns the number of entries that remain in the chunk of `entry`.
+fn choose_chunks(entry: &MetaDEntry, path: &mut PathBuf, chunks: &mut BTreeSet<PathBuf>) -> usize {
+    let DirEntry::Directory(dir) = &entry.entry else {
+        return 1;
+    };
+
+    let mut count = 1;
+    let mut subdirs = Vec::new();
+    for (name, child) in &dir.entries {
+        path.push(name);
+        let child_count = choose_chunks(child, path, chunks);
+        if child.is_dir() {
+            subdirs.push((child_count, path.clone()));
+        }
+        count += child_count;
+        path.pop();
+    }
+
+    // Split off the largest subdirectories until the rest is small enough for a single chunk
+    subdirs.sort_unstable_by(|a, b| b.cmp(a));
+    for (child_count, path) in subdirs {
+        if count <= MAX_CHUNK_ENTRIES || child_count < MIN_CHUNK_ENTRIES {
+            break;
+        }
+
+        chunks.insert(path);
+        count -= child_count;
+    }
+
+    count
+}
+
+/// Clones `entry` at `path`, leaving only the metadata of directories stored in other chunks.
+fn chunk_entry(
+    entry: &MetaDEntry,
+    path: &mut PathBuf,
+    is_chunk: &impl Fn(&Path) -> bool,
+) -> MetaDEntry {
+    let DirEntry::Directory(dir) = &entry.entry else {
+        return entry.clone();
+    };
+
+    let mut entries = BTreeMap::new();
+    for (name, child) in &dir.entries {
+        path.push(name);
+        let child = if child.is_dir() && is_chunk(path) {
+            MetaDEntry {
+                entry: DirEntry::Directory(Directory::default()),
+                metadata: child.metadata.clone(),
+                context: (),
+            }
+        } else {
+            chunk_entry(child, path, is_chunk)
+        };
+        entries.insert(name.clone(), child);
+        path.pop();
+    }
+
+    MetaDEntry {
+        entry: DirEntry::Directory(Directory { entries }),
+        metadata: entry.metadata.clone(),
+        context: (),
+    }
+}
+
+/// Returns the SHA2-256 digest of the given data.
+fn digest(data: &[u8]) -> Sha256Hash {
+    use sha2::Digest as _;
+
+    Sha256Hash {
+        bytes: sha2::Sha256::digest(data).into(),
+    }
+}
+
+/// Checks that `data` matches the `expected` digest, if there is one.
+fn verify(data: &[u8], expected: Option<&Sha256Hash>, what: &str) -> anyhow::Result<()> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+
+    let found = digest(data);
+    if &found != expected {
+        anyhow::bail!(
+            "the snapshot file is corrupted: {what} has the digest {found}, but {expected} was \
+            recorded"
+        );
+    }
+
+    Ok(())
+}
+
+/// Serializes and compresses the given value.
+fn compress(value: &impl Serialize, compression: Compression) -> anyhow::Result<Vec<u8>> {
+    use bincode::Options as _;
+
+    let data = SnapshotLatest::bincode()
+        .serialize(value)
+        .context("failed serialization of chunk")?;
+
+    Ok(compression.compress(&data)?)
+}
+
+/// Decompresses and deserializes a value from the given data.
+fn decompress<T: serde::de::DeserializeOwned>(
+    data: &[u8],
+    compression: Compression,
+) -> anyhow::Result<T> {
+    firestorm::profile_fn!(decompress_chunk);
+
+    let decoded_data = compression.decompress(data)?;
+
+    Ok(bincode::Options::deserialize_from(
+        SnapshotLatest::bincode(),
+        &decoded_data[..],
+    )?)
+}
+
+/// A chunked snapshot file whose directory tree is decoded on demand.
+pub(super) struct ChunkedFile {
+    /// The snapshot file.
+    file: std::fs::File,
+    /// The version of the snapshot format of the file.
+    version: u8,
+    /// The compression of the chunks and the index.
+    compression: Compression,
+    /// The index of the snapshot file.
+    pub(super) index: SnapshotIndex,
+}
+
+impl ChunkedFile {
+    /// Reads the index of the given chunked snapshot file of the given `version`, compressed with
+    /// `compression`.
+    ///
+    /// Since version 20, the index is checked against its digest.
+    pub(super) fn open(
+        file: std::fs::File,
+        version: u8,
+        compression: Compression,
+    ) -> anyhow::Result<Self> {
+        let len = file.metadata()?.len();
+        let trailer_len = if version >= 20 { 32 + 8 } else { 8 };
+
+        let mut trailer = vec![0; trailer_len as usize];
+        file.read_exact_at(&mut trailer, len.saturating_sub(trailer_len))
+            .context("could not read the index offset")?;
+        let (index_digest, index_offset) = trailer.split_at(trailer.len() - 8);
+        let index_offset = u64::from_le_bytes(index_offset.try_into().unwrap());
+
+        let index_len = len
+            .checked_sub(trailer_len)
+            .and_then(|end| end.checked_sub(index_offset))
+            .context("invalid index offset")?;
+        let mut index = vec![0; index_len as usize];
+        file.read_exact_at(&mut index, index_offset)
+            .context("could not read the index")?;
+
+        if version >= 20 {
+            let expected = Sha256Hash {
+                bytes: index_digest.try_into().unwrap(),
+            };
+            verify(&index, Some(&expected), "the index")?;
+        }
+
+        let index = match version {
+            ..=7 => decompress::<SnapshotIndexV5>(&index, compression).map(SnapshotIndex::from),
+            8 => decompress::<SnapshotIndexV8>(&index, compression).map(SnapshotIndex::from),
+            9 => decompress::<SnapshotIndexV9>(&index, compression).map(SnapshotIndex::from),
+            10..=19 => decompress::<SnapshotIndexV10>(&index, compression).map(SnapshotIndex::from),
+            20 => decompress::<SnapshotIndexV20>(&index, compression).map(SnapshotIndex::from),
+            _ => decompress(&index, compression),
+        }
+        .context("could not decode the index")?;
+
+        Ok(Self {
+            file,
+            version,
+            compression,
+            index,
+        })
+    }
+
+    /// Reads and decodes the given chunk, checking it against its digest if there is one.
+    fn read_chunk<T: serde::de::DeserializeOwned>(&self, chunk: &Chunk) -> anyhow::Result<T> {
+        let mut data = vec![0; chunk.len as usize];
+        self.file
+            .read_exact_at(&mut data, chunk.offset)
+            .with_context(|| format!("could not read chunk {}", chunk.path.display()))?;
+        verify(
+            &data,
+            chunk.digest.as_ref(),
+            &format!("chunk {}", chunk.path.display()),
+        )?;
+
+        decompress(&data, self.compression)
+            .with_context(|| format!("could not decode chunk {}", chunk.path.display()))
+    }
+
+    /// Reads and decodes the given chunk of the directory tree.
+    ///
+    /// Chunks of older versions are converted to the current format.
+    fn read_tree_chunk(&self, chunk: &Chunk) -> anyhow::Result<MetaDEntry> {
+        match self.version {
+            ..=10 => self
+                .read_chunk::<crate::fs::MetaDEntryV2>(chunk)
+                .map(MetaDEntry::from),
+            11 => self
+                .read_chunk::<crate::fs::MetaDEntryV3>(chunk)
+                .map(MetaDEntry::from),
+            12 => self
+                .read_chunk::<crate::fs::MetaDEntryV4>(chunk)
+                .map(MetaDEntry::from),
+            13 => self
+                .read_chunk::<crate::fs::MetaDEntryV5>(chunk)
+                .map(MetaDEntry::from),
+            14 => self
+                .read_chunk::<crate::fs::MetaDEntryV6>(chunk)
+                .map(MetaDEntry::from),
+            15 => self
+                .read_chunk::<crate::fs::MetaDEntryV7>(chunk)
+                .map(MetaDEntry::from),
+            16 => self
+                .read_chunk::<crate::fs::MetaDEntryV8>(chunk)
+                .map(MetaDEntry::from),
+            17 => self
+                .read_chunk::<crate::fs::MetaDEntryV9>(chunk)
+                .map(MetaDEntry::from),
+            18 => self
+                .read_chunk::<crate::fs::MetaDEntryV10>(chunk)
+                .map(MetaDEntry::from),
+            _ => self.read_chunk(chunk),
+        }
+    }
+
+    /// Decodes the whole directory tree.
+    pub(super) fn read_tree(&self) -> anyhow::Result<MetaDEntry> {
+        firestorm::profile_method!(read_tree);
+
+        self.assemble(self.index.chunks.iter(), None)
+    }
+
+    /// Decodes the directory tree, containing only the entries on the way to `path` and all
+    /// entries below it.
+    ///
+    /// Only the chunks that store these entries are decoded.
+    pub(super) fn read_subtree(&self, path: &Path) -> anyhow::Result<MetaDEntry> {
+        firestorm::profile_method!(read_subtree);
+
+        let path = Path::new("/").join(path);
+
+        let chunks = self.index.chunks.iter().filter(|chunk| {
+            starts_with_ignore_case(&path, &chunk.path)
+                || starts_with_ignore_case(&chunk.path, &path)
+        });
+
+        self.assemble(chunks, Some(&path))
+    }
+
+    /// Decodes the registry hives in parallel.
+    ///
+    /// If `path` is given, only the hives containing `path` or stored below it are decoded.
+    pub(super) fn read_hives(
+        &self,
+        path: Option<&Path>,
+    ) -> anyhow::Result<BTreeMap<PathBuf, Hive>> {
+        firestorm::profile_method!(read_hives);
+
+        use rayon::prelude::*;
+
+        let path = path.map(|path| Path::new("/").join(path));
+
+        self.index
+            .hives
+            .par_iter()
+            .filter(|chunk| match &path {
+                Some(path) => {
+                    starts_with_ignore_case(path, &chunk.path)
+                        || starts_with_ignore_case(&chunk.path, path)
+                }
+                None => true,
+            })
+            .map(|chunk| Ok((chunk.path.clone(), self.read_chunk(chunk)?)))
+            .collect()
+    }
+
+    /// Decodes the given chunks in parallel and inserts them into a single directory tree.
+    ///
+    /// If `prune_to` is given, only the entries on the way to this path are kept in chunks above
+    /// it.
+    fn assemble<'chunk>(
+        &self,
+        chunks: impl Iterator<Item = &'chunk Chunk>,
+        prune_to: Option<&Path>,
+    ) -> anyhow::Result<MetaDEntry> {
+        use rayon::prelude::*;
+
+        let chunks = chunks.collect::<Vec<_>>();
+        let mut entries = chunks
+            .par_iter()
+            .map(|chunk| {
+                let mut entry = self.read_tree_chunk(chunk)?;
+                if let Some(path) = prune_to
+                    && starts_with_ignore_case(path, &chunk.path)
+                {
+                    let mut components = path.components();
+                    for _ in chunk.path.components() {
+                        components.next();
+                    }
+                    prune(&mut entry, components);
+                }
+
+                Ok((&chunk.path, entry))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter();
+
+        let (_, mut root) = entries
+            .next()
+            .filter(|(path, _)| path.parent().is_none())
+            .context("the root chunk is missing")?;
+        for (path, entry) in entries {
+            root.insert(path, entry);
+        }
+
+        Ok(root)
+    }
+}
+
+/// Removes all entries below `entry` that are not on the way given by `components`.
+fn prune(entry: &mut MetaDEntry, mut components: Components) {
+    let Some(Component::Normal(name)) = components.next() else {
+        return;
+    };
+
+    if let DirEntry::Directory(dir) = &mut entry.entry {
+        dir.entries
+            .retain(|entry_name, _| entry_name.eq_ignore_ascii_case(name));
+        for child in dir.entries.values_mut() {
+            prune(child, components.clone());
+        }
+    }
+}
+
+/// Returns `true` if `path` starts with `base`, ignoring the case of ASCII characters.
+fn starts_with_ignore_case(path: &Path, base: &Path) -> bool {
+    let mut path = path.components();
+
+    base.components().all(|component| {
+        path.next()
+            .is_some_and(|path| path.as_os_str().eq_ignore_ascii_case(component.as_os_str()))
+    })
+}
@@ -0,0 +1,83 @@
+//! The compression codecs that can be used for snapshot files.
+
+use std::{
+    fmt,
+    io::{self, Read as _, Write as _},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The compression level used for zstd.
+///
+/// This is a good trade-off between the compression ratio and the time it takes to write a
+/// snapshot.
+const ZSTD_LEVEL: i32 = 9;
+
+/// A compression codec for the data in snapshot files.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum Compression {
+    /// The data is stored uncompressed.
+    None,
+    /// The data is compressed with gzip at the best compression level.
+    Gzip,
+    /// The data is compressed with zstd.
+    #[default]
+    Zstd,
+}
+
+impl Compression {
+    /// Compresses the given data.
+    pub(crate) fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        firestorm::profile_method!(compress);
+
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+        }
+    }
+
+    /// Decompresses the given data.
+    pub(crate) fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        firestorm::profile_method!(decompress);
+
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut decoded_data = Vec::new();
+                flate2::read::GzDecoder::new(data).read_to_end(&mut decoded_data)?;
+                Ok(decoded_data)
+            }
+            Compression::Zstd => zstd::stream::decode_all(data),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err("unrecognized compression, expected none, gzip or zstd"),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Records the errors that occurred while creating a snapshot.
+//!
+//! Entries that could not be read are missing from the snapshot, so the record of these errors
+//! allows differences to distinguish them from entries that were really added or removed.
+
+use std::{
+    collections::BTreeSet,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The operation that failed while creating a snapshot.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum Operation {
+    /// Listing the contents of a directory failed.
+    ListDirectory,
+    /// Reading the metadata or contents of an entry failed.
+    ReadEntry,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::ListDirectory => write!(f, "list directory"),
+            Operation::ReadEntry => write!(f, "read entry"),
+        }
+    }
+}
+
+/// The kind of an error that occurred while creating a snapshot.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum ErrorKind {
+    /// The entry did not exist anymore.
+    NotFound,
+    /// The permissions to access the entry were missing.
+    PermissionDenied,
+    /// The data of the entry was invalid, for example because of file system corruption.
+    InvalidData,
+    /// Any other error.
+    Other,
+}
+
+impl From<io::ErrorKind> for ErrorKind {
+    fn from(kind: io::ErrorKind) -> Self {
+        match kind {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => ErrorKind::InvalidData,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::NotFound => write!(f, "not found"),
+            ErrorKind::PermissionDenied => write!(f, "permission denied"),
+            ErrorKind::InvalidData => write!(f, "invalid data"),
+            ErrorKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// An error that occurred while creating a snapshot.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct AcquisitionError {
+    /// The path in the snapshot where the error occurred.
+    pub(crate) path: PathBuf,
+    /// The operation that failed.
+    pub(crate) operation: Operation,
+    /// The kind of the error.
+    pub(crate) kind: ErrorKind,
+    /// The error message.
+    pub(crate) message: String,
+}
+
+impl AcquisitionError {
+    /// Records the given error that occurred during `operation` at `path`.
+    pub(crate) fn new(path: PathBuf, operation: Operation, err: impl Into<anyhow::Error>) -> Self {
+        let err = err.into();
+        let kind = err
+            .chain()
+            .find_map(|err| err.downcast_ref::<io::Error>())
+            .map(|err| err.kind().into())
+            .unwrap_or(ErrorKind::Other);
+
+        Self {
+            path,
+            operation,
+            kind,
+            message: format!("{err:#}"),
+        }
+    }
+}
+
+impl fmt::Display for AcquisitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "could not {} {} ({}): {}",
+            self.operation,
+            self.path.display(),
+            self.kind,
+            self.message
+        )
+    }
+}
+
+/// Returns a function that decides whether the given path could not be read due to one of the
+/// given errors.
+///
+/// This is the case for the paths of the errors and all paths below them.
+pub(crate) fn unreadable<'errors>(
+    errors: impl IntoIterator<Item = &'errors AcquisitionError>,
+) -> impl Fn(&Path) -> bool {
+    let paths = errors
+        .into_iter()
+        .map(|error| error.path.clone())
+        .collect::<BTreeSet<_>>();
+
+    move |path| !paths.is_empty() && path.ancestors().any(|ancestor| paths.contains(ancestor))
+}
@@ -0,0 +1,218 @@
+//! Excludes paths matching glob patterns from snapshots.
+//!
+//! Patterns are matched against the absolute path of an entry within the snapshot.
+//! A pattern that does not start with `/` matches at any depth.
+//! The state of a path is determined by the closest ancestor (or the path itself) that matches any
+//! pattern, with include patterns taking precedence over exclude patterns.
+//! This allows, for example, to exclude `/Windows` but still include `/Windows/System32`.
+
+use std::{
+    collections::BTreeSet,
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context as _;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+
+/// What happens to excluded paths when creating a snapshot.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum ExcludedMode {
+    /// Excluded paths are not stored in the snapshot at all.
+    #[default]
+    Skip,
+    /// Only the metadata of excluded paths is stored, the contents of files are not read.
+    Metadata,
+    /// Excluded paths are stored completely, but are reported as excluded in differences.
+    Hash,
+}
+
+impl FromStr for ExcludedMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ExcludedMode::Skip),
+            "metadata" | "metadata-only" => Ok(ExcludedMode::Metadata),
+            "hash" => Ok(ExcludedMode::Hash),
+            _ => Err("unrecognized mode for excluded paths, expected skip, metadata or hash"),
+        }
+    }
+}
+
+impl fmt::Display for ExcludedMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExcludedMode::Skip => write!(f, "skip"),
+            ExcludedMode::Metadata => write!(f, "metadata"),
+            ExcludedMode::Hash => write!(f, "hash"),
+        }
+    }
+}
+
+/// The record of the excluded paths, stored in a snapshot.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct Exclusions {
+    /// The patterns of paths that were excluded.
+    pub(crate) exclude: Vec<String>,
+    /// The patterns of paths that were included again.
+    pub(crate) include: Vec<String>,
+    /// What happened to the excluded paths.
+    pub(crate) mode: ExcludedMode,
+    /// The excluded paths whose parent was not excluded.
+    pub(crate) paths: BTreeSet<PathBuf>,
+}
+
+impl Exclusions {
+    /// Returns the filter that decides which paths are excluded.
+    pub(crate) fn filter(&self) -> anyhow::Result<ExcludeFilter> {
+        ExcludeFilter::new(self.exclude.clone(), self.include.clone(), self.mode)
+    }
+}
+
+impl fmt::Display for Exclusions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Excluded ({}):", self.mode)?;
+        for pattern in &self.exclude {
+            write!(f, " {pattern}")?;
+        }
+        if !self.include.is_empty() {
+            write!(f, ", included:")?;
+            for pattern in &self.include {
+                write!(f, " {pattern}")?;
+            }
+        }
+        match self.paths.len() {
+            1 => write!(f, " (1 path)"),
+            len => write!(f, " ({len} paths)"),
+        }
+    }
+}
+
+/// Decides which paths are excluded from a snapshot.
+#[derive(Debug, Clone)]
+pub(crate) struct ExcludeFilter {
+    /// The patterns of paths that are excluded.
+    exclude_patterns: Vec<String>,
+    /// The patterns of paths that are included again.
+    include_patterns: Vec<String>,
+    /// The compiled exclude patterns.
+    exclude: GlobSet,
+    /// The compiled include patterns.
+    include: GlobSet,
+    /// What happens to the excluded paths.
+    pub(crate) mode: ExcludedMode,
+}
+
+impl Default for ExcludeFilter {
+    fn default() -> Self {
+        Self {
+            exclude_patterns: Vec::new(),
+            include_patterns: Vec::new(),
+            exclude: GlobSet::empty(),
+            include: GlobSet::empty(),
+            mode: ExcludedMode::default(),
+        }
+    }
+}
+
+impl ExcludeFilter {
+    /// Creates a filter from the given exclude and include patterns.
+    pub(crate) fn new(
+        exclude: Vec<String>,
+        include: Vec<String>,
+        mode: ExcludedMode,
+    ) -> anyhow::Result<Self> {
+        /// Compiles the given patterns into a set.
+        fn compile(patterns: &[String]) -> anyhow::Result<GlobSet> {
+            let mut set = GlobSetBuilder::new();
+            for pattern in patterns {
+                let anchored = if pattern.starts_with('/') {
+                    pattern.clone()
+                } else {
+                    format!("**/{pattern}")
+                };
+
+                let glob = GlobBuilder::new(anchored.trim_end_matches('/'))
+                    .literal_separator(true)
+                    .case_insensitive(true)
+                    .build()
+                    .with_context(|| format!("invalid pattern {pattern}"))?;
+                set.add(glob);
+            }
+
+            Ok(set.build()?)
+        }
+
+        Ok(Self {
+            exclude: compile(&exclude)?,
+            include: compile(&include)?,
+            exclude_patterns: exclude,
+            include_patterns: include,
+            mode,
+        })
+    }
+
+    /// Reads exclude patterns from a file, one pattern per line.
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    pub(crate) fn read_patterns(path: impl AsRef<Path>) -> io::Result<Vec<String>> {
+        let content = std::fs::read_to_string(path)?;
+
+        Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Returns `true` if no paths can be excluded by this filter.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.exclude_patterns.is_empty()
+    }
+
+    /// Returns `true` if the given path in the snapshot is excluded.
+    pub(crate) fn is_excluded(&self, path: &Path) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        for ancestor in path.ancestors() {
+            if self.include.is_match(ancestor) {
+                return false;
+            }
+            if self.exclude.is_match(ancestor) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Returns `true` if the directory at the given path can be skipped entirely.
+    ///
+    /// This is the case if it is excluded and none of its descendants can be included again.
+    pub(crate) fn skips_dir(&self, path: &Path) -> bool {
+        self.mode == ExcludedMode::Skip
+            && self.include_patterns.is_empty()
+            && self.is_excluded(path)
+    }
+
+    /// Returns `true` if the given path is excluded, but its parent is not.
+    pub(crate) fn is_exclusion_root(&self, path: &Path) -> bool {
+        self.is_excluded(path) && path.parent().is_none_or(|parent| !self.is_excluded(parent))
+    }
+
+    /// Records the given excluded paths together with the patterns of this filter.
+    pub(crate) fn record(&self, paths: BTreeSet<PathBuf>) -> Exclusions {
+        Exclusions {
+            exclude: self.exclude_patterns.clone(),
+            include: self.include_patterns.clone(),
+            mode: self.mode,
+            paths,
+        }
+    }
+}
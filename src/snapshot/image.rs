@@ -0,0 +1,217 @@
+//! Reading of virtual disk images.
+//!
+//! Every supported container format exposes the virtual disk it contains as a `ReadAt` device, so
+//! the partition and file system layers do not need to know about the container.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+
+use super::{
+    read_at::{HostFile, ReadAt},
+    Source,
+};
+
+mod qcow2;
+mod vdi;
+mod vhd;
+mod vhdx;
+mod vmdk;
+
+/// The container formats of disk images that can be read.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum ImageFormat {
+    /// A VirtualBox disk image.
+    Vdi,
+    /// A VMware virtual disk.
+    Vmdk,
+    /// A Virtual PC or Hyper-V virtual hard disk.
+    Vhd,
+    /// A Hyper-V virtual hard disk in the newer VHDX format.
+    Vhdx,
+    /// A QEMU copy-on-write disk image, version 2 or 3.
+    Qcow2,
+    /// A raw copy of a disk, for example created with `dd`.
+    Raw,
+}
+
+impl ImageFormat {
+    /// Detects the format of the given disk image by its magic bytes.
+    ///
+    /// Raw images are only recognized if they start with a partition table.
+    pub(crate) fn detect(file: &HostFile) -> io::Result<Option<Self>> {
+        let mut start = [0; 0x200];
+        let len = read_up_to(file, &mut start, 0)?;
+        let start = &start[..len];
+
+        let format = if start.starts_with(vdi::VDI_HEADER_TEXT) {
+            Some(ImageFormat::Vdi)
+        } else if vmdk::is_vmdk(start) {
+            Some(ImageFormat::Vmdk)
+        } else if start.starts_with(vhdx::VHDX_SIGNATURE) {
+            Some(ImageFormat::Vhdx)
+        } else if start.starts_with(qcow2::QCOW2_MAGIC) {
+            Some(ImageFormat::Qcow2)
+        } else if start.starts_with(vhd::VHD_COOKIE) || vhd::has_footer(file)? {
+            Some(ImageFormat::Vhd)
+        } else if start.get(0x1fe..0x200) == Some(&[0x55, 0xaa]) {
+            Some(ImageFormat::Raw)
+        } else {
+            None
+        };
+
+        Ok(format)
+    }
+
+    /// Opens the image at the given path, which must be in this format.
+    pub(crate) fn open(self, path: &Path) -> anyhow::Result<Box<dyn ReadAt>> {
+        let image: Box<dyn ReadAt> = match self {
+            ImageFormat::Vdi => Box::new(vdi::VdiImage::open(path)?),
+            ImageFormat::Vmdk => Box::new(vmdk::VmdkImage::open(path)?),
+            ImageFormat::Vhd => Box::new(vhd::VhdImage::open(path)?),
+            ImageFormat::Vhdx => Box::new(vhdx::VhdxImage::open(path)?),
+            ImageFormat::Qcow2 => Box::new(qcow2::Qcow2Image::open(path)?),
+            ImageFormat::Raw => Box::new(HostFile::open(path)?),
+        };
+
+        Ok(image)
+    }
+
+    /// Returns the snapshot source describing an image in this format at the given path.
+    pub(crate) fn source(self, path: PathBuf) -> Source {
+        match self {
+            ImageFormat::Vdi => Source::VdiImage(path),
+            ImageFormat::Vmdk => Source::VmdkImage(path),
+            ImageFormat::Vhd => Source::VhdImage(path),
+            ImageFormat::Vhdx => Source::VhdxImage(path),
+            ImageFormat::Qcow2 => Source::Qcow2Image(path),
+            ImageFormat::Raw => Source::RawImage(path),
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageFormat::Vdi => write!(f, "VDI"),
+            ImageFormat::Vmdk => write!(f, "VMDK"),
+            ImageFormat::Vhd => write!(f, "VHD"),
+            ImageFormat::Vhdx => write!(f, "VHDX"),
+            ImageFormat::Qcow2 => write!(f, "QCOW2"),
+            ImageFormat::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+/// Opens the disk image at the given path, detecting its format.
+///
+/// This is used for parent images of differencing images, which may have a different format.
+fn open_any(path: &Path) -> anyhow::Result<Box<dyn ReadAt>> {
+    let file =
+        HostFile::open(path).with_context(|| format!("could not open {}", path.display()))?;
+
+    let format = ImageFormat::detect(&file)?.unwrap_or(ImageFormat::Raw);
+
+    format
+        .open(path)
+        .with_context(|| format!("could not open {format} image {}", path.display()))
+}
+
+/// Reads as many bytes as possible into `buf`, stopping early only at the end of the data.
+fn read_up_to(source: &impl ReadAt, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let mut total = 0;
+
+    while total < buf.len() {
+        match source.read_at(&mut buf[total..], offset + total as u64) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(total)
+}
+
+/// Describes where the data at an offset of a sparse image is stored.
+enum Mapping {
+    /// The next `len` bytes are stored at the given offset in the image file.
+    Data {
+        /// The offset in the image file.
+        offset: u64,
+        /// The number of bytes stored there.
+        len: u64,
+    },
+    /// The next `len` bytes are stored in the given decompressed buffer at the given offset.
+    Decompressed {
+        /// The decompressed data.
+        data: std::sync::Arc<Vec<u8>>,
+        /// The offset in the decompressed data.
+        offset: usize,
+        /// The number of bytes stored there.
+        len: u64,
+    },
+    /// The next `len` bytes are zero.
+    Zero {
+        /// The number of zero bytes.
+        len: u64,
+    },
+    /// The next `len` bytes are not stored in this image, but in its parent, if it exists.
+    Unallocated {
+        /// The number of unallocated bytes.
+        len: u64,
+    },
+}
+
+/// Reads data from a sparse image, using `map` to find out where the data is stored.
+///
+/// Unallocated data is read from `parent` or is zero if there is no parent.
+fn read_mapped(
+    buf: &mut [u8],
+    offset: u64,
+    size: u64,
+    file: &HostFile,
+    parent: Option<&dyn ReadAt>,
+    map: impl FnOnce(u64) -> io::Result<Mapping>,
+) -> io::Result<usize> {
+    if offset >= size || buf.is_empty() {
+        return Ok(0);
+    }
+
+    let max_len = (buf.len() as u64).min(size - offset);
+    let clamp = |len: u64| len.min(max_len) as usize;
+
+    match map(offset)? {
+        Mapping::Data {
+            offset: file_offset,
+            len,
+        } => file.read_at(&mut buf[..clamp(len)], file_offset),
+        Mapping::Decompressed {
+            data,
+            offset: data_offset,
+            len,
+        } => {
+            let len = clamp(len).min(data.len().saturating_sub(data_offset));
+            buf[..len].copy_from_slice(&data[data_offset..data_offset + len]);
+            Ok(len)
+        }
+        Mapping::Zero { len } => {
+            let len = clamp(len);
+            buf[..len].fill(0);
+            Ok(len)
+        }
+        Mapping::Unallocated { len } => {
+            let len = clamp(len);
+            match parent {
+                Some(parent) if offset < parent.size() => parent.read_at(&mut buf[..len], offset),
+                _ => {
+                    buf[..len].fill(0);
+                    Ok(len)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Reading of QEMU copy-on-write disk images (QCOW2).
+//!
+//! The layout is described in the
+//! [QEMU documentation](https://gitlab.com/qemu-project/qemu/-/blob/master/docs/interop/qcow2.txt).
+
+use std::{
+    io::{self, Read as _},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+
+use super::{open_any, read_mapped, Mapping};
+use crate::snapshot::read_at::{HostFile, ReadAt};
+
+/// The magic number at the start of every QCOW2 file.
+pub(super) const QCOW2_MAGIC: &[u8] = b"QFI\xfb";
+
+/// The mask of the host offset in L1 and L2 table entries.
+const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+
+/// The flag marking compressed clusters in L2 table entries.
+const COMPRESSED: u64 = 1 << 62;
+
+/// The flag marking clusters that read as zeros in L2 table entries.
+const ZERO: u64 = 1;
+
+/// The incompatible feature bits that are understood (the dirty bit).
+const KNOWN_INCOMPATIBLE_FEATURES: u64 = 1 << 0;
+
+/// The largest supported cluster size exponent.
+const MAX_CLUSTER_BITS: u32 = 21;
+
+/// The maximum number of images in a chain of backing files.
+const MAX_CHAIN_LEN: usize = 256;
+
+/// Reads a big endian `u32` at the given offset in `data`.
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads a big endian `u64` at the given offset in `data`.
+fn be_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// A QEMU copy-on-write disk image, including its backing files.
+pub(crate) struct Qcow2Image {
+    /// The file containing the image.
+    file: HostFile,
+    /// The size of the virtual disk in bytes.
+    size: u64,
+    /// The number of bits of the cluster size.
+    cluster_bits: u32,
+    /// The L2 table entries of all clusters.
+    clusters: Vec<u64>,
+    /// The backing file, if there is one.
+    backing: Option<Box<dyn ReadAt>>,
+    /// The most recently decompressed cluster and its index.
+    cluster_cache: Mutex<Option<(usize, Arc<Vec<u8>>)>>,
+}
+
+impl Qcow2Image {
+    /// Opens the QCOW2 image at `path`.
+    pub(crate) fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::open_chain(path.as_ref(), 0)
+    }
+
+    /// Opens the QCOW2 image at `path` that has `depth` children.
+    fn open_chain(path: &Path, depth: usize) -> anyhow::Result<Self> {
+        if depth >= MAX_CHAIN_LEN {
+            anyhow::bail!("the chain of backing files is too long");
+        }
+
+        let file =
+            HostFile::open(path).with_context(|| format!("could not open {}", path.display()))?;
+
+        let mut header = [0; 104];
+        file.read_exact_at(&mut header[..72], 0)?;
+        if !header.starts_with(QCOW2_MAGIC) {
+            anyhow::bail!("{} is not a QCOW2 image", path.display());
+        }
+
+        let version = be_u32(&header, 4);
+        match version {
+            2 => (),
+            3 => {
+                file.read_exact_at(&mut header[72..], 72)?;
+                let incompatible = be_u64(&header, 72) & !KNOWN_INCOMPATIBLE_FEATURES;
+                if incompatible != 0 {
+                    anyhow::bail!("unsupported QCOW2 incompatible features {incompatible:#x}");
+                }
+            }
+            other => anyhow::bail!("unsupported QCOW2 version {other}"),
+        }
+
+        let backing_offset = be_u64(&header, 8);
+        let backing_len = be_u32(&header, 16);
+        let cluster_bits = be_u32(&header, 20);
+        let size = be_u64(&header, 24);
+        let encryption = be_u32(&header, 32);
+        let l1_len = be_u32(&header, 36);
+        let l1_offset = be_u64(&header, 40);
+
+        if encryption != 0 {
+            anyhow::bail!("encrypted QCOW2 images are not supported");
+        }
+        if !(9..=MAX_CLUSTER_BITS).contains(&cluster_bits) {
+            anyhow::bail!("unsupported QCOW2 cluster size 2^{cluster_bits}");
+        }
+
+        let mut l1 = vec![0; l1_len as usize * 8];
+        file.read_exact_at(&mut l1, l1_offset)
+            .context("could not read L1 table")?;
+
+        let cluster_count = size.div_ceil(1 << cluster_bits);
+        let l2_len = 1usize << (cluster_bits - 3);
+
+        let mut clusters = Vec::with_capacity(cluster_count as usize);
+        let mut l2 = vec![0; 1 << cluster_bits];
+        for l1_entry in l1.chunks_exact(8).map(|entry| be_u64(entry, 0)) {
+            if clusters.len() as u64 >= cluster_count {
+                break;
+            }
+
+            let l2_offset = l1_entry & OFFSET_MASK;
+            if l2_offset == 0 {
+                clusters.extend(std::iter::repeat_n(0, l2_len));
+                continue;
+            }
+
+            file.read_exact_at(&mut l2, l2_offset)
+                .context("could not read L2 table")?;
+            clusters.extend(l2.chunks_exact(8).map(|entry| be_u64(entry, 0)));
+        }
+        clusters.truncate(cluster_count as usize);
+
+        let backing = if backing_offset != 0 && backing_len != 0 {
+            let mut name = vec![0; backing_len as usize];
+            file.read_exact_at(&mut name, backing_offset)?;
+            let name = String::from_utf8(name).context("backing file name is not UTF-8")?;
+
+            let backing_path = path.parent().unwrap_or(Path::new(".")).join(name);
+            let backing_file = HostFile::open(&backing_path)
+                .with_context(|| format!("could not open {}", backing_path.display()))?;
+
+            // Keep track of the depth for QCOW2 backing files, to avoid following cycles forever
+            let mut magic = [0; QCOW2_MAGIC.len()];
+            backing_file.read_exact_at(&mut magic, 0)?;
+            let backing: Box<dyn ReadAt> = if magic == QCOW2_MAGIC {
+                Box::new(Self::open_chain(&backing_path, depth + 1)?)
+            } else {
+                open_any(&backing_path)?
+            };
+
+            Some(backing)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            file,
+            size,
+            cluster_bits,
+            clusters,
+            backing,
+            cluster_cache: Mutex::new(None),
+        })
+    }
+
+    /// Returns the decompressed content of the cluster with the given L2 table entry.
+    fn decompress_cluster(&self, cluster: usize, entry: u64) -> io::Result<Arc<Vec<u8>>> {
+        let mut cache = self.cluster_cache.lock().unwrap();
+        if let Some((cached, data)) = &*cache
+            && *cached == cluster
+        {
+            return Ok(Arc::clone(data));
+        }
+
+        // The descriptor of compressed clusters consists of the host offset and the number of
+        // additional 512 byte sectors the compressed data spans
+        let offset_bits = 62 - (self.cluster_bits - 8);
+        let offset = entry & ((1 << offset_bits) - 1);
+        let sectors = ((entry >> offset_bits) & ((1 << (62 - offset_bits)) - 1)) + 1;
+        let compressed_len = sectors * 512 - (offset % 512);
+
+        let mut compressed = vec![0; compressed_len as usize];
+        let len = super::read_up_to(&self.file, &mut compressed, offset)?;
+        compressed.truncate(len);
+
+        let cluster_size = 1 << self.cluster_bits;
+        let mut data = Vec::with_capacity(cluster_size as usize);
+        flate2::read::DeflateDecoder::new(&compressed[..])
+            .take(cluster_size)
+            .read_to_end(&mut data)?;
+        data.resize(cluster_size as usize, 0);
+
+        let data = Arc::new(data);
+        *cache = Some((cluster, Arc::clone(&data)));
+
+        Ok(data)
+    }
+}
+
+impl ReadAt for Qcow2Image {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        read_mapped(
+            buf,
+            offset,
+            self.size,
+            &self.file,
+            self.backing.as_deref(),
+            |offset| {
+                let cluster_size = 1 << self.cluster_bits;
+                let cluster = (offset >> self.cluster_bits) as usize;
+                let cluster_offset = offset & (cluster_size - 1);
+                let len = cluster_size - cluster_offset;
+
+                let entry = self.clusters.get(cluster).copied().unwrap_or(0);
+
+                Ok(if entry & COMPRESSED != 0 {
+                    Mapping::Decompressed {
+                        data: self.decompress_cluster(cluster, entry)?,
+                        offset: cluster_offset as usize,
+                        len,
+                    }
+                } else if entry & ZERO != 0 {
+                    Mapping::Zero { len }
+                } else if entry & OFFSET_MASK != 0 {
+                    Mapping::Data {
+                        offset: (entry & OFFSET_MASK) + cluster_offset,
+                        len,
+                    }
+                } else {
+                    Mapping::Unallocated { len }
+                })
+            },
+        )
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
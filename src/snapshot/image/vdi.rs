@@ -0,0 +1,265 @@
+//! Reading of VirtualBox disk images (VDI).
+//!
+//! The layout is described in the
+//! [VirtualBox source code](https://www.virtualbox.org/browser/vbox/trunk/src/VBox/Storage/VDICore.h).
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+
+use crate::snapshot::read_at::{HostFile, ReadAt};
+
+/// The text at the start of every VDI file.
+pub(super) const VDI_HEADER_TEXT: &[u8] = b"<<< Oracle VM VirtualBox Disk Image >>>\n";
+
+/// The signature following the text at the start of the file.
+const VDI_SIGNATURE: u32 = 0xbeda_107f;
+
+/// The offset of the signature in the file.
+const SIGNATURE_OFFSET: u64 = 0x40;
+
+/// The offset of the header in the file.
+const HEADER_OFFSET: u64 = 0x48;
+
+/// The length of the version 1 header that is used here.
+const HEADER_LEN: usize = 0x180;
+
+/// The image type of differencing images.
+const IMAGE_TYPE_DIFF: u32 = 4;
+
+/// The block map entry for blocks that are not allocated in this image.
+const BLOCK_FREE: u32 = 0xffff_ffff;
+
+/// The block map entry for blocks that only contain zeros.
+const BLOCK_ZERO: u32 = 0xffff_fffe;
+
+/// The maximum number of images in a chain of differencing images.
+const MAX_CHAIN_LEN: usize = 256;
+
+/// The header of a VDI file.
+#[derive(Debug)]
+struct VdiHeader {
+    /// The type of the image.
+    image_type: u32,
+    /// The offset of the block map in the file.
+    blocks_offset: u64,
+    /// The offset of the first data block in the file.
+    data_offset: u64,
+    /// The size of the virtual disk in bytes.
+    disk_size: u64,
+    /// The size of a block in bytes.
+    block_size: u64,
+    /// The size of the extra data preceding each block.
+    block_extra: u64,
+    /// The number of blocks in the image.
+    block_count: u32,
+    /// The UUID of this image.
+    uuid: [u8; 16],
+    /// The UUID of the parent image, if this is a differencing image.
+    parent_uuid: [u8; 16],
+}
+
+impl VdiHeader {
+    /// Reads the header of the given VDI file.
+    fn read(file: &HostFile) -> anyhow::Result<Self> {
+        let mut signature = [0; 8];
+        file.read_exact_at(&mut signature, SIGNATURE_OFFSET)?;
+
+        if u32::from_le_bytes(signature[..4].try_into().unwrap()) != VDI_SIGNATURE {
+            anyhow::bail!("not a VDI image");
+        }
+
+        let major_version = u16::from_le_bytes(signature[6..8].try_into().unwrap());
+        if major_version != 1 {
+            anyhow::bail!("unsupported VDI version {major_version}");
+        }
+
+        let mut data = vec![0; HEADER_LEN];
+        file.read_exact_at(&mut data, HEADER_OFFSET)?;
+
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+        Ok(Self {
+            image_type: u32_at(0x04),
+            blocks_offset: u32_at(0x10c).into(),
+            data_offset: u32_at(0x110).into(),
+            disk_size: u64_at(0x128),
+            block_size: u32_at(0x130).into(),
+            block_extra: u32_at(0x134).into(),
+            block_count: u32_at(0x138),
+            uuid: data[0x140..0x150].try_into().unwrap(),
+            parent_uuid: data[0x160..0x170].try_into().unwrap(),
+        })
+    }
+}
+
+/// A single VDI file in a chain of differencing images.
+struct VdiLayer {
+    /// The opened file.
+    file: HostFile,
+    /// The block map, mapping virtual blocks to blocks in the file.
+    block_map: Vec<u32>,
+    /// The offset of the first data block in the file.
+    data_offset: u64,
+    /// The size of the extra data preceding each block.
+    block_extra: u64,
+}
+
+/// A VirtualBox disk image, including all of its parent images.
+///
+/// The virtual disk can be read with `ReadAt` or, through `ReadAt::cursor`, with `Read` and
+/// `Seek`.
+pub(crate) struct VdiImage {
+    /// The layers of the image, starting with the image itself, followed by its parents.
+    layers: Vec<VdiLayer>,
+    /// The size of a block in bytes.
+    block_size: u64,
+    /// The size of the virtual disk in bytes.
+    disk_size: u64,
+}
+
+impl VdiImage {
+    /// Opens the VDI image at the given path.
+    ///
+    /// The parents of differencing images are searched next to the image and in `Snapshots`
+    /// directories, which is where VirtualBox stores them.
+    pub(crate) fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut path = path.as_ref().to_path_buf();
+
+        let mut layers = Vec::new();
+        let mut geometry = None;
+
+        loop {
+            let file = HostFile::open(&path)
+                .with_context(|| format!("could not open {}", path.display()))?;
+            let header = VdiHeader::read(&file)
+                .with_context(|| format!("could not read VDI header of {}", path.display()))?;
+
+            match geometry {
+                None => geometry = Some((header.block_size, header.disk_size)),
+                Some((block_size, _)) if block_size != header.block_size => anyhow::bail!(
+                    "{} has a different block size than its child",
+                    path.display()
+                ),
+                Some(_) => (),
+            }
+
+            let mut raw_map = vec![0; header.block_count as usize * 4];
+            file.read_exact_at(&mut raw_map, header.blocks_offset)
+                .with_context(|| format!("could not read block map of {}", path.display()))?;
+
+            layers.push(VdiLayer {
+                file,
+                block_map: raw_map
+                    .chunks_exact(4)
+                    .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+                    .collect(),
+                data_offset: header.data_offset,
+                block_extra: header.block_extra,
+            });
+
+            if header.image_type != IMAGE_TYPE_DIFF {
+                break;
+            }
+
+            if layers.len() >= MAX_CHAIN_LEN {
+                anyhow::bail!("the chain of differencing images is too long");
+            }
+
+            path = find_parent(&path, &header.parent_uuid)?;
+        }
+
+        let (block_size, disk_size) = geometry.unwrap();
+        if block_size == 0 {
+            anyhow::bail!("VDI image has a block size of zero");
+        }
+
+        Ok(Self {
+            layers,
+            block_size,
+            disk_size,
+        })
+    }
+}
+
+impl ReadAt for VdiImage {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.disk_size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block = (offset / self.block_size) as usize;
+        let block_offset = offset % self.block_size;
+        let len = buf
+            .len()
+            .min(usize::try_from(self.block_size - block_offset).unwrap_or(usize::MAX))
+            .min(usize::try_from(self.disk_size - offset).unwrap_or(usize::MAX));
+        let buf = &mut buf[..len];
+
+        for layer in &self.layers {
+            match layer.block_map.get(block).copied().unwrap_or(BLOCK_FREE) {
+                BLOCK_FREE => continue,
+                BLOCK_ZERO => break,
+                entry => {
+                    let block_start = layer.data_offset
+                        + u64::from(entry) * (self.block_size + layer.block_extra)
+                        + layer.block_extra;
+
+                    return layer.file.read_at(buf, block_start + block_offset);
+                }
+            }
+        }
+
+        // Blocks not allocated in any layer read as zeros
+        buf.fill(0);
+        Ok(len)
+    }
+
+    fn size(&self) -> u64 {
+        self.disk_size
+    }
+}
+
+/// Finds the parent image with the given UUID of the differencing image at `path`.
+fn find_parent(path: &Path, uuid: &[u8; 16]) -> anyhow::Result<PathBuf> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut search_dirs = vec![dir.to_path_buf(), dir.join("Snapshots")];
+    if let Some(parent_dir) = dir.parent() {
+        search_dirs.push(parent_dir.to_path_buf());
+        search_dirs.push(parent_dir.join("Snapshots"));
+    }
+
+    for search_dir in search_dirs {
+        let Ok(entries) = std::fs::read_dir(&search_dir) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let candidate = entry.path();
+            if candidate.extension().and_then(|ext| ext.to_str()) != Some("vdi")
+                || candidate == path
+            {
+                continue;
+            }
+
+            if let Ok(file) = HostFile::open(&candidate)
+                && let Ok(header) = VdiHeader::read(&file)
+                && &header.uuid == uuid
+            {
+                return Ok(candidate);
+            }
+        }
+    }
+
+    anyhow::bail!(
+        "could not find the parent image {} of {}",
+        hex::encode(uuid),
+        path.display()
+    )
+}
@@ -0,0 +1,331 @@
+//! Reading of Virtual PC and Hyper-V virtual hard disks (VHD).
+//!
+//! The layout is described in the
+//! [Virtual Hard Disk Image Format Specification](https://learn.microsoft.com/en-us/windows/win32/vstor/about-vhd).
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+
+use super::{open_any, read_mapped, Mapping};
+use crate::snapshot::read_at::{HostFile, ReadAt};
+
+/// The cookie at the start of the footer (and its copy at the start of dynamic disks).
+pub(super) const VHD_COOKIE: &[u8] = b"conectix";
+
+/// The cookie at the start of the dynamic disk header.
+const DYNAMIC_COOKIE: &[u8] = b"cxsparse";
+
+/// The size of the footer in bytes.
+const FOOTER_LEN: u64 = 512;
+
+/// The size of the dynamic disk header in bytes.
+const DYNAMIC_HEADER_LEN: usize = 1024;
+
+/// The size of a sector in bytes.
+const SECTOR_SIZE: u64 = 512;
+
+/// The disk type of fixed disks.
+const DISK_TYPE_FIXED: u32 = 2;
+
+/// The disk type of dynamic disks.
+const DISK_TYPE_DYNAMIC: u32 = 3;
+
+/// The disk type of differencing disks.
+const DISK_TYPE_DIFFERENCING: u32 = 4;
+
+/// The block allocation table entry of blocks that are not allocated.
+const BLOCK_UNUSED: u32 = 0xffff_ffff;
+
+/// The platform code of parent locators storing a relative Windows path.
+const PLATFORM_W2RU: &[u8] = b"W2ru";
+
+/// The platform code of parent locators storing an absolute Windows path.
+const PLATFORM_W2KU: &[u8] = b"W2ku";
+
+/// The maximum number of images in a chain of differencing images.
+const MAX_CHAIN_LEN: usize = 256;
+
+/// Returns `true` if the given file ends with a VHD footer.
+///
+/// Fixed disks only have a footer, so this is the only way to recognize them.
+pub(super) fn has_footer(file: &HostFile) -> io::Result<bool> {
+    let Some(offset) = file.size().checked_sub(FOOTER_LEN) else {
+        return Ok(false);
+    };
+
+    let mut cookie = [0; VHD_COOKIE.len()];
+    file.read_exact_at(&mut cookie, offset)?;
+
+    Ok(cookie == VHD_COOKIE)
+}
+
+/// Reads a big endian `u32` at the given offset in `data`.
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads a big endian `u64` at the given offset in `data`.
+fn be_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// The block allocation of a dynamic or differencing disk.
+struct Blocks {
+    /// The size of a block in bytes.
+    block_size: u64,
+    /// The size of the sector bitmap preceding each block in bytes.
+    bitmap_len: u64,
+    /// The sector of each block in the file.
+    table: Vec<u32>,
+    /// The parent disk, if this is a differencing disk.
+    parent: Option<Box<dyn ReadAt>>,
+}
+
+/// A Virtual PC or Hyper-V virtual hard disk, including its parent disks.
+pub(crate) struct VhdImage {
+    /// The file containing the disk.
+    file: HostFile,
+    /// The size of the virtual disk in bytes.
+    size: u64,
+    /// The block allocation, if this is not a fixed disk.
+    blocks: Option<Blocks>,
+}
+
+impl VhdImage {
+    /// Opens the VHD at `path`.
+    pub(crate) fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::open_chain(path.as_ref(), 0)
+    }
+
+    /// Opens the VHD at `path` that has `depth` children.
+    fn open_chain(path: &Path, depth: usize) -> anyhow::Result<Self> {
+        if depth >= MAX_CHAIN_LEN {
+            anyhow::bail!("the chain of differencing images is too long");
+        }
+
+        let file =
+            HostFile::open(path).with_context(|| format!("could not open {}", path.display()))?;
+
+        let footer_offset = file
+            .size()
+            .checked_sub(FOOTER_LEN)
+            .context("file is too small to be a VHD")?;
+        let mut footer = [0; FOOTER_LEN as usize];
+        file.read_exact_at(&mut footer, footer_offset)?;
+
+        // Dynamic disks have a copy of the footer at the start, which can be used if the footer
+        // at the end was not written completely
+        if !footer.starts_with(VHD_COOKIE) {
+            file.read_exact_at(&mut footer, 0)?;
+        }
+        if !footer.starts_with(VHD_COOKIE) {
+            anyhow::bail!("{} has no VHD footer", path.display());
+        }
+
+        let size = be_u64(&footer, 0x30);
+        let disk_type = be_u32(&footer, 0x3c);
+
+        let blocks = match disk_type {
+            DISK_TYPE_FIXED => None,
+            DISK_TYPE_DYNAMIC | DISK_TYPE_DIFFERENCING => {
+                Some(Self::read_blocks(&file, &footer, path, depth)?)
+            }
+            other => anyhow::bail!("unsupported VHD disk type {other}"),
+        };
+
+        Ok(Self { file, size, blocks })
+    }
+
+    /// Reads the block allocation table of a dynamic or differencing disk.
+    fn read_blocks(
+        file: &HostFile,
+        footer: &[u8],
+        path: &Path,
+        depth: usize,
+    ) -> anyhow::Result<Blocks> {
+        let header_offset = be_u64(footer, 0x10);
+        let mut header = [0; DYNAMIC_HEADER_LEN];
+        file.read_exact_at(&mut header, header_offset)
+            .context("could not read dynamic disk header")?;
+
+        if !header.starts_with(DYNAMIC_COOKIE) {
+            anyhow::bail!("invalid dynamic disk header");
+        }
+
+        let table_offset = be_u64(&header, 0x10);
+        let entries = be_u32(&header, 0x1c);
+        let block_size = u64::from(be_u32(&header, 0x20));
+
+        if block_size == 0 || block_size % SECTOR_SIZE != 0 {
+            anyhow::bail!("invalid VHD block size {block_size}");
+        }
+
+        let mut table = vec![0; entries as usize * 4];
+        file.read_exact_at(&mut table, table_offset)
+            .context("could not read block allocation table")?;
+        let table = table
+            .chunks_exact(4)
+            .map(|entry| be_u32(entry, 0))
+            .collect();
+
+        let bitmap_len = (block_size / SECTOR_SIZE)
+            .div_ceil(8)
+            .next_multiple_of(SECTOR_SIZE);
+
+        let parent = if be_u32(footer, 0x3c) == DISK_TYPE_DIFFERENCING {
+            let parent_path = Self::find_parent(file, &header, path)?;
+            let parent_file = HostFile::open(&parent_path)
+                .with_context(|| format!("could not open {}", parent_path.display()))?;
+
+            // Keep track of the depth for VHD parents, to avoid following cycles forever
+            let parent: Box<dyn ReadAt> = if has_footer(&parent_file)? {
+                Box::new(Self::open_chain(&parent_path, depth + 1)?)
+            } else {
+                open_any(&parent_path)?
+            };
+
+            Some(parent)
+        } else {
+            None
+        };
+
+        Ok(Blocks {
+            block_size,
+            bitmap_len,
+            table,
+            parent,
+        })
+    }
+
+    /// Finds the parent of a differencing disk using its parent locators.
+    fn find_parent(file: &HostFile, header: &[u8], path: &Path) -> anyhow::Result<PathBuf> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        let mut candidates = Vec::new();
+        for locator in header[0x240..0x240 + 8 * 24].chunks_exact(24) {
+            let code = &locator[0..4];
+            let len = be_u32(locator, 8);
+            let offset = be_u64(locator, 16);
+
+            if !(code == PLATFORM_W2RU || code == PLATFORM_W2KU) || len == 0 || len > 0x10000 {
+                continue;
+            }
+
+            let mut data = vec![0; len as usize];
+            file.read_exact_at(&mut data, offset)?;
+            let name = String::from_utf16_lossy(
+                &data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect::<Vec<_>>(),
+            );
+
+            candidates.push(dir.join(windows_path_to_relative(&name)));
+        }
+
+        // The parent name itself only contains the file name
+        let parent_name = String::from_utf16_lossy(
+            &header[0x40..0x240]
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0)
+                .collect::<Vec<_>>(),
+        );
+        if !parent_name.is_empty() {
+            candidates.push(dir.join(windows_path_to_relative(&parent_name)));
+        }
+
+        candidates
+            .iter()
+            .find(|candidate| candidate.is_file())
+            .cloned()
+            .with_context(|| {
+                format!(
+                    "could not find the parent disk {parent_name:?} of {}",
+                    path.display()
+                )
+            })
+    }
+}
+
+/// Converts a Windows path stored in a parent locator to a path relative to the child image.
+///
+/// Absolute paths cannot be resolved on the host, so only the file name is kept.
+fn windows_path_to_relative(name: &str) -> PathBuf {
+    let name = name.trim_end_matches('\0');
+    let is_absolute = name.starts_with('\\') || name.get(1..2) == Some(":");
+    let mut components = name
+        .split(['\\', '/'])
+        .filter(|c| !c.is_empty() && *c != ".");
+
+    if is_absolute {
+        components
+            .next_back()
+            .map(PathBuf::from)
+            .unwrap_or_default()
+    } else {
+        components.collect()
+    }
+}
+
+impl ReadAt for VhdImage {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let Some(blocks) = &self.blocks else {
+            let len = (buf.len() as u64).min(self.size.saturating_sub(offset)) as usize;
+            return self.file.read_at(&mut buf[..len], offset);
+        };
+
+        read_mapped(
+            buf,
+            offset,
+            self.size,
+            &self.file,
+            blocks.parent.as_deref(),
+            |offset| {
+                let block = (offset / blocks.block_size) as usize;
+                let block_offset = offset % blocks.block_size;
+
+                let sector = match blocks.table.get(block) {
+                    Some(&sector) if sector != BLOCK_UNUSED => u64::from(sector) * SECTOR_SIZE,
+                    _ => {
+                        return Ok(Mapping::Unallocated {
+                            len: blocks.block_size - block_offset,
+                        })
+                    }
+                };
+
+                let data_offset = sector + blocks.bitmap_len + block_offset;
+                if blocks.parent.is_none() {
+                    return Ok(Mapping::Data {
+                        offset: data_offset,
+                        len: blocks.block_size - block_offset,
+                    });
+                }
+
+                // In differencing disks, the sector bitmap records which sectors are present
+                let sector_in_block = block_offset / SECTOR_SIZE;
+                let len = SECTOR_SIZE - block_offset % SECTOR_SIZE;
+                let mut bitmap_byte = [0];
+                self.file
+                    .read_exact_at(&mut bitmap_byte, sector + sector_in_block / 8)?;
+
+                if bitmap_byte[0] & (0x80 >> (sector_in_block % 8)) != 0 {
+                    Ok(Mapping::Data {
+                        offset: data_offset,
+                        len,
+                    })
+                } else {
+                    Ok(Mapping::Unallocated { len })
+                }
+            },
+        )
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
@@ -0,0 +1,387 @@
+//! Reading of Hyper-V virtual hard disks in the VHDX format.
+//!
+//! The layout is described in the
+//! [VHDX Format Specification](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-vhdx/83e061f8-f6e2-4de1-91bd-5d518a43d477).
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+
+use super::{open_any, read_mapped, Mapping};
+use crate::snapshot::read_at::{HostFile, ReadAt};
+
+/// The signature at the start of every VHDX file.
+pub(super) const VHDX_SIGNATURE: &[u8] = b"vhdxfile";
+
+/// The offsets of the two copies of the header.
+const HEADER_OFFSETS: [u64; 2] = [0x1_0000, 0x2_0000];
+
+/// The offset of the region table.
+const REGION_TABLE_OFFSET: u64 = 0x3_0000;
+
+/// The size of the region table in bytes.
+const REGION_TABLE_LEN: usize = 0x1_0000;
+
+/// The size of the metadata table in bytes.
+const METADATA_TABLE_LEN: usize = 0x1_0000;
+
+/// The region containing the block allocation table.
+const BAT_REGION: [u8; 16] = [
+    0x66, 0x77, 0xc2, 0x2d, 0x23, 0xf6, 0x00, 0x42, 0x9d, 0x64, 0x11, 0x5e, 0x9b, 0xfd, 0x4a, 0x08,
+];
+
+/// The region containing the metadata.
+const METADATA_REGION: [u8; 16] = [
+    0x06, 0xa2, 0x7c, 0x8b, 0x90, 0x47, 0x9a, 0x4b, 0xb8, 0xfe, 0x57, 0x5f, 0x05, 0x0f, 0x88, 0x6e,
+];
+
+/// The metadata item containing the block size and flags.
+const FILE_PARAMETERS: [u8; 16] = [
+    0x37, 0x67, 0xa1, 0xca, 0x36, 0xfa, 0x43, 0x4d, 0xb3, 0xb6, 0x33, 0xf0, 0xaa, 0x44, 0xe7, 0x6b,
+];
+
+/// The metadata item containing the size of the virtual disk.
+const VIRTUAL_DISK_SIZE: [u8; 16] = [
+    0x24, 0x42, 0xa5, 0x2f, 0x1b, 0xcd, 0x76, 0x48, 0xb2, 0x11, 0x5d, 0xbe, 0xd8, 0x3b, 0xf4, 0xb8,
+];
+
+/// The metadata item containing the logical sector size.
+const LOGICAL_SECTOR_SIZE: [u8; 16] = [
+    0x1d, 0xbf, 0x41, 0x81, 0x6f, 0xa9, 0x09, 0x47, 0xba, 0x47, 0xf2, 0x33, 0xa8, 0xfa, 0xab, 0x5f,
+];
+
+/// The metadata item containing the location of the parent disk.
+const PARENT_LOCATOR: [u8; 16] = [
+    0x2d, 0x5f, 0xd3, 0xa8, 0x0b, 0xb3, 0x4d, 0x45, 0xab, 0xf7, 0xd3, 0xd8, 0x48, 0x34, 0xab, 0x0c,
+];
+
+/// The file parameters flag marking differencing disks.
+const HAS_PARENT: u32 = 1 << 1;
+
+/// The block state of blocks that are not stored in this file.
+const PAYLOAD_BLOCK_NOT_PRESENT: u64 = 0;
+
+/// The block state of blocks that are stored in this file.
+const PAYLOAD_BLOCK_FULLY_PRESENT: u64 = 6;
+
+/// The block state of blocks that are partially stored in this file.
+const PAYLOAD_BLOCK_PARTIALLY_PRESENT: u64 = 7;
+
+/// The maximum number of images in a chain of differencing images.
+const MAX_CHAIN_LEN: usize = 256;
+
+/// Reads a little endian `u16` at the given offset in `data`.
+fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+/// Reads a little endian `u32` at the given offset in `data`.
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads a little endian `u64` at the given offset in `data`.
+fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// A Hyper-V virtual hard disk in the VHDX format, including its parent disks.
+pub(crate) struct VhdxImage {
+    /// The file containing the disk.
+    file: HostFile,
+    /// The size of the virtual disk in bytes.
+    size: u64,
+    /// The size of a block in bytes.
+    block_size: u64,
+    /// The logical sector size in bytes.
+    sector_size: u64,
+    /// The number of payload blocks per sector bitmap block.
+    chunk_ratio: u64,
+    /// The block allocation table.
+    bat: Vec<u64>,
+    /// The parent disk, if this is a differencing disk.
+    parent: Option<Box<dyn ReadAt>>,
+}
+
+impl VhdxImage {
+    /// Opens the VHDX at `path`.
+    pub(crate) fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::open_chain(path.as_ref(), 0)
+    }
+
+    /// Opens the VHDX at `path` that has `depth` children.
+    fn open_chain(path: &Path, depth: usize) -> anyhow::Result<Self> {
+        if depth >= MAX_CHAIN_LEN {
+            anyhow::bail!("the chain of differencing images is too long");
+        }
+
+        let file =
+            HostFile::open(path).with_context(|| format!("could not open {}", path.display()))?;
+
+        Self::check_log(&file, path)?;
+
+        let mut region_table = vec![0; REGION_TABLE_LEN];
+        file.read_exact_at(&mut region_table, REGION_TABLE_OFFSET)?;
+        if !region_table.starts_with(b"regi") {
+            anyhow::bail!("invalid VHDX region table");
+        }
+
+        let mut bat_region = None;
+        let mut metadata_region = None;
+        let region_count = le_u32(&region_table, 8) as usize;
+        for entry in region_table[16..].chunks_exact(32).take(region_count) {
+            let region = (le_u64(entry, 16), le_u32(entry, 24));
+            match entry[..16].try_into().unwrap() {
+                BAT_REGION => bat_region = Some(region),
+                METADATA_REGION => metadata_region = Some(region),
+                guid if le_u32(entry, 28) & 1 != 0 => {
+                    anyhow::bail!("unknown required VHDX region {}", hex::encode(guid))
+                }
+                _ => (),
+            }
+        }
+
+        let (bat_offset, bat_len) = bat_region.context("VHDX has no block allocation table")?;
+        let (metadata_offset, _) = metadata_region.context("VHDX has no metadata region")?;
+
+        let metadata = Metadata::read(&file, metadata_offset)?;
+
+        let file_parameters = metadata.item(&file, FILE_PARAMETERS, 8)?;
+        let block_size = u64::from(le_u32(&file_parameters, 0));
+        let flags = le_u32(&file_parameters, 4);
+        let size = le_u64(&metadata.item(&file, VIRTUAL_DISK_SIZE, 8)?, 0);
+        let sector_size = u64::from(le_u32(&metadata.item(&file, LOGICAL_SECTOR_SIZE, 4)?, 0));
+
+        if block_size == 0 || sector_size == 0 || (1 << 23) * sector_size < block_size {
+            anyhow::bail!("invalid VHDX block size {block_size} or sector size {sector_size}");
+        }
+
+        let mut bat = vec![0; bat_len as usize];
+        file.read_exact_at(&mut bat, bat_offset)
+            .context("could not read block allocation table")?;
+        let bat = bat.chunks_exact(8).map(|entry| le_u64(entry, 0)).collect();
+
+        let parent = if flags & HAS_PARENT != 0 {
+            let parent_path = metadata.find_parent(&file, path)?;
+            let parent_file = HostFile::open(&parent_path)
+                .with_context(|| format!("could not open {}", parent_path.display()))?;
+
+            // Keep track of the depth for VHDX parents, to avoid following cycles forever
+            let mut signature = [0; VHDX_SIGNATURE.len()];
+            parent_file.read_exact_at(&mut signature, 0)?;
+            let parent: Box<dyn ReadAt> = if signature == VHDX_SIGNATURE {
+                Box::new(Self::open_chain(&parent_path, depth + 1)?)
+            } else {
+                open_any(&parent_path)?
+            };
+
+            Some(parent)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            file,
+            size,
+            block_size,
+            sector_size,
+            chunk_ratio: (1 << 23) * sector_size / block_size,
+            bat,
+            parent,
+        })
+    }
+
+    /// Checks that the log of the current header is empty.
+    ///
+    /// A non-empty log means that the disk was not closed cleanly and the data may be
+    /// inconsistent, since the log is not replayed here.
+    fn check_log(file: &HostFile, path: &Path) -> anyhow::Result<()> {
+        let mut current = None;
+        for offset in HEADER_OFFSETS {
+            let mut header = [0; 0x50];
+            file.read_exact_at(&mut header, offset)?;
+            if !header.starts_with(b"head") {
+                continue;
+            }
+
+            let sequence = le_u64(&header, 8);
+            if current.is_none_or(|(current_sequence, _)| sequence > current_sequence) {
+                current = Some((sequence, header));
+            }
+        }
+
+        let (_, header) = current.context("VHDX has no valid header")?;
+        if header[0x30..0x40] != [0; 16] {
+            eprintln!(
+                "Warning: the log of {} is not empty, the data may be inconsistent",
+                path.display()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The metadata region of a VHDX file.
+struct Metadata {
+    /// The offset of the metadata region in the file.
+    offset: u64,
+    /// The metadata table entries as (id, offset, length).
+    entries: Vec<([u8; 16], u32, u32)>,
+}
+
+impl Metadata {
+    /// Reads the metadata table at the given offset.
+    fn read(file: &HostFile, offset: u64) -> anyhow::Result<Self> {
+        let mut table = vec![0; METADATA_TABLE_LEN];
+        file.read_exact_at(&mut table, offset)?;
+        if !table.starts_with(b"metadata") {
+            anyhow::bail!("invalid VHDX metadata table");
+        }
+
+        let count = le_u16(&table, 10) as usize;
+        let entries = table[32..]
+            .chunks_exact(32)
+            .take(count)
+            .map(|entry| {
+                (
+                    entry[..16].try_into().unwrap(),
+                    le_u32(entry, 16),
+                    le_u32(entry, 20),
+                )
+            })
+            .collect();
+
+        Ok(Self { offset, entries })
+    }
+
+    /// Reads the metadata item with the given id, which must be at least `min_len` bytes long.
+    fn item(&self, file: &HostFile, id: [u8; 16], min_len: usize) -> anyhow::Result<Vec<u8>> {
+        let &(_, offset, len) = self
+            .entries
+            .iter()
+            .find(|(entry_id, _, _)| *entry_id == id)
+            .with_context(|| format!("VHDX metadata item {} is missing", hex::encode(id)))?;
+
+        if (len as usize) < min_len {
+            anyhow::bail!("VHDX metadata item {} is too short", hex::encode(id));
+        }
+
+        let mut data = vec![0; len as usize];
+        file.read_exact_at(&mut data, self.offset + u64::from(offset))?;
+
+        Ok(data)
+    }
+
+    /// Finds the parent of a differencing disk using its parent locator.
+    fn find_parent(&self, file: &HostFile, path: &Path) -> anyhow::Result<PathBuf> {
+        let locator = self.item(file, PARENT_LOCATOR, 20)?;
+        let count = le_u16(&locator, 18) as usize;
+
+        let utf16_at = |offset: u32, len: u16| {
+            let data = locator.get(offset as usize..offset as usize + len as usize)?;
+            Some(String::from_utf16_lossy(
+                &data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect::<Vec<_>>(),
+            ))
+        };
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut candidates = Vec::new();
+        for entry in locator[20..].chunks_exact(12).take(count) {
+            let (Some(key), Some(value)) = (
+                utf16_at(le_u32(entry, 0), le_u16(entry, 8)),
+                utf16_at(le_u32(entry, 4), le_u16(entry, 10)),
+            ) else {
+                continue;
+            };
+
+            let mut components = value
+                .split(['\\', '/'])
+                .filter(|c| !c.is_empty() && *c != ".");
+            match key.as_str() {
+                "relative_path" => candidates.insert(0, dir.join(components.collect::<PathBuf>())),
+                // Absolute paths cannot be resolved on the host, so only the file name is used
+                "absolute_win32_path" | "volume_path" => {
+                    if let Some(name) = components.next_back() {
+                        candidates.push(dir.join(name));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        candidates
+            .into_iter()
+            .find(|candidate| candidate.is_file())
+            .with_context(|| format!("could not find the parent disk of {}", path.display()))
+    }
+}
+
+impl ReadAt for VhdxImage {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        read_mapped(
+            buf,
+            offset,
+            self.size,
+            &self.file,
+            self.parent.as_deref(),
+            |offset| {
+                let block = offset / self.block_size;
+                let block_offset = offset % self.block_size;
+
+                // Every `chunk_ratio` payload blocks are followed by a sector bitmap block
+                let entry_index = block + block / self.chunk_ratio;
+                let entry = self.bat.get(entry_index as usize).copied().unwrap_or(0);
+                let block_len = self.block_size - block_offset;
+                let data_offset = ((entry >> 20) << 20) + block_offset;
+
+                Ok(match entry & 0b111 {
+                    PAYLOAD_BLOCK_NOT_PRESENT if self.parent.is_some() => {
+                        Mapping::Unallocated { len: block_len }
+                    }
+                    PAYLOAD_BLOCK_FULLY_PRESENT => Mapping::Data {
+                        offset: data_offset,
+                        len: block_len,
+                    },
+                    PAYLOAD_BLOCK_PARTIALLY_PRESENT => {
+                        let chunk = block / self.chunk_ratio;
+                        let bitmap_index = (chunk + 1) * (self.chunk_ratio + 1) - 1;
+                        let bitmap_entry =
+                            self.bat.get(bitmap_index as usize).copied().unwrap_or(0);
+
+                        let sector = (block % self.chunk_ratio) * self.block_size
+                            / self.sector_size
+                            + block_offset / self.sector_size;
+                        let mut bitmap_byte = [0];
+                        self.file.read_exact_at(
+                            &mut bitmap_byte,
+                            ((bitmap_entry >> 20) << 20) + sector / 8,
+                        )?;
+
+                        let len = self.sector_size - block_offset % self.sector_size;
+                        if bitmap_byte[0] & (1 << (sector % 8)) != 0 {
+                            Mapping::Data {
+                                offset: data_offset,
+                                len,
+                            }
+                        } else {
+                            Mapping::Unallocated { len }
+                        }
+                    }
+                    // Undefined, zero and unmapped blocks all read as zeros
+                    _ => Mapping::Zero { len: block_len },
+                })
+            },
+        )
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
@@ -0,0 +1,459 @@
+//! Reading of VMware virtual disks (VMDK).
+//!
+//! The layout is described in the
+//! [Virtual Disk Format 5.0 specification](https://www.vmware.com/app/vmdk/?src=vmdk).
+
+use std::{
+    io::{self, Read as _},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+
+use super::{read_mapped, Mapping};
+use crate::snapshot::read_at::{HostFile, ReadAt};
+
+/// The magic number at the start of a hosted sparse extent.
+const SPARSE_MAGIC: &[u8] = b"KDMV";
+
+/// The text at the start of a descriptor file.
+const DESCRIPTOR_START: &[u8] = b"# Disk DescriptorFile";
+
+/// The size of a sector in bytes.
+const SECTOR_SIZE: u64 = 512;
+
+/// The grain directory offset that signals that the real header is in the footer.
+const GD_AT_END: u64 = u64::MAX;
+
+/// The flag in the sparse header that marks the use of zeroed grain table entries.
+const FLAG_ZEROED_GTE: u32 = 1 << 2;
+
+/// The flag in the sparse header that marks compressed grains.
+const FLAG_COMPRESSED: u32 = 1 << 16;
+
+/// The grain table entry of a grain that only contains zeros.
+const GTE_ZERO: u32 = 1;
+
+/// The largest descriptor that will be read.
+const MAX_DESCRIPTOR_LEN: u64 = 0x10_0000;
+
+/// The maximum number of images in a chain of differencing images.
+const MAX_CHAIN_LEN: usize = 256;
+
+/// Returns `true` if the given start of a file belongs to a VMDK file.
+pub(super) fn is_vmdk(start: &[u8]) -> bool {
+    start.starts_with(SPARSE_MAGIC) || start.starts_with(DESCRIPTOR_START)
+}
+
+/// The header of a hosted sparse extent.
+#[derive(Debug)]
+struct SparseHeader {
+    /// The flags of the extent.
+    flags: u32,
+    /// The capacity of the extent in sectors.
+    capacity: u64,
+    /// The size of a grain in sectors.
+    grain_size: u64,
+    /// The offset of the embedded descriptor in sectors.
+    descriptor_offset: u64,
+    /// The size of the embedded descriptor in sectors.
+    descriptor_size: u64,
+    /// The number of entries in a grain table.
+    gtes_per_gt: u32,
+    /// The offset of the grain directory in sectors.
+    gd_offset: u64,
+    /// The compression algorithm of the grains.
+    compress_algorithm: u16,
+}
+
+impl SparseHeader {
+    /// Parses the header from the given bytes.
+    fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        if !data.starts_with(SPARSE_MAGIC) || data.len() < 0x4f {
+            anyhow::bail!("not a VMDK sparse extent");
+        }
+
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+        Ok(Self {
+            flags: u32_at(0x08),
+            capacity: u64_at(0x0c),
+            grain_size: u64_at(0x14),
+            descriptor_offset: u64_at(0x1c),
+            descriptor_size: u64_at(0x24),
+            gtes_per_gt: u32_at(0x2c),
+            gd_offset: u64_at(0x38),
+            compress_algorithm: u16::from_le_bytes(data[0x4d..0x4f].try_into().unwrap()),
+        })
+    }
+
+    /// Reads the header of the given sparse extent file.
+    ///
+    /// Stream optimized extents store the real header in a footer at the end of the file.
+    fn read(file: &HostFile) -> anyhow::Result<Self> {
+        let mut data = [0; SECTOR_SIZE as usize];
+        file.read_exact_at(&mut data, 0)?;
+        let header = Self::parse(&data)?;
+
+        if header.gd_offset == GD_AT_END {
+            // The footer is followed by an end-of-stream marker of one sector
+            let footer_offset = file
+                .size()
+                .checked_sub(2 * SECTOR_SIZE)
+                .context("stream optimized extent is too small for a footer")?;
+            file.read_exact_at(&mut data, footer_offset)?;
+
+            return Self::parse(&data).context("invalid footer of stream optimized extent");
+        }
+
+        Ok(header)
+    }
+}
+
+/// An extent of sparse data, storing data in grains.
+struct SparseExtent {
+    /// The file containing the extent.
+    file: HostFile,
+    /// The size of a grain in bytes.
+    grain_size: u64,
+    /// The grain table entries of all grains, in sectors.
+    grains: Vec<u32>,
+    /// Whether the grain table entries may mark grains as zero.
+    zeroed_gte: bool,
+    /// Whether the grains are compressed.
+    compressed: bool,
+    /// The most recently decompressed grain and its index.
+    grain_cache: Mutex<Option<(usize, Arc<Vec<u8>>)>>,
+}
+
+impl SparseExtent {
+    /// Opens the sparse extent in the given file.
+    fn open(file: HostFile) -> anyhow::Result<Self> {
+        let header = SparseHeader::read(&file)?;
+
+        if header.grain_size == 0 || header.gtes_per_gt == 0 {
+            anyhow::bail!("sparse extent has a grain size of zero");
+        }
+
+        let grain_count = header.capacity.div_ceil(header.grain_size);
+        let gt_count = grain_count.div_ceil(header.gtes_per_gt.into());
+
+        let mut directory = vec![0; gt_count as usize * 4];
+        file.read_exact_at(&mut directory, header.gd_offset * SECTOR_SIZE)
+            .context("could not read grain directory")?;
+
+        let mut grains = Vec::with_capacity(grain_count as usize);
+        let mut table = vec![0; header.gtes_per_gt as usize * 4];
+        for gde in directory
+            .chunks_exact(4)
+            .map(|gde| u32::from_le_bytes(gde.try_into().unwrap()))
+        {
+            if gde == 0 {
+                grains.extend(std::iter::repeat_n(0, header.gtes_per_gt as usize));
+                continue;
+            }
+
+            file.read_exact_at(&mut table, u64::from(gde) * SECTOR_SIZE)
+                .context("could not read grain table")?;
+            grains.extend(
+                table
+                    .chunks_exact(4)
+                    .map(|gte| u32::from_le_bytes(gte.try_into().unwrap())),
+            );
+        }
+        grains.truncate(grain_count as usize);
+
+        let compressed = header.flags & FLAG_COMPRESSED != 0;
+        if compressed && header.compress_algorithm != 1 {
+            anyhow::bail!(
+                "unsupported grain compression algorithm {}",
+                header.compress_algorithm
+            );
+        }
+
+        Ok(Self {
+            file,
+            grain_size: header.grain_size * SECTOR_SIZE,
+            grains,
+            zeroed_gte: header.flags & FLAG_ZEROED_GTE != 0,
+            compressed,
+            grain_cache: Mutex::new(None),
+        })
+    }
+
+    /// Returns the decompressed content of the grain stored at the given sector.
+    fn decompress_grain(&self, grain: usize, sector: u32) -> io::Result<Arc<Vec<u8>>> {
+        let mut cache = self.grain_cache.lock().unwrap();
+        if let Some((cached, data)) = &*cache
+            && *cached == grain
+        {
+            return Ok(Arc::clone(data));
+        }
+
+        // Compressed grains start with a marker of the grain's LBA and the compressed size
+        let mut marker = [0; 12];
+        self.file
+            .read_exact_at(&mut marker, u64::from(sector) * SECTOR_SIZE)?;
+        let compressed_len = u32::from_le_bytes(marker[8..12].try_into().unwrap());
+
+        let mut compressed = vec![0; compressed_len as usize];
+        self.file
+            .read_exact_at(&mut compressed, u64::from(sector) * SECTOR_SIZE + 12)?;
+
+        let mut data = Vec::with_capacity(self.grain_size as usize);
+        flate2::read::ZlibDecoder::new(&compressed[..])
+            .take(self.grain_size)
+            .read_to_end(&mut data)?;
+        data.resize(self.grain_size as usize, 0);
+
+        let data = Arc::new(data);
+        *cache = Some((grain, Arc::clone(&data)));
+
+        Ok(data)
+    }
+
+    /// Returns where the data at the given offset in the extent is stored.
+    fn map(&self, offset: u64) -> io::Result<Mapping> {
+        let grain = (offset / self.grain_size) as usize;
+        let grain_offset = offset % self.grain_size;
+        let len = self.grain_size - grain_offset;
+
+        Ok(match self.grains.get(grain).copied().unwrap_or(0) {
+            0 => Mapping::Unallocated { len },
+            GTE_ZERO if self.zeroed_gte => Mapping::Zero { len },
+            sector if self.compressed => Mapping::Decompressed {
+                data: self.decompress_grain(grain, sector)?,
+                offset: grain_offset as usize,
+                len,
+            },
+            sector => Mapping::Data {
+                offset: u64::from(sector) * SECTOR_SIZE + grain_offset,
+                len,
+            },
+        })
+    }
+}
+
+/// The different kinds of extents a VMDK disk can consist of.
+enum ExtentKind {
+    /// The data is stored unchanged at an offset in a file.
+    Flat {
+        /// The file containing the data.
+        file: HostFile,
+        /// The offset of the data in the file.
+        offset: u64,
+    },
+    /// The data is stored in grains in a sparse file.
+    Sparse(SparseExtent),
+    /// The data is all zeros.
+    Zero,
+}
+
+/// A single extent of a VMDK disk.
+struct Extent {
+    /// The offset of the extent on the virtual disk.
+    start: u64,
+    /// The size of the extent in bytes.
+    size: u64,
+    /// The kind of the extent.
+    kind: ExtentKind,
+}
+
+/// A VMware virtual disk, including its parent disks.
+pub(crate) struct VmdkImage {
+    /// The extents of the disk, ordered by their offset.
+    extents: Vec<Extent>,
+    /// The size of the virtual disk in bytes.
+    size: u64,
+    /// The parent disk, if this is a differencing disk.
+    parent: Option<Box<VmdkImage>>,
+}
+
+impl VmdkImage {
+    /// Opens the VMDK disk described by the descriptor file or monolithic sparse file at `path`.
+    pub(crate) fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::open_chain(path.as_ref(), 0)
+    }
+
+    /// Opens the VMDK disk at `path` that has `depth` children.
+    fn open_chain(path: &Path, depth: usize) -> anyhow::Result<Self> {
+        if depth >= MAX_CHAIN_LEN {
+            anyhow::bail!("the chain of differencing images is too long");
+        }
+
+        let file =
+            HostFile::open(path).with_context(|| format!("could not open {}", path.display()))?;
+
+        let mut start = [0; SPARSE_MAGIC.len()];
+        file.read_exact_at(&mut start, 0)?;
+
+        let descriptor = if start == SPARSE_MAGIC {
+            let header = SparseHeader::read(&file)?;
+            let len = header.descriptor_size * SECTOR_SIZE;
+            if header.descriptor_offset == 0 || len > MAX_DESCRIPTOR_LEN {
+                anyhow::bail!(
+                    "sparse extent {} has no embedded descriptor",
+                    path.display()
+                );
+            }
+
+            let mut descriptor = vec![0; len as usize];
+            file.read_exact_at(&mut descriptor, header.descriptor_offset * SECTOR_SIZE)?;
+            descriptor
+        } else {
+            let len = file.size().min(MAX_DESCRIPTOR_LEN);
+            let mut descriptor = vec![0; len as usize];
+            file.read_exact_at(&mut descriptor, 0)?;
+            descriptor
+        };
+
+        let descriptor = String::from_utf8_lossy(&descriptor);
+        let descriptor = descriptor.trim_end_matches('\0');
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        let mut extents = Vec::new();
+        let mut size = 0;
+        let mut parent_path = None;
+
+        for line in descriptor.lines().map(str::trim) {
+            if let Some(hint) = line.strip_prefix("parentFileNameHint=") {
+                parent_path = Some(dir.join(hint.trim_matches('"')));
+                continue;
+            }
+
+            let Some(extent) = parse_extent_line(line) else {
+                continue;
+            };
+
+            let extent_path = extent.file_name.map(|name| dir.join(name));
+            let open_extent_file = || -> anyhow::Result<HostFile> {
+                let extent_path = extent_path.as_ref().context("extent has no file name")?;
+                HostFile::open(extent_path)
+                    .with_context(|| format!("could not open extent {}", extent_path.display()))
+            };
+
+            let kind = match extent.kind {
+                "FLAT" | "VMFS" => ExtentKind::Flat {
+                    file: open_extent_file()?,
+                    offset: extent.offset * SECTOR_SIZE,
+                },
+                "SPARSE" | "VMFSSPARSE" => {
+                    ExtentKind::Sparse(SparseExtent::open(open_extent_file()?).with_context(
+                        || format!("could not open sparse extent of {}", path.display()),
+                    )?)
+                }
+                "ZERO" => ExtentKind::Zero,
+                other => anyhow::bail!("unsupported extent type {other}"),
+            };
+
+            extents.push(Extent {
+                start: size,
+                size: extent.sectors * SECTOR_SIZE,
+                kind,
+            });
+            size += extent.sectors * SECTOR_SIZE;
+        }
+
+        if extents.is_empty() {
+            anyhow::bail!("{} does not describe any extents", path.display());
+        }
+
+        let parent = parent_path
+            .map(|parent_path| {
+                Self::open_chain(&parent_path, depth + 1).with_context(|| {
+                    format!("could not open parent disk {}", parent_path.display())
+                })
+            })
+            .transpose()?
+            .map(Box::new);
+
+        Ok(Self {
+            extents,
+            size,
+            parent,
+        })
+    }
+}
+
+impl ReadAt for VmdkImage {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let Some(extent) = self
+            .extents
+            .iter()
+            .find(|extent| extent.start <= offset && offset < extent.start + extent.size)
+        else {
+            return Ok(0);
+        };
+
+        let extent_offset = offset - extent.start;
+        let extent_remaining = extent.size - extent_offset;
+        let len = (buf.len() as u64).min(extent_remaining) as usize;
+        let buf = &mut buf[..len];
+
+        match &extent.kind {
+            ExtentKind::Flat {
+                file,
+                offset: file_offset,
+            } => file.read_at(buf, file_offset + extent_offset),
+            ExtentKind::Zero => {
+                buf.fill(0);
+                Ok(len)
+            }
+            ExtentKind::Sparse(sparse) => read_mapped(
+                buf,
+                offset,
+                self.size,
+                &sparse.file,
+                self.parent.as_deref().map(|parent| parent as &dyn ReadAt),
+                |_| sparse.map(extent_offset),
+            ),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// An extent as described in a line of a descriptor file.
+struct ExtentLine<'line> {
+    /// The size of the extent in sectors.
+    sectors: u64,
+    /// The type of the extent.
+    kind: &'line str,
+    /// The name of the file containing the extent.
+    file_name: Option<PathBuf>,
+    /// The offset of the extent in its file in sectors.
+    offset: u64,
+}
+
+/// Parses an extent description such as `RW 4192256 SPARSE "disk-s001.vmdk"`.
+fn parse_extent_line(line: &str) -> Option<ExtentLine<'_>> {
+    let (access, rest) = line.split_once(char::is_whitespace)?;
+    if !matches!(access, "RW" | "RDONLY" | "NOACCESS") {
+        return None;
+    }
+
+    let mut parts = rest.trim_start().splitn(3, char::is_whitespace);
+    let sectors = parts.next()?.parse().ok()?;
+    let kind = parts.next()?;
+
+    let (file_name, offset) = match parts.next().map(str::trim) {
+        Some(rest) if rest.starts_with('"') => {
+            let (name, rest) = rest[1..].split_once('"')?;
+            (Some(PathBuf::from(name)), rest.trim().parse().unwrap_or(0))
+        }
+        _ => (None, 0),
+    };
+
+    Some(ExtentLine {
+        sectors,
+        kind,
+        file_name,
+        offset,
+    })
+}
@@ -0,0 +1,942 @@
+//! Reads NTFS volumes natively, without mounting them.
+//!
+//! The metadata is recorded to match what `ntfs-3g` reports for a volume mounted with the
+//! `no_def_opts,ro,show_sys_files` options, so that snapshots created from mounted volumes and
+//! snapshots created by reading the volume directly can be compared.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+
+use crate::{
+    fs::{
+        dir_entry_type::DirEntryType,
+        file::GenericFile as _,
+        metadata::{
+            reparse_point::ReparsePoint, AlternateDataStream, AlternateDataStreams, NtfsAttributes,
+        },
+        DEntry, DirEntry, File, MetaDirEntry, Metadata, Symlink,
+    },
+    timestamp::Timestamp,
+};
+
+use self::{
+    record::{
+        Attribute, AttributeListEntry, AttributeType, FileName, FileNameNamespace, FileRecord,
+        StandardInformation,
+    },
+    stream::{Stream, StreamData},
+};
+
+use super::{
+    errors::{AcquisitionError, Operation},
+    exclude::ExcludedMode,
+    read_at::ReadAt,
+    reuse::{Reuse, ReuseKey},
+    CreateOptions,
+};
+
+mod lznt1;
+mod record;
+mod stream;
+
+/// The mask to extract the record number from a file reference.
+///
+/// The upper 16 bits of a file reference contain the sequence number.
+const RECORD_NUMBER_MASK: u64 = 0x0000_ffff_ffff_ffff;
+
+/// The OEM ID in the boot sector of an NTFS volume.
+const NTFS_OEM_ID: &[u8] = b"NTFS    ";
+
+/// The record number of the `$MFT` file.
+const MFT_RECORD: u64 = 0;
+
+/// The record number of the root directory.
+const ROOT_RECORD: u64 = 5;
+
+/// The record number of the `$Secure` file.
+const SECURE_RECORD: u64 = 9;
+
+/// The number of records that are read at once when scanning the MFT.
+const RECORD_BATCH_SIZE: u64 = 1024;
+
+/// The largest file that is read into memory by `NtfsVolume::read_file`.
+const MAX_READ_FILE_LEN: u64 = 0x1000_0000;
+
+/// The largest `$Secure:$SDS` stream that is read into memory.
+///
+/// The stream grows by 256 KiB blocks with every set of new descriptors, it rarely exceeds a few
+/// dozen MiB even on large servers.
+const MAX_SECURITY_DESCRIPTOR_STREAM_LEN: u64 = 0x4000_0000;
+
+/// The reparse tag of a symbolic link.
+const IO_REPARSE_TAG_SYMLINK: u32 = 0xa000_000c;
+
+/// The reparse tag of a junction.
+const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xa000_0003;
+
+/// The reparse tag of a symbolic link created by WSL.
+const IO_REPARSE_TAG_LX_SYMLINK: u32 = 0xa000_001d;
+
+/// The reparse tag of a UNIX socket created by WSL.
+const IO_REPARSE_TAG_AF_UNIX: u32 = 0x8000_0023;
+
+/// The reparse tag of a FIFO created by WSL.
+const IO_REPARSE_TAG_LX_FIFO: u32 = 0x8000_0024;
+
+/// The reparse tag of a character device created by WSL.
+const IO_REPARSE_TAG_LX_CHR: u32 = 0x8000_0025;
+
+/// The reparse tag of a block device created by WSL.
+const IO_REPARSE_TAG_LX_BLK: u32 = 0x8000_0026;
+
+/// Returns `true` if the given device starts with an NTFS boot sector.
+pub(crate) fn is_ntfs_volume(device: &impl ReadAt) -> bool {
+    let mut oem_id = [0; NTFS_OEM_ID.len()];
+
+    device.read_exact_at(&mut oem_id, 3).is_ok() && oem_id == NTFS_OEM_ID
+}
+
+/// The information about a record that is needed to find its paths.
+#[derive(Debug)]
+struct RecordInfo {
+    /// The sequence number of the record.
+    sequence: u16,
+    /// Whether the record refers to a directory.
+    is_dir: bool,
+    /// The extension records that store further attributes of this record.
+    extensions: Vec<u64>,
+    /// The names of the record, excluding DOS names.
+    names: Vec<FileName>,
+}
+
+/// An NTFS volume that is read directly from a device.
+pub(crate) struct NtfsVolume<R> {
+    /// The device the volume is stored on.
+    device: R,
+    /// The size of a cluster in bytes.
+    cluster_size: u64,
+    /// The size of a file record in bytes.
+    record_size: u64,
+    /// The data of the `$MFT` file.
+    mft: StreamData,
+    /// The security descriptors stored in `$Secure:$SDS`, indexed by their ID.
+    security_descriptors: HashMap<u32, Vec<u8>>,
+    /// The records that are in use, indexed by their number.
+    records: HashMap<u64, RecordInfo>,
+    /// The paths of all entries on the volume, mapped to the record they refer to.
+    paths: BTreeMap<PathBuf, u64>,
+}
+
+impl<R: ReadAt> NtfsVolume<R> {
+    /// Opens the NTFS volume stored on the given device and indexes its records.
+    pub(crate) fn open(device: R) -> anyhow::Result<Self> {
+        let mut boot_sector = [0; 0x200];
+        device
+            .read_exact_at(&mut boot_sector, 0)
+            .context("could not read the boot sector")?;
+
+        if &boot_sector[3..11] != NTFS_OEM_ID {
+            anyhow::bail!("the device does not contain an NTFS volume");
+        }
+
+        let bytes_per_sector = u64::from(record::le_u16(&boot_sector, 0x0b)?);
+        let sectors_per_cluster = match boot_sector[0x0d] {
+            // values above 0x80 are used for clusters larger than 64 KiB
+            n @ 0x81.. => 1u64
+                .checked_shl(256 - u32::from(n))
+                .context("the boot sector contains an invalid cluster size")?,
+            n => u64::from(n),
+        };
+        let cluster_size = bytes_per_sector
+            .checked_mul(sectors_per_cluster)
+            .context("the boot sector contains an invalid cluster size")?;
+        let mft_lcn = record::le_u64(&boot_sector, 0x30)?;
+        let record_size = match boot_sector[0x40] as i8 {
+            n @ 1.. => cluster_size
+                .checked_mul(n as u64)
+                .context("the boot sector contains an invalid record size")?,
+            n => 1u64
+                .checked_shl(n.unsigned_abs().into())
+                .context("the boot sector contains an invalid record size")?,
+        };
+
+        if cluster_size == 0 || !(0x200..=0x10000).contains(&record_size) {
+            anyhow::bail!("the boot sector contains invalid sizes");
+        }
+
+        let mut volume = Self {
+            device,
+            cluster_size,
+            record_size,
+            mft: StreamData::Resident(Vec::new()),
+            security_descriptors: HashMap::new(),
+            records: HashMap::new(),
+            paths: BTreeMap::new(),
+        };
+
+        let mut mft_record = vec![0; record_size as usize];
+        volume
+            .device
+            .read_exact_at(
+                &mut mft_record,
+                mft_lcn
+                    .checked_mul(cluster_size)
+                    .context("the boot sector contains an invalid `$MFT` location")?,
+            )
+            .context("could not read the `$MFT` record")?;
+        let mft_record = FileRecord::parse(mft_record)
+            .context("could not parse the `$MFT` record")?
+            .context("the `$MFT` record is not in use")?;
+
+        // The first extent is always in the base record, which is enough to read the rest
+        volume.mft = StreamData::from_extents(data_extents(&mft_record, OsStr::new("")))?;
+        let mft_record = volume.read_full_record(MFT_RECORD, &mft_record)?;
+        volume.mft = StreamData::from_extents(data_extents(&mft_record, OsStr::new("")))?;
+
+        volume.index_records()?;
+
+        match volume.security_descriptor_stream() {
+            Ok(sds) => volume.security_descriptors = parse_security_descriptors(&sds),
+            Err(err) => eprintln!("could not read security descriptors: {err:#}"),
+        }
+
+        Ok(volume)
+    }
+
+    /// Returns a readable stream of the given data.
+    fn stream<'vol>(&'vol self, data: &'vol StreamData) -> Stream<'vol, R> {
+        Stream::new(&self.device, self.cluster_size, data)
+    }
+
+    /// Reads and parses the given record.
+    fn read_record(&self, number: u64) -> anyhow::Result<Option<FileRecord>> {
+        let mut data = vec![0; self.record_size as usize];
+        self.stream(&self.mft)
+            .read_exact_at(&mut data, number * self.record_size)
+            .with_context(|| format!("could not read record {number}"))?;
+
+        FileRecord::parse(data).with_context(|| format!("could not parse record {number}"))
+    }
+
+    /// Adds the attributes of all extension records to the given base record.
+    fn read_full_record(&self, number: u64, base: &FileRecord) -> anyhow::Result<FileRecord> {
+        let mut extensions = Vec::new();
+
+        for attr in base
+            .attributes
+            .iter()
+            .filter(|attr| attr.ty == AttributeType::AttributeList)
+        {
+            let list = match attr.resident_value() {
+                Some(value) => value.to_vec(),
+                None => {
+                    let data = StreamData::from_extents([attr])?;
+                    self.stream(&data).read_to_vec(MAX_READ_FILE_LEN)?
+                }
+            };
+
+            for entry in AttributeListEntry::parse_list(&list)? {
+                if entry.record != number && !extensions.contains(&entry.record) {
+                    extensions.push(entry.record);
+                }
+            }
+        }
+
+        let mut attributes = base.attributes.clone();
+        for extension in extensions {
+            if let Some(record) = self.read_record(extension)? {
+                attributes.extend(record.attributes);
+            }
+        }
+
+        Ok(FileRecord {
+            sequence: base.sequence,
+            is_dir: base.is_dir,
+            base_record: None,
+            attributes,
+        })
+    }
+
+    /// Reads the record with the given number including all of its extension records.
+    fn read_indexed_record(&self, number: u64) -> anyhow::Result<FileRecord> {
+        let info = self
+            .records
+            .get(&number)
+            .with_context(|| format!("record {number} is not in use"))?;
+
+        let mut record = self
+            .read_record(number)?
+            .with_context(|| format!("record {number} is not in use"))?;
+
+        for &extension in &info.extensions {
+            if let Some(extension) = self.read_record(extension)? {
+                record.attributes.extend(extension.attributes);
+            }
+        }
+
+        Ok(record)
+    }
+
+    /// Scans all records in the MFT and computes the paths of all entries.
+    fn index_records(&mut self) -> anyhow::Result<()> {
+        firestorm::profile_method!(index_records);
+
+        use rayon::prelude::*;
+
+        let record_count = self.mft.size() / self.record_size;
+        let batches = record_count.div_ceil(RECORD_BATCH_SIZE);
+
+        let scanned = (0..batches)
+            .into_par_iter()
+            .flat_map_iter(|batch| {
+                let first = batch * RECORD_BATCH_SIZE;
+                let count = RECORD_BATCH_SIZE.min(record_count - first);
+
+                let mut data = vec![0; (count * self.record_size) as usize];
+                if let Err(err) = self
+                    .stream(&self.mft)
+                    .read_exact_at(&mut data, first * self.record_size)
+                {
+                    eprintln!("could not read records {first} to {}: {err}", first + count);
+                    data.clear();
+                }
+
+                data.chunks_exact(self.record_size as usize)
+                    .zip(first..)
+                    .filter_map(|(data, number)| match FileRecord::parse(data.to_vec()) {
+                        Ok(record) => record.map(|record| (number, record)),
+                        Err(err) => {
+                            eprintln!("could not parse record {number}: {err:#}");
+                            None
+                        }
+                    })
+                    .map(|(number, record)| {
+                        let names = record
+                            .attributes
+                            .iter()
+                            .filter(|attr| attr.ty == AttributeType::FileName)
+                            .filter_map(|attr| FileName::parse(attr.resident_value()?).ok())
+                            .filter(|name| name.namespace != FileNameNamespace::Dos)
+                            .collect::<Vec<_>>();
+
+                        (
+                            number,
+                            record.base_record,
+                            record.sequence,
+                            record.is_dir,
+                            names,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut extension_records = Vec::new();
+        for (number, base_record, sequence, is_dir, names) in scanned {
+            if let Some(base) = base_record {
+                extension_records.push((base, number, names));
+            } else {
+                self.records.insert(
+                    number,
+                    RecordInfo {
+                        sequence,
+                        is_dir,
+                        extensions: Vec::new(),
+                        names,
+                    },
+                );
+            }
+        }
+
+        for (base, number, names) in extension_records {
+            if let Some(info) = self.records.get_mut(&base) {
+                info.extensions.push(number);
+                info.names.extend(names);
+            }
+        }
+
+        let mut dir_paths = HashMap::new();
+        dir_paths.insert(ROOT_RECORD, Some(PathBuf::from("/")));
+
+        let mut paths = BTreeMap::new();
+        for (&number, info) in &self.records {
+            if number == ROOT_RECORD {
+                continue;
+            }
+
+            for name in &info.names {
+                if let Some(parent_path) = self.dir_path(name, &mut dir_paths) {
+                    paths.insert(parent_path.join(&name.name), number);
+                }
+            }
+        }
+        paths.insert(PathBuf::from("/"), ROOT_RECORD);
+
+        self.paths = paths;
+
+        Ok(())
+    }
+
+    /// Returns the path of the parent directory of the given name, if it is reachable from root.
+    fn dir_path(
+        &self,
+        name: &FileName,
+        cache: &mut HashMap<u64, Option<PathBuf>>,
+    ) -> Option<PathBuf> {
+        let parent = self.records.get(&name.parent)?;
+        if !parent.is_dir || (name.parent_sequence != 0 && name.parent_sequence != parent.sequence)
+        {
+            return None;
+        }
+
+        if let Some(path) = cache.get(&name.parent) {
+            return path.clone();
+        }
+
+        // Mark the directory as unreachable first, so cycles in corrupted volumes terminate
+        cache.insert(name.parent, None);
+
+        let path = parent.names.first().and_then(|parent_name| {
+            self.dir_path(parent_name, cache)
+                .map(|path| path.join(&parent_name.name))
+        });
+
+        cache.insert(name.parent, path.clone());
+
+        path
+    }
+
+    /// Returns the content of the `$Secure:$SDS` stream.
+    fn security_descriptor_stream(&self) -> anyhow::Result<Vec<u8>> {
+        let record = self.read_indexed_record(SECURE_RECORD)?;
+        let data = StreamData::from_extents(data_extents(&record, OsStr::new("$SDS")))?;
+
+        Ok(self
+            .stream(&data)
+            .read_to_vec(MAX_SECURITY_DESCRIPTOR_STREAM_LEN)?)
+    }
+
+    /// Reads the content and modification time of the file at the given path.
+    pub(crate) fn read_file(&self, path: impl AsRef<Path>) -> anyhow::Result<(Vec<u8>, Timestamp)> {
+        let path = path.as_ref();
+
+        let &number = self
+            .paths
+            .get(path)
+            .with_context(|| format!("{} does not exist", path.display()))?;
+        let record = self.read_indexed_record(number)?;
+
+        let std_info = standard_information(&record)?;
+        let data = StreamData::from_extents(data_extents(&record, OsStr::new("")))?;
+
+        Ok((
+            self.stream(&data).read_to_vec(MAX_READ_FILE_LEN)?,
+            Timestamp::from_ntfs_timestamp(std_info.modified),
+        ))
+    }
+
+    /// Reads all entries on the volume into a directory tree, ignoring paths matching `skip`.
+    ///
+    /// The volume is placed at `snapshot_root` in the snapshot.
+    /// If a previous snapshot to reuse is given in the `options`, the file information of
+    /// unchanged files is taken from it.
+    /// Paths matching the exclude patterns in the `options` are handled accordingly, the excluded
+    /// paths whose parent is not excluded are added to `excluded`.
+    /// The paths of records with more than one path in the snapshot are added to `hard_links`.
+    pub(crate) fn read_tree(
+        &self,
+        skip: impl Fn(&Path) -> bool + Sync,
+        options: &CreateOptions,
+        snapshot_root: &Path,
+        excluded: &mut BTreeSet<PathBuf>,
+        errors: &mut Vec<AcquisitionError>,
+        hard_links: &mut Vec<Vec<PathBuf>>,
+    ) -> MetaDirEntry<DEntry, Metadata, ()> {
+        firestorm::profile_method!(read_tree);
+
+        let reuse = options.reuse.as_ref();
+        let exclude = &options.exclude;
+
+        // The contents of a record are read if at least one of its paths is not excluded
+        let mut paths_by_record = BTreeMap::<u64, (Vec<&Path>, bool)>::new();
+        for (path, &number) in &self.paths {
+            if skip(path) {
+                continue;
+            }
+
+            let mut read_contents = true;
+            let snapshot_path = snapshot_root.join(path.strip_prefix("/").unwrap_or(path));
+            if exclude.is_excluded(&snapshot_path) {
+                if exclude.is_exclusion_root(&snapshot_path) {
+                    excluded.insert(snapshot_path);
+                }
+
+                match exclude.mode {
+                    ExcludedMode::Skip => continue,
+                    ExcludedMode::Metadata => read_contents = false,
+                    ExcludedMode::Hash => (),
+                }
+            }
+
+            let (paths, read) = paths_by_record.entry(number).or_default();
+            paths.push(path);
+            *read |= read_contents;
+        }
+
+        hard_links.extend(
+            paths_by_record
+                .values()
+                .filter(|(paths, _)| paths.len() > 1)
+                .map(|(paths, _)| {
+                    paths
+                        .iter()
+                        .map(|path| snapshot_root.join(path.strip_prefix("/").unwrap_or(path)))
+                        .collect()
+                }),
+        );
+
+        // The root only takes the metadata of an inserted root entry while it has no entries, so
+        // its metadata is read before the other entries are inserted in parallel
+        paths_by_record.remove(&ROOT_RECORD);
+        let metadata = match self.read_indexed_record(ROOT_RECORD).and_then(|record| {
+            self.entry(
+                ROOT_RECORD,
+                &record,
+                None,
+                true,
+                options.max_inline_stream_len,
+            )
+        }) {
+            Ok((entry, _)) => entry.metadata,
+            Err(err) => {
+                eprintln!("could not read the root directory information: {err:#}");
+                errors.push(AcquisitionError::new(
+                    snapshot_root.to_path_buf(),
+                    Operation::ReadEntry,
+                    err,
+                ));
+
+                use crate::fs::metadata::GenericMetadata as _;
+                Metadata::meaningless()
+            }
+        };
+
+        use crate::fs::dir_entry::GenericDirEntry as _;
+        let mut root = MetaDirEntry {
+            metadata,
+            entry: DEntry::empty_dir(),
+            context: (),
+        };
+
+        crossbeam_utils::thread::scope(|s| {
+            let (sender, receiver) = crossbeam_channel::bounded::<
+                Result<(PathBuf, MetaDirEntry<DEntry, Metadata, ()>), AcquisitionError>,
+            >(100);
+
+            s.spawn(|_| {
+                for entry in receiver {
+                    match entry {
+                        Ok((path, entry)) => root.insert(path, entry),
+                        Err(err) => errors.push(err),
+                    }
+                }
+            });
+
+            use rayon::prelude::*;
+            paths_by_record
+                .par_iter()
+                .for_each(|(&number, (paths, read_contents))| {
+                    let reuse = reuse.map(|reuse| {
+                        let path =
+                            snapshot_root.join(paths[0].strip_prefix("/").unwrap_or(paths[0]));
+                        (reuse, path)
+                    });
+
+                    let (entry, error) = match self.read_indexed_record(number).and_then(|record| {
+                        self.entry(
+                            number,
+                            &record,
+                            reuse,
+                            *read_contents,
+                            options.max_inline_stream_len,
+                        )
+                    }) {
+                        Ok((entry, error)) => (Some(entry), error),
+                        Err(err) => (None, Some(err)),
+                    };
+
+                    if let Some(err) = error {
+                        eprintln!(
+                            "could not read directory entry information for {}: {:#}",
+                            paths[0].display(),
+                            err
+                        );
+                        let error =
+                            AcquisitionError::new(PathBuf::new(), Operation::ReadEntry, err);
+                        for path in paths {
+                            let path = snapshot_root.join(path.strip_prefix("/").unwrap_or(path));
+                            sender
+                                .send(Err(AcquisitionError {
+                                    path,
+                                    ..error.clone()
+                                }))
+                                .unwrap();
+                        }
+                    }
+
+                    let Some(entry) = entry else {
+                        return;
+                    };
+
+                    for path in paths {
+                        sender
+                            .send(Ok((path.to_path_buf(), entry.clone())))
+                            .unwrap();
+                    }
+                });
+        })
+        .unwrap();
+
+        root
+    }
+
+    /// Reads the directory entry and metadata of the given record.
+    ///
+    /// If `reuse` is given, the file information is reused if the file at the given path in the
+    /// previous snapshot is unchanged.
+    /// If `read_contents` is `false`, files are stored as entries of the file type instead.
+    /// The contents of alternate data streams are only stored up to `max_inline_stream_len` bytes.
+    ///
+    /// Files whose contents are stored outside of their data stream are stored as entries of the
+    /// file type as well, the reason is returned as an error together with the entry.
+    fn entry(
+        &self,
+        number: u64,
+        record: &FileRecord,
+        reuse: Option<(&Reuse, PathBuf)>,
+        read_contents: bool,
+        max_inline_stream_len: u64,
+    ) -> anyhow::Result<(MetaDirEntry<DEntry, Metadata, ()>, Option<anyhow::Error>)> {
+        let std_info = standard_information(record)?;
+
+        let resident = |ty: AttributeType, name: &str| {
+            record
+                .attributes
+                .iter()
+                .find(|attr| attr.ty == ty && attr.name == name)
+                .and_then(|attr| attr.resident_value())
+                .map(<[u8]>::to_vec)
+        };
+        let read_attribute = |ty: AttributeType, name: &str| -> anyhow::Result<Option<Vec<u8>>> {
+            let extents = record
+                .attributes
+                .iter()
+                .filter(|attr| attr.ty == ty && attr.name == name)
+                .collect::<Vec<_>>();
+
+            if extents.is_empty() {
+                return Ok(None);
+            }
+
+            let data = StreamData::from_extents(extents)?;
+            Ok(Some(self.stream(&data).read_to_vec(MAX_READ_FILE_LEN)?))
+        };
+
+        let reparse_data = read_attribute(AttributeType::ReparsePoint, "")?;
+        let acl = match read_attribute(AttributeType::SecurityDescriptor, "")? {
+            Some(acl) => Some(acl),
+            None => std_info
+                .security_id
+                .and_then(|id| self.security_descriptors.get(&id))
+                .cloned(),
+        };
+        let dos_name = record
+            .attributes
+            .iter()
+            .filter(|attr| attr.ty == AttributeType::FileName)
+            .filter_map(|attr| FileName::parse(attr.resident_value()?).ok())
+            .find(|name| name.namespace == FileNameNamespace::Dos)
+            .map(|name| name.name.to_string_lossy().into_owned().into_bytes());
+        let object_id = resident(AttributeType::ObjectId, "");
+        let efs_info = read_attribute(AttributeType::LoggedUtilityStream, "$EFS")?;
+        let ea = read_attribute(AttributeType::Ea, "")?;
+
+        let mut streams = BTreeMap::new();
+        let mut stream_names = record
+            .attributes
+            .iter()
+            .filter(|attr| attr.ty == AttributeType::Data && !attr.name.is_empty())
+            .map(|attr| &attr.name)
+            .collect::<Vec<_>>();
+        stream_names.sort();
+        stream_names.dedup();
+        for name in stream_names {
+            let data = StreamData::from_extents(data_extents(record, name))?;
+            let stream = AlternateDataStream::from_reader(
+                self.stream(&data).cursor(),
+                max_inline_stream_len,
+            )?;
+
+            let mut xattr_name = OsString::from("user.");
+            xattr_name.push(name);
+            streams.insert(xattr_name, Some(stream));
+        }
+
+        let link_count = record
+            .attributes
+            .iter()
+            .filter(|attr| attr.ty == AttributeType::FileName)
+            .filter_map(|attr| FileName::parse(attr.resident_value()?).ok())
+            .filter(|name| name.namespace != FileNameNamespace::Dos)
+            .count() as u64;
+
+        // Sparse runs, including the clusters saved by compression, are not allocated, while
+        // resident data takes up as much space in the record as its size.
+        // The alternate data streams are counted as well, because WOF-compressed files store their
+        // data in the `WofCompressedData` stream.
+        let allocated_size = record
+            .attributes
+            .iter()
+            .filter(|attr| {
+                attr.ty == AttributeType::Data || attr.ty == AttributeType::IndexAllocation
+            })
+            .map(|attr| match &attr.body {
+                record::AttributeBody::NonResident { runs, .. } => {
+                    runs.iter()
+                        .filter(|run| run.lcn.is_some())
+                        .map(|run| run.length)
+                        .sum::<u64>()
+                        * self.cluster_size
+                }
+                record::AttributeBody::Resident(value) => value.len() as u64,
+            })
+            .sum();
+
+        let reparse_entry = reparse_data.as_deref().and_then(reparse_entry);
+        let mut contents_error = None;
+
+        let (entry, size, unix_permissions, nlink) = if let Some(entry) = reparse_entry {
+            let size = match &entry {
+                DirEntry::Symlink(symlink) => symlink.link_path.as_os_str().len() as u64,
+                _ => 0,
+            };
+            let permissions = match &entry {
+                DirEntry::Symlink(_) => 0o120777,
+                DirEntry::Other(DirEntryType::Pipe) => 0o010777,
+                DirEntry::Other(DirEntryType::CharacterDevice) => 0o020777,
+                DirEntry::Other(DirEntryType::BlockDevice) => 0o060777,
+                _ => 0o140777,
+            };
+
+            (entry, size, permissions, link_count)
+        } else if record.is_dir {
+            use crate::fs::dir_entry::GenericDirEntry as _;
+
+            let size = record
+                .attributes
+                .iter()
+                .find_map(|attr| match &attr.body {
+                    record::AttributeBody::NonResident {
+                        start_vcn: 0,
+                        data_size,
+                        ..
+                    } if attr.ty == AttributeType::IndexAllocation && attr.name == "$I30" => {
+                        Some(*data_size)
+                    }
+                    _ => None,
+                })
+                .unwrap_or(0);
+
+            (DEntry::empty_dir(), size, 0o040777, 1)
+        } else {
+            let data = StreamData::from_extents(data_extents(record, OsStr::new("")))?;
+            let stream = self.stream(&data);
+            let read = || File::from_reader(stream.cursor());
+
+            let entry = if !read_contents {
+                DirEntry::Other(DirEntryType::File)
+            } else if let Some(point) = reparse_data.as_deref().and_then(external_contents) {
+                // The data stream is only a sparse placeholder, its zeros must not be hashed
+                contents_error = Some(anyhow::anyhow!(
+                    "the contents are stored outside of the data stream ({point})"
+                ));
+                DirEntry::Other(DirEntryType::File)
+            } else if let Some((reuse, path)) = reuse {
+                let key = ReuseKey {
+                    size: stream.size(),
+                    modified: Some(Timestamp::from_ntfs_timestamp(std_info.modified)),
+                    mft_modified: Some(Timestamp::from_ntfs_timestamp(std_info.mft_modified)),
+                    inode: Some(number),
+                };
+
+                DirEntry::File(reuse.file_or_read(&path, key, read)?)
+            } else {
+                DirEntry::File(read()?)
+            };
+
+            (entry, stream.size(), 0o100777, link_count)
+        };
+
+        let mut ntfs_attributes = std_info.file_attributes;
+        if record.is_dir {
+            ntfs_attributes |= NtfsAttributes::DIRECTORY.bits();
+        } else {
+            ntfs_attributes &= !NtfsAttributes::DIRECTORY.bits();
+        }
+        if ntfs_attributes == 0 {
+            ntfs_attributes = NtfsAttributes::NORMAL.bits();
+        }
+
+        let metadata = Metadata {
+            size,
+            allocated_size: Some(allocated_size),
+            created: Some(Timestamp::from_ntfs_timestamp(std_info.created)),
+            modified: Some(Timestamp::from_ntfs_timestamp(std_info.modified)),
+            accessed: Some(Timestamp::from_ntfs_timestamp(std_info.accessed)),
+            mft_modified: Some(Timestamp::from_ntfs_timestamp(std_info.mft_modified)),
+            ntfs_attributes: Some(unsafe { NtfsAttributes::from_bits_unchecked(ntfs_attributes) }),
+            unix_permissions: Some(unix_permissions),
+            nlink: Some(nlink),
+            // `ntfs-3g` reports the mounting user, which is root
+            uid: Some(0),
+            gid: Some(0),
+            reparse_data,
+            acl,
+            dos_name,
+            object_id,
+            efs_info,
+            ea,
+            streams: Some(AlternateDataStreams { streams }),
+            inode: Some(number),
+        };
+
+        Ok((
+            MetaDirEntry {
+                entry,
+                metadata,
+                context: (),
+            },
+            contents_error,
+        ))
+    }
+}
+
+/// Returns the decoded reparse point if the contents of a file with the given reparse data are
+/// not stored in its unnamed data stream.
+///
+/// These files are compressed by the Windows Overlay Filter or deduplicated, their data stream is
+/// sparse and reads as zeros.
+fn external_contents(reparse_data: &[u8]) -> Option<ReparsePoint> {
+    ReparsePoint::parse(reparse_data)
+        .filter(|point| matches!(point, ReparsePoint::Wof { .. } | ReparsePoint::Dedup))
+}
+
+/// Returns all extents of the `$DATA` attribute with the given name.
+fn data_extents<'rec>(
+    record: &'rec FileRecord,
+    name: &'rec OsStr,
+) -> impl Iterator<Item = &'rec Attribute> + Clone {
+    record
+        .attributes
+        .iter()
+        .filter(move |attr| attr.ty == AttributeType::Data && attr.name == name)
+}
+
+/// Parses the `$STANDARD_INFORMATION` attribute of the given record.
+fn standard_information(record: &FileRecord) -> anyhow::Result<StandardInformation> {
+    record
+        .attributes
+        .iter()
+        .find(|attr| attr.ty == AttributeType::StandardInformation)
+        .and_then(|attr| attr.resident_value())
+        .context("record has no `$STANDARD_INFORMATION` attribute")
+        .and_then(StandardInformation::parse)
+}
+
+/// Parses the entries of the `$Secure:$SDS` stream.
+///
+/// The stream consists of 256 KiB blocks that are each followed by a mirror copy.
+fn parse_security_descriptors(sds: &[u8]) -> HashMap<u32, Vec<u8>> {
+    /// The size of the blocks in the `$SDS` stream.
+    const SDS_BLOCK_SIZE: usize = 0x40000;
+
+    /// The size of the header before each security descriptor.
+    const SDS_ENTRY_HEADER_LEN: usize = 0x14;
+
+    let mut descriptors = HashMap::new();
+    let mut offset = 0;
+
+    while offset + SDS_ENTRY_HEADER_LEN <= sds.len() {
+        let id = record::le_u32(sds, offset + 4).unwrap();
+        let entry_offset = record::le_u64(sds, offset + 8).unwrap();
+        let len = record::le_u32(sds, offset + 0x10).unwrap() as usize;
+
+        // Entries in mirror blocks or the unused rest of a block don't point to themselves
+        if entry_offset != offset as u64 || len < SDS_ENTRY_HEADER_LEN || offset + len > sds.len() {
+            offset = (offset / SDS_BLOCK_SIZE + 1) * SDS_BLOCK_SIZE;
+            continue;
+        }
+
+        descriptors
+            .entry(id)
+            .or_insert_with(|| sds[offset + SDS_ENTRY_HEADER_LEN..offset + len].to_vec());
+
+        offset += (len + 0xf) & !0xf;
+    }
+
+    descriptors
+}
+
+/// Returns the directory entry for special reparse points, such as symlinks and junctions.
+///
+/// Other reparse points are treated like regular files or directories.
+fn reparse_entry(data: &[u8]) -> Option<DEntry> {
+    let tag = record::le_u32(data, 0).ok()?;
+
+    let windows_link = |buffer_start: usize, relative: bool| -> Option<DEntry> {
+        let substitute_offset = usize::from(record::le_u16(data, 8).ok()?);
+        let substitute_len = usize::from(record::le_u16(data, 10).ok()?);
+        let target =
+            record::utf16_name(data, buffer_start + substitute_offset, substitute_len / 2).ok()?;
+        let target = target.to_string_lossy().replace('\\', "/");
+
+        let link_path = if relative {
+            PathBuf::from(target)
+        } else {
+            // Absolute targets look like `\??\C:\path`, which refers to `/path` in the snapshot
+            let target = target.strip_prefix("/??/").unwrap_or(&target);
+            let target = match target.as_bytes() {
+                [drive, b':', ..] if drive.is_ascii_alphabetic() => &target[2..],
+                _ => target,
+            };
+
+            Path::new("/").join(target.trim_start_matches('/'))
+        };
+
+        Some(DirEntry::Symlink(Symlink { link_path }))
+    };
+
+    match tag {
+        IO_REPARSE_TAG_SYMLINK => {
+            let flags = record::le_u32(data, 0x10).ok()?;
+            windows_link(0x14, flags & 1 != 0)
+        }
+        IO_REPARSE_TAG_MOUNT_POINT => windows_link(0x10, false),
+        IO_REPARSE_TAG_LX_SYMLINK => {
+            let target = data.get(0x0c..)?;
+            Some(DirEntry::Symlink(Symlink {
+                link_path: PathBuf::from(String::from_utf8_lossy(target).into_owned()),
+            }))
+        }
+        IO_REPARSE_TAG_AF_UNIX => Some(DirEntry::Other(DirEntryType::Socket)),
+        IO_REPARSE_TAG_LX_FIFO => Some(DirEntry::Other(DirEntryType::Pipe)),
+        IO_REPARSE_TAG_LX_CHR => Some(DirEntry::Other(DirEntryType::CharacterDevice)),
+        IO_REPARSE_TAG_LX_BLK => Some(DirEntry::Other(DirEntryType::BlockDevice)),
+        _ => None,
+    }
+}
@@ -0,0 +1,143 @@
+//! Decompression of LZNT1, the algorithm used for compressed NTFS attributes.
+
+/// The uncompressed size of a single LZNT1 chunk.
+const CHUNK_SIZE: usize = 0x1000;
+
+/// Decompresses LZNT1 data, producing at most `max_len` bytes.
+pub(super) fn decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(max_len);
+    let mut offset = 0;
+
+    while offset + 2 <= data.len() && out.len() < max_len {
+        let header = u16::from_le_bytes([data[offset], data[offset + 1]]);
+        offset += 2;
+        if header == 0 {
+            break;
+        }
+
+        let chunk_len = usize::from(header & 0x0fff) + 1;
+        let chunk = data
+            .get(offset..offset + chunk_len)
+            .ok_or_else(|| format!("LZNT1 chunk at {:#x} exceeds the data", offset - 2))?;
+        offset += chunk_len;
+
+        let chunk_start = out.len();
+        if header & 0x8000 == 0 {
+            out.extend_from_slice(chunk);
+        } else {
+            decompress_chunk(chunk, &mut out, chunk_start)?;
+        }
+
+        // uncompressed chunks that are not full are padded up to the chunk size
+        if out.len() - chunk_start < CHUNK_SIZE && offset < data.len() {
+            out.resize(chunk_start + CHUNK_SIZE, 0);
+        }
+    }
+
+    out.truncate(max_len);
+
+    Ok(out)
+}
+
+/// Decompresses a single compressed chunk, appending the result to `out`.
+fn decompress_chunk(chunk: &[u8], out: &mut Vec<u8>, chunk_start: usize) -> Result<(), String> {
+    let mut offset = 0;
+
+    while offset < chunk.len() {
+        let flags = chunk[offset];
+        offset += 1;
+
+        for bit in 0..8 {
+            if offset >= chunk.len() {
+                break;
+            }
+
+            if flags & (1 << bit) == 0 {
+                out.push(chunk[offset]);
+                offset += 1;
+                continue;
+            }
+
+            let tuple = u16::from_le_bytes([
+                chunk[offset],
+                *chunk
+                    .get(offset + 1)
+                    .ok_or("LZNT1 back reference is truncated")?,
+            ]);
+            offset += 2;
+
+            // the split between offset and length bits depends on the position in the chunk
+            let pos = out.len() - chunk_start;
+            let mut shift = 12;
+            let mut mask = 0x0fff;
+            let mut d = pos.saturating_sub(1);
+            while d >= 0x10 {
+                shift -= 1;
+                mask >>= 1;
+                d >>= 1;
+            }
+
+            let back = usize::from(tuple >> shift) + 1;
+            let len = usize::from(tuple & mask) + 3;
+
+            if back > pos {
+                return Err(format!(
+                    "LZNT1 back reference to {back} bytes before position {pos}"
+                ));
+            }
+
+            for _ in 0..len {
+                out.push(out[out.len() - back]);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_back_reference() {
+        // "abc", followed by a reference to 9 bytes starting 3 bytes back
+        let data = [0x05, 0xb0, 0x08, b'a', b'b', b'c', 0x06, 0x20];
+
+        assert_eq!(decompress(&data, 0x1000).unwrap(), b"abcabcabcabc");
+        assert_eq!(decompress(&data, 5).unwrap(), b"abcab");
+    }
+
+    #[test]
+    fn back_reference_with_narrow_length() {
+        // after 17 bytes only 11 bits remain for the length of a back reference
+        let mut data = vec![0x15, 0xb0, 0x00];
+        data.extend_from_slice(b"01234567");
+        data.push(0x00);
+        data.extend_from_slice(b"89abcdef");
+        data.extend_from_slice(&[0x02, b'g', 0x0e, 0x80]);
+
+        assert_eq!(
+            decompress(&data, 0x1000).unwrap(),
+            b"0123456789abcdefg0123456789abcdefg"
+        );
+    }
+
+    #[test]
+    fn uncompressed_chunk() {
+        let mut data = vec![0xff, 0x3f];
+        data.extend((0..0x1000).map(|i| i as u8));
+
+        assert_eq!(decompress(&data, 0x1000).unwrap(), data[2..]);
+    }
+
+    #[test]
+    fn invalid_data() {
+        // a back reference before the start of the chunk
+        assert!(decompress(&[0x03, 0xb0, 0x02, b'a', 0x00, 0x10], 0x1000).is_err());
+        // a truncated back reference
+        assert!(decompress(&[0x02, 0xb0, 0x02, b'a', 0x00], 0x1000).is_err());
+        // a chunk that exceeds the data
+        assert!(decompress(&[0x10, 0xb0, 0x00, b'a'], 0x1000).is_err());
+    }
+}
@@ -0,0 +1,489 @@
+//! Parsing of MFT file records and the attributes they contain.
+//!
+//! The layouts are described in the
+//! [libfsntfs documentation](https://github.com/libyal/libfsntfs/blob/main/documentation/New%20Technologies%20File%20System%20(NTFS).asciidoc).
+
+use std::ffi::OsString;
+
+use anyhow::Context as _;
+
+use super::stream::DataRun;
+
+/// The size of the blocks protected by the update sequence array.
+const FIXUP_BLOCK_SIZE: usize = 512;
+
+/// The magic bytes at the start of an MFT file record.
+const FILE_RECORD_MAGIC: &[u8] = b"FILE";
+
+/// The flag in a file record that marks it as in use.
+const RECORD_IN_USE: u16 = 0x0001;
+
+/// The flag in a file record that marks it as a directory.
+const RECORD_IS_DIRECTORY: u16 = 0x0002;
+
+/// The attribute type that marks the end of the attributes in a record.
+const END_OF_ATTRIBUTES: u32 = 0xffff_ffff;
+
+/// The size of the header of a non-resident attribute, up to and including the initialized size.
+const NON_RESIDENT_HEADER_LEN: usize = 0x40;
+
+/// Reads a little endian `u16` at the given offset.
+pub(super) fn le_u16(data: &[u8], offset: usize) -> anyhow::Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of data reading u16 at {offset:#x}"))
+}
+
+/// Reads a little endian `u32` at the given offset.
+pub(super) fn le_u32(data: &[u8], offset: usize) -> anyhow::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of data reading u32 at {offset:#x}"))
+}
+
+/// Reads a little endian `u64` at the given offset.
+pub(super) fn le_u64(data: &[u8], offset: usize) -> anyhow::Result<u64> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of data reading u64 at {offset:#x}"))
+}
+
+/// Decodes the UTF-16LE name of the given length in characters at the given offset.
+pub(super) fn utf16_name(data: &[u8], offset: usize, len: usize) -> anyhow::Result<OsString> {
+    let bytes = data
+        .get(offset..offset + len * 2)
+        .ok_or_else(|| anyhow::anyhow!("name at {offset:#x} exceeds the data"))?;
+
+    let units = bytes
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]));
+
+    Ok(char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect::<String>()
+        .into())
+}
+
+/// Applies the update sequence array fixups to a multi-sector record in place.
+pub(super) fn apply_fixups(data: &mut [u8]) -> anyhow::Result<()> {
+    let usa_offset = usize::from(le_u16(data, 4)?);
+    let usa_count = usize::from(le_u16(data, 6)?);
+
+    if usa_count == 0 {
+        return Ok(());
+    }
+
+    let sequence = le_u16(data, usa_offset)?;
+
+    for i in 1..usa_count {
+        let end = i * FIXUP_BLOCK_SIZE;
+        if end > data.len() {
+            break;
+        }
+
+        if le_u16(data, end - 2)? != sequence {
+            anyhow::bail!("update sequence mismatch in block {i}, the record is torn");
+        }
+
+        let replacement = le_u16(data, usa_offset + i * 2)?;
+        data[end - 2..end].copy_from_slice(&replacement.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+/// The types of attributes that are of interest for snapshots.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(super) enum AttributeType {
+    /// `$STANDARD_INFORMATION`
+    StandardInformation,
+    /// `$ATTRIBUTE_LIST`
+    AttributeList,
+    /// `$FILE_NAME`
+    FileName,
+    /// `$OBJECT_ID`
+    ObjectId,
+    /// `$SECURITY_DESCRIPTOR`
+    SecurityDescriptor,
+    /// `$DATA`
+    Data,
+    /// `$INDEX_ALLOCATION`
+    IndexAllocation,
+    /// `$REPARSE_POINT`
+    ReparsePoint,
+    /// `$EA`
+    Ea,
+    /// `$LOGGED_UTILITY_STREAM`
+    LoggedUtilityStream,
+    /// Any other attribute type.
+    Other(u32),
+}
+
+impl From<u32> for AttributeType {
+    fn from(ty: u32) -> Self {
+        match ty {
+            0x10 => AttributeType::StandardInformation,
+            0x20 => AttributeType::AttributeList,
+            0x30 => AttributeType::FileName,
+            0x40 => AttributeType::ObjectId,
+            0x50 => AttributeType::SecurityDescriptor,
+            0x80 => AttributeType::Data,
+            0xa0 => AttributeType::IndexAllocation,
+            0xc0 => AttributeType::ReparsePoint,
+            0xe0 => AttributeType::Ea,
+            0x100 => AttributeType::LoggedUtilityStream,
+            other => AttributeType::Other(other),
+        }
+    }
+}
+
+/// The content of an attribute.
+#[derive(Debug, Clone)]
+pub(super) enum AttributeBody {
+    /// The content is stored directly in the record.
+    Resident(Vec<u8>),
+    /// The content is stored in clusters outside of the record.
+    NonResident {
+        /// The first virtual cluster number described by this attribute.
+        start_vcn: u64,
+        /// The data runs of this attribute.
+        runs: Vec<DataRun>,
+        /// The size of the data.
+        data_size: u64,
+        /// The number of bytes of the data that are initialized.
+        initialized_size: u64,
+        /// The log2 of the number of clusters in a compression unit, or `0` if uncompressed.
+        compression_unit: u8,
+    },
+}
+
+/// A single attribute in an MFT record.
+#[derive(Debug, Clone)]
+pub(super) struct Attribute {
+    /// The type of the attribute.
+    pub(super) ty: AttributeType,
+    /// The name of the attribute.
+    pub(super) name: OsString,
+    /// The content of the attribute.
+    pub(super) body: AttributeBody,
+}
+
+impl Attribute {
+    /// Parses the attribute at the start of `data`, returning it and its length in bytes.
+    fn parse(data: &[u8]) -> anyhow::Result<(Option<Self>, usize)> {
+        let ty = le_u32(data, 0)?;
+        if ty == END_OF_ATTRIBUTES {
+            return Ok((None, 0));
+        }
+
+        let len = le_u32(data, 4)? as usize;
+        if len < 0x18 || len > data.len() {
+            anyhow::bail!("invalid attribute length {len:#x}");
+        }
+        let data = &data[..len];
+
+        let non_resident = data[8] != 0;
+        let name_len = usize::from(data[9]);
+        let name_offset = usize::from(le_u16(data, 0x0a)?);
+        let name = utf16_name(data, name_offset, name_len)?;
+
+        let body = if non_resident {
+            if len < NON_RESIDENT_HEADER_LEN {
+                anyhow::bail!("invalid non-resident attribute length {len:#x}");
+            }
+
+            let start_vcn = le_u64(data, 0x10)?;
+            let runs_offset = usize::from(le_u16(data, 0x20)?);
+            let compression_unit = data[0x22];
+            let data_size = le_u64(data, 0x30)?;
+            let initialized_size = le_u64(data, 0x38)?;
+
+            let runs = DataRun::parse_list(
+                data.get(runs_offset..)
+                    .context("data runs start outside of the attribute")?,
+            )?;
+
+            AttributeBody::NonResident {
+                start_vcn,
+                runs,
+                data_size,
+                initialized_size,
+                compression_unit,
+            }
+        } else {
+            let value_len = le_u32(data, 0x10)? as usize;
+            let value_offset = usize::from(le_u16(data, 0x14)?);
+
+            AttributeBody::Resident(
+                data.get(value_offset..value_offset + value_len)
+                    .context("resident value exceeds the attribute")?
+                    .to_vec(),
+            )
+        };
+
+        Ok((
+            Some(Self {
+                ty: AttributeType::from(ty),
+                name,
+                body,
+            }),
+            len,
+        ))
+    }
+
+    /// Returns the resident value of the attribute, if it is resident.
+    pub(super) fn resident_value(&self) -> Option<&[u8]> {
+        match &self.body {
+            AttributeBody::Resident(value) => Some(value),
+            AttributeBody::NonResident { .. } => None,
+        }
+    }
+}
+
+/// A parsed MFT file record.
+#[derive(Debug)]
+pub(super) struct FileRecord {
+    /// The sequence number of the record.
+    pub(super) sequence: u16,
+    /// Whether the record refers to a directory.
+    pub(super) is_dir: bool,
+    /// The reference to the base record, if this is an extension record.
+    pub(super) base_record: Option<u64>,
+    /// The attributes of the record.
+    pub(super) attributes: Vec<Attribute>,
+}
+
+impl FileRecord {
+    /// Parses a file record from its raw bytes.
+    ///
+    /// Returns `None` if the record is not in use.
+    pub(super) fn parse(mut data: Vec<u8>) -> anyhow::Result<Option<Self>> {
+        if data.get(..4) != Some(FILE_RECORD_MAGIC) {
+            return Ok(None);
+        }
+
+        apply_fixups(&mut data)?;
+
+        let sequence = le_u16(&data, 0x10)?;
+        let attributes_offset = usize::from(le_u16(&data, 0x14)?);
+        let flags = le_u16(&data, 0x16)?;
+        let used_size = (le_u32(&data, 0x18)? as usize).min(data.len());
+        let base_record = le_u64(&data, 0x20)? & super::RECORD_NUMBER_MASK;
+
+        if flags & RECORD_IN_USE == 0 {
+            return Ok(None);
+        }
+
+        let mut attributes = Vec::new();
+        let mut offset = attributes_offset;
+        while offset < used_size {
+            let (attribute, len) = Attribute::parse(&data[offset..used_size])
+                .with_context(|| format!("invalid attribute at offset {offset:#x}"))?;
+
+            match attribute {
+                Some(attribute) => attributes.push(attribute),
+                None => break,
+            }
+
+            offset += len;
+        }
+
+        Ok(Some(Self {
+            sequence,
+            is_dir: flags & RECORD_IS_DIRECTORY != 0,
+            base_record: (base_record != 0).then_some(base_record),
+            attributes,
+        }))
+    }
+}
+
+/// An entry in an `$ATTRIBUTE_LIST` attribute.
+#[derive(Debug)]
+pub(super) struct AttributeListEntry {
+    /// The record number of the record containing the attribute.
+    pub(super) record: u64,
+}
+
+impl AttributeListEntry {
+    /// Parses all entries of an attribute list.
+    pub(super) fn parse_list(data: &[u8]) -> anyhow::Result<Vec<Self>> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        while offset + 0x1a <= data.len() {
+            let len = usize::from(le_u16(data, offset + 4)?);
+            if len == 0 {
+                break;
+            }
+
+            entries.push(Self {
+                record: le_u64(data, offset + 0x10)? & super::RECORD_NUMBER_MASK,
+            });
+
+            offset += len;
+        }
+
+        Ok(entries)
+    }
+}
+
+/// The namespace of a `$FILE_NAME` attribute.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(super) enum FileNameNamespace {
+    /// A case sensitive name using any characters except `/` and NUL.
+    Posix,
+    /// A case insensitive Windows name.
+    Win32,
+    /// A short name in the 8.3 DOS format.
+    Dos,
+    /// A name that is both a valid Windows and DOS name.
+    Win32AndDos,
+}
+
+/// The parsed content of a `$FILE_NAME` attribute.
+#[derive(Debug)]
+pub(super) struct FileName {
+    /// The record number of the parent directory.
+    pub(super) parent: u64,
+    /// The sequence number of the parent directory.
+    pub(super) parent_sequence: u16,
+    /// The name itself.
+    pub(super) name: OsString,
+    /// The namespace of the name.
+    pub(super) namespace: FileNameNamespace,
+}
+
+impl FileName {
+    /// Parses the value of a `$FILE_NAME` attribute.
+    pub(super) fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let parent_ref = le_u64(data, 0)?;
+        let name_len = usize::from(*data.get(0x40).context("file name too short")?);
+        let namespace = match data.get(0x41).context("file name too short")? {
+            0 => FileNameNamespace::Posix,
+            1 => FileNameNamespace::Win32,
+            2 => FileNameNamespace::Dos,
+            _ => FileNameNamespace::Win32AndDos,
+        };
+
+        Ok(Self {
+            parent: parent_ref & super::RECORD_NUMBER_MASK,
+            parent_sequence: (parent_ref >> 48) as u16,
+            name: utf16_name(data, 0x42, name_len)?,
+            namespace,
+        })
+    }
+}
+
+/// The parsed content of a `$STANDARD_INFORMATION` attribute.
+#[derive(Debug)]
+pub(super) struct StandardInformation {
+    /// The creation time as an NTFS timestamp.
+    pub(super) created: i64,
+    /// The last modification time as an NTFS timestamp.
+    pub(super) modified: i64,
+    /// The last time the MFT record was changed as an NTFS timestamp.
+    pub(super) mft_modified: i64,
+    /// The last access time as an NTFS timestamp.
+    pub(super) accessed: i64,
+    /// The file attribute flags.
+    pub(super) file_attributes: u32,
+    /// The ID of the security descriptor in `$Secure`, if present.
+    pub(super) security_id: Option<u32>,
+}
+
+impl StandardInformation {
+    /// Parses the value of a `$STANDARD_INFORMATION` attribute.
+    pub(super) fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            created: le_u64(data, 0x00)? as i64,
+            modified: le_u64(data, 0x08)? as i64,
+            mft_modified: le_u64(data, 0x10)? as i64,
+            accessed: le_u64(data, 0x18)? as i64,
+            file_attributes: le_u32(data, 0x20)?,
+            security_id: le_u32(data, 0x34).ok(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The update sequence number used by the test records.
+    const SEQUENCE: [u8; 2] = [0x2a, 0x00];
+
+    /// Builds a protected record of two blocks with a single attribute of the given length.
+    fn record(attribute_len: u32) -> Vec<u8> {
+        let mut data = vec![0; 2 * FIXUP_BLOCK_SIZE];
+        data[..4].copy_from_slice(FILE_RECORD_MAGIC);
+        data[4..6].copy_from_slice(&0x30u16.to_le_bytes());
+        data[6..8].copy_from_slice(&3u16.to_le_bytes());
+        data[0x14..0x16].copy_from_slice(&0x38u16.to_le_bytes());
+        data[0x16..0x18].copy_from_slice(&RECORD_IN_USE.to_le_bytes());
+        data[0x18..0x1c].copy_from_slice(&0x100u32.to_le_bytes());
+
+        // a non-resident `$DATA` attribute without any runs
+        data[0x38..0x3c].copy_from_slice(&0x80u32.to_le_bytes());
+        data[0x3c..0x40].copy_from_slice(&attribute_len.to_le_bytes());
+        data[0x40] = 1;
+        data[0x42..0x44].copy_from_slice(&0x40u16.to_le_bytes());
+        data[0x58..0x5a].copy_from_slice(&0x40u16.to_le_bytes());
+        let end = 0x38 + attribute_len as usize;
+        data[end..end + 4].copy_from_slice(&END_OF_ATTRIBUTES.to_le_bytes());
+
+        // the last two bytes of each block are moved to the update sequence array
+        data[0x30..0x32].copy_from_slice(&SEQUENCE);
+        data[0x32..0x34].copy_from_slice(&[0x11, 0x22]);
+        data[0x34..0x36].copy_from_slice(&[0x33, 0x44]);
+        data[FIXUP_BLOCK_SIZE - 2..FIXUP_BLOCK_SIZE].copy_from_slice(&SEQUENCE);
+        data[2 * FIXUP_BLOCK_SIZE - 2..].copy_from_slice(&SEQUENCE);
+
+        data
+    }
+
+    #[test]
+    fn fixups() {
+        let mut data = record(0x48);
+        apply_fixups(&mut data).unwrap();
+
+        assert_eq!(data[FIXUP_BLOCK_SIZE - 2..FIXUP_BLOCK_SIZE], [0x11, 0x22]);
+        assert_eq!(data[2 * FIXUP_BLOCK_SIZE - 2..], [0x33, 0x44]);
+    }
+
+    #[test]
+    fn parse_record() {
+        let record = FileRecord::parse(record(0x48)).unwrap().unwrap();
+
+        assert!(!record.is_dir);
+        assert_eq!(record.base_record, None);
+        assert_eq!(record.attributes.len(), 1);
+        assert_eq!(record.attributes[0].ty, AttributeType::Data);
+    }
+
+    #[test]
+    fn torn_record() {
+        let mut data = record(0x48);
+        data[2 * FIXUP_BLOCK_SIZE - 1] ^= 0xff;
+
+        assert!(apply_fixups(&mut data.clone()).is_err());
+        assert!(FileRecord::parse(data).is_err());
+    }
+
+    #[test]
+    fn truncated_record() {
+        let data = record(0x48);
+
+        for len in [4, 8, 0x31, 0x40, 0x50] {
+            assert!(FileRecord::parse(data[..len].to_vec()).is_err());
+        }
+
+        let mut data = data;
+        data[4..6].copy_from_slice(&0xfffeu16.to_le_bytes());
+        assert!(FileRecord::parse(data).is_err());
+    }
+
+    #[test]
+    fn short_non_resident_attribute() {
+        assert!(FileRecord::parse(record(0x28)).is_err());
+    }
+}
@@ -0,0 +1,462 @@
+//! Reading of attribute data that is stored in the clusters of a volume.
+
+use std::{io, sync::Mutex};
+
+use anyhow::Context as _;
+
+use super::{
+    lznt1,
+    record::{Attribute, AttributeBody},
+};
+use crate::snapshot::read_at::ReadAt;
+
+/// The largest supported log2 of the number of clusters in a compression unit.
+///
+/// Windows only ever writes compression units of 16 clusters.
+const MAX_COMPRESSION_UNIT: u8 = 4;
+
+/// A contiguous run of clusters in a non-resident attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct DataRun {
+    /// The first logical cluster of the run or `None` if the run is sparse.
+    pub(super) lcn: Option<u64>,
+    /// The length of the run in clusters.
+    pub(super) length: u64,
+}
+
+impl DataRun {
+    /// Decodes a list of data runs (a "mapping pairs array").
+    pub(super) fn parse_list(data: &[u8]) -> anyhow::Result<Vec<Self>> {
+        let mut runs = Vec::new();
+        let mut offset = 0;
+        let mut lcn: i64 = 0;
+
+        while let Some(&header) = data.get(offset) {
+            if header == 0 {
+                break;
+            }
+
+            let length_size = usize::from(header & 0x0f);
+            let offset_size = usize::from(header >> 4);
+            offset += 1;
+
+            let length_bytes = data
+                .get(offset..offset + length_size)
+                .context("data run length exceeds the run list")?;
+            offset += length_size;
+            let offset_bytes = data
+                .get(offset..offset + offset_size)
+                .context("data run offset exceeds the run list")?;
+            offset += offset_size;
+
+            if length_size > 8 || offset_size > 8 {
+                anyhow::bail!("data run header {header:#x} is invalid");
+            }
+
+            let length = length_bytes
+                .iter()
+                .rev()
+                .fold(0u64, |acc, &byte| (acc << 8) | u64::from(byte));
+
+            let run_lcn = if offset_size == 0 {
+                None
+            } else {
+                // the offset is a signed number relative to the previous run
+                let mut relative = offset_bytes
+                    .iter()
+                    .rev()
+                    .fold(0i64, |acc, &byte| (acc << 8) | i64::from(byte));
+                let unused_bits = 64 - 8 * offset_size as u32;
+                relative = (relative << unused_bits) >> unused_bits;
+
+                lcn = lcn
+                    .checked_add(relative)
+                    .context("data run offset overflows")?;
+                Some(u64::try_from(lcn).context("data run points before the volume start")?)
+            };
+
+            runs.push(Self {
+                lcn: run_lcn,
+                length,
+            });
+        }
+
+        Ok(runs)
+    }
+}
+
+/// Describes where the data of a non-resident attribute is stored.
+#[derive(Debug, Clone, Default)]
+pub(super) struct StreamLayout {
+    /// The runs of the stream, starting at virtual cluster 0.
+    runs: Vec<DataRun>,
+    /// The first virtual cluster number of each run.
+    run_starts: Vec<u64>,
+    /// The size of the data in bytes.
+    data_size: u64,
+    /// The number of initialized bytes, anything after this reads as zero.
+    initialized_size: u64,
+    /// The log2 of the number of clusters in a compression unit, or `0` if uncompressed.
+    compression_unit: u8,
+}
+
+impl StreamLayout {
+    /// Combines the extents of a non-resident attribute into a single layout.
+    ///
+    /// The attributes may be given in any order.
+    pub(super) fn from_extents<'a>(
+        extents: impl IntoIterator<Item = &'a Attribute>,
+    ) -> anyhow::Result<Self> {
+        let mut extents = extents
+            .into_iter()
+            .filter_map(|attr| match &attr.body {
+                AttributeBody::NonResident {
+                    start_vcn,
+                    runs,
+                    data_size,
+                    initialized_size,
+                    compression_unit,
+                } => Some((
+                    *start_vcn,
+                    runs,
+                    *data_size,
+                    *initialized_size,
+                    *compression_unit,
+                )),
+                AttributeBody::Resident(_) => None,
+            })
+            .collect::<Vec<_>>();
+        extents.sort_by_key(|(start_vcn, ..)| *start_vcn);
+
+        let mut layout = Self::default();
+        let mut next_vcn = 0;
+
+        for (i, (start_vcn, runs, data_size, initialized_size, compression_unit)) in
+            extents.into_iter().enumerate()
+        {
+            // only the first extent carries the sizes of the stream
+            if i == 0 {
+                if compression_unit > MAX_COMPRESSION_UNIT {
+                    anyhow::bail!("unsupported compression unit size 2^{compression_unit}");
+                }
+
+                layout.data_size = data_size;
+                layout.initialized_size = initialized_size;
+                layout.compression_unit = compression_unit;
+            }
+
+            if start_vcn > next_vcn {
+                layout.push(DataRun {
+                    lcn: None,
+                    length: start_vcn - next_vcn,
+                });
+                next_vcn = start_vcn;
+            }
+
+            for run in runs {
+                layout.push(*run);
+                next_vcn += run.length;
+            }
+        }
+
+        Ok(layout)
+    }
+
+    /// Appends a run to the end of the layout.
+    fn push(&mut self, run: DataRun) {
+        let start = self
+            .run_starts
+            .last()
+            .zip(self.runs.last())
+            .map(|(start, run)| start + run.length)
+            .unwrap_or(0);
+
+        self.run_starts.push(start);
+        self.runs.push(run);
+    }
+
+    /// The size of the data in bytes.
+    pub(super) fn data_size(&self) -> u64 {
+        self.data_size
+    }
+
+    /// Returns the index of the run containing the given virtual cluster.
+    fn run_index(&self, vcn: u64) -> Option<usize> {
+        let idx = match self.run_starts.binary_search(&vcn) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+
+        (vcn < self.run_starts[idx] + self.runs[idx].length).then_some(idx)
+    }
+}
+
+/// The data of an attribute, either stored in the record or in clusters.
+#[derive(Debug, Clone)]
+pub(super) enum StreamData {
+    /// The data is stored in the record itself.
+    Resident(Vec<u8>),
+    /// The data is stored in clusters of the volume.
+    NonResident(StreamLayout),
+}
+
+impl StreamData {
+    /// Creates stream data from all extents of a single attribute.
+    pub(super) fn from_extents<'a>(
+        extents: impl IntoIterator<Item = &'a Attribute> + Clone,
+    ) -> anyhow::Result<Self> {
+        if let Some(value) = extents
+            .clone()
+            .into_iter()
+            .find_map(|attr| attr.resident_value())
+        {
+            Ok(StreamData::Resident(value.to_vec()))
+        } else {
+            Ok(StreamData::NonResident(StreamLayout::from_extents(
+                extents,
+            )?))
+        }
+    }
+
+    /// The size of the data in bytes.
+    pub(super) fn size(&self) -> u64 {
+        match self {
+            StreamData::Resident(data) => data.len() as u64,
+            StreamData::NonResident(layout) => layout.data_size(),
+        }
+    }
+}
+
+/// A readable stream of an attribute on a volume.
+pub(super) struct Stream<'vol, R: ?Sized> {
+    /// The device the volume is stored on.
+    device: &'vol R,
+    /// The size of a cluster in bytes.
+    cluster_size: u64,
+    /// The data of the stream.
+    data: &'vol StreamData,
+    /// The most recently decompressed compression unit and its index.
+    unit_cache: Mutex<Option<(u64, Vec<u8>)>>,
+}
+
+impl<'vol, R: ReadAt + ?Sized> Stream<'vol, R> {
+    /// Creates a new readable stream.
+    pub(super) fn new(device: &'vol R, cluster_size: u64, data: &'vol StreamData) -> Self {
+        Self {
+            device,
+            cluster_size,
+            data,
+            unit_cache: Mutex::new(None),
+        }
+    }
+
+    /// Reads the entire stream, failing if it is larger than `limit` bytes.
+    pub(super) fn read_to_vec(&self, limit: u64) -> io::Result<Vec<u8>> {
+        if self.size() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("stream of {} bytes exceeds the limit", self.size()),
+            ));
+        }
+
+        let mut data = vec![0; self.size() as usize];
+        self.read_exact_at(&mut data, 0)?;
+
+        Ok(data)
+    }
+
+    /// Returns the byte offset on the device of `offset` bytes into the cluster `relative`
+    /// clusters after `lcn`.
+    fn device_offset(&self, lcn: u64, relative: u64, offset: u64) -> io::Result<u64> {
+        lcn.checked_add(relative)
+            .and_then(|lcn| lcn.checked_mul(self.cluster_size))
+            .and_then(|start| start.checked_add(offset))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("cluster {lcn} + {relative} is out of range"),
+                )
+            })
+    }
+
+    /// Reads uncompressed data at the given offset, stopping at the end of the containing run.
+    fn read_uncompressed(
+        &self,
+        layout: &StreamLayout,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> io::Result<usize> {
+        let vcn = offset / self.cluster_size;
+        let Some(idx) = layout.run_index(vcn) else {
+            // runs missing at the end of the stream are treated as sparse
+            buf.fill(0);
+            return Ok(buf.len());
+        };
+
+        let run = layout.runs[idx];
+        let run_offset = offset - layout.run_starts[idx] * self.cluster_size;
+        let run_remaining = run.length.saturating_mul(self.cluster_size) - run_offset;
+        let len = buf
+            .len()
+            .min(usize::try_from(run_remaining).unwrap_or(usize::MAX));
+
+        match run.lcn {
+            Some(lcn) => self
+                .device
+                .read_at(&mut buf[..len], self.device_offset(lcn, 0, run_offset)?),
+            None => {
+                buf[..len].fill(0);
+                Ok(len)
+            }
+        }
+    }
+
+    /// Returns the decompressed data of the given compression unit.
+    fn decompress_unit(&self, layout: &StreamLayout, unit: u64) -> io::Result<Vec<u8>> {
+        let unit_clusters = 1u64 << layout.compression_unit;
+        let unit_size = (unit_clusters * self.cluster_size) as usize;
+        let first_vcn = unit * unit_clusters;
+
+        let mut raw = Vec::with_capacity(unit_size);
+        let mut allocated = 0;
+        for vcn in first_vcn..first_vcn + unit_clusters {
+            let Some(idx) = layout.run_index(vcn) else {
+                break;
+            };
+            let run = layout.runs[idx];
+            if let Some(lcn) = run.lcn {
+                let mut cluster = vec![0; self.cluster_size as usize];
+                self.device.read_exact_at(
+                    &mut cluster,
+                    self.device_offset(lcn, vcn - layout.run_starts[idx], 0)?,
+                )?;
+                raw.extend_from_slice(&cluster);
+                allocated += 1;
+            }
+        }
+
+        if allocated == 0 {
+            Ok(vec![0; unit_size])
+        } else if allocated == unit_clusters {
+            Ok(raw)
+        } else {
+            let mut decompressed = lznt1::decompress(&raw, unit_size)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            decompressed.resize(unit_size, 0);
+            Ok(decompressed)
+        }
+    }
+
+    /// Reads compressed data at the given offset, stopping at the end of the compression unit.
+    fn read_compressed(
+        &self,
+        layout: &StreamLayout,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> io::Result<usize> {
+        let unit_size = (1u64 << layout.compression_unit) * self.cluster_size;
+        let unit = offset / unit_size;
+        let unit_offset = (offset % unit_size) as usize;
+
+        let mut cache = self.unit_cache.lock().unwrap();
+        let data = match &*cache {
+            Some((cached_unit, data)) if *cached_unit == unit => data,
+            _ => &cache.insert((unit, self.decompress_unit(layout, unit)?)).1,
+        };
+
+        let len = buf.len().min(data.len() - unit_offset);
+        buf[..len].copy_from_slice(&data[unit_offset..unit_offset + len]);
+
+        Ok(len)
+    }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for Stream<'_, R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let size = self.size();
+        if offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(size - offset).unwrap_or(usize::MAX));
+        let buf = &mut buf[..len];
+
+        match self.data {
+            StreamData::Resident(data) => {
+                let offset = offset as usize;
+                buf.copy_from_slice(&data[offset..offset + len]);
+                Ok(len)
+            }
+            StreamData::NonResident(layout) => {
+                if offset >= layout.initialized_size {
+                    buf.fill(0);
+                    return Ok(len);
+                }
+                let initialized_len = buf
+                    .len()
+                    .min(usize::try_from(layout.initialized_size - offset).unwrap_or(usize::MAX));
+                let buf = &mut buf[..initialized_len];
+
+                if layout.compression_unit != 0 {
+                    self.read_compressed(layout, buf, offset)
+                } else {
+                    self.read_uncompressed(layout, buf, offset)
+                }
+            }
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.data.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_runs() {
+        let data = [
+            0x21, 0x10, 0x00, 0x01, // 16 clusters at 0x100
+            0x01, 0x08, // 8 sparse clusters
+            0x11, 0x04, 0xf0, // 4 clusters 16 before the previous run
+            0x31, 0x02, 0x00, 0x00, 0x01, // 2 clusters 0x10000 after the previous run
+            0x00, 0xff, // the end of the list
+        ];
+
+        assert_eq!(
+            DataRun::parse_list(&data).unwrap(),
+            [
+                DataRun {
+                    lcn: Some(0x100),
+                    length: 16,
+                },
+                DataRun {
+                    lcn: None,
+                    length: 8,
+                },
+                DataRun {
+                    lcn: Some(0xf0),
+                    length: 4,
+                },
+                DataRun {
+                    lcn: Some(0x100f0),
+                    length: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_runs() {
+        // a run before the start of the volume
+        assert!(DataRun::parse_list(&[0x11, 0x01, 0x80]).is_err());
+        // a truncated run
+        assert!(DataRun::parse_list(&[0x21, 0x10, 0x00]).is_err());
+        // an offset that is too large
+        assert!(DataRun::parse_list(&[0x91, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]).is_err());
+    }
+}
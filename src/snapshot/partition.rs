@@ -0,0 +1,551 @@
+//! Reading of MBR and GPT partition tables.
+
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+use super::read_at::ReadAt;
+
+/// The size of a sector that is assumed for MBR partition tables.
+const SECTOR_SIZE: u64 = 512;
+
+/// The sector sizes that are tried for GPT partition tables, whose LBAs depend on the logical
+/// sector size of the disk.
+const GPT_SECTOR_SIZES: [u64; 2] = [512, 4096];
+
+/// The offset of the disk signature in the MBR.
+const MBR_SIGNATURE_OFFSET: usize = 0x1b8;
+
+/// The offset of the partition entries in the MBR.
+const MBR_ENTRIES_OFFSET: usize = 0x1be;
+
+/// The number of partition entries in the MBR.
+const MBR_ENTRY_COUNT: usize = 4;
+
+/// The MBR partition type of a protective MBR in front of a GPT.
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+
+/// The MBR partition types of extended partitions, containing logical partitions.
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// The largest number of logical partitions that will be read from an extended partition.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+/// The signature of a GPT header.
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+
+/// The largest number of GPT entries that will be read.
+const GPT_MAX_ENTRIES: u32 = 1024;
+
+/// Well known GPT partition types and their names.
+const GPT_TYPE_NAMES: &[(&str, &str)] = &[
+    ("C12A7328-F81F-11D2-BA4B-00A0C93EC93B", "EFI system"),
+    ("E3C9E316-0B5C-4DB8-817D-F92DF00215AE", "Microsoft reserved"),
+    (
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7",
+        "Microsoft basic data",
+    ),
+    (
+        "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC",
+        "Windows recovery environment",
+    ),
+    (
+        "5808C8AA-7E8F-42E0-85D2-E1E90434CFB3",
+        "Windows LDM metadata",
+    ),
+    ("AF9B60A0-1431-4F62-BC68-3311714A69AD", "Windows LDM data"),
+    (
+        "E75CAF8F-F680-4CEE-AFA3-B001E56EFC2D",
+        "Windows storage spaces",
+    ),
+    ("0FC63DAF-8483-4772-8E79-3D69D8477DE4", "Linux filesystem"),
+    ("0657FD6D-A4AB-43C4-84E5-0933C84B4F4F", "Linux swap"),
+    ("E6D6D379-F507-44C2-A23C-238F2A3DF928", "Linux LVM"),
+    ("21686148-6449-6E6F-744E-656564454649", "BIOS boot"),
+];
+
+/// Well known MBR partition types and their names.
+const MBR_TYPE_NAMES: &[(u8, &str)] = &[
+    (0x01, "FAT12"),
+    (0x04, "FAT16"),
+    (0x05, "extended"),
+    (0x06, "FAT16"),
+    (0x07, "NTFS/exFAT"),
+    (0x0b, "FAT32"),
+    (0x0c, "FAT32 (LBA)"),
+    (0x0e, "FAT16 (LBA)"),
+    (0x0f, "extended (LBA)"),
+    (0x27, "Windows recovery environment"),
+    (0x42, "Windows dynamic disk"),
+    (0x82, "Linux swap"),
+    (0x83, "Linux"),
+    (0x85, "Linux extended"),
+    (0x8e, "Linux LVM"),
+    (0xee, "GPT protective"),
+    (0xef, "EFI system"),
+];
+
+/// A GUID as used in GPT partition tables.
+///
+/// The bytes are stored in the mixed endian on-disk order.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct Guid(pub(crate) [u8; 16]);
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{}-{}",
+            u32::from_le_bytes(b[0..4].try_into().unwrap()),
+            u16::from_le_bytes(b[4..6].try_into().unwrap()),
+            u16::from_le_bytes(b[6..8].try_into().unwrap()),
+            hex::encode_upper(&b[8..10]),
+            hex::encode_upper(&b[10..16]),
+        )
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl FromStr for Guid {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_start_matches('{').trim_end_matches('}');
+
+        let parts = s.split('-').collect::<Vec<_>>();
+        if parts.iter().map(|part| part.len()).collect::<Vec<_>>() != [8, 4, 4, 4, 12] {
+            anyhow::bail!("GUIDs must have the form XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX");
+        }
+
+        let mut bytes = [0; 16];
+        bytes[0..4].copy_from_slice(&u32::from_str_radix(parts[0], 16)?.to_le_bytes());
+        bytes[4..6].copy_from_slice(&u16::from_str_radix(parts[1], 16)?.to_le_bytes());
+        bytes[6..8].copy_from_slice(&u16::from_str_radix(parts[2], 16)?.to_le_bytes());
+        hex::decode_to_slice(parts[3], &mut bytes[8..10])?;
+        hex::decode_to_slice(parts[4], &mut bytes[10..16])?;
+
+        Ok(Guid(bytes))
+    }
+}
+
+/// The type of a partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum PartitionType {
+    /// The type byte of an MBR partition.
+    Mbr(u8),
+    /// The type GUID of a GPT partition.
+    Gpt(Guid),
+}
+
+impl fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionType::Mbr(ty) => match MBR_TYPE_NAMES.iter().find(|(known, _)| known == ty) {
+                Some((_, name)) => write!(f, "{name} ({ty:#04x})"),
+                None => write!(f, "{ty:#04x}"),
+            },
+            PartitionType::Gpt(guid) => {
+                let guid = guid.to_string();
+                match GPT_TYPE_NAMES.iter().find(|(known, _)| *known == guid) {
+                    Some((_, name)) => write!(f, "{name}"),
+                    None => write!(f, "{guid}"),
+                }
+            }
+        }
+    }
+}
+
+/// A partition on a disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Partition {
+    /// The number of the partition, starting at 1.
+    ///
+    /// For MBR disks, logical partitions are numbered starting at 5.
+    pub(crate) index: u32,
+    /// The offset of the partition on the disk in bytes.
+    pub(crate) offset: u64,
+    /// The size of the partition in bytes.
+    pub(crate) size: u64,
+    /// The type of the partition.
+    pub(crate) partition_type: PartitionType,
+    /// The unique GUID of the partition, only available for GPT partitions.
+    pub(crate) guid: Option<Guid>,
+    /// The label of the partition, only available for GPT partitions.
+    pub(crate) label: Option<String>,
+    /// The path in the snapshot where the file system of the partition was placed.
+    ///
+    /// This is `None` for partitions that were not included in the snapshot.
+    pub(crate) root: Option<PathBuf>,
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "partition {}: {}, offset {}, size {}B",
+            self.index,
+            self.partition_type,
+            self.offset,
+            size_format::SizeFormatterBinary::new(self.size),
+        )?;
+
+        if let Some(label) = &self.label
+            && !label.is_empty()
+        {
+            write!(f, ", label {label:?}")?;
+        }
+
+        if let Some(guid) = &self.guid {
+            write!(f, ", GUID {guid}")?;
+        }
+
+        if let Some(root) = &self.root {
+            write!(f, ", snapshotted at {}", root.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The kind of a partition table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum PartitionTableKind {
+    /// A master boot record with the given disk signature.
+    Mbr {
+        /// The disk signature stored in the MBR.
+        disk_signature: u32,
+    },
+    /// A GUID partition table with the given disk GUID.
+    Gpt {
+        /// The GUID of the disk.
+        disk_guid: Guid,
+    },
+}
+
+/// The partition table of a disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PartitionTable {
+    /// The kind of the partition table.
+    pub(crate) kind: PartitionTableKind,
+    /// The partitions in the table.
+    pub(crate) partitions: Vec<Partition>,
+}
+
+impl fmt::Display for PartitionTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            PartitionTableKind::Mbr { disk_signature } => write!(
+                f,
+                "MBR partition table (disk signature {disk_signature:08X})"
+            )?,
+            PartitionTableKind::Gpt { disk_guid } => {
+                write!(f, "GPT partition table (disk GUID {disk_guid})")?
+            }
+        }
+
+        for partition in &self.partitions {
+            write!(f, "\n  {partition}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Selects which partitions of a disk are included in a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) enum PartitionSelector {
+    /// The largest partition with a supported file system.
+    #[default]
+    Largest,
+    /// All partitions with a supported file system, each under its own root.
+    All,
+    /// The partition with the given number.
+    Index(u32),
+    /// The GPT partition with the given unique GUID.
+    Guid(Guid),
+    /// The GPT partition with the given label.
+    Label(String),
+}
+
+impl PartitionSelector {
+    /// Returns `true` if the given partition is selected explicitly by this selector.
+    pub(crate) fn matches(&self, partition: &Partition) -> bool {
+        match self {
+            PartitionSelector::Largest | PartitionSelector::All => false,
+            PartitionSelector::Index(index) => partition.index == *index,
+            PartitionSelector::Guid(guid) => partition.guid == Some(*guid),
+            PartitionSelector::Label(label) => partition.label.as_ref() == Some(label),
+        }
+    }
+}
+
+impl fmt::Display for PartitionSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionSelector::Largest => write!(f, "the largest partition"),
+            PartitionSelector::All => write!(f, "all partitions"),
+            PartitionSelector::Index(index) => write!(f, "partition number {index}"),
+            PartitionSelector::Guid(guid) => write!(f, "partition GUID {guid}"),
+            PartitionSelector::Label(label) => write!(f, "partition label {label:?}"),
+        }
+    }
+}
+
+impl FromStr for PartitionSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "largest" => PartitionSelector::Largest,
+            "all" => PartitionSelector::All,
+            _ => {
+                if let Ok(index) = s.parse() {
+                    PartitionSelector::Index(index)
+                } else if let Ok(guid) = s.parse() {
+                    PartitionSelector::Guid(guid)
+                } else {
+                    PartitionSelector::Label(s.strip_prefix("label:").unwrap_or(s).to_string())
+                }
+            }
+        })
+    }
+}
+
+/// Reads a little endian `u32` at the given offset in `data`.
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads a little endian `u64` at the given offset in `data`.
+fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Reads the partition table of the given disk.
+///
+/// Returns `None` if the disk has no partition table.
+pub(crate) fn read_partition_table(disk: &impl ReadAt) -> anyhow::Result<Option<PartitionTable>> {
+    let mut mbr = [0; SECTOR_SIZE as usize];
+    disk.read_exact_at(&mut mbr, 0)
+        .context("could not read the master boot record")?;
+
+    if mbr[0x1fe..] != [0x55, 0xaa] {
+        return Ok(None);
+    }
+
+    let entries = mbr[MBR_ENTRIES_OFFSET..MBR_ENTRIES_OFFSET + MBR_ENTRY_COUNT * 16]
+        .chunks_exact(16)
+        .collect::<Vec<_>>();
+
+    if entries
+        .iter()
+        .any(|entry| entry[4] == MBR_TYPE_GPT_PROTECTIVE)
+    {
+        return read_gpt(disk).map(Some);
+    }
+
+    // Volume boot records also end in 0x55aa, so only accept tables with sensible entries
+    if entries
+        .iter()
+        .any(|entry| entry[0] != 0 && entry[0] != 0x80)
+    {
+        return Ok(None);
+    }
+
+    let mut partitions = Vec::new();
+    for (i, entry) in entries.into_iter().enumerate() {
+        let partition_type = entry[4];
+        let offset = u64::from(le_u32(entry, 8)) * SECTOR_SIZE;
+        let size = u64::from(le_u32(entry, 12)) * SECTOR_SIZE;
+
+        if partition_type == 0 || size == 0 {
+            continue;
+        }
+
+        if MBR_TYPES_EXTENDED.contains(&partition_type) {
+            read_logical_partitions(disk, offset, &mut partitions)?;
+            continue;
+        }
+
+        partitions.push(Partition {
+            index: i as u32 + 1,
+            offset,
+            size,
+            partition_type: PartitionType::Mbr(partition_type),
+            guid: None,
+            label: None,
+            root: None,
+        });
+    }
+
+    Ok(Some(PartitionTable {
+        kind: PartitionTableKind::Mbr {
+            disk_signature: le_u32(&mbr, MBR_SIGNATURE_OFFSET),
+        },
+        partitions,
+    }))
+}
+
+/// Reads the logical partitions in the extended partition starting at `extended_offset`.
+///
+/// The logical partitions are stored as a linked list of extended boot records, each containing
+/// one logical partition relative to itself and a link relative to the extended partition.
+fn read_logical_partitions(
+    disk: &impl ReadAt,
+    extended_offset: u64,
+    partitions: &mut Vec<Partition>,
+) -> anyhow::Result<()> {
+    let mut ebr_offset = extended_offset;
+
+    for index in 5..5 + MAX_LOGICAL_PARTITIONS as u32 {
+        let mut ebr = [0; SECTOR_SIZE as usize];
+        disk.read_exact_at(&mut ebr, ebr_offset)
+            .context("could not read an extended boot record")?;
+
+        if ebr[0x1fe..] != [0x55, 0xaa] {
+            break;
+        }
+
+        let entry = &ebr[MBR_ENTRIES_OFFSET..MBR_ENTRIES_OFFSET + 16];
+        let size = u64::from(le_u32(entry, 12)) * SECTOR_SIZE;
+        if entry[4] != 0 && size != 0 {
+            partitions.push(Partition {
+                index,
+                offset: ebr_offset + u64::from(le_u32(entry, 8)) * SECTOR_SIZE,
+                size,
+                partition_type: PartitionType::Mbr(entry[4]),
+                guid: None,
+                label: None,
+                root: None,
+            });
+        }
+
+        let link = &ebr[MBR_ENTRIES_OFFSET + 16..MBR_ENTRIES_OFFSET + 32];
+        let next = u64::from(le_u32(link, 8)) * SECTOR_SIZE;
+        if !MBR_TYPES_EXTENDED.contains(&link[4]) || next == 0 {
+            break;
+        }
+        ebr_offset = extended_offset + next;
+    }
+
+    Ok(())
+}
+
+/// Reads the GPT header at the given LBA of a disk with the given sector size, returning it if it
+/// and its entries are valid.
+fn read_gpt_header(
+    disk: &impl ReadAt,
+    lba: u64,
+    sector_size: u64,
+) -> anyhow::Result<([u8; 0x5c], Vec<u8>)> {
+    let mut sector = vec![0; sector_size as usize];
+    disk.read_exact_at(&mut sector, lba * sector_size)
+        .context("could not read the GPT header")?;
+
+    if &sector[..8] != GPT_SIGNATURE {
+        anyhow::bail!("no GPT header found at LBA {lba} with {sector_size} byte sectors");
+    }
+
+    let header_size = (le_u32(&sector, 0x0c) as usize).clamp(0x5c, sector_size as usize);
+    let mut check = sector[..header_size].to_vec();
+    check[0x10..0x14].fill(0);
+    if crc32fast::hash(&check) != le_u32(&sector, 0x10) {
+        anyhow::bail!("the GPT header at LBA {lba} has an invalid checksum");
+    }
+
+    let entries_lba = le_u64(&sector, 0x48);
+    let entry_count = le_u32(&sector, 0x50);
+    let entry_size = le_u32(&sector, 0x54);
+
+    if entry_count > GPT_MAX_ENTRIES || entry_size < 0x80 {
+        anyhow::bail!("the GPT header contains invalid entry sizes");
+    }
+
+    let mut entries = vec![0; entry_count as usize * entry_size as usize];
+    let entries_offset = entries_lba
+        .checked_mul(sector_size)
+        .context("the GPT header contains an invalid entry location")?;
+    disk.read_exact_at(&mut entries, entries_offset)
+        .context("could not read the GPT entries")?;
+
+    if crc32fast::hash(&entries) != le_u32(&sector, 0x58) {
+        anyhow::bail!("the GPT entries of the header at LBA {lba} have an invalid checksum");
+    }
+
+    Ok((sector[..0x5c].try_into().unwrap(), entries))
+}
+
+/// Reads the GPT header of the given disk, trying all supported sector sizes.
+///
+/// Returns the header, its entries and the sector size of the disk.
+/// If the primary table is damaged, the backup table at the end of the disk is used.
+fn find_gpt_header(disk: &impl ReadAt) -> anyhow::Result<([u8; 0x5c], Vec<u8>, u64)> {
+    let mut primary_err = None;
+    for sector_size in GPT_SECTOR_SIZES {
+        match read_gpt_header(disk, 1, sector_size) {
+            Ok((header, entries)) => return Ok((header, entries, sector_size)),
+            Err(err) => {
+                primary_err.get_or_insert(err);
+            }
+        }
+    }
+
+    for sector_size in GPT_SECTOR_SIZES {
+        let last_lba = (disk.size() / sector_size).saturating_sub(1);
+        if let Ok((header, entries)) = read_gpt_header(disk, last_lba, sector_size) {
+            return Ok((header, entries, sector_size));
+        }
+    }
+
+    Err(primary_err.unwrap())
+        .context("the disk has a protective MBR, but neither a valid primary nor backup GPT")
+}
+
+/// Reads the GUID partition table of the given disk.
+fn read_gpt(disk: &impl ReadAt) -> anyhow::Result<PartitionTable> {
+    let (header, entries, sector_size) = find_gpt_header(disk)?;
+
+    let entry_size = le_u32(&header, 0x54) as usize;
+
+    let partitions = entries
+        .chunks_exact(entry_size)
+        .enumerate()
+        .filter(|(_, entry)| entry[..16].iter().any(|&byte| byte != 0))
+        .filter_map(|(i, entry)| {
+            let first_lba = le_u64(entry, 0x20);
+            let last_lba = le_u64(entry, 0x28);
+
+            let label = String::from_utf16_lossy(
+                &entry[0x38..0x80]
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|&c| c != 0)
+                    .collect::<Vec<_>>(),
+            );
+
+            Some(Partition {
+                index: i as u32 + 1,
+                offset: first_lba.checked_mul(sector_size)?,
+                size: (last_lba.checked_sub(first_lba)? + 1).checked_mul(sector_size)?,
+                partition_type: PartitionType::Gpt(Guid(entry[..16].try_into().unwrap())),
+                guid: Some(Guid(entry[16..32].try_into().unwrap())),
+                label: Some(label),
+                root: None,
+            })
+        })
+        .collect();
+
+    Ok(PartitionTable {
+        kind: PartitionTableKind::Gpt {
+            disk_guid: Guid(header[0x38..0x48].try_into().unwrap()),
+        },
+        partitions,
+    })
+}
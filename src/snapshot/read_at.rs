@@ -0,0 +1,184 @@
+//! Positioned reading from data sources that are shared between threads.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// A source of data that can be read at arbitrary offsets without any shared cursor.
+///
+/// This allows multiple threads to read from the same source at the same time.
+pub(crate) trait ReadAt: Send + Sync {
+    /// Reads data at the given offset into `buf`, returning the number of bytes read.
+    ///
+    /// A return value of `0` means that the end of the data was reached.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// The total size of the data in bytes.
+    fn size(&self) -> u64;
+
+    /// Reads exactly enough bytes at the given offset to fill `buf`.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns a cursor over the data, implementing `Read` and `Seek`.
+    fn cursor(&self) -> ReadAtCursor<'_, Self>
+    where
+        Self: Sized,
+    {
+        ReadAtCursor {
+            inner: self,
+            pos: 0,
+        }
+    }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for &R {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    fn size(&self) -> u64 {
+        (**self).size()
+    }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for Box<R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    fn size(&self) -> u64 {
+        (**self).size()
+    }
+}
+
+/// A file on the host system that is read at offsets.
+pub(crate) struct HostFile {
+    /// The opened file.
+    file: std::fs::File,
+    /// The size of the file.
+    size: u64,
+}
+
+impl HostFile {
+    /// Opens the file at the given path for positioned reading.
+    pub(crate) fn open(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+
+        // Block devices report a length of zero in their metadata, so seek to the end instead
+        let size = file.seek(SeekFrom::End(0))?;
+
+        Ok(Self { file, size })
+    }
+}
+
+impl ReadAt for HostFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        use std::os::unix::fs::FileExt as _;
+
+        self.file.read_at(buf, offset)
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// A cursor over a `ReadAt` source, so it can be used where `Read` and `Seek` are expected.
+pub(crate) struct ReadAtCursor<'src, R: ?Sized> {
+    /// The source of the data.
+    inner: &'src R,
+    /// The current position of the cursor.
+    pos: u64,
+}
+
+impl<R: ReadAt + ?Sized> Read for ReadAtCursor<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.inner.size().saturating_sub(self.pos);
+        let len = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+
+        let n = self.inner.read_at(&mut buf[..len], self.pos)?;
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<R: ReadAt + ?Sized> Seek for ReadAtCursor<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.inner.size().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        match new_pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+/// A window into a part of another source, such as a partition on a disk.
+pub(crate) struct Slice<R> {
+    /// The source containing the data.
+    inner: R,
+    /// The offset of the window in the source.
+    offset: u64,
+    /// The size of the window.
+    size: u64,
+}
+
+impl<R: ReadAt> Slice<R> {
+    /// Creates a window of `size` bytes starting at `offset` in `inner`.
+    pub(crate) fn new(inner: R, offset: u64, size: u64) -> Self {
+        Self {
+            inner,
+            offset,
+            size,
+        }
+    }
+}
+
+impl<R: ReadAt> ReadAt for Slice<R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let remaining = self.size.saturating_sub(offset);
+        let len = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        self.inner.read_at(&mut buf[..len], self.offset + offset)
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
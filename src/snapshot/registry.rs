@@ -0,0 +1,453 @@
+//! Parsing of Windows registry hive files in the `regf` format.
+//!
+//! Only the key and value tree is extracted, so that it can be compared between snapshots.
+//! The format is described in the
+//! [libregf documentation](https://github.com/libyal/libregf/blob/main/documentation/Windows%20NT%20Registry%20File%20(REGF)%20format.asciidoc).
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{fs::file::Sha256Hash, timestamp::Timestamp};
+
+/// The magic bytes at the start of a registry hive file.
+pub(crate) const HIVE_MAGIC: &[u8] = b"regf";
+
+/// The size of the base block at the start of a hive file.
+const BASE_BLOCK_SIZE: usize = 0x1000;
+
+/// The file type of a primary hive file, as opposed to a transaction log.
+const FILE_TYPE_PRIMARY: u32 = 0;
+
+/// The flag in a key node that marks its name as stored in ASCII instead of UTF-16.
+const KEY_COMP_NAME: u16 = 0x0020;
+
+/// The flag in a value key that marks its name as stored in ASCII instead of UTF-16.
+const VALUE_COMP_NAME: u16 = 0x0001;
+
+/// The bit in the data size of a value that marks the data as stored in the data offset field.
+const DATA_IS_RESIDENT: u32 = 0x8000_0000;
+
+/// The largest value data that is stored in a single cell, larger data uses a big data record.
+const MAX_CELL_DATA_LEN: usize = 16344;
+
+/// The maximum nesting depth of keys, as enforced by Windows.
+const MAX_KEY_DEPTH: usize = 512;
+
+/// The number of bytes of value data that is stored in snapshots.
+///
+/// Longer data is only recorded with its size and hash.
+const MAX_STORED_DATA_LEN: usize = 256;
+
+/// The number of bytes of binary value data that is displayed.
+const MAX_DISPLAYED_DATA_LEN: usize = 32;
+
+/// A registry hive with its tree of keys.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct Hive {
+    /// The last time the hive was written to.
+    pub(crate) last_written: Timestamp,
+    /// The root key of the hive.
+    pub(crate) root: Key,
+}
+
+/// A key in a registry hive.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct Key {
+    /// The last time the key was written to.
+    pub(crate) last_written: Timestamp,
+    /// The values of the key by name, the default value has an empty name.
+    pub(crate) values: BTreeMap<String, Value>,
+    /// The subkeys of the key by name.
+    pub(crate) subkeys: BTreeMap<String, Key>,
+}
+
+/// A value in a registry key.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct Value {
+    /// The type of the data, such as `REG_SZ`.
+    pub(crate) data_type: u32,
+    /// The size of the data in bytes.
+    pub(crate) size: u32,
+    /// The data of the value, truncated to `MAX_STORED_DATA_LEN` bytes.
+    pub(crate) data: Vec<u8>,
+    /// The SHA2-256 hash of the complete data, if it was truncated.
+    pub(crate) hash: Option<Sha256Hash>,
+}
+
+impl Key {
+    /// Returns the subkey with the given name, ignoring the case of ASCII characters.
+    pub(crate) fn subkey(&self, name: &str) -> Option<&Key> {
+        self.subkeys.get(name).or_else(|| {
+            self.subkeys
+                .iter()
+                .find(|(subkey_name, _)| subkey_name.eq_ignore_ascii_case(name))
+                .map(|(_, subkey)| subkey)
+        })
+    }
+
+    /// Returns the key at the given path of names below this key.
+    pub(crate) fn get<'name>(&self, path: impl IntoIterator<Item = &'name str>) -> Option<&Key> {
+        path.into_iter()
+            .try_fold(self, |key, name| key.subkey(name))
+    }
+}
+
+impl Value {
+    /// Creates the value with the given type from its complete data.
+    fn new(data_type: u32, data: &[u8]) -> Self {
+        use sha2::Digest as _;
+
+        let hash = (data.len() > MAX_STORED_DATA_LEN).then(|| Sha256Hash {
+            bytes: sha2::Sha256::digest(data).into(),
+        });
+
+        Self {
+            data_type,
+            size: data.len() as u32,
+            data: data[..data.len().min(MAX_STORED_DATA_LEN)].to_vec(),
+            hash,
+        }
+    }
+
+    /// Returns the name of the data type of the value.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self.data_type {
+            0 => "REG_NONE",
+            1 => "REG_SZ",
+            2 => "REG_EXPAND_SZ",
+            3 => "REG_BINARY",
+            4 => "REG_DWORD",
+            5 => "REG_DWORD_BIG_ENDIAN",
+            6 => "REG_LINK",
+            7 => "REG_MULTI_SZ",
+            8 => "REG_RESOURCE_LIST",
+            9 => "REG_FULL_RESOURCE_DESCRIPTOR",
+            10 => "REG_RESOURCE_REQUIREMENTS_LIST",
+            11 => "REG_QWORD",
+            _ => "unknown type",
+        }
+    }
+
+    /// Returns `true` if the data was truncated when it was stored.
+    pub(crate) fn is_truncated(&self) -> bool {
+        self.data.len() < self.size as usize
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Decodes the given UTF-16LE data, stopping at the first null character.
+        fn utf16(data: &[u8]) -> String {
+            let units = data
+                .chunks_exact(2)
+                .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+                .take_while(|&unit| unit != 0);
+
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        }
+
+        write!(f, "{} ", self.type_name())?;
+
+        match (self.data_type, &self.data[..]) {
+            (1 | 2 | 6, data) => write!(f, "{:?}", utf16(data))?,
+            (7, data) => {
+                let units = data
+                    .chunks_exact(2)
+                    .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+                    .collect::<Vec<_>>();
+                let strings = units
+                    .split(|&unit| unit == 0)
+                    .filter(|string| !string.is_empty())
+                    .map(String::from_utf16_lossy)
+                    .collect::<Vec<_>>();
+                write!(f, "{strings:?}")?
+            }
+            (4, &[a, b, c, d]) => {
+                let value = u32::from_le_bytes([a, b, c, d]);
+                write!(f, "{value:#010x} ({value})")?
+            }
+            (5, &[a, b, c, d]) => {
+                let value = u32::from_be_bytes([a, b, c, d]);
+                write!(f, "{value:#010x} ({value})")?
+            }
+            (11, &[a, b, c, d, e, f_, g, h]) => {
+                let value = u64::from_le_bytes([a, b, c, d, e, f_, g, h]);
+                write!(f, "{value:#018x} ({value})")?
+            }
+            (_, data) => {
+                for byte in data.iter().take(MAX_DISPLAYED_DATA_LEN) {
+                    write!(f, "{byte:02x}")?;
+                }
+                if data.len() > MAX_DISPLAYED_DATA_LEN && !self.is_truncated() {
+                    write!(f, "... ({} bytes)", self.size)?;
+                }
+            }
+        }
+
+        if self.is_truncated() {
+            write!(f, "... ({} bytes", self.size)?;
+            if let Some(hash) = &self.hash {
+                write!(f, ", SHA2-256 {hash}")?;
+            }
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns `true` if a file starting with the given bytes may be a registry hive file.
+///
+/// Transaction logs share the magic bytes of hive files, they are only told apart by `parse`.
+pub(crate) fn is_hive(first_bytes: &[u8]) -> bool {
+    first_bytes.starts_with(HIVE_MAGIC)
+}
+
+/// Reads a little endian `u16` at the given offset.
+fn le_u16(data: &[u8], offset: usize) -> anyhow::Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of data reading u16 at {offset:#x}"))
+}
+
+/// Reads a little endian `u32` at the given offset.
+fn le_u32(data: &[u8], offset: usize) -> anyhow::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of data reading u32 at {offset:#x}"))
+}
+
+/// Reads a little endian `u64` at the given offset.
+fn le_u64(data: &[u8], offset: usize) -> anyhow::Result<u64> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of data reading u64 at {offset:#x}"))
+}
+
+/// Decodes a key or value name of `len` bytes at `offset`.
+///
+/// Compressed names store one byte per character, others are stored in UTF-16LE.
+fn name(data: &[u8], offset: usize, len: usize, compressed: bool) -> anyhow::Result<String> {
+    let bytes = data
+        .get(offset..offset + len)
+        .ok_or_else(|| anyhow::anyhow!("name at {offset:#x} exceeds the cell"))?;
+
+    if compressed {
+        Ok(bytes.iter().copied().map(char::from).collect())
+    } else {
+        let units = bytes
+            .chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]));
+
+        Ok(char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect())
+    }
+}
+
+/// A parser for the cells in the hive bins of a hive file.
+struct HiveParser<'data> {
+    /// The data of the hive bins, starting after the base block.
+    bins: &'data [u8],
+    /// The offsets of the key nodes that were already parsed, to detect cycles.
+    visited: HashSet<u32>,
+}
+
+impl<'data> HiveParser<'data> {
+    /// Returns the data of the allocated cell at the given offset.
+    fn cell(&self, offset: u32) -> anyhow::Result<&'data [u8]> {
+        let start = offset as usize;
+        let size =
+            le_u32(self.bins, start).with_context(|| format!("no cell at {offset:#x}"))? as i32;
+        anyhow::ensure!(size < 0, "the cell at {offset:#x} is not allocated");
+
+        self.bins
+            .get(start + 4..start + size.unsigned_abs() as usize)
+            .with_context(|| format!("the cell at {offset:#x} exceeds the hive"))
+    }
+
+    /// Parses the key node at the given offset with all of its values and subkeys.
+    fn key(&mut self, offset: u32, depth: usize) -> anyhow::Result<(String, Key)> {
+        anyhow::ensure!(depth <= MAX_KEY_DEPTH, "keys are nested too deeply");
+        anyhow::ensure!(
+            self.visited.insert(offset),
+            "the key at {offset:#x} is referenced more than once"
+        );
+
+        let cell = self.cell(offset)?;
+        anyhow::ensure!(cell.starts_with(b"nk"), "no key node at {offset:#x}");
+
+        let flags = le_u16(cell, 0x02)?;
+        let last_written = Timestamp::from_ntfs_timestamp(le_u64(cell, 0x04)? as i64);
+        let subkey_count = le_u32(cell, 0x14)?;
+        let subkey_list = le_u32(cell, 0x1c)?;
+        let value_count = le_u32(cell, 0x24)?;
+        let value_list = le_u32(cell, 0x28)?;
+        let name_len = le_u16(cell, 0x48)?;
+        let name = name(cell, 0x4c, name_len.into(), flags & KEY_COMP_NAME != 0)?;
+
+        let mut values = BTreeMap::new();
+        if value_count > 0 {
+            let list = self
+                .cell(value_list)
+                .context("could not read the value list")?;
+            for i in 0..value_count as usize {
+                let value_offset = le_u32(list, i * 4)?;
+                let (value_name, value) = self
+                    .value(value_offset)
+                    .with_context(|| format!("could not parse value {i} of {name}"))?;
+                values.insert(value_name, value);
+            }
+        }
+
+        let mut subkeys = BTreeMap::new();
+        if subkey_count > 0 {
+            let mut offsets = Vec::new();
+            self.subkey_offsets(subkey_list, &mut offsets, false)
+                .with_context(|| format!("could not read the subkey list of {name}"))?;
+            for subkey_offset in offsets {
+                let (subkey_name, subkey) = self
+                    .key(subkey_offset, depth + 1)
+                    .with_context(|| format!("could not parse subkey of {name}"))?;
+                subkeys.insert(subkey_name, subkey);
+            }
+        }
+
+        Ok((
+            name,
+            Key {
+                last_written,
+                values,
+                subkeys,
+            },
+        ))
+    }
+
+    /// Collects the offsets of the key nodes in the subkey list at the given offset.
+    ///
+    /// Index roots refer to further subkey lists, which may not be index roots themselves.
+    fn subkey_offsets(
+        &self,
+        offset: u32,
+        offsets: &mut Vec<u32>,
+        in_index_root: bool,
+    ) -> anyhow::Result<()> {
+        let cell = self.cell(offset)?;
+        let count = usize::from(le_u16(cell, 0x02)?);
+
+        match cell.get(..2) {
+            Some(b"li") => {
+                for i in 0..count {
+                    offsets.push(le_u32(cell, 0x04 + i * 4)?);
+                }
+            }
+            Some(b"lf" | b"lh") => {
+                for i in 0..count {
+                    offsets.push(le_u32(cell, 0x04 + i * 8)?);
+                }
+            }
+            Some(b"ri") if !in_index_root => {
+                for i in 0..count {
+                    self.subkey_offsets(le_u32(cell, 0x04 + i * 4)?, offsets, true)?;
+                }
+            }
+            _ => anyhow::bail!("no subkey list at {offset:#x}"),
+        }
+
+        Ok(())
+    }
+
+    /// Parses the value key at the given offset.
+    fn value(&self, offset: u32) -> anyhow::Result<(String, Value)> {
+        let cell = self.cell(offset)?;
+        anyhow::ensure!(cell.starts_with(b"vk"), "no value key at {offset:#x}");
+
+        let name_len = le_u16(cell, 0x02)?;
+        let size = le_u32(cell, 0x04)?;
+        let data_offset = le_u32(cell, 0x08)?;
+        let data_type = le_u32(cell, 0x0c)?;
+        let flags = le_u16(cell, 0x10)?;
+        let name = name(cell, 0x14, name_len.into(), flags & VALUE_COMP_NAME != 0)?;
+
+        let len = (size & !DATA_IS_RESIDENT) as usize;
+        let value = if size & DATA_IS_RESIDENT != 0 {
+            let data = &cell[0x08..0x0c];
+            Value::new(data_type, &data[..len.min(data.len())])
+        } else if len == 0 {
+            Value::new(data_type, &[])
+        } else {
+            let data = self
+                .cell(data_offset)
+                .with_context(|| format!("could not read the data of {name}"))?;
+
+            if len > MAX_CELL_DATA_LEN && data.starts_with(b"db") {
+                let data = self
+                    .big_data(data, len)
+                    .with_context(|| format!("could not read the big data of {name}"))?;
+                Value::new(data_type, &data)
+            } else {
+                let data = data
+                    .get(..len)
+                    .with_context(|| format!("the data of {name} exceeds its cell"))?;
+                Value::new(data_type, data)
+            }
+        };
+
+        Ok((name, value))
+    }
+
+    /// Assembles the data of `len` bytes in the segments of the given big data record.
+    fn big_data(&self, record: &[u8], len: usize) -> anyhow::Result<Vec<u8>> {
+        let count = usize::from(le_u16(record, 0x02)?);
+        let segments = self.cell(le_u32(record, 0x04)?)?;
+
+        let mut data = Vec::with_capacity(len);
+        for i in 0..count {
+            let segment = self.cell(le_u32(segments, i * 4)?)?;
+            let remaining = len - data.len();
+            data.extend_from_slice(&segment[..segment.len().min(MAX_CELL_DATA_LEN).min(remaining)]);
+        }
+        anyhow::ensure!(data.len() == len, "the big data segments are too short");
+
+        Ok(data)
+    }
+}
+
+/// Parses the registry hive file with the given contents.
+///
+/// Returns `None` if the file is a transaction log instead of a primary hive file.
+pub(crate) fn parse(data: &[u8]) -> anyhow::Result<Option<Hive>> {
+    firestorm::profile_fn!(parse_hive);
+
+    anyhow::ensure!(data.starts_with(HIVE_MAGIC), "missing regf signature");
+    if le_u32(data, 0x1c)? != FILE_TYPE_PRIMARY {
+        return Ok(None);
+    }
+
+    let last_written = Timestamp::from_ntfs_timestamp(le_u64(data, 0x0c)? as i64);
+    let root_offset = le_u32(data, 0x24)?;
+    let bins_len = le_u32(data, 0x28)? as usize;
+
+    let bins = data
+        .get(BASE_BLOCK_SIZE..)
+        .context("the hive has no hive bins")?;
+    let bins = &bins[..bins_len.min(bins.len())];
+    anyhow::ensure!(bins.starts_with(b"hbin"), "missing hbin signature");
+
+    let mut parser = HiveParser {
+        bins,
+        visited: HashSet::new(),
+    };
+    let (_, root) = parser
+        .key(root_offset, 0)
+        .context("could not parse the root key")?;
+
+    Ok(Some(Hive { last_written, root }))
+}
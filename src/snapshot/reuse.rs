@@ -0,0 +1,171 @@
+//! Reuses the file information of a previous snapshot for unchanged files.
+//!
+//! Reading and hashing every file takes a long time on full system images, while most files do
+//! not change between two snapshots of the same system.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher as _,
+    io,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    fs::{DirEntry, File, MetaDEntry, Metadata},
+    timestamp::Timestamp,
+};
+
+use super::CURRENT_SNAPSHOT_VERSION;
+
+/// One in how many reused files is read again to verify it in paranoid mode.
+const PARANOID_SAMPLE_RATE: u64 = 100;
+
+/// The properties of a file that must be unchanged for its information to be reused.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct ReuseKey {
+    /// The size of the file.
+    pub(crate) size: u64,
+    /// The last modification time of the file.
+    pub(crate) modified: Option<Timestamp>,
+    /// The last modification time of the MFT entry of the file.
+    pub(crate) mft_modified: Option<Timestamp>,
+    /// The inode number of the file.
+    pub(crate) inode: Option<u64>,
+}
+
+impl From<&Metadata> for ReuseKey {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            size: metadata.size,
+            modified: metadata.modified,
+            mft_modified: metadata.mft_modified,
+            inode: metadata.inode,
+        }
+    }
+}
+
+/// Statistics about the reuse of file information.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ReuseStats {
+    /// The number of files whose information was reused.
+    pub(crate) reused: u64,
+    /// The number of reused files that were read again for verification.
+    pub(crate) verified: u64,
+    /// The number of verified files whose information did not match the previous snapshot.
+    pub(crate) mismatched: u64,
+}
+
+/// Provides the file information of a previous snapshot.
+pub(crate) struct Reuse {
+    /// The root of the previous snapshot.
+    previous: MetaDEntry,
+    /// Whether the previous snapshot has the current version.
+    ///
+    /// Files of older versions lack the information that was added since, so they are never
+    /// reused.
+    current: bool,
+    /// Whether a sample of the reused files should be read again for verification.
+    paranoid: bool,
+    /// Used to randomly choose the files that are verified.
+    sampler: RandomState,
+    /// The number of files whose information was reused.
+    reused: AtomicU64,
+    /// The number of reused files that were read again for verification.
+    verified: AtomicU64,
+    /// The number of verified files whose information did not match the previous snapshot.
+    mismatched: AtomicU64,
+}
+
+impl Reuse {
+    /// Reuses the file information in the given root of a previous snapshot of `version`.
+    ///
+    /// If `paranoid` is set, a random sample of the reused files is read again to check that
+    /// the reused information is still correct.
+    pub(crate) fn new(previous: MetaDEntry, version: u8, paranoid: bool) -> Self {
+        let current = version >= CURRENT_SNAPSHOT_VERSION;
+        if !current {
+            eprintln!(
+                "warning: the previous snapshot has version {version} instead of {CURRENT_SNAPSHOT_VERSION}, all files are read again"
+            );
+        }
+
+        Self {
+            previous,
+            current,
+            paranoid,
+            sampler: RandomState::new(),
+            reused: AtomicU64::new(0),
+            verified: AtomicU64::new(0),
+            mismatched: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the file information for the file at `path` in the snapshot.
+    ///
+    /// If the file at the same path in the previous snapshot has the same `key`, its information
+    /// is reused, otherwise `read` is used to read the file.
+    pub(crate) fn file_or_read(
+        &self,
+        path: &Path,
+        key: ReuseKey,
+        read: impl FnOnce() -> io::Result<File>,
+    ) -> io::Result<File> {
+        let Some(previous) = self.lookup(path, key) else {
+            return read();
+        };
+
+        self.reused.fetch_add(1, Ordering::Relaxed);
+
+        if self.paranoid
+            && self
+                .sampler
+                .hash_one(path)
+                .is_multiple_of(PARANOID_SAMPLE_RATE)
+        {
+            self.verified.fetch_add(1, Ordering::Relaxed);
+
+            let file = read()?;
+            if file != *previous {
+                self.mismatched.fetch_add(1, Ordering::Relaxed);
+                eprintln!(
+                    "warning: {} changed without changing its metadata, the reused file information would have been wrong",
+                    path.display()
+                );
+            }
+
+            return Ok(file);
+        }
+
+        Ok(previous.clone())
+    }
+
+    /// Returns the file in the previous snapshot at `path`, if it has the given `key`.
+    fn lookup(&self, path: &Path, key: ReuseKey) -> Option<&File> {
+        if !self.current {
+            return None;
+        }
+
+        // Without a modification time, changes cannot be detected
+        key.modified?;
+
+        let previous = self.previous.get(path).ok()?;
+        if ReuseKey::from(&previous.metadata) != key {
+            return None;
+        }
+
+        match &previous.entry {
+            DirEntry::File(file) => Some(file),
+            _ => None,
+        }
+    }
+
+    /// Returns the statistics about reused files so far.
+    pub(crate) fn stats(&self) -> ReuseStats {
+        ReuseStats {
+            reused: self.reused.load(Ordering::Relaxed),
+            verified: self.verified.load(Ordering::Relaxed),
+            mismatched: self.mismatched.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -131,20 +131,30 @@ impl Updates {
     pub(crate) fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
         let path = path.as_ref();
 
+        let metadata = path.metadata()?;
+
+        Self::from_reader(File::open(path)?, metadata.modified()?.into())
+    }
+
+    /// Reads the update information from the given reader.
+    ///
+    /// The `recording_time` should be the last modification time of the updates file.
+    pub(crate) fn from_reader(
+        reader: impl io::Read,
+        recording_time: Timestamp,
+    ) -> io::Result<Self> {
         let mut updates: Vec<Update> = Vec::new();
 
-        for raw_update in csv::Reader::from_reader(File::open(path)?).deserialize() {
+        for raw_update in csv::Reader::from_reader(reader).deserialize() {
             let raw_update: BTreeMap<String, String> = raw_update?;
             if let Ok(update) = raw_update.try_into() {
                 updates.push(update);
             }
         }
 
-        let metadata = path.metadata()?;
-
         Ok(Self {
             updates,
-            recording_time: metadata.modified()?.into(),
+            recording_time,
         })
     }
 }